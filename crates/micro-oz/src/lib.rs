@@ -166,6 +166,7 @@ impl Pinhead {
             valid_until:    tx_request
                 .valid_until
                 .unwrap_or(Utc::now() + chrono::Duration::hours(24)),
+            nonce:          None,
         };
 
         txs.insert(tx_id.clone(), Arc::new(Mutex::new(tx.clone())));
@@ -0,0 +1,6 @@
+CREATE TABLE root_finalizations (
+    root          BYTEA       NOT NULL,
+    chain_id      BIGINT      NOT NULL,
+    seen_at       TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (root, chain_id)
+);
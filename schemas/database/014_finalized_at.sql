@@ -0,0 +1,2 @@
+-- When this root was finalized on every chain
+ALTER TABLE identities ADD COLUMN finalized_at TIMESTAMPTZ;
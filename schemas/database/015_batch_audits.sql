@@ -0,0 +1,10 @@
+-- The proof and inputs of every submitted insertion batch, kept for audits
+CREATE TABLE batch_audits (
+    post_root       BYTEA       NOT NULL PRIMARY KEY,
+    pre_root        BYTEA       NOT NULL,
+    start_index     BIGINT      NOT NULL,
+    commitments     BYTEA[]     NOT NULL,
+    proof           BYTEA[]     NOT NULL,
+    transaction_ids TEXT[]      NOT NULL,
+    submitted_at    TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- Insertion batches that were proved but not yet submitted, so that their
+-- proofs survive a restart
+CREATE TABLE pending_proofs (
+    input_hash  BYTEA       NOT NULL PRIMARY KEY,
+    start_index BIGINT      NOT NULL,
+    pre_root    BYTEA       NOT NULL,
+    post_root   BYTEA       NOT NULL,
+    commitments BYTEA[]     NOT NULL,
+    proof       BYTEA[]     NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
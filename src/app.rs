@@ -1,29 +1,35 @@
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, OnceLock};
 use std::time::Instant;
 
 use chrono::{Duration, Utc};
+use ethers::types::U256;
 use ruint::Uint;
-use semaphore::poseidon_tree::LazyPoseidonTree;
 use semaphore::protocol::verify_proof;
+use tokio::sync::Mutex;
 use tracing::{info, instrument, warn};
 
 use crate::config::Config;
-use crate::contracts::{IdentityManager, SharedIdentityManager};
+use crate::contracts::{settle_pending_transactions, IdentityManager, SharedIdentityManager};
 use crate::database::Database;
 use crate::ethereum::Ethereum;
 use crate::identity_tree::{
-    CanonicalTreeBuilder, Hash, InclusionProof, ProcessedStatus, RootItem, Status, TreeState,
-    TreeUpdate, TreeVersionReadOps, UnprocessedStatus,
+    empty_tree_root, CanonicalTreeBuilder, DeletionStatus, Hash, InclusionProof, ProcessedStatus,
+    RootItem, Status, TreeState, TreeUpdate, TreeVersionReadOps, UnprocessedStatus,
 };
 use crate::prover::map::initialize_prover_maps;
-use crate::prover::{ProverConfig, ProverType};
+use crate::prover::queue::{ProvingQueue, TreeOperation};
+use crate::prover::{ProverConfig, ProverSettings, ProverType};
 use crate::server::data::{
     IdentityHistoryEntry, IdentityHistoryEntryKind, IdentityHistoryEntryStatus,
-    InclusionProofResponse, ListBatchSizesResponse, VerifySemaphoreProofQuery,
+    InclusionProofResponse, ListBatchSizesResponse, QueueStateResponse, VerifySemaphoreProofQuery,
     VerifySemaphoreProofRequest, VerifySemaphoreProofResponse,
 };
 use crate::server::error::Error as ServerError;
+use crate::stats::{collect_sequencer_stats, SequencerStats};
+use crate::task_monitor::TaskMonitor;
+use crate::utils::batch_trace::BatchSpans;
 use crate::utils::tree_updates::dedup_tree_updates;
 
 pub struct App {
@@ -32,6 +38,13 @@ pub struct App {
     tree_state:             OnceLock<TreeState>,
     pub snark_scalar_field: Hash,
     pub config:             Config,
+    /// Insertions and deletions waiting to be applied to the latest tree,
+    /// deletions first. They're put back into the database on shutdown
+    pub proving_queue:      Mutex<ProvingQueue<TreeOperation>>,
+    /// Set while an operator has paused proving, submission and finalization
+    paused:                 AtomicBool,
+    /// Spans of submitted batches, kept until their roots are finalized
+    pub batch_spans:        BatchSpans,
 }
 
 impl App {
@@ -58,7 +71,12 @@ impl App {
 
         database.insert_provers(non_inserted_provers).await?;
 
-        let (insertion_prover_map, deletion_prover_map) = initialize_prover_maps(provers)?;
+        if let Some(root) = config.app.resume_finalization_from_root {
+            Self::validate_resumed_root(&database, root).await?;
+        }
+
+        let (insertion_prover_map, deletion_prover_map) =
+            initialize_prover_maps(provers, &ProverSettings::from(&config.app))?;
 
         let identity_manager = Arc::new(
             IdentityManager::new(
@@ -70,6 +88,16 @@ impl App {
             .await?,
         );
 
+        identity_manager
+            .verify_provers()
+            .await
+            .enforce(config.app.prover_startup_check)?;
+
+        if config.app.prover_warmup {
+            let identity_manager = identity_manager.clone();
+            tokio::spawn(async move { identity_manager.warmup_provers().await });
+        }
+
         // TODO Export the reduced-ness check that this is enabling from the
         //  `semaphore-rs` library when we bump the version.
         let snark_scalar_field = Hash::from_str_radix(
@@ -84,26 +112,50 @@ impl App {
             tree_state: OnceLock::new(),
             snark_scalar_field,
             config,
+            proving_queue: Mutex::new(ProvingQueue::new()),
+            paused: AtomicBool::new(false),
+            batch_spans: BatchSpans::default(),
         });
 
         Ok(app)
     }
 
+    /// Ensures that the root configured for resumed finalization is known to
+    /// the database, so that we don't enqueue a root we have no record of.
+    async fn validate_resumed_root(database: &Database, root: U256) -> anyhow::Result<()> {
+        if database.get_root_state(&root.into()).await?.is_none() {
+            return Err(anyhow::anyhow!(
+                "Root {root:#x} configured for resumed finalization does not exist in the database"
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Initializes the tree state. This should only ever be called once.
     /// Attempts to call this method more than once will result in a panic.
     pub async fn init_tree(self: Arc<Self>) -> anyhow::Result<()> {
         // Await for all pending transactions
-        self.identity_manager.await_clean_slate().await?;
+        settle_pending_transactions(
+            self.identity_manager.as_ref(),
+            self.config.app.skip_clean_slate,
+        )
+        .await?;
 
         // Prefetch latest root & mark it as mined
         let root_hash = self.identity_manager.latest_root().await?;
         let root_hash = root_hash.into();
 
-        let initial_root_hash = LazyPoseidonTree::new(
+        let initial_root_hash: Hash = empty_tree_root(
             self.identity_manager.tree_depth(),
             self.identity_manager.initial_leaf_value(),
         )
-        .root();
+        .into();
+
+        if let Some(from_block) = self.config.network.backfill_from_block {
+            self.backfill_if_empty(root_hash, initial_root_hash, from_block)
+                .await?;
+        }
 
         // We don't store the initial root in the database, so we have to skip this step
         // if the contract root hash is equal to initial root hash
@@ -132,6 +184,15 @@ impl App {
             tree_state = self.restore_or_initialize_tree(initial_root_hash).await?;
         }
 
+        TaskMonitor::log_tree_occupancy(
+            &self
+                .identity_manager
+                .tree_occupancy(tree_state.get_batching_tree().next_leaf()),
+        );
+
+        self.identity_manager
+            .set_batching_tree(tree_state.get_batching_tree());
+
         self.tree_state.set(tree_state).map_err(|_| {
             anyhow::anyhow!(
                 "Failed to set tree state. 'App::init_tree' should only be called once."
@@ -141,6 +202,37 @@ impl App {
         Ok::<(), anyhow::Error>(())
     }
 
+    /// Imports the identities already held by the identity manager when the
+    /// database has none of them.
+    async fn backfill_if_empty(
+        &self,
+        contract_root: Hash,
+        initial_root_hash: Hash,
+        from_block: u64,
+    ) -> anyhow::Result<()> {
+        if contract_root == initial_root_hash || self.database.get_next_leaf_index().await? != 0 {
+            return Ok(());
+        }
+
+        let backfilled_root = self
+            .identity_manager
+            .backfill(
+                &self.database,
+                from_block,
+                self.config.app.scanning_window_size,
+            )
+            .await?;
+
+        if backfilled_root != contract_root {
+            return Err(anyhow::anyhow!(
+                "Backfilled root {backfilled_root:?} does not match the contract root \
+                 {contract_root:?}"
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn restore_or_initialize_tree(
         &self,
         initial_root_hash: Hash,
@@ -500,6 +592,9 @@ impl App {
                 Status::Processed(ProcessedStatus::Processed) => IdentityHistoryEntryStatus::Mined,
                 Status::Processed(ProcessedStatus::Mined) => IdentityHistoryEntryStatus::Bridged,
                 Status::Unprocessed(UnprocessedStatus::New) => IdentityHistoryEntryStatus::Buffered,
+                Status::Deletion(DeletionStatus::PendingDeletion) => {
+                    IdentityHistoryEntryStatus::PendingDeletion
+                }
             };
 
             match status {
@@ -602,6 +697,30 @@ impl App {
         Ok(())
     }
 
+    /// Changes the timeout of an existing prover without restarting it.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the requested batch size does not exist.
+    /// Will return `Err` if the timeout fails to be written to the database.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn set_prover_timeout(
+        &self,
+        batch_size: usize,
+        prover_type: ProverType,
+        timeout_seconds: u64,
+    ) -> Result<(), ServerError> {
+        self.identity_manager
+            .set_prover_timeout(batch_size, prover_type, timeout_seconds)
+            .await?;
+
+        self.database
+            .update_prover_timeout(batch_size, prover_type, timeout_seconds)
+            .await?;
+
+        Ok(())
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if something unknown went wrong.
@@ -612,6 +731,117 @@ impl App {
         Ok(ListBatchSizesResponse::from(batches))
     }
 
+    /// Stops proving, submitting and finalizing batches, e.g. during an
+    /// incident. Identities keep being accepted and queued. Batches already
+    /// being submitted are finished first.
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            warn!("Pipeline paused");
+        }
+    }
+
+    /// Resumes the work stopped by [`Self::pause`].
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!("Pipeline resumed");
+        }
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Exports the number of identities waiting to be batched, per status.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the queue state cannot be read from the database.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn queue_state(&self) -> Result<QueueStateResponse, ServerError> {
+        let queue_state = self.database.get_queue_state().await?;
+
+        Ok(QueueStateResponse::from(queue_state))
+    }
+
+    /// Returns how many more identities have to be queued before the smallest
+    /// insertion prover can be filled, zero if the queued ones already fill
+    /// it.
+    ///
+    /// Queued identities are the ones eligible for insertion in the database,
+    /// the ones waiting to be applied to the latest tree and the ones in the
+    /// latest tree that are not batched yet.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tree is not initialized yet, if the database
+    /// cannot be queried or if no insertion provers are registered.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn identities_until_next_batch(&self) -> anyhow::Result<usize> {
+        let buffered = self.database.get_queue_state().await?.buffered as usize;
+
+        let applying = self
+            .proving_queue
+            .lock()
+            .await
+            .iter()
+            .filter(|operation| matches!(operation, TreeOperation::Insertion(_)))
+            .count();
+
+        let tree_state = self.tree_state()?;
+        let unbatched = tree_state
+            .latest_tree()
+            .next_leaf()
+            .saturating_sub(tree_state.batching_tree().next_leaf());
+
+        self.identity_manager
+            .identities_until_next_batch(buffered + applying + unbatched)
+            .await
+    }
+
+    /// Writes the insertions and deletions still waiting to be applied to the
+    /// tree back to their database queues, so that they are picked up again
+    /// after a restart.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the database cannot be written to.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn drain_to_database(&self) -> anyhow::Result<()> {
+        let operations = self.proving_queue.lock().await.drain();
+
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        info!(count = operations.len(), "Re-queueing in-flight operations");
+        self.database.requeue_operations(&operations).await?;
+
+        Ok(())
+    }
+
+    /// Collects a snapshot of the sequencer's state for debugging and
+    /// monitoring.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tree is not initialized yet, or if the database
+    /// or the chain cannot be queried.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn sequencer_stats(&self) -> anyhow::Result<SequencerStats> {
+        let tree_state = self.tree_state()?;
+        let provers = self.identity_manager.prover_health().await;
+
+        collect_sequencer_stats(
+            &self.database,
+            self.identity_manager.as_ref(),
+            tree_state.latest_tree(),
+            self.identity_manager.tree_depth(),
+            provers,
+        )
+        .await
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the provided index is out of bounds.
@@ -630,10 +860,11 @@ impl App {
             .await?
         {
             return Ok(InclusionProofResponse(InclusionProof {
-                status:  status.into(),
-                root:    None,
-                proof:   None,
-                message: Some(error_message),
+                status:      status.into(),
+                root:        None,
+                proof:       None,
+                message:     Some(error_message),
+                root_status: None,
             }));
         }
 
@@ -643,15 +874,41 @@ impl App {
             .await?
             .ok_or(ServerError::IdentityCommitmentNotFound)?;
 
-        let (leaf, proof) = self.tree_state()?.get_proof_for(&item);
+        let (leaf, mut proof) = self.tree_state()?.get_proof_for(&item);
 
         if leaf != *commitment {
             return Err(ServerError::InvalidCommitment);
         }
 
+        // The identity is still part of the tree, so the proof stays valid until
+        // the deletion is batched
+        if self.is_pending_deletion(commitment).await? {
+            proof.root_status = Some(item.status);
+            proof.status = DeletionStatus::PendingDeletion.into();
+        }
+
         Ok(InclusionProofResponse(proof))
     }
 
+    /// Returns inclusion proofs for up to `count` consecutive leaves starting
+    /// at `start`, leaving out leaves past the end of the tree.
+    pub fn inclusion_proofs_range(
+        &self,
+        start: usize,
+        count: usize,
+    ) -> anyhow::Result<Vec<InclusionProof>> {
+        self.tree_state()?.inclusion_proofs_range(start, count)
+    }
+
+    /// Returns whether the identity is queued for deletion but not yet removed
+    /// from the tree.
+    pub async fn is_pending_deletion(&self, commitment: &Hash) -> anyhow::Result<bool> {
+        Ok(self
+            .database
+            .identity_is_queued_for_deletion(commitment)
+            .await?)
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the provided proof is invalid.
@@ -1,14 +1,18 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
 use std::time::Duration;
 
-use ethers::types::{Address, H160};
+use ethers::types::{Address, H160, H256, U256};
 use semaphore::Field;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::prover::ProverConfig;
+use crate::utils::retry::Backoff;
 use crate::utils::secret::SecretUrl;
-use crate::utils::serde_utils::JsonStrWrapper;
+use crate::utils::serde_utils::{lenient_field, JsonStrWrapper};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -21,6 +25,43 @@ pub struct Config {
     pub server:    ServerConfig,
 }
 
+impl Config {
+    /// Rejects settings that can't take effect with the rest of the config.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` describing the first invalid setting.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.network.nonce_strategy.is_some()
+            && self.network.submission_mode == SubmissionMode::Public
+        {
+            anyhow::bail!(
+                "`nonce_strategy` only applies to private submission, the relayer assigns the \
+                 nonces of public transactions"
+            );
+        }
+
+        for (setting, multiplier) in [
+            (
+                "owner_check_retry_multiplier",
+                self.network.owner_check_retry_multiplier,
+            ),
+            (
+                "view_call_retry_multiplier",
+                self.network.view_call_retry_multiplier,
+            ),
+        ] {
+            // A smaller multiplier would shrink the delays, and a negative one
+            // would make them negative
+            if multiplier.is_nan() || multiplier < 1.0 {
+                anyhow::bail!("`{setting}` must be at least 1.0, got {multiplier}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     /// A list of prover urls (along with batch size, type and timeout) that
@@ -33,6 +74,11 @@ pub struct AppConfig {
     #[serde(default = "default::batch_insertion_timeout")]
     pub batch_insertion_timeout: Duration,
 
+    /// The order identities are inserted into the tree in, and thus the order
+    /// they take within a batch and its proof
+    #[serde(default)]
+    pub insertion_ordering: OrderingStrategy,
+
     /// The maximum number of seconds the sequencer will wait before sending a
     /// batch of deletions to the chain, even if the batch is not full.
     #[serde(with = "humantime_serde")]
@@ -68,9 +114,196 @@ pub struct AppConfig {
     #[serde(default = "default::time_between_scans")]
     pub time_between_scans: Duration,
 
+    /// The longest time to wait before the first scan for finalized roots.
+    /// The actual delay is drawn at random up to it, so that many instances
+    /// started at once don't poll the RPC providers in lockstep
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::finalization_startup_jitter")]
+    pub finalization_startup_jitter: Duration,
+
     /// The number of txs in the channel that we'll be monitoring
     #[serde(default = "default::monitored_txs_capacity")]
     pub monitored_txs_capacity: usize,
+
+    /// The number of blocks a root must be buried under before it's
+    /// considered mined. The block containing the root counts as the first
+    /// confirmation.
+    ///
+    /// By default the value is set to 0 so roots are considered mined as soon
+    /// as they appear on chain
+    #[serde(default = "default::min_confirmations")]
+    pub min_confirmations: u64,
+
+    /// A root to enqueue for finalization on startup. Useful after a manual
+    /// intervention left a root stuck as processed.
+    ///
+    /// The root must already exist in the database.
+    pub resume_finalization_from_root: Option<U256>,
+
+    /// The maximum number of proof requests sent to a single prover per
+    /// second. Requests are not rate limited if unset.
+    pub prover_max_requests_per_second: Option<NonZeroU32>,
+
+    /// A domain separator prepended to the preimage of the insertion proof
+    /// input hash. Must match the prover and the identity manager contract.
+    pub input_hash_domain: Option<H256>,
+
+    /// The hash function used to compute the proof input hashes. Must match
+    /// the circuits the provers run.
+    #[serde(default)]
+    pub input_hash_function: HashFunction,
+
+    /// The byte order of the 256-bit words in the keccak256 input hash
+    /// preimage. Must match the circuits the provers run.
+    #[serde(default)]
+    pub input_hash_endianness: Endianness,
+
+    /// The format the provers return proofs in
+    #[serde(default)]
+    pub prover_proof_format: ProofFormat,
+
+    /// If set, a synthetic proof request is sent to every prover on startup
+    /// to reduce the latency of the first real request
+    #[serde(default = "default::prover_warmup")]
+    pub prover_warmup: bool,
+
+    /// What happens if a prover can't be reached, or reports another batch
+    /// size than configured, when the provers are checked at startup
+    #[serde(default)]
+    pub prover_startup_check: ProverStartupCheck,
+
+    /// The number of consecutive failures after which a prover is skipped for
+    /// `prover_failure_cooldown`. Failing provers are never skipped if unset.
+    pub prover_failure_threshold: Option<NonZeroU32>,
+
+    /// How long a prover that tripped `prover_failure_threshold` is skipped
+    /// for before it is tried again
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::prover_failure_cooldown")]
+    pub prover_failure_cooldown: Duration,
+
+    /// If a root waits longer than this to be finalized on every chain, a
+    /// high-severity log is emitted and the `finalization_alerts` metric is
+    /// incremented. Finalization keeps waiting regardless. No alerts are
+    /// emitted if unset.
+    #[serde(with = "humantime_serde")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finalization_alert_after: Option<Duration>,
+
+    /// A url that is notified with a POST request every time a root is
+    /// finalized. Delivery is retried up to `finalization_webhook_retries`
+    /// times and never holds up finalization.
+    pub finalization_webhook_url: Option<Url>,
+
+    /// The number of times a failed finalization notification is retried
+    #[serde(default = "default::finalization_webhook_retries")]
+    pub finalization_webhook_retries: u32,
+
+    /// The User-Agent sent with every request to the provers. Defaults to
+    /// `signup-sequencer/<version>`.
+    pub prover_user_agent: Option<String>,
+
+    /// If set, proof requests also carry the tree depth and batch size so the
+    /// prover can check them against its circuit. Only enable this for provers
+    /// that accept these fields.
+    #[serde(default)]
+    pub prover_circuit_validation: bool,
+
+    /// If set, a proof whose response echoes public inputs is rejected unless
+    /// they hash to the input hash it was requested for.
+    #[serde(default)]
+    pub verify_echoed_inputs: bool,
+
+    /// How often every prover is asked for the batch size of its circuit, to
+    /// catch provers redeployed with a different batch size. Provers are not
+    /// checked if unset.
+    #[serde(with = "humantime_serde")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prover_check_interval: Option<Duration>,
+
+    /// If set, a prover whose reported batch size no longer matches the
+    /// configured one is not sent any batches until the two match again.
+    #[serde(default)]
+    pub pause_drifted_provers: bool,
+
+    /// The number of merkle proof elements logged per identity when proof
+    /// requests are traced. Longer proofs are cut short with an ellipsis.
+    #[serde(default = "default::log_proof_truncate_elements")]
+    pub log_proof_truncate_elements: usize,
+
+    /// The largest response body accepted from a prover, in bytes. Larger
+    /// responses fail the proof request instead of being buffered.
+    #[serde(default = "default::prover_max_response_bytes")]
+    pub prover_max_response_bytes: usize,
+
+    /// If set, no new batches are submitted while more than this many roots
+    /// are waiting to be finalized on every chain, so that a slow bridge
+    /// doesn't let the backlog grow without bound.
+    pub max_unfinalized_roots: Option<usize>,
+
+    /// The maximum number of RPC requests per second made while polling for
+    /// the finalization of roots, shared by all the roots being polled.
+    /// Unlimited if not set.
+    pub finalization_rpc_rate_limit: Option<NonZeroU32>,
+
+    /// How far apart the clocks of the bridged chains may drift. Roots added
+    /// to bridged chains are finalized in the order of their timestamps, but
+    /// roots added within this much of each other keep the order they were
+    /// seen in.
+    ///
+    /// By default the value is set to 0 so the timestamps are trusted as they
+    /// are
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::max_bridge_clock_skew")]
+    pub max_bridge_clock_skew: Duration,
+
+    /// The number of proofs kept in memory per prover, keyed by their input
+    /// hash, so that a batch proved again doesn't hit the prover a second
+    /// time. Proofs aren't cached if not set.
+    pub prover_proof_cache_capacity: Option<usize>,
+
+    /// Skips waiting for the transactions pending at startup to be mined, for
+    /// fast restarts during development. Batches may then be submitted while
+    /// earlier ones are still in flight
+    #[serde(default)]
+    pub skip_clean_slate: bool,
+
+    /// Which post root to use if the prover reports a different one than the
+    /// sequencer computed. Batches are only ever submitted with the computed
+    /// one. Fails the batch by default
+    #[serde(default)]
+    pub on_root_mismatch: OnRootMismatch,
+
+    /// The minimum time between consecutive batch submissions, to stay within
+    /// the per-block limits of the RPC provider. Unlike
+    /// `prover_max_requests_per_second`, this doesn't limit proving.
+    ///
+    /// By default the value is set to 0 so batches are submitted as soon as
+    /// they are proved
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::inter_batch_submission_delay")]
+    pub inter_batch_submission_delay: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashFunction {
+    /// `keccak256` over the byte concatenation of the inputs, see
+    /// [`Endianness`]
+    #[default]
+    Keccak256,
+    /// Poseidon over the inputs as field elements
+    Poseidon,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    /// Most significant byte first, as the contracts encode words
+    #[default]
+    Big,
+    /// Least significant byte first
+    Little,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -100,6 +333,10 @@ pub struct TreeConfig {
 
     /// Initial value of the Merkle tree leaves. Defaults to the initial value
     /// used in the identity manager contract.
+    ///
+    /// Accepts `0x`-prefixed hex, bare hex and `dec:`-prefixed decimal values.
+    /// Bare values are read as hex.
+    #[serde(with = "lenient_field")]
     #[serde(default = "default::initial_leaf_value")]
     pub initial_leaf_value: Field,
 }
@@ -113,6 +350,195 @@ pub struct NetworkConfig {
     /// mapped by chain id
     #[serde(default)]
     pub relayed_identity_manager_addresses: JsonStrWrapper<HashMap<u64, Address>>,
+
+    /// The address of an identity manager contract on the same chain that
+    /// mirrors the primary one. Every batch is also submitted to it and, while
+    /// it has received every batch, roots are only finalized once they are
+    /// mined on both. A mirror that misses a batch is skipped until it is
+    /// brought back to the primary's root.
+    pub mirrored_identity_manager_address: Option<Address>,
+
+    /// Whether transactions are submitted to the public mempool or through a
+    /// private transaction relay
+    #[serde(default)]
+    pub submission_mode: SubmissionMode,
+
+    /// How nonces are assigned to transactions sent through a private relay,
+    /// locally tracked by default. Can't be set for public submission
+    #[serde(default)]
+    pub nonce_strategy: Option<NonceStrategy>,
+
+    /// The time to wait for a transaction signed by the sequencer, i.e. sent by
+    /// the `signer` relayer or through a private relay, to be mined before it
+    /// is considered dropped
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::mining_timeout")]
+    pub mining_timeout: Duration,
+
+    /// Where the fees of identity manager transactions come from
+    #[serde(default)]
+    pub gas_price_oracle: GasOracleConfig,
+
+    /// How many more times the identity operator of the identity manager is
+    /// checked on startup if it doesn't match the signer, e.g. because an
+    /// ownership transfer is still in progress.
+    ///
+    /// By default the value is set to 0 so the sequencer refuses to start
+    /// right away
+    #[serde(default = "default::owner_check_retries")]
+    pub owner_check_retries: u32,
+
+    /// The time to wait before the first repeated identity operator check.
+    /// Later checks back off exponentially, with jitter
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::owner_check_retry_delay")]
+    pub owner_check_retry_delay: Duration,
+
+    /// The upper bound of the time to wait between identity operator checks
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::owner_check_retry_max_delay")]
+    pub owner_check_retry_max_delay: Duration,
+
+    /// The factor the time between identity operator checks grows by after
+    /// every check
+    #[serde(default = "default::owner_check_retry_multiplier")]
+    pub owner_check_retry_multiplier: f64,
+
+    /// How many more times a failing read of the identity manager state, e.g.
+    /// of its latest root, is attempted
+    #[serde(default = "default::view_call_retries")]
+    pub view_call_retries: u32,
+
+    /// The time to wait before retrying a failed read. Later retries back off
+    /// exponentially, with jitter
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::view_call_retry_delay")]
+    pub view_call_retry_delay: Duration,
+
+    /// The upper bound of the time to wait between retries of a failed read
+    #[serde(with = "humantime_serde")]
+    #[serde(default = "default::view_call_retry_max_delay")]
+    pub view_call_retry_max_delay: Duration,
+
+    /// The factor the time between retries of a failed read grows by after
+    /// every retry
+    #[serde(default = "default::view_call_retry_multiplier")]
+    pub view_call_retry_multiplier: f64,
+
+    /// The Multicall3 contract the mined status of roots is checked through
+    /// in batches. Defaults to its canonical deployment. If there is no code
+    /// at the address, e.g. on a local devnet, roots are checked one call at
+    /// a time
+    #[serde(default = "default::multicall_address")]
+    pub multicall_address: Address,
+
+    /// The block to start reading past `TreeChanged` events from when the
+    /// database is empty but the identity manager already holds identities.
+    /// The identities are then imported from the chain on startup
+    pub backfill_from_block: Option<u64>,
+
+    /// The keccak256 hash of the code deployed at the identity manager
+    /// address. If set, the sequencer refuses to start against any other
+    /// contract build, e.g. one that hasn't been audited
+    pub expected_contract_codehash: Option<H256>,
+}
+
+impl NetworkConfig {
+    #[must_use]
+    pub fn owner_check_backoff(&self) -> Backoff {
+        Backoff::new(
+            self.owner_check_retry_delay,
+            self.owner_check_retry_max_delay,
+            self.owner_check_retry_multiplier,
+        )
+    }
+
+    #[must_use]
+    pub fn view_call_backoff(&self) -> Backoff {
+        Backoff::new(
+            self.view_call_retry_delay,
+            self.view_call_retry_max_delay,
+            self.view_call_retry_multiplier,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofFormat {
+    /// The points of the proof as a flat object, as returned by semaphore-mtb
+    #[default]
+    Flat,
+    /// The `pi_a`, `pi_b` and `pi_c` arrays produced by snarkjs
+    #[serde(rename = "snarkjs")]
+    SnarkJs,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingStrategy {
+    /// In the order the identities were queued in
+    #[default]
+    Fifo,
+    /// Sorted by commitment, so that the same identities always make up the
+    /// same batch
+    ByCommitment,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProverStartupCheck {
+    /// Log the failing provers and start anyway
+    #[default]
+    Warn,
+    /// Refuse to start
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnRootMismatch {
+    /// Fail the batch
+    #[default]
+    Error,
+    /// Keep the proof for the post root reported by the prover. The batching
+    /// tree can't reach a root it didn't compute, so the batch is still
+    /// rejected before it is submitted
+    TrustProver,
+    /// Submit the proof with the post root computed by the sequencer, once
+    /// the identity manager accepts it for that root
+    TrustLocal,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionMode {
+    /// Submit transactions through the configured relayer
+    #[default]
+    Public,
+    /// Submit transactions through the private relay at the given url
+    Private(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GasOracleConfig {
+    /// Leave the fees to the provider or relayer
+    #[default]
+    ProviderDefault,
+    /// Fetch the fees from the BlockNative gas price API at the given url
+    BlockNative(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonceStrategy {
+    /// Leave nonce assignment to the provider
+    ProviderManaged,
+    /// Track the next nonce in memory and assign it to each transaction, so
+    /// that transactions held back by the relay don't get the same nonce
+    #[default]
+    LocallyTracked,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,6 +546,11 @@ pub struct ProvidersConfig {
     /// Provider url for the primary chain
     pub primary_network_provider: SecretUrl,
 
+    /// Provider url transactions to the primary chain are sent through, e.g.
+    /// a low-latency node next to an archive node used for reads. Defaults to
+    /// `primary_network_provider`.
+    pub write_network_provider: Option<SecretUrl>,
+
     /// Provider urls for the secondary chains
     #[serde(default)]
     pub relayed_network_providers: JsonStrWrapper<Vec<SecretUrl>>,
@@ -131,14 +562,47 @@ pub struct ProvidersConfig {
 pub enum RelayerConfig {
     OzDefender(OzDefenderConfig),
     TxSitter(TxSitterConfig),
+    /// Sign transactions locally and send them through the write provider
+    Signer(SignerKind),
 }
 
 impl RelayerConfig {
     // TODO: Extract into a common field
-    pub fn address(&self) -> Address {
+    /// Returns the address transactions are sent from, or `None` for a
+    /// [`RelayerConfig::Signer`], whose address is only known once its key is
+    /// loaded.
+    pub fn address(&self) -> Option<Address> {
+        match self {
+            RelayerConfig::OzDefender(config) => Some(config.oz_address),
+            RelayerConfig::TxSitter(config) => Some(config.tx_sitter_address),
+            RelayerConfig::Signer(_) => None,
+        }
+    }
+}
+
+/// The key the `signer` relayer signs transactions with.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "signer")]
+#[serde(rename_all = "snake_case")]
+pub enum SignerKind {
+    /// A hex encoded secp256k1 private key
+    LocalKey { private_key: String },
+    /// A secp256k1 key held in AWS KMS. Requires the `aws-kms` feature.
+    AwsKms { key_id: String, region: String },
+}
+
+impl fmt::Debug for SignerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RelayerConfig::OzDefender(config) => config.oz_address,
-            RelayerConfig::TxSitter(config) => config.tx_sitter_address,
+            SignerKind::LocalKey { .. } => f
+                .debug_struct("LocalKey")
+                .field("private_key", &"<REDACTED>")
+                .finish(),
+            SignerKind::AwsKms { key_id, region } => f
+                .debug_struct("AwsKms")
+                .field("key_id", key_id)
+                .field("region", region)
+                .finish(),
         }
     }
 }
@@ -251,10 +715,86 @@ pub mod default {
         Duration::from_secs(30)
     }
 
+    pub fn finalization_startup_jitter() -> Duration {
+        Duration::from_secs(0)
+    }
+
     pub fn monitored_txs_capacity() -> usize {
         100
     }
 
+    pub fn min_confirmations() -> u64 {
+        0
+    }
+
+    pub fn prover_warmup() -> bool {
+        false
+    }
+
+    pub fn prover_failure_cooldown() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn max_bridge_clock_skew() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    pub fn inter_batch_submission_delay() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    pub fn mining_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn multicall_address() -> ethers::types::Address {
+        ethers::contract::MULTICALL_ADDRESS
+    }
+
+    pub fn owner_check_retries() -> u32 {
+        0
+    }
+
+    pub fn owner_check_retry_delay() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    pub fn owner_check_retry_max_delay() -> Duration {
+        Duration::from_secs(5 * 60)
+    }
+
+    pub fn owner_check_retry_multiplier() -> f64 {
+        2.0
+    }
+
+    pub fn view_call_retries() -> u32 {
+        3
+    }
+
+    pub fn view_call_retry_delay() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    pub fn view_call_retry_max_delay() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    pub fn view_call_retry_multiplier() -> f64 {
+        2.0
+    }
+
+    pub fn finalization_webhook_retries() -> u32 {
+        3
+    }
+
+    pub fn log_proof_truncate_elements() -> usize {
+        4
+    }
+
+    pub fn prover_max_response_bytes() -> usize {
+        1024 * 1024
+    }
+
     pub fn serve_timeout() -> Duration {
         Duration::from_secs(30)
     }
@@ -331,13 +871,32 @@ mod tests {
         [app]
         provers_urls = "[]"
         batch_insertion_timeout = "3m"
+        insertion_ordering = "fifo"
         batch_deletion_timeout = "1h"
         min_batch_deletion_size = 100
         max_epoch_duration = "0s"
         scanning_window_size = 100
         scanning_chain_head_offset = 0
         time_between_scans = "30s"
+        finalization_startup_jitter = "0s"
         monitored_txs_capacity = 100
+        min_confirmations = 0
+        input_hash_function = "keccak256"
+        input_hash_endianness = "big"
+        prover_proof_format = "flat"
+        prover_warmup = false
+        prover_startup_check = "warn"
+        prover_failure_cooldown = "1m"
+        finalization_webhook_retries = 3
+        prover_circuit_validation = false
+        verify_echoed_inputs = false
+        pause_drifted_provers = false
+        log_proof_truncate_elements = 4
+        prover_max_response_bytes = 1048576
+        max_bridge_clock_skew = "0s"
+        skip_clean_slate = false
+        on_root_mismatch = "error"
+        inter_batch_submission_delay = "0s"
 
         [tree]
         tree_depth = 30
@@ -350,6 +909,18 @@ mod tests {
         [network]
         identity_manager_address = "0x0000000000000000000000000000000000000000"
         relayed_identity_manager_addresses = "{}"
+        submission_mode = "public"
+        mining_timeout = "1m"
+        gas_price_oracle = "provider_default"
+        owner_check_retries = 0
+        owner_check_retry_delay = "10s"
+        owner_check_retry_max_delay = "5m"
+        owner_check_retry_multiplier = 2.0
+        view_call_retries = 3
+        view_call_retry_delay = "1s"
+        view_call_retry_max_delay = "30s"
+        view_call_retry_multiplier = 2.0
+        multicall_address = "0xca11bde05977b3631167028862be2a173976ca11"
 
         [providers]
         primary_network_provider = "http://localhost:8545/"
@@ -371,6 +942,105 @@ mod tests {
         serve_timeout = "30s"
     "#};
 
+    fn with_relayer(relayer: &str) -> Config {
+        let toml = MINIMAL_TOML.replace(
+            indoc::indoc! {r#"
+                kind = "tx_sitter"
+                tx_sitter_url = "http://localhost:3000"
+                tx_sitter_address = "0x0000000000000000000000000000000000000000"
+            "#},
+            relayer,
+        );
+
+        toml::from_str(&toml).unwrap()
+    }
+
+    #[test]
+    fn deserialize_local_key_signer() {
+        let config = with_relayer(indoc::indoc! {r#"
+            kind = "signer"
+            signer = "local_key"
+            private_key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        "#});
+
+        assert!(matches!(
+            config.relayer,
+            RelayerConfig::Signer(SignerKind::LocalKey { .. })
+        ));
+        assert!(!format!("{config:?}").contains("ac0974"));
+    }
+
+    #[test]
+    fn deserialize_aws_kms_signer() {
+        let config = with_relayer(indoc::indoc! {r#"
+            kind = "signer"
+            signer = "aws_kms"
+            key_id = "alias/sequencer"
+            region = "eu-central-1"
+        "#});
+
+        assert!(matches!(
+            config.relayer,
+            RelayerConfig::Signer(SignerKind::AwsKms { key_id, region })
+                if key_id == "alias/sequencer" && region == "eu-central-1"
+        ));
+    }
+
+    #[test]
+    fn zero_limits_are_rejected() {
+        for setting in [
+            "prover_max_requests_per_second",
+            "finalization_rpc_rate_limit",
+            "prover_failure_threshold",
+        ] {
+            let toml = MINIMAL_TOML.replace(
+                "provers_urls = \"[]\"",
+                &format!("provers_urls = \"[]\"\n{setting} = 0"),
+            );
+
+            assert!(toml::from_str::<Config>(&toml).is_err(), "{setting}");
+        }
+    }
+
+    #[test]
+    fn nonce_strategy_is_rejected_for_public_submission() {
+        let toml = MINIMAL_TOML.replace(
+            "[providers]",
+            "nonce_strategy = \"locally_tracked\"\n\n[providers]",
+        );
+        let config: Config = toml::from_str(&toml).unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_retry_multipliers_are_rejected() {
+        for setting in ["owner_check_retry_multiplier", "view_call_retry_multiplier"] {
+            for multiplier in ["0.5", "-2.0", "nan"] {
+                let toml = MINIMAL_TOML.replace(
+                    "[providers]",
+                    &format!("{setting} = {multiplier}\n\n[providers]"),
+                );
+                let config: Config = toml::from_str(&toml).unwrap();
+
+                assert!(config.validate().is_err(), "{setting} = {multiplier}");
+            }
+        }
+    }
+
+    #[test]
+    fn nonce_strategy_is_accepted_for_private_submission() {
+        let mut config = with_relayer(indoc::indoc! {r#"
+            kind = "signer"
+            signer = "local_key"
+            private_key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        "#});
+        config.network.submission_mode = SubmissionMode::Private("http://localhost:8546".into());
+        config.network.nonce_strategy = Some(NonceStrategy::ProviderManaged);
+
+        config.validate().unwrap();
+    }
+
     #[test]
     fn full_toml_round_trip() {
         let config: Config = toml::from_str(FULL_TOML).unwrap();
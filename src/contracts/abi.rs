@@ -42,9 +42,24 @@ abigen!(
         function identityOperator() public view virtual returns (address)
         function queryRoot(uint256 root) public view virtual returns (RootInfo memory)
         function getRootHistoryExpiry() external view returns (uint256)
+        function getRegisterIdentitiesVerifierLookupTableAddress() public view virtual returns (address)
     ]"#,
 );
 
+abigen!(
+    VerifierLookupTable,
+    r#"[
+        function getVerifierFor(uint256 batchSize) public view returns (address)
+    ]"#
+);
+
+abigen!(
+    TreeVerifier,
+    r#"[
+        function verifyProof(uint256[8] calldata proof, uint256[1] calldata input) external view
+    ]"#
+);
+
 abigen!(
     BridgedWorldId,
     r#"[
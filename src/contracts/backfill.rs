@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context};
+use ethers::abi::{AbiDecode, RawLog};
+use ethers::contract::EthEvent;
+use ethers::providers::Middleware;
+use ethers::types::{Address, Bytes, Filter, Log, U256};
+use semaphore::lazy_merkle_tree::Derived;
+use semaphore::poseidon_tree::LazyPoseidonTree;
+use semaphore::Field;
+use tracing::{info, warn};
+
+use super::abi::{DeleteIdentitiesCall, RegisterIdentitiesCall, TreeChangeKind, TreeChangedFilter};
+use crate::database::Database;
+use crate::identity_tree::{Hash, PoseidonTree, TreeUpdate};
+use crate::utils::field_util::u256_to_field;
+use crate::utils::index_packing::unpack_indices;
+
+/// The updates a single `TreeChanged` event applied to the tree, reconstructed
+/// from the calldata of the transaction that emitted it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    pub pre_root:  U256,
+    pub post_root: U256,
+    pub updates:   Vec<TreeUpdate>,
+}
+
+/// A commitment the chain holds at a leaf index the database doesn't know it
+/// at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingCommitment {
+    pub leaf_index: usize,
+    pub commitment: Hash,
+}
+
+/// Fetches the logs matching `filter` from `from_block` to `to_block`
+/// inclusive, in windows of at most `window_size` blocks.
+///
+/// Providers cap how many blocks or logs a single `eth_getLogs` call may span,
+/// so a window the provider refuses is halved and retried until it spans a
+/// single block.
+pub async fn get_logs_in_chunks<M>(
+    provider: &M,
+    filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+    window_size: u64,
+) -> anyhow::Result<Vec<Log>>
+where
+    M: Middleware,
+    <M as Middleware>::Error: 'static,
+{
+    let mut logs = vec![];
+    let mut window_size = window_size.max(1);
+    let mut from = from_block;
+
+    while from <= to_block {
+        let to = to_block.min(from.saturating_add(window_size - 1));
+        let window_filter = filter.clone().from_block(from).to_block(to);
+
+        match provider.get_logs(&window_filter).await {
+            Ok(window_logs) => logs.extend(window_logs),
+            Err(error) if window_size > 1 => {
+                window_size /= 2;
+                warn!(
+                    from,
+                    to,
+                    window_size,
+                    ?error,
+                    "Failed to fetch logs, retrying with a smaller window"
+                );
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        let Some(next) = to.checked_add(1) else {
+            break;
+        };
+        from = next;
+    }
+
+    Ok(logs)
+}
+
+/// Reads every `TreeChanged` event emitted by the identity manager at
+/// `address` between `from_block` and `to_block`, and reconstructs the tree
+/// updates from the transactions that emitted them.
+pub async fn fetch_tree_changes<M>(
+    provider: &M,
+    address: Address,
+    from_block: u64,
+    to_block: u64,
+    window_size: u64,
+    tree_depth: usize,
+) -> anyhow::Result<Vec<TreeChange>>
+where
+    M: Middleware,
+    <M as Middleware>::Error: 'static,
+{
+    let filter = Filter::new()
+        .address(address)
+        .topic0(TreeChangedFilter::signature());
+
+    let logs = get_logs_in_chunks(provider, &filter, from_block, to_block, window_size).await?;
+
+    let mut changes = Vec::with_capacity(logs.len());
+    for log in logs {
+        let raw_log = RawLog::from((log.topics.clone(), log.data.to_vec()));
+        let event = TreeChangedFilter::decode_log(&raw_log)?;
+
+        let tx_hash = log
+            .transaction_hash
+            .context("Log is missing its transaction hash")?;
+        let tx = provider
+            .get_transaction(tx_hash)
+            .await?
+            .context("Missing tx")?;
+
+        let updates = match TreeChangeKind::from(event.kind) {
+            TreeChangeKind::Insertion => insertion_updates(&tx.input)?,
+            TreeChangeKind::Deletion => deletion_updates(&tx.input, tree_depth)?,
+            TreeChangeKind::Update => {
+                bail!("Identity updates can't be backfilled, found one in {tx_hash:?}")
+            }
+        };
+
+        changes.push(TreeChange {
+            pre_root: event.pre_root,
+            post_root: event.post_root,
+            updates,
+        });
+    }
+
+    Ok(changes)
+}
+
+/// Decodes the identities inserted by a `registerIdentities` call, leaving out
+/// the zero padding.
+fn insertion_updates(input: &Bytes) -> anyhow::Result<Vec<TreeUpdate>> {
+    let call = RegisterIdentitiesCall::decode(input)?;
+    let start_index = call.start_index as usize;
+
+    Ok(call
+        .identity_commitments
+        .into_iter()
+        .enumerate()
+        .filter(|(_, commitment)| !commitment.is_zero())
+        .map(|(offset, commitment)| {
+            TreeUpdate::new(start_index + offset, u256_to_field(commitment))
+        })
+        .collect())
+}
+
+/// Decodes the leaves cleared by a `deleteIdentities` call, leaving out the
+/// padding indices.
+fn deletion_updates(input: &Bytes, tree_depth: usize) -> anyhow::Result<Vec<TreeUpdate>> {
+    let call = DeleteIdentitiesCall::decode(input)?;
+    let padding_index = 2u32.pow(tree_depth as u32);
+
+    Ok(unpack_indices(call.packed_deletion_indices.as_ref())
+        .into_iter()
+        .filter(|index| *index != padding_index)
+        .map(|index| TreeUpdate::new(index as usize, Hash::ZERO))
+        .collect())
+}
+
+/// Applies `change` to `tree`, checking that it starts from the pre root and
+/// ends at the post root of the event. Returns the root after every update.
+fn apply_tree_change(
+    tree: &mut PoseidonTree<Derived>,
+    change: &TreeChange,
+) -> anyhow::Result<Vec<Hash>> {
+    if tree.root() != u256_to_field(change.pre_root) {
+        return Err(anyhow!(
+            "Tree root {:?} does not match the pre root {:?} of the event",
+            tree.root(),
+            change.pre_root
+        ));
+    }
+
+    let mut roots = Vec::with_capacity(change.updates.len());
+    for update in &change.updates {
+        *tree = tree.update(update.leaf_index, &update.element);
+        roots.push(tree.root());
+    }
+
+    if tree.root() != u256_to_field(change.post_root) {
+        return Err(anyhow!(
+            "Tree root {:?} does not match the post root {:?} of the event",
+            tree.root(),
+            change.post_root
+        ));
+    }
+
+    Ok(roots)
+}
+
+/// Replays `changes` on top of an empty tree and stores every update in the
+/// database as mined. Returns the root of the rebuilt tree.
+pub async fn backfill_database(
+    database: &Database,
+    tree_depth: usize,
+    initial_leaf_value: Field,
+    changes: &[TreeChange],
+) -> anyhow::Result<Hash> {
+    let mut tree = LazyPoseidonTree::new(tree_depth, initial_leaf_value).derived();
+
+    for change in changes {
+        let roots = apply_tree_change(&mut tree, change)?;
+
+        for (update, root) in change.updates.iter().zip(&roots) {
+            database
+                .insert_pending_identity(update.leaf_index, &update.element, root)
+                .await?;
+        }
+
+        if let Some(root) = roots.last() {
+            database.mark_root_as_mined(root).await?;
+        }
+
+        info!(
+            pre_root = ?change.pre_root,
+            post_root = ?change.post_root,
+            updates = change.updates.len(),
+            "Backfilled batch"
+        );
+    }
+
+    Ok(tree.root())
+}
+
+/// Returns the commitments left in the tree by `changes` that the database
+/// doesn't hold at the same leaf index, sorted by leaf index. Any such
+/// commitment means the database is behind the chain.
+pub async fn find_missing_commitments(
+    database: &Database,
+    changes: &[TreeChange],
+) -> anyhow::Result<Vec<MissingCommitment>> {
+    // Later updates of a leaf override earlier ones
+    let mut leaves = BTreeMap::new();
+    for update in changes.iter().flat_map(|change| &change.updates) {
+        leaves.insert(update.leaf_index, update.element);
+    }
+
+    let mut missing = vec![];
+    for (leaf_index, commitment) in leaves {
+        if commitment == Hash::ZERO {
+            continue;
+        }
+
+        let stored = database.get_identity_leaf_index(&commitment).await?;
+        if stored.map_or(true, |item| item.leaf_index != leaf_index) {
+            missing.push(MissingCommitment {
+                leaf_index,
+                commitment,
+            });
+        }
+    }
+
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+    use ethers::types::{Transaction, H256};
+
+    use super::*;
+    use crate::test_utils::setup_db;
+    use crate::utils::field_util::field_to_u256;
+    use crate::utils::index_packing::pack_indices;
+
+    const TREE_DEPTH: usize = 4;
+    const CONTRACT: Address = Address::repeat_byte(0x11);
+
+    struct Batch {
+        kind:  TreeChangeKind,
+        input: Bytes,
+        block: u64,
+    }
+
+    fn insertion(start_index: u32, commitments: &[u64], block: u64) -> Batch {
+        let call = RegisterIdentitiesCall {
+            insertion_proof: [U256::zero(); 8],
+            pre_root: U256::zero(),
+            start_index,
+            identity_commitments: commitments.iter().copied().map(U256::from).collect(),
+            post_root: U256::zero(),
+        };
+
+        Batch {
+            kind: TreeChangeKind::Insertion,
+            input: call.encode().into(),
+            block,
+        }
+    }
+
+    fn deletion(indices: &[u32], block: u64) -> Batch {
+        let call = DeleteIdentitiesCall {
+            deletion_proof:          [U256::zero(); 8],
+            packed_deletion_indices: pack_indices(indices).into(),
+            pre_root:                U256::zero(),
+            post_root:               U256::zero(),
+        };
+
+        Batch {
+            kind: TreeChangeKind::Deletion,
+            input: call.encode().into(),
+            block,
+        }
+    }
+
+    fn topic(value: U256) -> H256 {
+        let mut bytes = [0; 32];
+        value.to_big_endian(&mut bytes);
+        H256(bytes)
+    }
+
+    /// Builds the `TreeChanged` logs and transactions the contract would have
+    /// produced for `batches`. Returns them along with the final root.
+    fn synthetic_chain(batches: &[Batch]) -> (Vec<Log>, Vec<Transaction>, Hash) {
+        let mut tree = LazyPoseidonTree::new(TREE_DEPTH, Field::ZERO).derived();
+        let mut logs = vec![];
+        let mut txs = vec![];
+
+        for (i, batch) in batches.iter().enumerate() {
+            let updates = match batch.kind {
+                TreeChangeKind::Insertion => insertion_updates(&batch.input).unwrap(),
+                _ => deletion_updates(&batch.input, TREE_DEPTH).unwrap(),
+            };
+
+            let pre_root = field_to_u256(tree.root());
+            for update in &updates {
+                tree = tree.update(update.leaf_index, &update.element);
+            }
+            let post_root = field_to_u256(tree.root());
+
+            let tx_hash = H256::from_low_u64_be(i as u64 + 1);
+            let kind = match batch.kind {
+                TreeChangeKind::Insertion => 0u8,
+                _ => 1u8,
+            };
+
+            logs.push(Log {
+                address: CONTRACT,
+                topics: vec![
+                    TreeChangedFilter::signature(),
+                    topic(pre_root),
+                    H256::from_low_u64_be(kind.into()),
+                    topic(post_root),
+                ],
+                block_number: Some(batch.block.into()),
+                transaction_hash: Some(tx_hash),
+                ..Default::default()
+            });
+
+            txs.push(Transaction {
+                hash: tx_hash,
+                input: batch.input.clone(),
+                ..Default::default()
+            });
+        }
+
+        (logs, txs, tree.root())
+    }
+
+    /// Queues the responses for the given `eth_getLogs` windows followed by one
+    /// transaction lookup per log, in the order they are requested.
+    fn push_responses(mock: &MockProvider, windows: Vec<MockResponse>, txs: &[Transaction]) {
+        // Responses are popped from the back
+        for tx in txs.iter().rev() {
+            mock.push(tx.clone()).unwrap();
+        }
+        for window in windows.into_iter().rev() {
+            mock.push_response(window);
+        }
+    }
+
+    fn logs_response(logs: &[Log]) -> MockResponse {
+        MockResponse::Value(serde_json::to_value(logs).unwrap())
+    }
+
+    fn replay(changes: &[TreeChange]) -> anyhow::Result<Hash> {
+        let mut tree = LazyPoseidonTree::new(TREE_DEPTH, Field::ZERO).derived();
+        for change in changes {
+            apply_tree_change(&mut tree, change)?;
+        }
+
+        Ok(tree.root())
+    }
+
+    #[tokio::test]
+    async fn tree_is_reconstructed_from_events() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let (logs, txs, root) = synthetic_chain(&[
+            insertion(0, &[1, 2, 3, 0], 3),
+            insertion(3, &[4, 5, 0, 0], 12),
+            deletion(&[1, 16], 14),
+        ]);
+
+        // Blocks 0..=14 in windows of 10 blocks
+        push_responses(
+            &mock,
+            vec![logs_response(&logs[..1]), logs_response(&logs[1..])],
+            &txs,
+        );
+
+        let changes = fetch_tree_changes(&provider, CONTRACT, 0, 14, 10, TREE_DEPTH).await?;
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[1].updates, vec![
+            TreeUpdate::new(3, Hash::from(4)),
+            TreeUpdate::new(4, Hash::from(5)),
+        ]);
+        assert_eq!(changes[2].updates, vec![TreeUpdate::new(1, Hash::ZERO)]);
+        assert_eq!(replay(&changes)?, root);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refused_windows_are_halved() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let (logs, txs, root) = synthetic_chain(&[insertion(0, &[1, 2], 7)]);
+
+        let too_many_logs = MockResponse::Error(JsonRpcError {
+            code:    -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data:    None,
+        });
+
+        // The first window of 10 blocks is refused, blocks 0..=4 and 5..=9 are
+        // then fetched separately
+        push_responses(
+            &mock,
+            vec![too_many_logs, logs_response(&[]), logs_response(&logs)],
+            &txs,
+        );
+
+        let changes = fetch_tree_changes(&provider, CONTRACT, 0, 9, 10, TREE_DEPTH).await?;
+
+        assert_eq!(replay(&changes)?, root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_rejects_mismatching_roots() {
+        let empty_tree = LazyPoseidonTree::new(TREE_DEPTH, Field::ZERO).derived();
+        let mut change = TreeChange {
+            pre_root:  field_to_u256(empty_tree.root()),
+            post_root: field_to_u256(empty_tree.update(0, &Hash::from(1)).root()),
+            updates:   vec![TreeUpdate::new(0, Hash::from(1))],
+        };
+
+        assert!(replay(&[change.clone()]).is_ok());
+
+        change.post_root = U256::from(42);
+        assert!(replay(&[change.clone()]).is_err());
+
+        change.pre_root = U256::from(42);
+        assert!(replay(&[change]).is_err());
+    }
+
+    #[tokio::test]
+    async fn commitments_missing_from_the_database_are_reported() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let (provider, mock) = Provider::mocked();
+        let (logs, txs, _) = synthetic_chain(&[
+            insertion(0, &[1, 2, 3, 0], 3),
+            insertion(3, &[4, 5, 0, 0], 12),
+            deletion(&[1, 16], 14),
+        ]);
+        push_responses(&mock, vec![logs_response(&logs)], &txs);
+
+        let changes = fetch_tree_changes(&provider, CONTRACT, 0, 14, 20, TREE_DEPTH).await?;
+
+        // The database only knows the first batch, with commitment 3 stored at
+        // the wrong leaf
+        let root = Hash::from(100);
+        database
+            .insert_pending_identity(0, &Hash::from(1), &root)
+            .await?;
+        database
+            .insert_pending_identity(1, &Hash::from(2), &root)
+            .await?;
+        database
+            .insert_pending_identity(5, &Hash::from(3), &root)
+            .await?;
+
+        let missing = find_missing_commitments(&database, &changes).await?;
+
+        assert_eq!(missing, vec![
+            MissingCommitment {
+                leaf_index: 2,
+                commitment: Hash::from(3),
+            },
+            MissingCommitment {
+                leaf_index: 3,
+                commitment: Hash::from(4),
+            },
+            MissingCommitment {
+                leaf_index: 4,
+                commitment: Hash::from(5),
+            },
+        ]);
+
+        Ok(())
+    }
+}
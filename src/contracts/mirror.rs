@@ -0,0 +1,186 @@
+//! A second identity manager that batches are mirrored to for redundancy.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ethers::providers::Middleware;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::U256;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use tracing::{error, info, warn};
+
+use super::abi::WorldId;
+use crate::ethereum::{Ethereum, ReadProvider};
+
+static MIRROR_SUBMISSION_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "mirror_submission_failures",
+        "Batches that could not be sent to or were reverted by the mirrored identity manager."
+    )
+    .unwrap()
+});
+
+static MIRROR_IN_SYNC: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "mirror_in_sync",
+        "Whether the mirrored identity manager has received every batch."
+    )
+    .unwrap()
+});
+
+/// The mirrored identity manager and whether it is in sync with the primary
+/// one.
+///
+/// A mirror that misses a batch rejects every later one, as their pre-roots
+/// no longer match. It is then considered out of sync: batches are no longer
+/// sent to it and it is no longer required for a root to count as mined, so
+/// that it can fall behind without stalling finalization. It is back in sync
+/// once its latest root is the pre-root of the next batch, e.g. after an
+/// operator replayed the missing batches.
+#[derive(Debug)]
+pub struct Mirror<M = ReadProvider> {
+    abi:     WorldId<M>,
+    in_sync: Arc<AtomicBool>,
+}
+
+impl<M> Mirror<M>
+where
+    M: Middleware + 'static,
+{
+    pub fn new(abi: WorldId<M>) -> Self {
+        MIRROR_IN_SYNC.set(1);
+
+        Self {
+            abi,
+            in_sync: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    pub const fn abi(&self) -> &WorldId<M> {
+        &self.abi
+    }
+
+    /// Whether the mirror has received every batch, and so is required for a
+    /// root to count as mined.
+    pub fn is_in_sync(&self) -> bool {
+        self.in_sync.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the batch starting at `pre_root` should be sent to the
+    /// mirror. A mirror that is out of sync only receives it if it has caught
+    /// up with `pre_root` since.
+    pub async fn accepts_batch(&self, pre_root: U256) -> bool {
+        if self.is_in_sync() {
+            return true;
+        }
+
+        match self.abi.latest_root().call().await {
+            Ok(latest_root) if latest_root == pre_root => {
+                info!(?pre_root, "Mirrored identity manager is back in sync");
+                self.in_sync.store(true, Ordering::SeqCst);
+                MIRROR_IN_SYNC.set(1);
+                true
+            }
+            Ok(latest_root) => {
+                warn!(
+                    ?latest_root,
+                    ?pre_root,
+                    "Mirrored identity manager is out of sync, skipping the batch"
+                );
+                false
+            }
+            Err(error) => {
+                warn!(%error, "Failed to read the latest root of the mirrored identity manager");
+                false
+            }
+        }
+    }
+
+    /// Sends the transaction to the mirror and watches it in the background.
+    ///
+    /// Neither a failure to send it nor a revert fails the batch, which is
+    /// already on its way to the primary identity manager. Both mark the
+    /// mirror as out of sync instead.
+    pub async fn submit(&self, ethereum: &Ethereum, transaction: TypedTransaction) {
+        let transaction_id = match ethereum.send_transaction(transaction, true).await {
+            Ok(transaction_id) => transaction_id,
+            Err(error) => {
+                mark_out_of_sync(&self.in_sync, &error);
+                return;
+            }
+        };
+
+        info!(
+            ?transaction_id,
+            "Batch sent to the mirrored identity manager"
+        );
+
+        let ethereum = ethereum.clone();
+        let in_sync = self.in_sync.clone();
+        tokio::spawn(async move {
+            match ethereum.mine_transaction(transaction_id).await {
+                Ok(true) => {}
+                Ok(false) => mark_out_of_sync(&in_sync, &"transaction reverted"),
+                Err(error) => mark_out_of_sync(&in_sync, &error),
+            }
+        });
+    }
+}
+
+fn mark_out_of_sync(in_sync: &AtomicBool, reason: &dyn std::fmt::Display) {
+    MIRROR_SUBMISSION_FAILURES.inc();
+    MIRROR_IN_SYNC.set(0);
+
+    if in_sync.swap(false, Ordering::SeqCst) {
+        error!(
+            %reason,
+            "Mirrored identity manager missed a batch and is out of sync"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::{encode, Token};
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{Address, Bytes};
+
+    use super::*;
+
+    fn mocked_mirror() -> (Mirror<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::repeat_byte(0x02), Arc::new(provider));
+
+        (Mirror::new(abi), mock)
+    }
+
+    fn root_response(root: U256) -> Bytes {
+        encode(&[Token::Uint(root)]).into()
+    }
+
+    #[tokio::test]
+    async fn mirror_in_sync_accepts_batches_without_queries() {
+        let (mirror, _mock) = mocked_mirror();
+
+        assert!(mirror.accepts_batch(U256::from(1)).await);
+    }
+
+    #[tokio::test]
+    async fn mirror_out_of_sync_skips_batches_until_it_catches_up() -> anyhow::Result<()> {
+        let (mirror, mock) = mocked_mirror();
+
+        mark_out_of_sync(&mirror.in_sync, &"test");
+        assert!(!mirror.is_in_sync());
+
+        mock.push(root_response(U256::from(1)))?;
+        assert!(!mirror.accepts_batch(U256::from(2)).await);
+        assert!(!mirror.is_in_sync());
+
+        mock.push(root_response(U256::from(2)))?;
+        assert!(mirror.accepts_batch(U256::from(2)).await);
+        assert!(mirror.is_in_sync());
+
+        Ok(())
+    }
+}
@@ -1,24 +1,85 @@
 //! Functionality for interacting with smart contracts deployed on chain.
 pub mod abi;
+pub mod backfill;
+pub mod mirror;
 pub mod scanner;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
+use async_trait::async_trait;
+use ethers::contract::Multicall;
 use ethers::providers::Middleware;
-use ethers::types::{H256, U256};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, TransactionReceipt, H256, U256};
+use ethers::utils::{format_ether, keccak256};
+use futures::{stream, StreamExt};
+use once_cell::sync::Lazy;
+use prometheus::{register_gauge, register_int_gauge, Gauge, IntGauge};
+use semaphore::poseidon_tree::LazyPoseidonTree;
 use semaphore::Field;
+use thiserror::Error;
 use tokio::sync::{RwLock, RwLockReadGuard};
 use tracing::{error, info, instrument, warn};
 
-use self::abi::{BridgedWorldId, DeleteIdentitiesCall, WorldId};
+use self::abi::{BridgedWorldId, DeleteIdentitiesCall, TreeVerifier, VerifierLookupTable, WorldId};
+use self::backfill::{
+    backfill_database, fetch_tree_changes, find_missing_commitments, MissingCommitment,
+};
+use self::mirror::Mirror;
 use crate::config::Config;
-use crate::ethereum::write::TransactionId;
-use crate::ethereum::{Ethereum, ReadProvider};
+use crate::database::Database;
+use crate::ethereum::write::{BatchSubmitError, SubmitError, TransactionId};
+use crate::ethereum::{Ethereum, ReadProvider, TxError};
+use crate::identity_tree::{Intermediate, TreeOccupancy, TreeVersion, TreeVersionReadOps, Version};
 use crate::prover::identity::Identity;
-use crate::prover::{Proof, Prover, ProverConfig, ProverMap, ProverType};
+use crate::prover::map::ProverReport;
+use crate::prover::proof::{ProofKind, VerifyingKey};
+use crate::prover::transition::{
+    compute_insertion_roots, rebuild_insertion_batch, verify_insertion_transition,
+};
+use crate::prover::{
+    build_insertion_proof_input, compute_insertion_proof_input_hash, Proof, ProofInput, Prover,
+    ProverConfig, ProverHealth, ProverMap, ProverSettings, ProverType,
+};
 use crate::server::error::Error as ServerError;
+use crate::utils::field_util::{field_to_u256, merkle_proof_siblings, u256_to_field};
 use crate::utils::index_packing::unpack_indices;
+use crate::utils::retry::Backoff;
+
+/// How many pending transactions are awaited at once on startup.
+const CLEAN_SLATE_CONCURRENCY: usize = 8;
+
+/// The most roots checked in a single multicall, or at once without one.
+const MAX_ROOTS_PER_QUERY: usize = 100;
+
+/// The order of the BN254 scalar field the verifiers work in.
+static SNARK_SCALAR_FIELD: Lazy<U256> = Lazy::new(|| {
+    U256::from_dec_str(
+        "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+    )
+    .expect("This should just parse.")
+});
+
+static SIGNER_BALANCE: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "signer_balance_ether",
+        "The balance of the account sending identity manager transactions, in ether."
+    )
+    .unwrap()
+});
+
+static DELETION_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "deletion_queue_depth",
+        "The number of identities queued for deletion."
+    )
+    .unwrap()
+});
 
 /// A structure representing the interface to the batch-based identity manager
 /// contract.
@@ -28,9 +89,15 @@ pub struct IdentityManager {
     insertion_prover_map: RwLock<ProverMap>,
     deletion_prover_map:  RwLock<ProverMap>,
     abi:                  WorldId<ReadProvider>,
+    mirror:               Option<Mirror>,
     secondary_abis:       Vec<BridgedWorldId<ReadProvider>>,
+    multicall_address:    Option<Address>,
     initial_leaf_value:   Field,
     tree_depth:           usize,
+    prover_settings:      ProverSettings,
+    view_call_retries:    u32,
+    view_call_backoff:    Backoff,
+    batching_tree:        OnceLock<TreeVersion<Intermediate>>,
 }
 
 impl IdentityManager {
@@ -55,13 +122,12 @@ impl IdentityManager {
     {
         // Check that there is code deployed at the target address.
         let address = config.network.identity_manager_address;
-        let code = ethereum.provider().get_code(address, None).await?;
-        if code.as_ref().is_empty() {
-            error!(
-                ?address,
-                "No contract code is deployed at the provided address."
-            );
-        }
+        verify_contract_code(
+            ethereum.provider().as_ref(),
+            address,
+            config.network.expected_contract_codehash,
+        )
+        .await?;
 
         // Connect to the running batching contract.
         let abi = WorldId::new(
@@ -69,7 +135,13 @@ impl IdentityManager {
             ethereum.provider().clone(),
         );
 
-        let operator = abi.identity_operator().call().await?;
+        let operator = wait_for_identity_operator(
+            &abi,
+            ethereum.address(),
+            config.network.owner_check_retries,
+            &config.network.owner_check_backoff(),
+        )
+        .await?;
         if operator != ethereum.address() {
             error!(?operator, signer = ?ethereum.address(), "Signer is not the identity operator of the identity manager contract.");
             panic!("Cannot currently continue in read-only mode.")
@@ -81,6 +153,26 @@ impl IdentityManager {
             "Connected to the WorldID Identity Manager"
         );
 
+        let mirror = match config.network.mirrored_identity_manager_address {
+            Some(address) => {
+                let abi = WorldId::new(address, ethereum.provider().clone());
+
+                let operator = abi.identity_operator().call().await?;
+                if operator != ethereum.address() {
+                    return Err(anyhow!(
+                        "Signer {signer:?} is not the identity operator of the mirrored identity \
+                         manager {address:?}",
+                        signer = ethereum.address()
+                    ));
+                }
+
+                info!(?address, "Mirroring batches to a second identity manager");
+
+                Some(Mirror::new(abi))
+            }
+            None => None,
+        };
+
         let secondary_providers = ethereum.secondary_providers();
 
         let mut secondary_abis = Vec::new();
@@ -93,8 +185,17 @@ impl IdentityManager {
             secondary_abis.push(abi);
         }
 
+        let multicall_address = deployed_multicall(
+            ethereum.provider().as_ref(),
+            config.network.multicall_address,
+        )
+        .await?;
+
         let initial_leaf_value = config.tree.initial_leaf_value;
         let tree_depth = config.tree.tree_depth;
+        let prover_settings = ProverSettings::from(&config.app);
+        let view_call_retries = config.network.view_call_retries;
+        let view_call_backoff = config.network.view_call_backoff();
 
         let insertion_prover_map = RwLock::new(insertion_prover_map);
         let deletion_prover_map = RwLock::new(deletion_prover_map);
@@ -104,9 +205,15 @@ impl IdentityManager {
             insertion_prover_map,
             deletion_prover_map,
             abi,
+            mirror,
             secondary_abis,
+            multicall_address,
             initial_leaf_value,
             tree_depth,
+            prover_settings,
+            view_call_retries,
+            view_call_backoff,
+            batching_tree: OnceLock::new(),
         };
 
         Ok(identity_manager)
@@ -117,6 +224,37 @@ impl IdentityManager {
         self.tree_depth
     }
 
+    /// Returns how full a tree with `next_leaf` leaves filled is relative to
+    /// the tree depth the identity manager works with.
+    #[must_use]
+    pub fn tree_occupancy(&self, next_leaf: usize) -> TreeOccupancy {
+        TreeOccupancy::new(next_leaf, self.tree_depth)
+    }
+
+    /// Retries a failing read of the identity manager state, backing off
+    /// between attempts so that a recovering provider isn't flooded.
+    async fn retry_view_call<T, F, Fut>(&self, call: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        self.view_call_backoff
+            .retry(self.view_call_retries, call)
+            .await
+    }
+
+    /// Returns the health of every insertion and deletion prover.
+    pub async fn prover_health(&self) -> Vec<ProverHealth> {
+        let insertion_provers = self.insertion_prover_map.read().await;
+        let deletion_provers = self.deletion_prover_map.read().await;
+
+        insertion_provers
+            .provers()
+            .chain(deletion_provers.provers())
+            .map(Prover::health)
+            .collect()
+    }
+
     pub async fn max_insertion_batch_size(&self) -> usize {
         self.insertion_prover_map.read().await.max_batch_size()
     }
@@ -125,6 +263,47 @@ impl IdentityManager {
         self.deletion_prover_map.read().await.max_batch_size()
     }
 
+    /// Returns how many more identities have to be queued on top of
+    /// `queued_identities` before the smallest insertion prover can be
+    /// filled, zero if a batch can already be filled.
+    pub async fn identities_until_next_batch(
+        &self,
+        queued_identities: usize,
+    ) -> anyhow::Result<usize> {
+        self.insertion_prover_map
+            .read()
+            .await
+            .identities_until_next_batch(queued_identities)
+            .ok_or_else(|| anyhow!("No insertion provers are registered"))
+    }
+
+    /// Splits `num_identities` pending updates into the sizes of consecutive
+    /// batches, filling the largest prover first. Unless `include_partial` is
+    /// set, a trailing batch that doesn't fill a prover is left out.
+    pub async fn split_into_batches(
+        &self,
+        prover_type: ProverType,
+        num_identities: usize,
+        include_partial: bool,
+    ) -> Vec<usize> {
+        let map = match prover_type {
+            ProverType::Insertion => self.insertion_prover_map.read().await,
+            ProverType::Deletion => self.deletion_prover_map.read().await,
+        };
+
+        let mut batch_sizes = map.split_into_batches(num_identities);
+
+        if !include_partial
+            && batch_sizes
+                .last()
+                .map_or(false, |size| !map.batch_size_exists(*size))
+        {
+            batch_sizes.pop();
+        }
+
+        batch_sizes
+    }
+
     #[must_use]
     pub const fn initial_leaf_value(&self) -> Field {
         self.initial_leaf_value
@@ -133,17 +312,39 @@ impl IdentityManager {
     /// Validates that merkle proofs are of the correct length against tree
     /// depth
     pub fn validate_merkle_proofs(&self, identity_commitments: &[Identity]) -> anyhow::Result<()> {
-        for id in identity_commitments {
-            if id.merkle_proof.len() != self.tree_depth {
-                return Err(anyhow!(format!(
-                    "Length of merkle proof ({len}) did not match tree depth ({depth})",
-                    len = id.merkle_proof.len(),
-                    depth = self.tree_depth
-                )));
-            }
+        match find_proof_length_issues(identity_commitments, self.tree_depth).first() {
+            Some(issue) => Err(anyhow!(
+                "Length of merkle proof ({len}) did not match tree depth ({depth})",
+                len = issue.length,
+                depth = issue.expected
+            )),
+            None => Ok(()),
         }
+    }
 
-        Ok(())
+    /// Builds the [`Identity`] of every commitment from its current merkle
+    /// proof in `tree`, for callers that only have the commitments. Fails if
+    /// any commitment is not a leaf of the tree.
+    pub fn derive_merkle_proofs<V: Version>(
+        &self,
+        tree: &TreeVersion<V>,
+        commitments: &[U256],
+    ) -> anyhow::Result<Vec<Identity>>
+    where
+        TreeVersion<V>: TreeVersionReadOps,
+    {
+        let identities = derive_identities(tree, commitments)?;
+
+        self.validate_merkle_proofs(&identities)?;
+
+        Ok(identities)
+    }
+
+    /// Like [`Self::validate_merkle_proofs`], but reports every proof whose
+    /// length doesn't match the tree depth instead of stopping at the first.
+    #[must_use]
+    pub fn validate_merkle_proofs_report(&self, identities: &[Identity]) -> Vec<ProofLengthIssue> {
+        find_proof_length_issues(identities, self.tree_depth)
     }
 
     pub async fn get_suitable_insertion_prover(
@@ -174,10 +375,257 @@ impl IdentityManager {
         }
     }
 
+    /// Checks that every registered insertion and deletion prover can be
+    /// reached and serves the configured batch size.
+    #[instrument(level = "info", skip_all)]
+    pub async fn verify_provers(&self) -> ProverReport {
+        let mut report = self.insertion_prover_map.read().await.verify().await;
+        report.extend(self.deletion_prover_map.read().await.verify().await);
+
+        report
+    }
+
+    /// Sends a synthetic proof request to every registered prover to prime
+    /// their caches. Failures are logged but otherwise ignored.
+    #[instrument(level = "info", skip_all)]
+    pub async fn warmup_provers(&self) {
+        let mut provers: Vec<Prover> = self
+            .insertion_prover_map
+            .read()
+            .await
+            .provers()
+            .cloned()
+            .collect();
+        provers.extend(self.deletion_prover_map.read().await.provers().cloned());
+
+        for prover in provers {
+            let batch_size = prover.batch_size();
+            let prover_type = prover.prover_type();
+
+            match prover
+                .warmup(self.tree_depth, self.initial_leaf_value)
+                .await
+            {
+                Ok(()) => info!(batch_size, %prover_type, "Prover warmed up"),
+                Err(error) => warn!(batch_size, %prover_type, ?error, "Prover warmup failed"),
+            }
+        }
+    }
+
+    /// Returns whether at least one prover of the given type can be reached.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn any_prover_reachable(&self, prover_type: ProverType) -> bool {
+        let provers: Vec<Prover> = match prover_type {
+            ProverType::Insertion => self.insertion_prover_map.read().await,
+            ProverType::Deletion => self.deletion_prover_map.read().await,
+        }
+        .provers()
+        .cloned()
+        .collect();
+
+        for prover in provers {
+            if prover.is_reachable().await {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Asks every registered prover for the batch size of its circuit and
+    /// alerts on any that no longer match the configured batch size. Provers
+    /// that can't be reached are logged and skipped.
+    ///
+    /// Returns the number of provers whose batch size drifted.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn check_prover_batch_sizes(&self) -> usize {
+        let mut provers: Vec<Prover> = self
+            .insertion_prover_map
+            .read()
+            .await
+            .provers()
+            .cloned()
+            .collect();
+        provers.extend(self.deletion_prover_map.read().await.provers().cloned());
+
+        let mut drifted = 0;
+        for prover in provers {
+            let url = prover.url();
+            let batch_size = prover.batch_size();
+            let prover_type = prover.prover_type();
+
+            match prover.check_batch_size().await {
+                Ok(None) => {}
+                Ok(Some(reported_batch_size)) => {
+                    drifted += 1;
+                    error!(
+                        url,
+                        batch_size,
+                        reported_batch_size,
+                        %prover_type,
+                        "Prover batch size no longer matches the configured batch size"
+                    );
+                }
+                Err(error) => {
+                    warn!(url, batch_size, %prover_type, ?error, "Prover info check failed")
+                }
+            }
+        }
+
+        drifted
+    }
+
     pub async fn root_history_expiry(&self) -> anyhow::Result<U256> {
         Ok(self.abi.get_root_history_expiry().call().await?)
     }
 
+    /// Returns the address of the verifier lookup table the identity manager
+    /// uses to verify insertion proofs.
+    pub async fn verifier_address(&self) -> anyhow::Result<Address> {
+        query_verifier_address(&self.abi).await
+    }
+
+    /// Returns the address transactions are sent from and its current balance,
+    /// so that operators can top it up before it runs out of funds for gas.
+    pub async fn signer_status(&self) -> anyhow::Result<SignerStatus> {
+        query_signer_status(self.ethereum.provider().as_ref(), self.ethereum.address()).await
+    }
+
+    /// Returns the number of identities queued for deletion and records it in
+    /// the `deletion_queue_depth` gauge.
+    pub async fn deletion_queue_depth(&self, database: &Database) -> anyhow::Result<usize> {
+        let depth = database.pending_deletions().await?.len();
+        DELETION_QUEUE_DEPTH.set(depth.try_into().unwrap_or(i64::MAX));
+
+        Ok(depth)
+    }
+
+    /// Builds the proof input for an insertion batch without sending it to a
+    /// prover, e.g. to serialize it and invoke the prover binary directly.
+    pub fn build_proof_input(
+        &self,
+        start_index: usize,
+        pre_root: U256,
+        post_root: U256,
+        identities: &[Identity],
+    ) -> anyhow::Result<ProofInput> {
+        let start_index: u32 = start_index
+            .try_into()
+            .context("Start index does not fit into u32")?;
+
+        Ok(ProofInput::Insertion(build_insertion_proof_input(
+            start_index,
+            pre_root,
+            post_root,
+            identities,
+            self.prover_settings.input_hash_function,
+            self.prover_settings.input_hash_endianness,
+            self.prover_settings.input_hash_domain.as_ref(),
+        )))
+    }
+
+    /// Hands the identity manager the batching tree once the tree state is
+    /// initialized. Only the first tree handed over is kept.
+    pub fn set_batching_tree(&self, batching_tree: TreeVersion<Intermediate>) {
+        let _ = self.batching_tree.set(batching_tree);
+    }
+
+    /// Returns the pre root and post root of an insertion batch appended to
+    /// the batching tree: its current root, and its root once the batch is
+    /// applied to a clone of it. The tree itself is left untouched, so that
+    /// the proof of the batch can be prepared ahead of time.
+    pub fn next_batch_roots(&self, identities: &[Identity]) -> anyhow::Result<(U256, U256)> {
+        let batching_tree = self
+            .batching_tree
+            .get()
+            .context("The batching tree is not initialized yet")?;
+
+        let commitments: Vec<Field> = identities
+            .iter()
+            .map(|identity| u256_to_field(identity.commitment))
+            .collect();
+        let (pre_root, post_root) = batching_tree.roots_after_append(&commitments)?;
+
+        Ok((field_to_u256(pre_root), field_to_u256(post_root)))
+    }
+
+    /// Dry-runs an insertion batch starting at `start_index`: computes its root
+    /// transition, has a prover of its batch size prove it and estimates the
+    /// gas of submitting it. Nothing is sent on-chain.
+    #[instrument(level = "info", skip(self, identities))]
+    pub async fn simulate_batch(
+        &self,
+        start_index: usize,
+        identities: &[Identity],
+    ) -> anyhow::Result<BatchSimulation> {
+        let start_index: u32 = start_index
+            .try_into()
+            .context("Start index does not fit into u32")?;
+        let prover = self.get_suitable_insertion_prover(identities.len()).await?;
+
+        simulate_insertion_batch(
+            &self.abi,
+            self.ethereum.address(),
+            &prover,
+            start_index,
+            identities,
+            self.initial_leaf_value,
+        )
+        .await
+    }
+
+    /// Checks that the provers and the deployed verifier agree end to end: a
+    /// fixed batch is proved by the smallest insertion prover and the proof
+    /// is verified by the verifier the identity manager uses for that batch
+    /// size, via `eth_call`.
+    ///
+    /// Meant as a self-test for new deployments, where a prover built for
+    /// another verifying key would otherwise only be noticed once the first
+    /// batch is rejected on-chain.
+    #[instrument(level = "info", skip(self))]
+    pub async fn check_prover_verifier_compatibility(&self) -> anyhow::Result<()> {
+        let prover = self.get_suitable_insertion_prover(1).await?;
+
+        check_verifier_compatibility(
+            &self.abi,
+            &prover,
+            self.tree_depth,
+            self.initial_leaf_value,
+            &self.prover_settings,
+        )
+        .await
+    }
+
+    /// Checks an insertion batch locally before it is submitted, recomputing
+    /// the post root from the merkle proofs and, if a verifying key is given,
+    /// verifying the proof against the recomputed input hash.
+    pub fn verify_transition_offline(
+        &self,
+        start_index: usize,
+        pre_root: U256,
+        post_root: U256,
+        identities: &[Identity],
+        proof: &Proof,
+        verifying_key: Option<&VerifyingKey>,
+    ) -> anyhow::Result<()> {
+        let start_index: u32 = start_index
+            .try_into()
+            .context("Start index does not fit into u32")?;
+
+        verify_insertion_transition(
+            start_index,
+            pre_root,
+            post_root,
+            identities,
+            self.initial_leaf_value,
+            self.prover_settings.input_hash_function,
+            self.prover_settings.input_hash_endianness,
+            self.prover_settings.input_hash_domain.as_ref(),
+            proof,
+            verifying_key,
+        )
+    }
+
     #[instrument(level = "debug", skip(prover, identity_commitments))]
     pub async fn prepare_insertion_proof(
         prover: &Prover,
@@ -185,7 +633,7 @@ impl IdentityManager {
         pre_root: U256,
         identity_commitments: &[Identity],
         post_root: U256,
-    ) -> anyhow::Result<Proof> {
+    ) -> anyhow::Result<(Proof, U256)> {
         let batch_size = identity_commitments.len();
 
         let actual_start_index: u32 = start_index.try_into()?;
@@ -196,8 +644,8 @@ impl IdentityManager {
             prover.batch_size()
         );
 
-        let proof_data: Proof = prover
-            .generate_insertion_proof(
+        let (proof_data, post_root) = prover
+            .generate_insertion_proof_with_root(
                 actual_start_index,
                 pre_root,
                 post_root,
@@ -205,7 +653,42 @@ impl IdentityManager {
             )
             .await?;
 
-        Ok(proof_data)
+        Ok((proof_data, post_root))
+    }
+
+    /// Asks the identity manager, via `eth_call`, whether it would accept an
+    /// insertion batch, without submitting it.
+    #[instrument(level = "debug", skip(self, identity_commitments, proof))]
+    pub async fn check_insertion_batch(
+        &self,
+        start_index: usize,
+        pre_root: U256,
+        post_root: U256,
+        identity_commitments: &[Identity],
+        proof: &Proof,
+    ) -> anyhow::Result<()> {
+        let start_index: u32 = start_index
+            .try_into()
+            .context("Start index does not fit into u32")?;
+        let commitments: Vec<U256> = identity_commitments
+            .iter()
+            .map(|id| id.commitment)
+            .collect();
+
+        self.abi
+            .register_identities(
+                proof.clone().into(),
+                pre_root,
+                start_index,
+                commitments,
+                post_root,
+            )
+            .from(self.ethereum.address())
+            .estimate_gas()
+            .await
+            .context("The identity manager rejects the batch")?;
+
+        Ok(())
     }
 
     #[instrument(level = "debug", skip(prover, identity_commitments))]
@@ -229,6 +712,42 @@ impl IdentityManager {
         Ok(proof_data)
     }
 
+    /// Returns the ABI-encoded calldata of the `registerIdentities` call for an
+    /// insertion batch without sending it, so it can be signed and submitted
+    /// out-of-band, e.g. through a multisig.
+    pub fn encode_register_calldata(
+        &self,
+        start_index: usize,
+        pre_root: U256,
+        post_root: U256,
+        identity_commitments: &[Identity],
+        proof_data: Proof,
+    ) -> anyhow::Result<Bytes> {
+        let start_index: u32 = start_index
+            .try_into()
+            .context("Start index does not fit into u32")?;
+
+        let identities: Vec<U256> = identity_commitments
+            .iter()
+            .map(|id| id.commitment)
+            .collect();
+
+        encode_register_identities_calldata(
+            &self.abi,
+            proof_data.into(),
+            pre_root,
+            start_index,
+            &identities,
+            post_root,
+        )
+    }
+
+    /// Submits an insertion batch to the identity manager and, if configured,
+    /// to the mirrored identity manager. Returns the id of the transaction
+    /// sent to the primary contract, the mirrored one is watched separately.
+    ///
+    /// If `proof_input_hash` is given, the batch is only submitted if it still
+    /// hashes to the input the proof was generated for.
     #[instrument(level = "debug", skip(self, identity_commitments, proof_data))]
     pub async fn register_identities(
         &self,
@@ -236,61 +755,196 @@ impl IdentityManager {
         pre_root: U256,
         post_root: U256,
         identity_commitments: Vec<Identity>,
-        proof_data: Proof,
-    ) -> anyhow::Result<TransactionId> {
-        let actual_start_index: u32 = start_index.try_into()?;
+        proof_data: impl Into<ProofKind>,
+        proof_input_hash: Option<U256>,
+    ) -> Result<TransactionId, BatchSubmitError> {
+        let leaf_indices = start_index..start_index + identity_commitments.len();
+        let batch_error = |error: SubmitError| BatchSubmitError {
+            leaf_indices: leaf_indices.clone(),
+            error,
+        };
 
-        let proof_points_array: [U256; 8] = proof_data.into();
-        let identities = identity_commitments
+        let actual_start_index: u32 = start_index
+            .try_into()
+            .map_err(|err| batch_error(TxError::Fill(Box::new(err)).into()))?;
+
+        let proof_points_array = groth16_points(proof_data.into()).map_err(batch_error)?;
+        let identities: Vec<U256> = identity_commitments
             .iter()
             .map(|id| id.commitment)
             .collect();
 
-        // We want to send the transaction through our ethereum provider rather than
-        // directly now. To that end, we create it, and then send it later, waiting for
-        // it to complete.
-        let register_identities_transaction = self
-            .abi
-            .register_identities(
-                proof_points_array,
-                pre_root,
+        if let Some(expected) = proof_input_hash {
+            ensure_input_hash_matches(
+                &self.prover_settings,
                 actual_start_index,
-                identities,
+                pre_root,
                 post_root,
+                &identities,
+                expected,
             )
-            .tx;
+            .map_err(|error| {
+                error!(?leaf_indices, %error, "Batch changed since it was proved");
+                batch_error(error)
+            })?;
+        }
 
-        self.ethereum
-            .send_transaction(register_identities_transaction, true)
+        // We want to send the transactions through our ethereum provider rather than
+        // directly now. To that end, we create them, and then send them later,
+        // waiting for them to complete.
+        let register_identities_transactions = build_register_identities_transactions(
+            std::iter::once(&self.abi).chain(self.mirror_accepting_batch(pre_root).await),
+            proof_points_array,
+            pre_root,
+            actual_start_index,
+            &identities,
+            post_root,
+        );
+
+        info!(
+            ?leaf_indices,
+            ?pre_root,
+            ?post_root,
+            "Registering identities"
+        );
+
+        self.send_to_every_contract(register_identities_transactions)
             .await
-            .map_err(|tx_err| anyhow!("{}", tx_err.to_string()))
+            .map_err(|err| {
+                let error = batch_error(err);
+                error!(?leaf_indices, %error, "Failed to register identities");
+                error
+            })
     }
 
-    // TODO: docs
+    /// Re-derives the proof for the identity at `leaf_index` of `tree` and
+    /// submits it again if the identity manager stopped right before that
+    /// leaf, e.g. because its original batch was dropped. Nothing is sent if
+    /// the chain has already moved past the leaf.
+    ///
+    /// This is meant for manual recovery by an operator: the leaf is submitted
+    /// on its own, padded to the smallest available batch size, and any batch
+    /// being processed concurrently will conflict with it.
+    #[instrument(level = "info", skip(self, tree))]
+    pub async fn reprocess_leaf(
+        &self,
+        tree: &impl TreeVersionReadOps,
+        leaf_index: usize,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            leaf_index < tree.next_leaf(),
+            "Leaf index {leaf_index} is not in the tree"
+        );
+
+        let (commitment, merkle_proof) = tree.get_proof(leaf_index);
+        ensure!(
+            commitment != Field::ZERO,
+            "Leaf index {leaf_index} holds no identity"
+        );
+
+        let siblings: Vec<U256> = merkle_proof_siblings(&merkle_proof);
+
+        let prover = self.get_suitable_insertion_prover(1).await?;
+        let identities = rebuild_insertion_batch(
+            leaf_index,
+            &siblings,
+            field_to_u256(commitment),
+            prover.batch_size(),
+            self.initial_leaf_value,
+        );
+        let (pre_root, post_root) =
+            compute_insertion_roots(leaf_index, &identities, self.initial_leaf_value)?;
+
+        if !is_leaf_missing(&self.abi, pre_root).await? {
+            info!(
+                leaf_index,
+                "Leaf is consistent with the chain, nothing to reprocess"
+            );
+            return Ok(());
+        }
+
+        let (proof, post_root) =
+            Self::prepare_insertion_proof(&prover, leaf_index, pre_root, &identities, post_root)
+                .await?;
+        drop(prover);
+
+        let transaction_id = self
+            .register_identities(leaf_index, pre_root, post_root, identities, proof, None)
+            .await?;
+
+        info!(leaf_index, ?transaction_id, "Leaf resubmitted");
+
+        Ok(())
+    }
+
+    /// Submits a deletion batch to the identity manager and, if configured,
+    /// to the mirrored identity manager. Returns the id of the transaction
+    /// sent to the primary contract, the mirrored one is watched separately.
     #[instrument(level = "debug")]
     pub async fn delete_identities(
         &self,
-        deletion_proof: Proof,
+        deletion_proof: impl Into<ProofKind> + fmt::Debug,
         packed_deletion_indices: Vec<u8>,
         pre_root: U256,
         post_root: U256,
-    ) -> anyhow::Result<TransactionId> {
-        let proof_points_array: [U256; 8] = deletion_proof.into();
-
-        let register_identities_transaction = self
-            .abi
-            .delete_identities(
-                proof_points_array,
-                packed_deletion_indices.into(),
-                pre_root,
-                post_root,
-            )
-            .tx;
+    ) -> Result<TransactionId, SubmitError> {
+        let proof_points_array = groth16_points(deletion_proof.into())?;
+
+        let delete_identities_transactions = build_delete_identities_transactions(
+            std::iter::once(&self.abi).chain(self.mirror_accepting_batch(pre_root).await),
+            proof_points_array,
+            &packed_deletion_indices,
+            pre_root,
+            post_root,
+        );
 
-        self.ethereum
-            .send_transaction(register_identities_transaction, true)
+        self.send_to_every_contract(delete_identities_transactions)
             .await
-            .map_err(|tx_err| anyhow!("{}", tx_err.to_string()))
+    }
+
+    /// Returns the mirrored identity manager if the batch starting at
+    /// `pre_root` should be sent to it.
+    async fn mirror_accepting_batch(&self, pre_root: U256) -> Option<&WorldId<ReadProvider>> {
+        let mirror = self.mirror.as_ref()?;
+
+        mirror.accepts_batch(pre_root).await.then_some(mirror.abi())
+    }
+
+    /// Sends the transactions built for the primary and the mirrored identity
+    /// managers, primary first.
+    ///
+    /// Only a failure to send to the primary contract fails the batch, and
+    /// only the primary transaction is returned to be monitored. The mirrored
+    /// one is watched by the [`Mirror`], so that a mirror missing a batch
+    /// never fails the pipeline.
+    async fn send_to_every_contract(
+        &self,
+        transactions: Vec<TypedTransaction>,
+    ) -> Result<TransactionId, SubmitError> {
+        let mut transactions = transactions.into_iter();
+
+        let primary = transactions
+            .next()
+            .expect("A transaction is always built for the primary contract");
+        let transaction_id = self.ethereum.send_transaction(primary, true).await?;
+
+        if let (Some(mirror), Some(mirrored)) = (&self.mirror, transactions.next()) {
+            mirror.submit(&self.ethereum, mirrored).await;
+        }
+
+        Ok(transaction_id)
+    }
+
+    /// The identity managers a root must be mined on to count as mined: the
+    /// primary one and, while it is in sync, the mirrored one.
+    fn contracts_required_for_mining(&self) -> impl Iterator<Item = &WorldId<ReadProvider>> {
+        let mirror = self
+            .mirror
+            .as_ref()
+            .filter(|mirror| mirror.is_in_sync())
+            .map(Mirror::abi);
+
+        std::iter::once(&self.abi).chain(mirror)
     }
 
     #[instrument(level = "debug", skip(self))]
@@ -300,6 +954,20 @@ impl IdentityManager {
         Ok(result)
     }
 
+    /// Waits until the transaction is buried under `confirmations` blocks
+    #[instrument(level = "debug", skip(self))]
+    pub async fn await_confirmations(
+        &self,
+        transaction_id: TransactionId,
+        confirmations: u64,
+    ) -> anyhow::Result<TransactionReceipt> {
+        self.ethereum
+            .await_confirmations(transaction_id, confirmations)
+            .await
+    }
+
+    /// Returns the identity manager transactions that are not yet mined,
+    /// sorted by nonce.
     #[instrument(level = "debug", skip_all)]
     pub async fn fetch_pending_identities(&self) -> anyhow::Result<Vec<TransactionId>> {
         let pending_identities = self.ethereum.fetch_pending_transactions().await?;
@@ -307,19 +975,32 @@ impl IdentityManager {
         Ok(pending_identities)
     }
 
-    /// Waits until all the pending transactions have been mined or failed
+    /// Waits until all the pending transactions have been mined or failed and
+    /// reports the outcome of each of them.
     #[instrument(level = "debug", skip_all)]
-    pub async fn await_clean_slate(&self) -> anyhow::Result<()> {
+    pub async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport> {
         // Await for all pending transactions
         let pending_identities = self.fetch_pending_identities().await?;
 
-        for pending_identity_tx in pending_identities {
-            // Ignores the result of each transaction - we only care about a clean slate in
-            // terms of pending transactions
-            drop(self.mine_transaction(pending_identity_tx).await);
+        let results =
+            await_transactions(pending_identities.clone(), CLEAN_SLATE_CONCURRENCY, |tx| {
+                self.mine_transaction(tx)
+            })
+            .await;
+
+        // A failed transaction doesn't prevent a clean slate, we only care that
+        // nothing is pending anymore
+        let report = summarize_clean_slate(pending_identities, results);
+        for (transaction_id, reason) in &report.failed {
+            warn!(?transaction_id, %reason, "Pending transaction failed");
         }
+        info!(
+            mined = report.mined.len(),
+            failed = report.failed.len(),
+            "Pending transactions settled"
+        );
 
-        Ok(())
+        Ok(report)
     }
 
     #[instrument(level = "debug", skip_all)]
@@ -329,6 +1010,89 @@ impl IdentityManager {
         Ok(latest_root)
     }
 
+    /// Checks that the latest root of the identity manager is the current
+    /// root of `tree`, failing with a [`RootMismatch`] otherwise.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn assert_root_matches<V: Version>(&self, tree: &TreeVersion<V>) -> anyhow::Result<()>
+    where
+        TreeVersion<V>: TreeVersionReadOps,
+    {
+        assert_latest_root(&self.abi, tree.get_root().into()).await
+    }
+
+    /// Imports every identity inserted into the identity manager since
+    /// `from_block` into an empty database, reading the events in windows of
+    /// `window_size` blocks. Returns the root of the rebuilt tree.
+    #[instrument(level = "info", skip(self, database))]
+    pub async fn backfill(
+        &self,
+        database: &Database,
+        from_block: u64,
+        window_size: u64,
+    ) -> anyhow::Result<Field> {
+        let to_block = self.latest_block_number().await?;
+
+        let changes = fetch_tree_changes(
+            self.ethereum.provider().as_ref(),
+            self.abi.address(),
+            from_block,
+            to_block,
+            window_size,
+            self.tree_depth,
+        )
+        .await?;
+
+        info!(batches = changes.len(), "Backfilling the database");
+
+        backfill_database(database, self.tree_depth, self.initial_leaf_value, &changes).await
+    }
+
+    /// Compares the identities inserted into the identity manager since
+    /// `from_block` with the database, returning the commitments the chain
+    /// holds but the database doesn't. Any such commitment means the database
+    /// is behind the chain.
+    #[instrument(level = "info", skip(self, database))]
+    pub async fn find_commitments_missing_locally(
+        &self,
+        database: &Database,
+        from_block: u64,
+        window_size: u64,
+    ) -> anyhow::Result<Vec<MissingCommitment>> {
+        let to_block = self.latest_block_number().await?;
+
+        let changes = fetch_tree_changes(
+            self.ethereum.provider().as_ref(),
+            self.abi.address(),
+            from_block,
+            to_block,
+            window_size,
+            self.tree_depth,
+        )
+        .await?;
+
+        let missing = find_missing_commitments(database, &changes).await?;
+        for MissingCommitment {
+            leaf_index,
+            commitment,
+        } in &missing
+        {
+            warn!(
+                leaf_index,
+                ?commitment,
+                "Commitment found on-chain is missing locally"
+            );
+        }
+
+        Ok(missing)
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    pub async fn latest_block_number(&self) -> anyhow::Result<u64> {
+        let block_number = self.ethereum.provider().get_block_number().await?;
+
+        Ok(block_number.as_u64())
+    }
+
     /// Fetches the identity commitments from a
     /// `deleteIdentities` transaction by tx hash
     #[instrument(level = "debug", skip_all)]
@@ -358,35 +1122,51 @@ impl IdentityManager {
             .collect())
     }
 
+    /// Checks whether the root has been mined on mainnet. With a mirrored
+    /// identity manager configured, the root must also be mined on the mirror
+    /// while it is in sync.
     #[instrument(level = "debug", skip_all)]
     pub async fn is_root_mined(&self, root: U256) -> anyhow::Result<bool> {
-        let (root_on_mainnet, ..) = self.abi.query_root(root).call().await?;
+        for abi in self.contracts_required_for_mining() {
+            let (root_on_mainnet, ..) = abi.query_root(root).call().await?;
 
-        if root_on_mainnet.is_zero() {
-            return Ok(false);
+            if root_on_mainnet.is_zero() {
+                return Ok(false);
+            }
         }
 
         Ok(true)
     }
 
+    /// Checks whether each of the provided roots has been mined on mainnet, in
+    /// the same sense as [`Self::is_root_mined`].
+    ///
+    /// The roots are checked through Multicall3 in batches, so catching up on
+    /// many roots doesn't take a round trip per root. The returned statuses
+    /// are in the same order as `roots`.
     #[instrument(level = "debug", skip_all)]
-    pub async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
-        let (root_on_mainnet, ..) = self.abi.query_root(root).call().await?;
+    pub async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>> {
+        let mut mined = vec![true; roots.len()];
 
-        if root_on_mainnet.is_zero() {
-            return Ok(false);
+        for abi in self.contracts_required_for_mining() {
+            let mined_on_contract = query_roots_mined(abi, self.multicall_address, roots).await?;
+
+            for (mined, mined_on_contract) in mined.iter_mut().zip(mined_on_contract) {
+                *mined &= mined_on_contract;
+            }
         }
 
-        for bridged_world_id in &self.secondary_abis {
-            let root_timestamp = bridged_world_id.root_history(root).call().await?;
+        Ok(mined)
+    }
 
-            // root_history only returns superseded roots, so we must also check the latest
-            // root
-            let latest_root = bridged_world_id.latest_root().call().await?;
+    #[instrument(level = "debug", skip_all)]
+    pub async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
+        if !self.is_root_mined(root).await? {
+            return Ok(false);
+        }
 
-            // If root is not superseded and it's not the latest root
-            // then it's not mined
-            if root_timestamp == 0 && root != latest_root {
+        for bridged_world_id in &self.secondary_abis {
+            if !is_root_bridged(bridged_world_id, root).await? {
                 return Ok(false);
             }
         }
@@ -394,6 +1174,26 @@ impl IdentityManager {
         Ok(true)
     }
 
+    /// Returns the ids of the chains the root has reached: mainnet once the
+    /// root is mined there, followed by every secondary chain it has been
+    /// bridged to.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn chains_with_root(&self, root: U256) -> anyhow::Result<Vec<u64>> {
+        if !self.is_root_mined(root).await? {
+            return Ok(vec![]);
+        }
+
+        let mut chain_ids = vec![self.abi.client().chain_id.as_u64()];
+
+        for bridged_world_id in &self.secondary_abis {
+            if is_root_bridged(bridged_world_id, root).await? {
+                chain_ids.push(bridged_world_id.client().chain_id.as_u64());
+            }
+        }
+
+        Ok(chain_ids)
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if the provided batch size already exists.
@@ -413,12 +1213,15 @@ impl IdentityManager {
             return Err(ServerError::BatchSizeAlreadyExists);
         }
 
-        let prover = Prover::new(&ProverConfig {
-            url: url.to_string(),
-            batch_size,
-            prover_type,
-            timeout_s: timeout_seconds,
-        })?;
+        let prover = Prover::new(
+            &ProverConfig {
+                url: url.to_string(),
+                batch_size,
+                prover_type,
+                timeout_s: timeout_seconds,
+            },
+            &self.prover_settings,
+        )?;
 
         map.add(batch_size, prover);
 
@@ -450,8 +1253,36 @@ impl IdentityManager {
         }
     }
 
-    pub async fn list_batch_sizes(&self) -> Result<Vec<ProverConfig>, ServerError> {
-        let mut provers = self
+    /// Changes the timeout of the prover registered for the given batch size.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if no prover is registered for the batch size, or if
+    /// the timeout is zero.
+    pub async fn set_prover_timeout(
+        &self,
+        batch_size: usize,
+        prover_type: ProverType,
+        timeout_seconds: u64,
+    ) -> Result<(), ServerError> {
+        let map = match prover_type {
+            ProverType::Insertion => self.insertion_prover_map.read().await,
+            ProverType::Deletion => self.deletion_prover_map.read().await,
+        };
+
+        let prover = map
+            .get_exact(batch_size)
+            .ok_or(ServerError::NoSuchBatchSize)?;
+
+        prover
+            .set_timeout(timeout_seconds)
+            .map_err(|_| ServerError::InvalidTimeout)?;
+
+        Ok(())
+    }
+
+    pub async fn list_batch_sizes(&self) -> Result<Vec<ProverConfig>, ServerError> {
+        let mut provers = self
             .insertion_prover_map
             .read()
             .await
@@ -471,5 +1302,1693 @@ impl IdentityManager {
     }
 }
 
+/// A merkle proof in a batch whose length doesn't match the tree depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofLengthIssue {
+    /// The position of the identity in the batch
+    pub index:    usize,
+    pub length:   usize,
+    pub expected: usize,
+}
+
+impl fmt::Display for ProofLengthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Identity at position {} has a merkle proof of length {}, expected {}",
+            self.index, self.length, self.expected
+        )
+    }
+}
+
+/// The latest root of the identity manager differs from the expected one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("Latest root on chain is {chain_root:#x}, expected {expected_root:#x}")]
+pub struct RootMismatch {
+    pub expected_root: U256,
+    pub chain_root:    U256,
+}
+
+/// The code deployed at the identity manager address is not the expected
+/// build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("Contract code at {address:?} hashes to {codehash:?}, expected {expected_codehash:?}")]
+pub struct CodehashMismatch {
+    pub address:           Address,
+    pub expected_codehash: H256,
+    pub codehash:          H256,
+}
+
+/// The account identity manager transactions are sent from, as returned by
+/// [`IdentityManager::signer_status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignerStatus {
+    pub address: Address,
+    /// The balance of the account, in wei
+    pub balance: U256,
+}
+
+/// What happened to the transactions that were pending on startup, as
+/// returned by [`IdentityManager::await_clean_slate`].
+#[derive(Clone, Debug, Default)]
+pub struct CleanSlateReport {
+    pub mined:  Vec<TransactionId>,
+    /// The transactions that failed or could not be awaited, with the reason
+    pub failed: Vec<(TransactionId, String)>,
+}
+
+/// The outcome of dry-running an insertion batch with
+/// [`IdentityManager::simulate_batch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchSimulation {
+    pub pre_root:   U256,
+    pub post_root:  U256,
+    /// The gas `registerIdentities` is estimated to use for the batch
+    pub gas:        U256,
+    /// How long the prover took to prove the batch
+    pub proof_time: Duration,
+}
+
+fn derive_identities(
+    tree: &impl TreeVersionReadOps,
+    commitments: &[U256],
+) -> anyhow::Result<Vec<Identity>> {
+    let mut leaf_indices: HashMap<Field, Option<usize>> = commitments
+        .iter()
+        .map(|commitment| (u256_to_field(*commitment), None))
+        .collect();
+
+    // Scanning from the end finds the latest leaf of a re-inserted commitment
+    for leaf_index in (0..tree.next_leaf()).rev() {
+        if let Some(found @ None) = leaf_indices.get_mut(&tree.get_leaf(leaf_index)) {
+            *found = Some(leaf_index);
+        }
+    }
+
+    commitments
+        .iter()
+        .map(|commitment| {
+            let leaf_index = leaf_indices[&u256_to_field(*commitment)]
+                .ok_or_else(|| anyhow!("Commitment {commitment:#x} is not in the tree"))?;
+
+            let (_, proof) = tree.get_proof(leaf_index);
+            let merkle_proof = merkle_proof_siblings(&proof);
+
+            Ok(Identity::new(*commitment, merkle_proof))
+        })
+        .collect()
+}
+
+fn find_proof_length_issues(identities: &[Identity], tree_depth: usize) -> Vec<ProofLengthIssue> {
+    identities
+        .iter()
+        .enumerate()
+        .filter(|(_, identity)| identity.merkle_proof.len() != tree_depth)
+        .map(|(index, identity)| ProofLengthIssue {
+            index,
+            length: identity.merkle_proof.len(),
+            expected: tree_depth,
+        })
+        .collect()
+}
+
 /// A type for an identity manager object that can be sent across threads.
 pub type SharedIdentityManager = Arc<IdentityManager>;
+
+/// The on-chain queries of the [`IdentityManager`] used by the background
+/// tasks, so that the tasks can be tested without a chain.
+#[async_trait]
+pub trait IdentityManagerTrait: Send + Sync {
+    async fn latest_root(&self) -> anyhow::Result<U256>;
+
+    async fn latest_block_number(&self) -> anyhow::Result<u64>;
+
+    async fn root_history_expiry(&self) -> anyhow::Result<U256>;
+
+    async fn is_root_mined(&self, root: U256) -> anyhow::Result<bool>;
+
+    async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>>;
+
+    async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool>;
+
+    async fn chains_with_root(&self, root: U256) -> anyhow::Result<Vec<u64>>;
+
+    async fn fetch_deletion_indices_from_tx(&self, tx_hash: H256) -> anyhow::Result<Vec<usize>>;
+
+    async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport>;
+}
+
+#[async_trait]
+impl IdentityManagerTrait for IdentityManager {
+    async fn latest_root(&self) -> anyhow::Result<U256> {
+        self.retry_view_call(|| IdentityManager::latest_root(self))
+            .await
+    }
+
+    async fn latest_block_number(&self) -> anyhow::Result<u64> {
+        self.retry_view_call(|| IdentityManager::latest_block_number(self))
+            .await
+    }
+
+    async fn root_history_expiry(&self) -> anyhow::Result<U256> {
+        self.retry_view_call(|| IdentityManager::root_history_expiry(self))
+            .await
+    }
+
+    async fn is_root_mined(&self, root: U256) -> anyhow::Result<bool> {
+        self.retry_view_call(|| IdentityManager::is_root_mined(self, root))
+            .await
+    }
+
+    async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>> {
+        self.retry_view_call(|| IdentityManager::are_roots_mined(self, roots))
+            .await
+    }
+
+    async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
+        self.retry_view_call(|| IdentityManager::is_root_mined_multi_chain(self, root))
+            .await
+    }
+
+    async fn chains_with_root(&self, root: U256) -> anyhow::Result<Vec<u64>> {
+        self.retry_view_call(|| IdentityManager::chains_with_root(self, root))
+            .await
+    }
+
+    async fn fetch_deletion_indices_from_tx(&self, tx_hash: H256) -> anyhow::Result<Vec<usize>> {
+        IdentityManager::fetch_deletion_indices_from_tx(self, tx_hash).await
+    }
+
+    async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport> {
+        IdentityManager::await_clean_slate(self).await
+    }
+}
+
+/// Waits until all the pending transactions have been mined or failed, unless
+/// `skip_clean_slate` is set, in which case the report is empty.
+pub async fn settle_pending_transactions(
+    identity_manager: &dyn IdentityManagerTrait,
+    skip_clean_slate: bool,
+) -> anyhow::Result<CleanSlateReport> {
+    if skip_clean_slate {
+        warn!(
+            "Not waiting for pending transactions to settle, transactions sent before the restart \
+             may still be in flight. Never skip this outside of development"
+        );
+
+        return Ok(CleanSlateReport::default());
+    }
+
+    identity_manager.await_clean_slate().await
+}
+
+/// Waits for every transaction with `mine`, running up to `concurrency`
+/// lookups at once. The results are returned in the order of `transactions`.
+async fn await_transactions<F, Fut>(
+    transactions: Vec<TransactionId>,
+    concurrency: usize,
+    mine: F,
+) -> Vec<anyhow::Result<bool>>
+where
+    F: Fn(TransactionId) -> Fut,
+    Fut: Future<Output = anyhow::Result<bool>>,
+{
+    stream::iter(transactions)
+        .map(mine)
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Sorts the `results` of awaiting `transactions`, in the same order, into
+/// mined and failed transactions.
+fn summarize_clean_slate(
+    transactions: Vec<TransactionId>,
+    results: Vec<anyhow::Result<bool>>,
+) -> CleanSlateReport {
+    let mut report = CleanSlateReport::default();
+
+    for (transaction_id, result) in transactions.into_iter().zip(results) {
+        match result {
+            Ok(true) => report.mined.push(transaction_id),
+            Ok(false) => report
+                .failed
+                .push((transaction_id, "Transaction failed".to_string())),
+            Err(error) => report.failed.push((transaction_id, error.to_string())),
+        }
+    }
+
+    report
+}
+
+/// Queries the identity operator of the identity manager, checking up to
+/// `retries` more times, spaced out by `backoff`, while it doesn't match
+/// `signer`. Returns the last operator seen.
+async fn wait_for_identity_operator<M>(
+    abi: &WorldId<M>,
+    signer: Address,
+    retries: u32,
+    backoff: &Backoff,
+) -> anyhow::Result<Address>
+where
+    M: Middleware + 'static,
+{
+    let mut operator = abi.identity_operator().call().await?;
+
+    for attempt in 1..=retries {
+        if operator == signer {
+            break;
+        }
+
+        let retry_delay = backoff.delay(attempt);
+        warn!(
+            ?operator,
+            ?signer,
+            attempt,
+            retries,
+            "Signer is not the identity operator yet, checking again in {retry_delay:?}"
+        );
+        tokio::time::sleep(retry_delay).await;
+
+        operator = abi.identity_operator().call().await?;
+    }
+
+    Ok(operator)
+}
+
+/// Returns the words of a proof as the identity manager takes them, which only
+/// verifies Groth16 proofs.
+fn groth16_points(proof: ProofKind) -> Result<[U256; 8], SubmitError> {
+    match proof {
+        ProofKind::Groth16(points) => Ok(points),
+        ProofKind::Plonk(points) => Err(SubmitError::UnsupportedProof {
+            words: points.len(),
+        }),
+    }
+}
+
+/// Checks that an insertion batch still hashes to the input hash its proof was
+/// generated for, e.g. in case the tree changed between proving and
+/// submission.
+fn ensure_input_hash_matches(
+    settings: &ProverSettings,
+    start_index: u32,
+    pre_root: U256,
+    post_root: U256,
+    identity_commitments: &[U256],
+    expected: U256,
+) -> Result<(), SubmitError> {
+    let actual = compute_insertion_proof_input_hash(
+        start_index,
+        pre_root,
+        post_root,
+        identity_commitments,
+        settings.input_hash_function,
+        settings.input_hash_endianness,
+        settings.input_hash_domain.as_ref(),
+    );
+
+    if actual != expected {
+        return Err(SubmitError::InputHashMismatch { expected, actual });
+    }
+
+    Ok(())
+}
+
+/// Builds the same `registerIdentities` call for every identity manager in
+/// `abis`.
+fn build_register_identities_transactions<'a, M>(
+    abis: impl IntoIterator<Item = &'a WorldId<M>>,
+    proof_points: [U256; 8],
+    pre_root: U256,
+    start_index: u32,
+    identities: &[U256],
+    post_root: U256,
+) -> Vec<TypedTransaction>
+where
+    M: Middleware + 'static,
+{
+    abis.into_iter()
+        .map(|abi| {
+            abi.register_identities(
+                proof_points,
+                pre_root,
+                start_index,
+                identities.to_vec(),
+                post_root,
+            )
+            .tx
+        })
+        .collect()
+}
+
+/// Builds the same `deleteIdentities` call for every identity manager in
+/// `abis`.
+fn build_delete_identities_transactions<'a, M>(
+    abis: impl IntoIterator<Item = &'a WorldId<M>>,
+    proof_points: [U256; 8],
+    packed_deletion_indices: &[u8],
+    pre_root: U256,
+    post_root: U256,
+) -> Vec<TypedTransaction>
+where
+    M: Middleware + 'static,
+{
+    abis.into_iter()
+        .map(|abi| {
+            abi.delete_identities(
+                proof_points,
+                packed_deletion_indices.to_vec().into(),
+                pre_root,
+                post_root,
+            )
+            .tx
+        })
+        .collect()
+}
+
+fn encode_register_identities_calldata<M>(
+    abi: &WorldId<M>,
+    proof_points: [U256; 8],
+    pre_root: U256,
+    start_index: u32,
+    identities: &[U256],
+    post_root: U256,
+) -> anyhow::Result<Bytes>
+where
+    M: Middleware + 'static,
+{
+    abi.register_identities(
+        proof_points,
+        pre_root,
+        start_index,
+        identities.to_vec(),
+        post_root,
+    )
+    .calldata()
+    .context("Missing calldata for registerIdentities")
+}
+
+/// Checks whether each of the roots is known to the identity manager, in
+/// batches of at most [`MAX_ROOTS_PER_QUERY`] roots. Each batch is a single
+/// call to the `multicall` contract if there is one, or concurrent calls to
+/// the identity manager otherwise.
+async fn query_roots_mined<M>(
+    abi: &WorldId<M>,
+    multicall: Option<Address>,
+    roots: &[U256],
+) -> anyhow::Result<Vec<bool>>
+where
+    M: Middleware + 'static,
+{
+    let mut mined = Vec::with_capacity(roots.len());
+
+    for batch in roots.chunks(MAX_ROOTS_PER_QUERY) {
+        let root_infos: Vec<(U256, u128, bool)> = match multicall {
+            Some(address) => {
+                let mut multicall =
+                    Multicall::new_with_chain_id(abi.client(), Some(address), None::<u64>)?;
+                for root in batch {
+                    multicall.add_call(abi.query_root(*root), false);
+                }
+
+                multicall.call_array().await?
+            }
+            None => {
+                let queries = batch.iter().map(|root| abi.query_root(*root).call());
+
+                futures::future::try_join_all(queries).await?
+            }
+        };
+
+        mined.extend(
+            root_infos
+                .into_iter()
+                .map(|(root_on_mainnet, ..)| !root_on_mainnet.is_zero()),
+        );
+    }
+
+    Ok(mined)
+}
+
+/// Returns `address` if a contract is deployed there to batch calls through,
+/// see [`query_roots_mined`].
+async fn deployed_multicall<M>(provider: &M, address: Address) -> anyhow::Result<Option<Address>>
+where
+    M: Middleware + 'static,
+{
+    let code = provider.get_code(address, None).await?;
+    if code.as_ref().is_empty() {
+        warn!(
+            ?address,
+            "No Multicall3 contract is deployed at the provided address, roots are checked one \
+             call at a time"
+        );
+
+        return Ok(None);
+    }
+
+    Ok(Some(address))
+}
+
+async fn is_root_bridged<M>(
+    bridged_world_id: &BridgedWorldId<M>,
+    root: U256,
+) -> anyhow::Result<bool>
+where
+    M: Middleware + 'static,
+{
+    let root_timestamp = bridged_world_id.root_history(root).call().await?;
+
+    // root_history only returns superseded roots, so we must also check the latest
+    // root
+    let latest_root = bridged_world_id.latest_root().call().await?;
+
+    // If root is not superseded and it's not the latest root
+    // then it's not mined
+    Ok(root_timestamp != 0 || root == latest_root)
+}
+
+async fn simulate_insertion_batch<M>(
+    abi: &WorldId<M>,
+    operator: Address,
+    prover: &Prover,
+    start_index: u32,
+    identities: &[Identity],
+    empty_leaf: Field,
+) -> anyhow::Result<BatchSimulation>
+where
+    M: Middleware + 'static,
+{
+    let (pre_root, post_root) =
+        compute_insertion_roots(start_index as usize, identities, empty_leaf)?;
+
+    let proving_started = Instant::now();
+    let proof = prover
+        .generate_insertion_proof(start_index, pre_root, post_root, identities)
+        .await?;
+    let proof_time = proving_started.elapsed();
+
+    let commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
+    let gas = abi
+        .register_identities(proof.into(), pre_root, start_index, commitments, post_root)
+        .from(operator)
+        .estimate_gas()
+        .await?;
+
+    Ok(BatchSimulation {
+        pre_root,
+        post_root,
+        gas,
+        proof_time,
+    })
+}
+
+/// Builds the batch used to check a prover against the deployed verifier: the
+/// commitments `1..=batch_size` inserted into an empty tree.
+fn sample_insertion_batch(
+    tree_depth: usize,
+    batch_size: usize,
+    empty_leaf: Field,
+) -> Vec<Identity> {
+    let mut tree = LazyPoseidonTree::new(tree_depth, empty_leaf).derived();
+
+    (0..batch_size)
+        .map(|leaf_index| {
+            let commitment = U256::from(leaf_index + 1);
+            tree = tree.update(leaf_index, &u256_to_field(commitment));
+
+            let merkle_proof = merkle_proof_siblings(&tree.proof(leaf_index));
+
+            Identity::new(commitment, merkle_proof)
+        })
+        .collect()
+}
+
+async fn check_verifier_compatibility<M>(
+    abi: &WorldId<M>,
+    prover: &Prover,
+    tree_depth: usize,
+    empty_leaf: Field,
+    settings: &ProverSettings,
+) -> anyhow::Result<()>
+where
+    M: Middleware + 'static,
+{
+    let batch_size = prover.batch_size();
+    let identities = sample_insertion_batch(tree_depth, batch_size, empty_leaf);
+    let (pre_root, post_root) = compute_insertion_roots(0, &identities, empty_leaf)?;
+
+    let proof = prover
+        .generate_insertion_proof(0, pre_root, post_root, &identities)
+        .await
+        .context("Prover failed to prove the sample batch")?;
+
+    let commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
+    let input_hash = compute_insertion_proof_input_hash(
+        0,
+        pre_root,
+        post_root,
+        &commitments,
+        settings.input_hash_function,
+        settings.input_hash_endianness,
+        settings.input_hash_domain.as_ref(),
+    );
+
+    let lookup_table = VerifierLookupTable::new(query_verifier_address(abi).await?, abi.client());
+    let verifier_address = lookup_table
+        .get_verifier_for(U256::from(batch_size))
+        .call()
+        .await
+        .with_context(|| format!("No verifier is registered for batch size {batch_size}"))?;
+
+    // The contracts reduce the input hash into the scalar field before
+    // passing it to the verifier
+    TreeVerifier::new(verifier_address, abi.client())
+        .verify_proof(proof.into(), [input_hash % *SNARK_SCALAR_FIELD])
+        .call()
+        .await
+        .with_context(|| {
+            format!(
+                "Verifier at {verifier_address:?} rejected the proof of a batch of size \
+                 {batch_size}"
+            )
+        })?;
+
+    info!(
+        batch_size,
+        ?verifier_address,
+        "Prover is compatible with the verifier"
+    );
+
+    Ok(())
+}
+
+async fn assert_latest_root<M>(abi: &WorldId<M>, expected_root: U256) -> anyhow::Result<()>
+where
+    M: Middleware + 'static,
+{
+    let chain_root = abi.latest_root().call().await?;
+
+    if chain_root != expected_root {
+        return Err(RootMismatch {
+            expected_root,
+            chain_root,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Checks whether the latest root of the identity manager is `pre_root`, the
+/// root right before a leaf, so that the leaf has to be submitted again. A
+/// chain that has moved past `pre_root` already holds the leaf, while one that
+/// never reached it is missing earlier leaves as well.
+async fn is_leaf_missing<M>(abi: &WorldId<M>, pre_root: U256) -> anyhow::Result<bool>
+where
+    M: Middleware + 'static,
+{
+    let chain_root = abi.latest_root().call().await?;
+    if chain_root == pre_root {
+        return Ok(true);
+    }
+
+    let (root, ..) = abi.query_root(pre_root).call().await?;
+    ensure!(
+        !root.is_zero(),
+        "Latest root on chain {chain_root:#x} does not descend from {pre_root:#x}, earlier leaves \
+         are missing"
+    );
+
+    Ok(false)
+}
+
+/// Checks that contract code is deployed at `address` and, if an
+/// `expected_codehash` is given, that the code hashes to it.
+async fn verify_contract_code<M>(
+    provider: &M,
+    address: Address,
+    expected_codehash: Option<H256>,
+) -> anyhow::Result<()>
+where
+    M: Middleware + 'static,
+{
+    let code = provider.get_code(address, None).await?;
+    if code.as_ref().is_empty() {
+        error!(
+            ?address,
+            "No contract code is deployed at the provided address."
+        );
+    }
+
+    let Some(expected_codehash) = expected_codehash else {
+        return Ok(());
+    };
+
+    let codehash = H256::from(keccak256(&code));
+    if codehash != expected_codehash {
+        return Err(CodehashMismatch {
+            address,
+            expected_codehash,
+            codehash,
+        }
+        .into());
+    }
+
+    info!(
+        ?address,
+        ?codehash,
+        "Contract code matches the expected build"
+    );
+
+    Ok(())
+}
+
+async fn query_signer_status<M>(provider: &M, address: Address) -> anyhow::Result<SignerStatus>
+where
+    M: Middleware + 'static,
+{
+    let balance = provider.get_balance(address, None).await?;
+
+    SIGNER_BALANCE.set(format_ether(balance).parse().unwrap_or(f64::MAX));
+
+    Ok(SignerStatus { address, balance })
+}
+
+async fn query_verifier_address<M>(abi: &WorldId<M>) -> anyhow::Result<Address>
+where
+    M: Middleware + 'static,
+{
+    Ok(abi
+        .get_register_identities_verifier_lookup_table_address()
+        .call()
+        .await?)
+}
+
+#[cfg(test)]
+impl IdentityManager {
+    /// Connects an identity manager at a fixed address to `rpc`, without any
+    /// provers, sending transactions signed with a local key.
+    pub(crate) async fn connect_to_mock(
+        rpc: &crate::test_utils::RpcMock,
+        tree_depth: usize,
+    ) -> anyhow::Result<Self> {
+        use serde_json::json;
+
+        use crate::config::{NetworkConfig, ProvidersConfig, RelayerConfig};
+
+        let providers: ProvidersConfig = serde_json::from_value(json!({
+            "primary_network_provider": rpc.url(),
+        }))?;
+        let relayer: RelayerConfig = serde_json::from_value(json!({
+            "kind": "signer",
+            "signer": "local_key",
+            // The first account of the default anvil and hardhat mnemonic
+            "private_key": "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        }))?;
+        let network: NetworkConfig = serde_json::from_value(json!({
+            "identity_manager_address": Address::repeat_byte(0x01),
+        }))?;
+
+        let ethereum = Ethereum::connect(&providers, &relayer, &network).await?;
+
+        Ok(Self {
+            abi: WorldId::new(Address::repeat_byte(0x01), ethereum.provider().clone()),
+            ethereum,
+            insertion_prover_map: RwLock::new(ProverMap::default()),
+            deletion_prover_map: RwLock::new(ProverMap::default()),
+            mirror: None,
+            secondary_abis: vec![],
+            multicall_address: None,
+            initial_leaf_value: Field::ZERO,
+            tree_depth,
+            prover_settings: ProverSettings::default(),
+            view_call_retries: 0,
+            view_call_backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(30), 2.0),
+            batching_tree: OnceLock::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use ethers::abi::{encode, AbiDecode, Token};
+    use ethers::providers::{JsonRpcError, MockResponse, Provider};
+    use semaphore::poseidon_tree::Branch;
+
+    use super::*;
+    use crate::contracts::abi::RegisterIdentitiesCall;
+    use crate::identity_tree::{CanonicalTreeBuilder, Latest};
+    use crate::prover::mock::{mock_prover_config, MockProver};
+    use crate::test_utils::{empty_trees, RpcMock};
+
+    /// An identity manager that only counts how often it awaits a clean slate.
+    #[derive(Default)]
+    struct CleanSlateCounter {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl IdentityManagerTrait for CleanSlateCounter {
+        async fn latest_root(&self) -> anyhow::Result<U256> {
+            Ok(U256::zero())
+        }
+
+        async fn latest_block_number(&self) -> anyhow::Result<u64> {
+            Ok(0)
+        }
+
+        async fn root_history_expiry(&self) -> anyhow::Result<U256> {
+            Ok(U256::zero())
+        }
+
+        async fn is_root_mined(&self, _root: U256) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+
+        async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>> {
+            Ok(vec![false; roots.len()])
+        }
+
+        async fn is_root_mined_multi_chain(&self, _root: U256) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+
+        async fn chains_with_root(&self, _root: U256) -> anyhow::Result<Vec<u64>> {
+            Ok(vec![])
+        }
+
+        async fn fetch_deletion_indices_from_tx(
+            &self,
+            _tx_hash: H256,
+        ) -> anyhow::Result<Vec<usize>> {
+            Ok(vec![])
+        }
+
+        async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            Ok(CleanSlateReport::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_slate_is_awaited_by_default() -> anyhow::Result<()> {
+        let identity_manager = CleanSlateCounter::default();
+
+        settle_pending_transactions(&identity_manager, false).await?;
+
+        assert_eq!(identity_manager.calls.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clean_slate_can_be_skipped() -> anyhow::Result<()> {
+        let identity_manager = CleanSlateCounter::default();
+
+        settle_pending_transactions(&identity_manager, true).await?;
+
+        assert_eq!(identity_manager.calls.load(Ordering::SeqCst), 0);
+
+        Ok(())
+    }
+
+    fn root_info(root: U256) -> Bytes {
+        encode(&[
+            Token::Uint(root),
+            Token::Uint(U256::zero()),
+            Token::Bool(!root.is_zero()),
+        ])
+        .into()
+    }
+
+    #[tokio::test]
+    async fn queries_mined_status_of_every_root() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let roots = [U256::from(1), U256::from(2), U256::from(3)];
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(root_info(U256::from(3)))?;
+        mock.push(root_info(U256::zero()))?;
+        mock.push(root_info(U256::from(1)))?;
+
+        let statuses = query_roots_mined(&abi, None, &roots).await?;
+
+        assert_eq!(statuses, vec![true, false, true]);
+
+        Ok(())
+    }
+
+    /// Encodes the result of an `aggregate3` call to Multicall3 querying each
+    /// of the roots, as returned by [`root_info`].
+    fn multicall_response(roots: impl IntoIterator<Item = U256>) -> Bytes {
+        let results = roots
+            .into_iter()
+            .map(|root| {
+                Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::Bytes(root_info(root).to_vec()),
+                ])
+            })
+            .collect();
+
+        encode(&[Token::Array(results)]).into()
+    }
+
+    #[tokio::test]
+    async fn queries_mined_status_through_multicall() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+        let multicall = Some(Address::repeat_byte(0xca));
+
+        let roots = [U256::from(1), U256::from(2), U256::from(3)];
+
+        // A single call returns the status of every root
+        mock.push(multicall_response([
+            U256::from(1),
+            U256::zero(),
+            U256::from(3),
+        ]))?;
+
+        let statuses = query_roots_mined(&abi, multicall, &roots).await?;
+
+        assert_eq!(statuses, vec![true, false, true]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multicalls_are_bounded_in_size() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+        let multicall = Some(Address::repeat_byte(0xca));
+
+        let roots: Vec<U256> = (1..=MAX_ROOTS_PER_QUERY as u64 + 1)
+            .map(U256::from)
+            .collect();
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(multicall_response([U256::zero()]))?;
+        mock.push(multicall_response(
+            roots[..MAX_ROOTS_PER_QUERY].iter().copied(),
+        ))?;
+
+        let statuses = query_roots_mined(&abi, multicall, &roots).await?;
+
+        assert_eq!(statuses.len(), roots.len());
+        assert!(statuses[..MAX_ROOTS_PER_QUERY].iter().all(|mined| *mined));
+        assert!(!statuses[MAX_ROOTS_PER_QUERY]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn no_roots_require_no_queries() -> anyhow::Result<()> {
+        let (provider, _mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let statuses = query_roots_mined(&abi, None, &[]).await?;
+
+        assert!(statuses.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_verifier_address() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let verifier = Address::repeat_byte(0xab);
+        mock.push(address_response(verifier))?;
+
+        assert_eq!(query_verifier_address(&abi).await?, verifier);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn matching_contract_codehash_is_accepted() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let code = Bytes::from(vec![0x60, 0x80, 0x60, 0x40]);
+        mock.push(code.clone())?;
+
+        let expected_codehash = H256::from(keccak256(&code));
+        verify_contract_code(&provider, Address::zero(), Some(expected_codehash)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mismatching_contract_codehash_is_rejected() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let code = Bytes::from(vec![0x60, 0x80, 0x60, 0x40]);
+        mock.push(code.clone())?;
+
+        let address = Address::repeat_byte(0x11);
+        let expected_codehash = H256::repeat_byte(0xaa);
+        let error = verify_contract_code(&provider, address, Some(expected_codehash))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<CodehashMismatch>(),
+            Some(&CodehashMismatch {
+                address,
+                expected_codehash,
+                codehash: H256::from(keccak256(&code)),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_signer_balance() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        let signer = Address::repeat_byte(0x42);
+        let balance = U256::exp10(18) * 3 / 2;
+        mock.push(balance)?;
+
+        let status = query_signer_status(&provider, signer).await?;
+
+        assert_eq!(status, SignerStatus {
+            address: signer,
+            balance,
+        });
+        assert_eq!(SIGNER_BALANCE.get(), 1.5);
+
+        Ok(())
+    }
+
+    fn root_response(root: U256) -> Bytes {
+        encode(&[Token::Uint(root)]).into()
+    }
+
+    fn tree_with_identities(dir: &tempfile::TempDir, identities: &[Field]) -> TreeVersion<Latest> {
+        let (_, tree) = empty_trees(10, dir);
+        let _ = tree.append_many(identities);
+
+        tree
+    }
+
+    #[test]
+    fn merkle_proofs_are_derived_for_commitments_in_the_tree() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let tree = tree_with_identities(&dir, &[Field::from(1), Field::from(2), Field::from(3)]);
+
+        let identities = derive_identities(&tree, &[U256::from(3), U256::from(1)])?;
+
+        assert_eq!(identities.len(), 2);
+        for (identity, leaf_index) in identities.iter().zip([2, 0]) {
+            let (_, proof) = tree.get_proof(leaf_index);
+            let root = to_poseidon_proof(&identity.merkle_proof, leaf_index)
+                .root(u256_to_field(identity.commitment));
+
+            assert_eq!(identity.merkle_proof.len(), proof.0.len());
+            assert_eq!(root, tree.get_root());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn commitments_missing_from_the_tree_are_rejected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let tree = tree_with_identities(&dir, &[Field::from(1), Field::from(2)]);
+
+        let error = derive_identities(&tree, &[U256::from(1), U256::from(7)]).unwrap_err();
+
+        assert!(error.to_string().contains("0x7 is not in the tree"));
+
+        Ok(())
+    }
+
+    fn to_poseidon_proof(siblings: &[U256], leaf_index: usize) -> semaphore::poseidon_tree::Proof {
+        semaphore::poseidon_tree::Proof(
+            siblings
+                .iter()
+                .enumerate()
+                .map(|(depth, sibling)| {
+                    if (leaf_index >> depth) & 1 == 0 {
+                        Branch::Left(u256_to_field(*sibling))
+                    } else {
+                        Branch::Right(u256_to_field(*sibling))
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn root_of_matching_tree_version_is_accepted() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let dir = tempfile::tempdir()?;
+        let tree = tree_with_identities(&dir, &[Field::from(1), Field::from(2)]);
+        mock.push(root_response(tree.get_root().into()))?;
+
+        assert_latest_root(&abi, tree.get_root().into()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn root_of_other_tree_version_is_reported() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let dir = tempfile::tempdir()?;
+        let tree = tree_with_identities(&dir, &[Field::from(1), Field::from(2)]);
+        let chain_root = U256::from(42);
+        mock.push(root_response(chain_root))?;
+
+        let error = assert_latest_root(&abi, tree.get_root().into())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<RootMismatch>(),
+            Some(&RootMismatch {
+                expected_root: tree.get_root().into(),
+                chain_root,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn consistent_leaf_is_not_reprocessed() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let pre_root = U256::from(1);
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(root_info(pre_root))?;
+        mock.push(root_response(U256::from(2)))?;
+
+        assert!(!is_leaf_missing(&abi, pre_root).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leaf_after_the_latest_root_is_reprocessed() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let dir = tempfile::tempdir()?;
+        let tree = tree_with_identities(&dir, &[Field::from(1), Field::from(2), Field::from(3)]);
+        let (commitment, merkle_proof) = tree.get_proof(1);
+        let siblings: Vec<U256> = merkle_proof_siblings(&merkle_proof);
+
+        let identities =
+            rebuild_insertion_batch(1, &siblings, field_to_u256(commitment), 2, Field::ZERO);
+        let (pre_root, post_root) = compute_insertion_roots(1, &identities, Field::ZERO)?;
+
+        let expected = LazyPoseidonTree::new(10, Field::ZERO).update(0, &Field::from(1));
+        assert_eq!(pre_root, field_to_u256(expected.root()));
+        let expected = expected.update(1, &Field::from(2));
+        assert_eq!(post_root, field_to_u256(expected.root()));
+
+        mock.push(root_response(pre_root))?;
+
+        assert!(is_leaf_missing(&abi, pre_root).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leaf_beyond_the_latest_root_is_rejected() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(root_info(U256::zero()))?;
+        mock.push(root_response(U256::from(2)))?;
+
+        let error = is_leaf_missing(&abi, U256::from(1)).await.unwrap_err();
+
+        assert!(error.to_string().contains("earlier leaves are missing"));
+
+        Ok(())
+    }
+
+    fn address_response(address: Address) -> Bytes {
+        encode(&[Token::Address(address)]).into()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn operator_check_is_retried_until_it_matches() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let signer = Address::repeat_byte(0x01);
+        let previous_owner = Address::repeat_byte(0x02);
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(address_response(signer))?;
+        mock.push(address_response(previous_owner))?;
+        mock.push(address_response(previous_owner))?;
+
+        let backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60), 2.0);
+        let start = tokio::time::Instant::now();
+        let operator = wait_for_identity_operator(&abi, signer, 5, &backoff).await?;
+
+        assert_eq!(operator, signer);
+
+        // Two retries, jittered within the upper half of 10s and 20s
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(15));
+        assert!(elapsed <= Duration::from_secs(30));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_transactions_are_awaited_concurrently() {
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+
+        let transactions: Vec<TransactionId> =
+            (0..20).map(|i| TransactionId(i.to_string())).collect();
+
+        let start = tokio::time::Instant::now();
+        let results = await_transactions(transactions, 4, |tx| {
+            let in_flight = &in_flight;
+            let max_in_flight = &max_in_flight;
+
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                // Every third transaction fails
+                let index: usize = tx.0.parse()?;
+                if index % 3 == 0 {
+                    anyhow::bail!("Transaction {index} failed");
+                }
+
+                Ok::<_, anyhow::Error>(index % 2 == 0)
+            }
+        })
+        .await;
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 4);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        assert_eq!(results.len(), 20);
+        for (index, result) in results.iter().enumerate() {
+            match result {
+                Ok(mined) => assert_eq!(*mined, index % 2 == 0),
+                Err(_) => assert_eq!(index % 3, 0),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_slate_report_categorizes_transactions() {
+        let transactions: Vec<TransactionId> = ["mined", "reverted", "unreachable", "also-mined"]
+            .into_iter()
+            .map(|id| TransactionId(id.to_string()))
+            .collect();
+
+        let results = await_transactions(transactions.clone(), 2, |tx| async move {
+            match tx.0.as_str() {
+                "reverted" => Ok(false),
+                "unreachable" => anyhow::bail!("Relayer unreachable"),
+                _ => Ok(true),
+            }
+        })
+        .await;
+
+        let report = summarize_clean_slate(transactions, results);
+
+        let mined: Vec<&str> = report.mined.iter().map(|tx| tx.0.as_str()).collect();
+        let failed: Vec<(&str, &str)> = report
+            .failed
+            .iter()
+            .map(|(tx, reason)| (tx.0.as_str(), reason.as_str()))
+            .collect();
+
+        assert_eq!(mined, vec!["mined", "also-mined"]);
+        assert_eq!(failed, vec![
+            ("reverted", "Transaction failed"),
+            ("unreachable", "Relayer unreachable"),
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn operator_check_gives_up_after_retries() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let signer = Address::repeat_byte(0x01);
+        let other = Address::repeat_byte(0x02);
+
+        // A third response that is never requested
+        mock.push(address_response(signer))?;
+        mock.push(address_response(other))?;
+        mock.push(address_response(other))?;
+
+        let backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60), 2.0);
+        let operator = wait_for_identity_operator(&abi, signer, 1, &backoff).await?;
+
+        assert_eq!(operator, other);
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_identities_is_built_for_every_contract() {
+        let (provider, _mock) = Provider::mocked();
+        let provider = Arc::new(provider);
+
+        let primary = WorldId::new(Address::repeat_byte(0x01), provider.clone());
+        let mirrored = WorldId::new(Address::repeat_byte(0x02), provider);
+
+        let transactions = build_delete_identities_transactions(
+            [&primary, &mirrored],
+            [U256::from(7); 8],
+            &[0, 0, 0, 4],
+            U256::from(3),
+            U256::from(5),
+        );
+
+        let targets: Vec<_> = transactions.iter().map(TypedTransaction::to_addr).collect();
+        assert_eq!(targets, vec![
+            Some(&Address::repeat_byte(0x01)),
+            Some(&Address::repeat_byte(0x02)),
+        ]);
+
+        let expected_data = primary
+            .delete_identities(
+                [U256::from(7); 8],
+                vec![0, 0, 0, 4].into(),
+                U256::from(3),
+                U256::from(5),
+            )
+            .calldata();
+        assert!(transactions
+            .iter()
+            .all(|tx| tx.data() == expected_data.as_ref()));
+    }
+
+    #[test]
+    fn register_identities_is_built_for_every_contract() {
+        let (provider, _mock) = Provider::mocked();
+        let provider = Arc::new(provider);
+
+        let primary = WorldId::new(Address::repeat_byte(0x01), provider.clone());
+        let mirrored = WorldId::new(Address::repeat_byte(0x02), provider);
+
+        let identities = [U256::from(1), U256::from(2)];
+        let transactions = build_register_identities_transactions(
+            [&primary, &mirrored],
+            [U256::from(7); 8],
+            U256::from(3),
+            4,
+            &identities,
+            U256::from(5),
+        );
+
+        let targets: Vec<_> = transactions.iter().map(TypedTransaction::to_addr).collect();
+        assert_eq!(targets, vec![
+            Some(&Address::repeat_byte(0x01)),
+            Some(&Address::repeat_byte(0x02)),
+        ]);
+
+        let expected_data = primary
+            .register_identities(
+                [U256::from(7); 8],
+                U256::from(3),
+                4,
+                identities.to_vec(),
+                U256::from(5),
+            )
+            .calldata();
+        assert!(transactions
+            .iter()
+            .all(|tx| tx.data() == expected_data.as_ref()));
+    }
+
+    #[test]
+    fn only_groth16_proofs_are_submitted() {
+        let points: [U256; 8] = std::array::from_fn(|i| U256::from(i + 1));
+        assert_eq!(groth16_points(ProofKind::Groth16(points)).unwrap(), points);
+
+        let error = groth16_points(ProofKind::Plonk(vec![U256::one(); 24])).unwrap_err();
+        assert!(matches!(error, SubmitError::UnsupportedProof { words: 24 }));
+    }
+
+    #[test]
+    fn batch_matching_its_proof_input_is_accepted() {
+        let settings = ProverSettings::default();
+        let identities = [U256::from(1), U256::from(2)];
+        let input_hash = compute_insertion_proof_input_hash(
+            4,
+            U256::from(3),
+            U256::from(5),
+            &identities,
+            settings.input_hash_function,
+            settings.input_hash_endianness,
+            None,
+        );
+
+        ensure_input_hash_matches(
+            &settings,
+            4,
+            U256::from(3),
+            U256::from(5),
+            &identities,
+            input_hash,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn post_root_changed_after_proving_is_rejected() {
+        let settings = ProverSettings::default();
+        let identities = [U256::from(1), U256::from(2)];
+        let input_hash = compute_insertion_proof_input_hash(
+            4,
+            U256::from(3),
+            U256::from(5),
+            &identities,
+            settings.input_hash_function,
+            settings.input_hash_endianness,
+            None,
+        );
+
+        let error = ensure_input_hash_matches(
+            &settings,
+            4,
+            U256::from(3),
+            U256::from(6),
+            &identities,
+            input_hash,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            error,
+            SubmitError::InputHashMismatch { expected, actual }
+                if expected == input_hash && actual != input_hash
+        ));
+    }
+
+    #[test]
+    fn register_calldata_decodes_to_its_arguments() -> anyhow::Result<()> {
+        let (provider, _mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let proof_points: [U256; 8] = std::array::from_fn(|i| U256::from(i + 1));
+        let identities = [U256::from(10), U256::from(11), U256::from(12)];
+
+        let calldata = encode_register_identities_calldata(
+            &abi,
+            proof_points,
+            U256::from(3),
+            7,
+            &identities,
+            U256::from(5),
+        )?;
+
+        let call = RegisterIdentitiesCall::decode(&calldata)?;
+
+        assert_eq!(call.insertion_proof, proof_points);
+        assert_eq!(call.pre_root, U256::from(3));
+        assert_eq!(call.start_index, 7);
+        assert_eq!(call.identity_commitments, identities);
+        assert_eq!(call.post_root, U256::from(5));
+
+        Ok(())
+    }
+
+    /// Inserts `commitments` into an empty tree of depth `tree_depth`, taking
+    /// the merkle proof of every leaf right after it is inserted.
+    fn insertion_batch(tree_depth: usize, commitments: &[u64]) -> Vec<Identity> {
+        let mut tree = LazyPoseidonTree::new(tree_depth, Field::ZERO).derived();
+
+        commitments
+            .iter()
+            .enumerate()
+            .map(|(leaf_index, commitment)| {
+                tree = tree.update(leaf_index, &Field::from(*commitment));
+
+                let merkle_proof = merkle_proof_siblings(&tree.proof(leaf_index));
+
+                Identity::new(U256::from(*commitment), merkle_proof)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn simulation_reports_roots_gas_and_proof_time() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+
+        let prover = Prover::with_backend(
+            &mock_prover_config("http://localhost"),
+            &ProverSettings::default(),
+            Arc::new(MockProver::default()),
+        )?;
+
+        // The batch of the default proof fixture, whose odd post root the mock
+        // prover accepts
+        let identities = insertion_batch(10, &[1, 2, 3]);
+        let gas = U256::from(1_500_000);
+        mock.push(gas)?;
+
+        let simulation =
+            simulate_insertion_batch(&abi, Address::zero(), &prover, 0, &identities, Field::ZERO)
+                .await?;
+
+        let (pre_root, post_root) = compute_insertion_roots(0, &identities, Field::ZERO)?;
+        assert_eq!(simulation.pre_root, pre_root);
+        assert_eq!(simulation.post_root, post_root);
+        assert_eq!(simulation.gas, gas);
+        assert!(simulation.proof_time > Duration::ZERO);
+
+        Ok(())
+    }
+
+    fn sample_prover() -> anyhow::Result<Prover> {
+        Prover::with_backend(
+            &mock_prover_config("http://localhost"),
+            &ProverSettings::default(),
+            Arc::new(MockProver::default()),
+        )
+    }
+
+    #[tokio::test]
+    async fn prover_accepted_by_the_verifier_is_compatible() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+        let prover = sample_prover()?;
+
+        // Responses are popped from the back
+        mock.push(Bytes::default())?;
+        mock.push(address_response(Address::repeat_byte(0xcd)))?;
+        mock.push(address_response(Address::repeat_byte(0xab)))?;
+
+        // The sample batch of depth 10 is the batch of the default proof
+        // fixture, whose odd post root the mock prover accepts
+        check_verifier_compatibility(&abi, &prover, 10, Field::ZERO, &ProverSettings::default())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_rejected_by_the_verifier_is_incompatible() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+        let abi = WorldId::new(Address::zero(), Arc::new(provider));
+        let prover = sample_prover()?;
+
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    3,
+            message: "execution reverted: ProofInvalid()".to_string(),
+            data:    None,
+        }));
+        mock.push(address_response(Address::repeat_byte(0xcd)))?;
+        mock.push(address_response(Address::repeat_byte(0xab)))?;
+
+        let error = check_verifier_compatibility(
+            &abi,
+            &prover,
+            10,
+            Field::ZERO,
+            &ProverSettings::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.to_string().contains("rejected the proof"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_batch_matches_the_proof_fixture_batch() {
+        assert_eq!(
+            sample_insertion_batch(10, 3, Field::ZERO),
+            insertion_batch(10, &[1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn reports_every_proof_length_mismatch() {
+        let identity = |proof_len: usize| Identity::new(U256::one(), vec![U256::zero(); proof_len]);
+        let identities = [
+            identity(4),
+            identity(3),
+            identity(4),
+            identity(5),
+            identity(0),
+        ];
+
+        let issues = find_proof_length_issues(&identities, 4);
+
+        assert_eq!(issues, vec![
+            ProofLengthIssue {
+                index:    1,
+                length:   3,
+                expected: 4,
+            },
+            ProofLengthIssue {
+                index:    3,
+                length:   5,
+                expected: 4,
+            },
+            ProofLengthIssue {
+                index:    4,
+                length:   0,
+                expected: 4,
+            },
+        ]);
+        assert_eq!(
+            issues[0].to_string(),
+            "Identity at position 1 has a merkle proof of length 3, expected 4"
+        );
+    }
+
+    #[test]
+    fn consistent_batch_has_no_proof_length_issues() {
+        let identities = vec![Identity::new(U256::one(), vec![U256::zero(); 4]); 3];
+
+        assert!(find_proof_length_issues(&identities, 4).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_batch_reports_its_leaf_indices() -> anyhow::Result<()> {
+        let rpc = RpcMock::rejecting_transactions("nonce too low").await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 4).await?;
+
+        let identities = vec![Identity::new(U256::one(), vec![U256::zero(); 4]); 3];
+        let error = identity_manager
+            .register_identities(
+                10,
+                U256::from(1),
+                U256::from(2),
+                identities,
+                ProofKind::Groth16([U256::one(); 8]),
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(rpc.called("eth_sendRawTransaction"));
+        assert_eq!(error.leaf_indices, 10..13);
+        assert!(matches!(error.error, SubmitError::NonceTooLow(_)));
+
+        let message = error.to_string();
+        assert!(
+            message.contains("10..13"),
+            "Missing index range in: {message}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn next_batch_roots_match_applying_the_batch() -> anyhow::Result<()> {
+        let rpc = RpcMock::spawn().await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 10).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Field::ZERO,
+            &[Field::from(1), Field::from(2)],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let (_, batching_builder) = processed_builder.seal_and_continue();
+        let (batching_tree, latest_builder) = batching_builder.seal_and_continue();
+        let latest_tree = latest_builder.seal();
+
+        let identities: Vec<Identity> = [3, 4, 0]
+            .into_iter()
+            .map(|commitment| Identity::new(U256::from(commitment), vec![]))
+            .collect();
+
+        assert!(identity_manager.next_batch_roots(&identities).is_err());
+
+        identity_manager.set_batching_tree(batching_tree.clone());
+        let batching_root = batching_tree.get_root();
+
+        let (pre_root, post_root) = identity_manager.next_batch_roots(&identities)?;
+
+        assert_eq!(pre_root, field_to_u256(batching_root));
+        assert_eq!(batching_tree.get_root(), batching_root);
+
+        let _ = latest_tree.append_many(&[Field::from(3), Field::from(4)]);
+        assert_eq!(post_root, field_to_u256(latest_tree.get_root()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn next_batch_roots_reject_a_batch_overflowing_the_tree() -> anyhow::Result<()> {
+        let rpc = RpcMock::spawn().await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 2).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            2,
+            2,
+            0,
+            Field::ZERO,
+            &[Field::from(1), Field::from(2)],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let (_, batching_builder) = processed_builder.seal_and_continue();
+        let (batching_tree, _) = batching_builder.seal_and_continue();
+        identity_manager.set_batching_tree(batching_tree);
+
+        let identities = vec![Identity::new(U256::from(3), vec![]); 3];
+
+        assert!(identity_manager.next_batch_roots(&identities).is_err());
+
+        Ok(())
+    }
+}
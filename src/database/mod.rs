@@ -8,20 +8,26 @@ use std::collections::HashSet;
 
 use anyhow::{anyhow, Context, Error as ErrReport};
 use chrono::{DateTime, Utc};
+use ethers::types::U256;
 use sqlx::migrate::{Migrate, MigrateDatabase, Migrator};
 use sqlx::pool::PoolOptions;
 use sqlx::{Executor, Pool, Postgres, Row};
 use thiserror::Error;
 use tracing::{error, info, instrument, warn};
 
-use self::types::{CommitmentHistoryEntry, DeletionEntry, LatestDeletionEntry, RecoveryEntry};
+use self::types::{
+    BatchAudit, ChainProgress, CommitmentHistoryEntry, DeletionEntry, LatestDeletionEntry,
+    PendingProof, QueueState, RecoveryEntry, RootFinalizationStatus,
+};
 use crate::config::DatabaseConfig;
 use crate::identity_tree::{
     Hash, ProcessedStatus, RootItem, TreeItem, TreeUpdate, UnprocessedStatus,
 };
 
 pub mod types;
-use crate::prover::{ProverConfig, ProverType};
+use crate::prover::queue::TreeOperation;
+use crate::prover::{Proof, ProverConfig, ProverType};
+use crate::utils::field_util::u256_to_be_bytes;
 
 // Statically link in migration files
 static MIGRATOR: Migrator = sqlx::migrate!("schemas/database");
@@ -198,7 +204,7 @@ impl Database {
         let update_next_roots = sqlx::query(
             r#"
             UPDATE identities
-            SET    status = $2, mined_at = NULL
+            SET    status = $2, mined_at = NULL, finalized_at = NULL
             WHERE  id > $1
             "#,
         )
@@ -213,6 +219,33 @@ impl Database {
         Ok(())
     }
 
+    /// Removes the pending identities recorded after `root`, e.g. when the
+    /// updates that produced them are rolled back. Every pending identity is
+    /// removed if `root` isn't in the database, i.e. it is the root of the
+    /// empty tree.
+    #[instrument(skip(self), level = "debug")]
+    pub async fn remove_pending_identities_after(&self, root: &Hash) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let root_id = Self::get_id_by_root(&mut tx, root).await?.unwrap_or(0) as i64;
+
+        let remove_next_roots = sqlx::query(
+            r#"
+            DELETE FROM identities
+            WHERE  id > $1
+            AND    status = $2
+            "#,
+        )
+        .bind(root_id)
+        .bind(<&str>::from(ProcessedStatus::Pending));
+
+        tx.execute(remove_next_roots).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     /// Marks all the identities in the db as
     #[instrument(skip(self), level = "debug")]
     pub async fn mark_all_as_pending(&self) -> Result<(), Error> {
@@ -221,7 +254,7 @@ impl Database {
         let update_all_identities = sqlx::query(
             r#"
             UPDATE identities
-            SET    status = $1, mined_at = NULL
+            SET    status = $1, mined_at = NULL, finalized_at = NULL
             WHERE  status <> $1
             "#,
         )
@@ -234,8 +267,11 @@ impl Database {
 
     /// Marks the identities and roots from before a given root hash as
     /// finalized
+    ///
+    /// This is idempotent. Returns `true` if any identity transitioned to the
+    /// mined status and `false` if the root had already been marked as mined.
     #[instrument(skip(self), level = "debug")]
-    pub async fn mark_root_as_mined(&self, root: &Hash) -> Result<(), Error> {
+    pub async fn mark_root_as_mined(&self, root: &Hash) -> Result<bool, Error> {
         let mined_status = ProcessedStatus::Mined;
 
         let mut tx = self.pool.begin().await?;
@@ -251,7 +287,7 @@ impl Database {
         let update_previous_roots = sqlx::query(
             r#"
             UPDATE identities
-            SET    status = $2
+            SET    status = $2, finalized_at = CURRENT_TIMESTAMP
             WHERE  id <= $1
             AND    status <> $2
             "#,
@@ -259,11 +295,11 @@ impl Database {
         .bind(root_id)
         .bind(<&str>::from(mined_status));
 
-        tx.execute(update_previous_roots).await?;
+        let updated = tx.execute(update_previous_roots).await?.rows_affected();
 
         tx.commit().await?;
 
-        Ok(())
+        Ok(updated > 0)
     }
 
     pub async fn get_next_leaf_index(&self) -> Result<usize, Error> {
@@ -312,6 +348,51 @@ impl Database {
         Ok(Some(TreeItem { status, leaf_index }))
     }
 
+    /// Returns, for each of the given commitments that was inserted below
+    /// `before_leaf_index`, the lowest leaf index at which it was inserted.
+    pub async fn get_earlier_leaf_indices(
+        &self,
+        commitments: &[Hash],
+        before_leaf_index: usize,
+    ) -> Result<Vec<(Hash, usize)>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT commitment, MIN(leaf_index)
+            FROM identities
+            WHERE commitment = ANY($1)
+            AND leaf_index < $2
+            GROUP BY commitment
+            "#,
+        )
+        .bind(encode_commitments(commitments))
+        .bind(before_leaf_index as i64);
+
+        let rows = self.pool.fetch_all(query).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get::<Hash, _>(0), row.get::<i64, _>(1) as usize))
+            .collect())
+    }
+
+    /// Returns the root among `roots` that was added to the tree last.
+    pub async fn get_newest_root(&self, roots: &[Hash]) -> Result<Option<Hash>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT root
+            FROM identities
+            WHERE root = ANY($1)
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(encode_commitments(roots));
+
+        let row = self.pool.fetch_optional(query).await?;
+
+        Ok(row.map(|row| row.get::<Hash, _>(0)))
+    }
+
     pub async fn get_commitments_by_status(
         &self,
         status: ProcessedStatus,
@@ -514,6 +595,287 @@ impl Database {
         Ok(result.get::<i64, _>(0) as i32)
     }
 
+    /// Returns the number of identities and deletions waiting to be batched,
+    /// grouped by their status
+    pub async fn get_queue_state(&self) -> Result<QueueState, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT
+                (
+                    SELECT COUNT(*) FROM unprocessed_identities
+                    WHERE status = $1 AND CURRENT_TIMESTAMP > eligibility
+                ) AS buffered,
+                (
+                    SELECT COUNT(*) FROM unprocessed_identities
+                    WHERE status = $1 AND (eligibility IS NULL OR CURRENT_TIMESTAMP <= eligibility)
+                ) AS queued,
+                (
+                    SELECT COUNT(*) FROM identities
+                    WHERE status = $2
+                ) AS pending,
+                (
+                    SELECT COUNT(*) FROM deletions
+                ) AS deletions
+            "#,
+        )
+        .bind(<&str>::from(UnprocessedStatus::New))
+        .bind(<&str>::from(ProcessedStatus::Pending));
+
+        let row = self.pool.fetch_one(query).await?;
+
+        Ok(QueueState {
+            buffered:  row.get::<i64, _>(0),
+            queued:    row.get::<i64, _>(1),
+            pending:   row.get::<i64, _>(2),
+            deletions: row.get::<i64, _>(3),
+        })
+    }
+
+    /// Records that the root has been seen on the given chains. Chains the
+    /// root was already recorded on keep their original timestamp.
+    pub async fn record_root_on_chains(&self, root: &Hash, chain_ids: &[u64]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for chain_id in chain_ids {
+            let query = sqlx::query(
+                r#"
+                INSERT INTO root_finalizations (root, chain_id)
+                VALUES ($1, $2)
+                ON CONFLICT (root, chain_id) DO NOTHING
+                "#,
+            )
+            .bind(root)
+            .bind(*chain_id as i64);
+
+            tx.execute(query).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Returns the roots that are mined on mainnet but not yet finalized on
+    /// every chain, together with the chains each root has reached so far.
+    ///
+    /// Only roots recorded with [`Self::record_root_on_chains`] are reported.
+    /// Roots are ordered by when they were first seen.
+    pub async fn pending_finalization_roots(&self) -> Result<Vec<RootFinalizationStatus>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT f.root, f.chain_id, f.seen_at
+            FROM   root_finalizations f
+            WHERE  EXISTS (
+                SELECT 1 FROM identities i
+                WHERE  i.root = f.root
+                AND    i.status = $1
+            )
+            ORDER BY f.seen_at, f.chain_id
+            "#,
+        )
+        .bind(<&str>::from(ProcessedStatus::Processed));
+
+        let rows = self.pool.fetch_all(query).await?;
+
+        let mut statuses: Vec<RootFinalizationStatus> = vec![];
+        for row in rows {
+            let root = row.get::<Hash, _>(0);
+            let progress = ChainProgress {
+                chain_id: row.get::<i64, _>(1) as u64,
+                seen_at:  row.get::<DateTime<Utc>, _>(2),
+            };
+
+            match statuses.iter_mut().find(|status| status.root == root) {
+                Some(status) => status.chains.push(progress),
+                None => statuses.push(RootFinalizationStatus {
+                    root,
+                    chains: vec![progress],
+                }),
+            }
+        }
+
+        Ok(statuses)
+    }
+
+    /// Returns up to `limit` of the most recently finalized roots, newest
+    /// first, together with when they were finalized.
+    ///
+    /// Roots finalized along with a later root share its timestamp.
+    pub async fn recent_finalized_roots(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<(Hash, DateTime<Utc>)>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT root, finalized_at
+            FROM   identities
+            WHERE  status = $1
+            AND    finalized_at IS NOT NULL
+            ORDER BY finalized_at DESC, id DESC
+            LIMIT  $2
+            "#,
+        )
+        .bind(<&str>::from(ProcessedStatus::Mined))
+        .bind(limit as i64);
+
+        let rows = self.pool.fetch_all(query).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get::<Hash, _>(0), row.get::<DateTime<Utc>, _>(1)))
+            .collect())
+    }
+
+    /// Records the proof and inputs an insertion batch was submitted with. A
+    /// batch submitted again for the same post root replaces the earlier
+    /// record.
+    pub async fn insert_batch_audit(
+        &self,
+        start_index: usize,
+        pre_root: &Hash,
+        post_root: &Hash,
+        commitments: &[Hash],
+        proof: &Proof,
+        transaction_ids: &[String],
+    ) -> Result<(), Error> {
+        let commitments = encode_commitments(commitments);
+        let proof_points = encode_proof(proof);
+
+        let query = sqlx::query(
+            r#"
+            INSERT INTO batch_audits
+                (post_root, pre_root, start_index, commitments, proof, transaction_ids)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (post_root) DO UPDATE
+            SET    pre_root = EXCLUDED.pre_root,
+                   start_index = EXCLUDED.start_index,
+                   commitments = EXCLUDED.commitments,
+                   proof = EXCLUDED.proof,
+                   transaction_ids = EXCLUDED.transaction_ids,
+                   submitted_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(post_root)
+        .bind(pre_root)
+        .bind(start_index as i64)
+        .bind(commitments)
+        .bind(proof_points)
+        .bind(transaction_ids);
+
+        self.pool.execute(query).await?;
+
+        Ok(())
+    }
+
+    /// Returns the proof and inputs the insertion batch with the given post
+    /// root was submitted with, if it was recorded.
+    pub async fn get_batch_audit(&self, post_root: &Hash) -> Result<Option<BatchAudit>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT start_index, pre_root, commitments, proof, transaction_ids, submitted_at
+            FROM   batch_audits
+            WHERE  post_root = $1
+            "#,
+        )
+        .bind(post_root);
+
+        let Some(row) = self.pool.fetch_optional(query).await? else {
+            return Ok(None);
+        };
+
+        let malformed = || Error::MalformedBatchAudit { root: *post_root };
+
+        let commitments =
+            decode_commitments(&row.get::<Vec<Vec<u8>>, _>(2)).ok_or_else(malformed)?;
+        let proof = decode_proof(&row.get::<Vec<Vec<u8>>, _>(3)).ok_or_else(malformed)?;
+
+        Ok(Some(BatchAudit {
+            start_index: row.get::<i64, _>(0) as usize,
+            pre_root: row.get::<Hash, _>(1),
+            post_root: *post_root,
+            commitments,
+            proof,
+            transaction_ids: row.get::<Vec<String>, _>(4),
+            submitted_at: row.get::<DateTime<Utc>, _>(5),
+        }))
+    }
+
+    /// Persists the proof of an insertion batch until it is submitted. A batch
+    /// proved again for the same input hash replaces the earlier proof.
+    pub async fn insert_pending_proof(&self, pending_proof: &PendingProof) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO pending_proofs
+                (input_hash, start_index, pre_root, post_root, commitments, proof)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (input_hash) DO UPDATE
+            SET    start_index = EXCLUDED.start_index,
+                   pre_root = EXCLUDED.pre_root,
+                   post_root = EXCLUDED.post_root,
+                   commitments = EXCLUDED.commitments,
+                   proof = EXCLUDED.proof,
+                   created_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(u256_to_be_bytes(pending_proof.input_hash).to_vec())
+        .bind(pending_proof.start_index as i64)
+        .bind(pending_proof.pre_root)
+        .bind(pending_proof.post_root)
+        .bind(encode_commitments(&pending_proof.commitments))
+        .bind(encode_proof(&pending_proof.proof));
+
+        self.pool.execute(query).await?;
+
+        Ok(())
+    }
+
+    /// Returns the proved but unsubmitted insertion batches, in the order of
+    /// their start index.
+    pub async fn get_pending_proofs(&self) -> Result<Vec<PendingProof>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT input_hash, start_index, pre_root, post_root, commitments, proof
+            FROM   pending_proofs
+            ORDER BY start_index ASC, created_at ASC
+            "#,
+        );
+
+        let rows = self.pool.fetch_all(query).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let input_hash = U256::from_big_endian(&row.get::<Vec<u8>, _>(0));
+                let malformed = || Error::MalformedPendingProof { input_hash };
+
+                Ok(PendingProof {
+                    input_hash,
+                    start_index: row.get::<i64, _>(1) as usize,
+                    pre_root: row.get::<Hash, _>(2),
+                    post_root: row.get::<Hash, _>(3),
+                    commitments: decode_commitments(&row.get::<Vec<Vec<u8>>, _>(4))
+                        .ok_or_else(malformed)?,
+                    proof: decode_proof(&row.get::<Vec<Vec<u8>>, _>(5)).ok_or_else(malformed)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Forgets the proof of an insertion batch, once it is submitted or no
+    /// longer applies.
+    pub async fn remove_pending_proof(&self, input_hash: &U256) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+            DELETE FROM pending_proofs
+            WHERE input_hash = $1
+            "#,
+        )
+        .bind(u256_to_be_bytes(*input_hash).to_vec());
+
+        self.pool.execute(query).await?;
+
+        Ok(())
+    }
+
     pub async fn get_provers(&self) -> Result<HashSet<ProverConfig>, Error> {
         let query = sqlx::query(
             r#"
@@ -591,6 +953,26 @@ impl Database {
         Ok(())
     }
 
+    pub async fn update_prover_timeout(
+        &self,
+        batch_size: usize,
+        prover_type: ProverType,
+        timeout_seconds: u64,
+    ) -> Result<(), Error> {
+        let query = sqlx::query(
+            r#"
+              UPDATE provers SET timeout_s = $3 WHERE batch_size = $1 AND prover_type = $2
+            "#,
+        )
+        .bind(batch_size as i64)
+        .bind(prover_type)
+        .bind(timeout_seconds as i64);
+
+        self.pool.execute(query).await?;
+
+        Ok(())
+    }
+
     pub async fn remove_prover(
         &self,
         batch_size: usize,
@@ -628,6 +1010,42 @@ impl Database {
         Ok(identity)
     }
 
+    /// Puts the insertions back into the unprocessed queue, immediately
+    /// eligible, and the deletions back into the deletions table. Operations
+    /// that are already there are left as they are.
+    pub async fn requeue_operations(&self, operations: &[TreeOperation]) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for operation in operations {
+            let query = match operation {
+                TreeOperation::Insertion(commitment) => sqlx::query(
+                    r#"
+                    INSERT INTO unprocessed_identities (commitment, status, created_at, eligibility)
+                    VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+                    ON CONFLICT (commitment) DO NOTHING
+                    "#,
+                )
+                .bind(commitment)
+                .bind(<&str>::from(UnprocessedStatus::New)),
+                TreeOperation::Deletion(deletion) => sqlx::query(
+                    r#"
+                    INSERT INTO deletions (leaf_index, commitment)
+                    VALUES ($1, $2)
+                    ON CONFLICT DO NOTHING
+                    "#,
+                )
+                .bind(deletion.leaf_index as i64)
+                .bind(deletion.commitment),
+            };
+
+            tx.execute(query).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub async fn insert_new_recovery(
         &self,
         existing_commitment: &Hash,
@@ -758,6 +1176,24 @@ impl Database {
             .collect::<Vec<DeletionEntry>>())
     }
 
+    /// Returns the leaf indices queued for deletion, in leaf order.
+    pub async fn pending_deletions(&self) -> Result<Vec<u32>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT leaf_index
+            FROM deletions
+            ORDER BY leaf_index ASC
+            "#,
+        );
+
+        let result = self.pool.fetch_all(query).await?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| row.get::<i64, _>(0) as u32)
+            .collect())
+    }
+
     /// Remove a list of entries from the deletions table
     pub async fn remove_deletions(&self, commitments: Vec<Hash>) -> Result<(), Error> {
         let placeholders: String = commitments
@@ -883,26 +1319,65 @@ pub enum Error {
 
     #[error("Tried to mine missing root {root:?}")]
     MissingRoot { root: Hash },
+
+    #[error("Malformed audit record of the batch with post root {root:?}")]
+    MalformedBatchAudit { root: Hash },
+
+    #[error("Malformed pending proof for input hash {input_hash:#x}")]
+    MalformedPendingProof { input_hash: U256 },
+}
+
+fn encode_commitments(commitments: &[Hash]) -> Vec<Vec<u8>> {
+    commitments
+        .iter()
+        .map(|commitment| commitment.to_be_bytes::<32>().to_vec())
+        .collect()
+}
+
+fn decode_commitments(commitments: &[Vec<u8>]) -> Option<Vec<Hash>> {
+    commitments
+        .iter()
+        .map(|bytes| Hash::try_from_be_slice(bytes))
+        .collect()
+}
+
+fn encode_proof(proof: &Proof) -> Vec<Vec<u8>> {
+    let proof_points: [U256; 8] = proof.clone().into();
+
+    proof_points
+        .iter()
+        .map(|point| u256_to_be_bytes(*point).to_vec())
+        .collect()
+}
+
+fn decode_proof(proof_points: &[Vec<u8>]) -> Option<Proof> {
+    let proof_points: [U256; 8] = proof_points
+        .iter()
+        .map(|bytes| (bytes.len() == 32).then(|| U256::from_big_endian(bytes)))
+        .collect::<Option<Vec<U256>>>()?
+        .try_into()
+        .ok()?;
+
+    Some(Proof::from(proof_points))
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
-    use std::str::FromStr;
     use std::time::Duration;
 
     use anyhow::Context;
     use chrono::{Days, Utc};
     use ethers::types::U256;
-    use postgres_docker_utils::DockerContainerGuard;
     use ruint::Uint;
     use semaphore::Field;
 
+    use super::types::{DeletionEntry, PendingProof, QueueState};
     use super::Database;
-    use crate::config::DatabaseConfig;
     use crate::identity_tree::{Hash, ProcessedStatus, Status, UnprocessedStatus};
-    use crate::prover::{ProverConfig, ProverType};
-    use crate::utils::secret::SecretUrl;
+    use crate::prover::queue::{Priority, ProvingQueue, TreeOperation};
+    use crate::prover::{Proof, ProverConfig, ProverType};
+    use crate::test_utils::setup_db;
 
     macro_rules! assert_same_time {
         ($a:expr, $b:expr, $diff:expr) => {
@@ -924,23 +1399,6 @@ mod test {
         chrono::Duration::milliseconds(x.num_milliseconds().abs())
     }
 
-    // TODO: we should probably consolidate all tests that propagate errors to
-    // TODO: either use anyhow or eyre
-    async fn setup_db() -> anyhow::Result<(Database, DockerContainerGuard)> {
-        let db_container = postgres_docker_utils::setup().await?;
-        let db_socket_addr = db_container.address();
-        let url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
-
-        let db = Database::new(&DatabaseConfig {
-            database:        SecretUrl::from_str(&url)?,
-            migrate:         true,
-            max_connections: 1,
-        })
-        .await?;
-
-        Ok((db, db_container))
-    }
-
     fn mock_roots(n: usize) -> Vec<Field> {
         (1..=n).map(Field::from).collect()
     }
@@ -1157,6 +1615,25 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn pending_deletions_are_listed_in_leaf_order() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        assert!(db.pending_deletions().await?.is_empty());
+
+        db.insert_new_deletion(7, &Hash::from(1)).await?;
+        db.insert_new_deletion(2, &Hash::from(2)).await?;
+        db.insert_new_deletion(5, &Hash::from(3)).await?;
+
+        assert_eq!(db.pending_deletions().await?, vec![2, 5, 7]);
+
+        db.remove_deletions(vec![Hash::from(3)]).await?;
+
+        assert_eq!(db.pending_deletions().await?, vec![2, 7]);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_eligible_unprocessed_commitments() -> anyhow::Result<()> {
         let (db, _db_container) = setup_db().await?;
@@ -1189,6 +1666,235 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn in_flight_operations_are_requeued_on_shutdown() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let queued: Hash = Uint::from(1);
+        let in_flight: Vec<Hash> = vec![Uint::from(2), Uint::from(3)];
+        let deletion = DeletionEntry {
+            leaf_index: 0,
+            commitment: Uint::from(4),
+        };
+
+        db.insert_new_identity(queued, Utc::now()).await?;
+
+        let mut queue = ProvingQueue::new();
+        for commitment in &in_flight {
+            let operation = TreeOperation::Insertion(*commitment);
+            queue.push(operation.priority(), operation);
+        }
+        // Already in the unprocessed queue, so it must not be duplicated
+        queue.push(Priority::Normal, TreeOperation::Insertion(queued));
+        queue.push(Priority::High, TreeOperation::Deletion(deletion.clone()));
+
+        // Simulate a shutdown
+        db.requeue_operations(&queue.drain()).await?;
+        assert!(queue.is_empty());
+
+        let mut unprocessed: Vec<Hash> = db
+            .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
+            .await?
+            .into_iter()
+            .map(|unprocessed| unprocessed.commitment)
+            .collect();
+        unprocessed.sort();
+
+        assert_eq!(unprocessed, vec![queued, in_flight[0], in_flight[1]]);
+        assert_eq!(db.get_deletions().await?, vec![deletion]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn submitted_batch_can_be_audited() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let roots = mock_roots(2);
+        let commitments: Vec<Hash> = vec![Uint::from(10), Uint::from(11), Uint::from(12)];
+        let proof = Proof::from(std::array::from_fn(|i| U256::from(i + 1)));
+        let transaction_ids = vec!["tx-1".to_string()];
+
+        assert_eq!(db.get_batch_audit(&roots[1]).await?, None);
+
+        db.insert_batch_audit(
+            5,
+            &roots[0],
+            &roots[1],
+            &commitments,
+            &proof,
+            &transaction_ids,
+        )
+        .await?;
+
+        let audit = db
+            .get_batch_audit(&roots[1])
+            .await?
+            .context("Missing batch audit")?;
+
+        assert_eq!(audit.start_index, 5);
+        assert_eq!(audit.pre_root, roots[0]);
+        assert_eq!(audit.post_root, roots[1]);
+        assert_eq!(audit.commitments, commitments);
+        assert_eq!(audit.proof, proof);
+        assert_eq!(audit.transaction_ids, transaction_ids);
+        assert_same_time!(audit.submitted_at, Utc::now(), chrono::Duration::seconds(5));
+
+        // Only the batch's post root identifies it
+        assert_eq!(db.get_batch_audit(&roots[0]).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proved_batch_is_kept_until_submitted() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let roots = mock_roots(3);
+
+        let first = PendingProof {
+            input_hash:  U256::from(0xabcd),
+            start_index: 4,
+            pre_root:    roots[0],
+            post_root:   roots[1],
+            commitments: vec![Uint::from(10), Uint::from(11), Hash::ZERO],
+            proof:       Proof::from(std::array::from_fn(|i| U256::from(i + 1))),
+        };
+        let second = PendingProof {
+            input_hash: U256::MAX,
+            start_index: 7,
+            pre_root: roots[1],
+            post_root: roots[2],
+            ..first.clone()
+        };
+
+        db.insert_pending_proof(&second).await?;
+        db.insert_pending_proof(&first).await?;
+
+        assert_eq!(db.get_pending_proofs().await?, vec![
+            first.clone(),
+            second.clone()
+        ]);
+
+        db.remove_pending_proof(&first.input_hash).await?;
+
+        assert_eq!(db.get_pending_proofs().await?, vec![second]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_queue_state() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        assert_eq!(db.get_queue_state().await?, QueueState::default());
+
+        let eligible_now = Utc::now();
+        let eligible_later = Utc::now()
+            .checked_add_days(Days::new(7))
+            .expect("Could not create eligibility timestamp");
+
+        db.insert_new_identity(Uint::from(1), eligible_now).await?;
+        db.insert_new_identity(Uint::from(2), eligible_now).await?;
+        db.insert_new_identity(Uint::from(3), eligible_later)
+            .await?;
+
+        let identities = mock_identities(3);
+        let roots = mock_roots(3);
+        for i in 0..3 {
+            db.insert_pending_identity(i, &identities[i], &roots[i])
+                .await?;
+        }
+        db.mark_root_as_processed(&roots[0]).await?;
+
+        db.insert_new_deletion(1, &identities[1]).await?;
+
+        // Make sure the eligibility timestamp of the first identities has passed
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let queue_state = db.get_queue_state().await?;
+
+        assert_eq!(queue_state, QueueState {
+            buffered:  2,
+            queued:    1,
+            pending:   2,
+            deletions: 1,
+        });
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pending_finalization_roots() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        let identities = mock_identities(3);
+        let roots = mock_roots(3);
+        for i in 0..3 {
+            db.insert_pending_identity(i, &identities[i], &roots[i])
+                .await?;
+        }
+
+        // The first two roots are mined on mainnet, the first one is also
+        // finalized everywhere
+        db.mark_root_as_processed(&roots[1]).await?;
+        db.mark_root_as_mined(&roots[0]).await?;
+
+        db.record_root_on_chains(&roots[0], &[1, 10, 137]).await?;
+        db.record_root_on_chains(&roots[1], &[1]).await?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        db.record_root_on_chains(&roots[1], &[1, 10]).await?;
+
+        let pending = db.pending_finalization_roots().await?;
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].root, roots[1]);
+
+        let chains: Vec<u64> = pending[0]
+            .chains
+            .iter()
+            .map(|chain| chain.chain_id)
+            .collect();
+        assert_eq!(chains, vec![1, 10]);
+        assert!(pending[0].chains[0].seen_at < pending[0].chains[1].seen_at);
+
+        // Once finalized the root is no longer reported
+        db.mark_root_as_mined(&roots[1]).await?;
+
+        assert!(db.pending_finalization_roots().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recent_finalized_roots() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        let identities = mock_identities(4);
+        let roots = mock_roots(4);
+        for i in 0..4 {
+            db.insert_pending_identity(i, &identities[i], &roots[i])
+                .await?;
+        }
+
+        assert!(db.recent_finalized_roots(10).await?.is_empty());
+
+        db.mark_root_as_processed(&roots[2]).await?;
+        db.mark_root_as_mined(&roots[0]).await?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // Also finalizes the second root
+        db.mark_root_as_mined(&roots[2]).await?;
+
+        let recent = db.recent_finalized_roots(10).await?;
+        let recent_roots: Vec<Hash> = recent.iter().map(|(root, _)| *root).collect();
+
+        assert_eq!(recent_roots, vec![roots[2], roots[1], roots[0]]);
+        assert_eq!(recent[0].1, recent[1].1);
+        assert!(recent[2].1 < recent[1].1);
+
+        let latest = db.recent_finalized_roots(1).await?;
+        assert_eq!(latest, vec![recent[0]]);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_unprocessed_commitments() -> anyhow::Result<()> {
         let (db, _db_container) = setup_db().await?;
@@ -1237,6 +1943,22 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_other_identity_is_not_queued_for_deletion() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let existing_commitment: Uint<256, 4> = Uint::from(1);
+        let other_commitment: Uint<256, 4> = Uint::from(2);
+
+        db.insert_new_deletion(0, &existing_commitment).await?;
+
+        assert!(
+            !db.identity_is_queued_for_deletion(&other_commitment)
+                .await?
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_update_eligibility_timestamp() -> anyhow::Result<()> {
         let (db, _db_container) = setup_db().await?;
@@ -1460,6 +2182,84 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn get_earlier_leaf_indices_finds_duplicates() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        let identities = mock_identities(2);
+        let roots = mock_roots(4);
+
+        db.insert_pending_identity(0, &identities[0], &roots[0])
+            .await?;
+        db.insert_pending_identity(1, &identities[1], &roots[1])
+            .await?;
+        db.insert_pending_identity(2, &identities[0], &roots[2])
+            .await?;
+        db.insert_pending_identity(3, &identities[0], &roots[3])
+            .await?;
+
+        assert_eq!(db.get_earlier_leaf_indices(&identities, 3).await?, vec![(
+            identities[0],
+            0
+        )]);
+        assert_eq!(
+            db.get_earlier_leaf_indices(&identities[..1], 0).await?,
+            vec![]
+        );
+        assert_eq!(
+            db.get_earlier_leaf_indices(&identities[1..], 1).await?,
+            vec![]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_root_as_mined_is_idempotent() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+
+        let identities = mock_identities(3);
+        let roots = mock_roots(3);
+
+        for i in 0..3 {
+            db.insert_pending_identity(i, &identities[i], &roots[i])
+                .await
+                .context("Inserting identity")?;
+        }
+
+        assert!(
+            db.mark_root_as_mined(&roots[1]).await?,
+            "First call should transition the root to mined"
+        );
+        assert!(
+            !db.mark_root_as_mined(&roots[1]).await?,
+            "Second call should be a no-op"
+        );
+        assert!(
+            !db.mark_root_as_mined(&roots[0]).await?,
+            "Marking an earlier root should be a no-op"
+        );
+
+        for root in roots.iter().take(2) {
+            let root = db
+                .get_root_state(root)
+                .await?
+                .context("Fetching root state")?;
+
+            assert_eq!(root.status, ProcessedStatus::Mined);
+        }
+
+        let pending_identities = db.count_pending_identities().await?;
+        assert_eq!(pending_identities, 1, "There should be 1 pending identity");
+
+        assert!(
+            db.mark_root_as_mined(&roots[2]).await?,
+            "Marking a later root should transition it to mined"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn mark_root_as_mined_interaction_with_mark_root_as_processed() -> anyhow::Result<()> {
         let (db, _db_container) = setup_db().await?;
@@ -1,6 +1,9 @@
 use chrono::{DateTime, Utc};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
 
 use crate::identity_tree::{Hash, Status, UnprocessedStatus};
+use crate::prover::Proof;
 
 pub struct UnprocessedCommitment {
     pub commitment:            Hash,
@@ -20,7 +23,7 @@ pub struct LatestDeletionEntry {
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct DeletionEntry {
     pub leaf_index: usize,
     pub commitment: Hash,
@@ -35,3 +38,59 @@ pub struct CommitmentHistoryEntry {
     pub held_back:  bool,
     pub status:     Status,
 }
+
+/// A snapshot of the identities and deletions waiting to be batched
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueState {
+    /// Unprocessed identities that are eligible for insertion
+    pub buffered:  i64,
+    /// Unprocessed identities whose eligibility timestamp is in the future
+    pub queued:    i64,
+    /// Identities inserted into the tree but not yet mined on chain
+    pub pending:   i64,
+    /// Identities queued for deletion
+    pub deletions: i64,
+}
+
+/// The chains a root mined on mainnet has reached so far, on its way to being
+/// finalized on every chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootFinalizationStatus {
+    pub root:   Hash,
+    /// The chains the root was seen on, in the order it reached them
+    pub chains: Vec<ChainProgress>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainProgress {
+    pub chain_id: u64,
+    pub seen_at:  DateTime<Utc>,
+}
+
+/// The proof and inputs an insertion batch was submitted with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchAudit {
+    pub start_index:     usize,
+    pub pre_root:        Hash,
+    pub post_root:       Hash,
+    pub commitments:     Vec<Hash>,
+    pub proof:           Proof,
+    pub transaction_ids: Vec<String>,
+    pub submitted_at:    DateTime<Utc>,
+}
+
+/// An insertion batch that was proved but not yet submitted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingProof {
+    /// The input hash the proof was generated for
+    pub input_hash:  U256,
+    pub start_index: usize,
+    pub pre_root:    Hash,
+    pub post_root:   Hash,
+    /// The commitments of the batch, including the padding
+    pub commitments: Vec<Hash>,
+    pub proof:       Proof,
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use ethers::providers::Middleware;
+use ethers::types::{TransactionReceipt, H256};
+use tracing::debug;
+
+/// Polls `provider` until the transaction with `tx_hash` is buried under
+/// `confirmations` blocks. The block containing the transaction counts as the
+/// first confirmation.
+///
+/// Returns `None` if the transaction has no receipt, e.g. because it was
+/// reorged out of the chain.
+pub async fn await_receipt_confirmations<M: Middleware>(
+    provider: &M,
+    tx_hash: H256,
+    confirmations: u64,
+    poll_interval: Duration,
+) -> Result<Option<TransactionReceipt>, M::Error> {
+    loop {
+        let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+            return Ok(None);
+        };
+
+        let Some(block_number) = receipt.block_number else {
+            return Ok(None);
+        };
+
+        let head = provider.get_block_number().await?;
+
+        if head >= block_number && (head - block_number).as_u64() + 1 >= confirmations {
+            return Ok(Some(receipt));
+        }
+
+        debug!(?tx_hash, %block_number, %head, confirmations, "Waiting for confirmations");
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+    use ethers::types::U64;
+
+    use super::*;
+
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    fn receipt_in_block(block_number: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            block_number: Some(U64::from(block_number)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_until_enough_blocks_are_mined() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        // The mock returns responses in reverse order of pushing
+        mock.push(U64::from(12))?;
+        mock.push(receipt_in_block(10))?;
+        mock.push(U64::from(11))?;
+        mock.push(receipt_in_block(10))?;
+        mock.push(U64::from(10))?;
+        mock.push(receipt_in_block(10))?;
+
+        let receipt =
+            await_receipt_confirmations(&provider, H256::zero(), 3, POLL_INTERVAL).await?;
+
+        assert_eq!(receipt, Some(receipt_in_block(10)));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_confirmation_returns_immediately() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        mock.push(U64::from(10))?;
+        mock.push(receipt_in_block(10))?;
+
+        let receipt =
+            await_receipt_confirmations(&provider, H256::zero(), 1, POLL_INTERVAL).await?;
+
+        assert_eq!(receipt, Some(receipt_in_block(10)));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reorged_transaction_returns_none() -> anyhow::Result<()> {
+        let (provider, mock) = Provider::mocked();
+
+        mock.push(Option::<TransactionReceipt>::None)?;
+        mock.push(U64::from(10))?;
+        mock.push(receipt_in_block(10))?;
+
+        let receipt =
+            await_receipt_confirmations(&provider, H256::zero(), 3, POLL_INTERVAL).await?;
+
+        assert_eq!(receipt, None);
+
+        Ok(())
+    }
+}
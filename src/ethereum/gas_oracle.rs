@@ -0,0 +1,214 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Context;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::U256;
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::GasOracleConfig;
+
+const WEI_PER_GWEI: f64 = 1e9;
+
+/// The EIP-1559 fees set on a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Eip1559Fees {
+    pub max_fee_per_gas:          U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// Where the fees of submitted transactions come from.
+#[derive(Clone)]
+pub enum GasOracle {
+    /// Leave the fees to the provider or relayer sending the transaction
+    ProviderDefault,
+    /// Fetch the fees from the BlockNative gas price API at the given url
+    BlockNative(Url),
+    /// Compute the fees with the given function
+    Custom(Arc<dyn Fn() -> Eip1559Fees + Send + Sync>),
+}
+
+impl fmt::Debug for GasOracle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProviderDefault => f.write_str("ProviderDefault"),
+            Self::BlockNative(url) => f.debug_tuple("BlockNative").field(url).finish(),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl GasOracle {
+    pub fn from_config(config: &GasOracleConfig) -> anyhow::Result<Self> {
+        match config {
+            GasOracleConfig::ProviderDefault => Ok(Self::ProviderDefault),
+            GasOracleConfig::BlockNative(url) => Ok(Self::BlockNative(
+                Url::parse(url).context("Invalid BlockNative url")?,
+            )),
+        }
+    }
+
+    /// Returns the fees to set on the next transaction, or `None` if they are
+    /// left to the provider.
+    pub async fn fees(&self) -> anyhow::Result<Option<Eip1559Fees>> {
+        match self {
+            Self::ProviderDefault => Ok(None),
+            Self::BlockNative(url) => {
+                let prices: BlockPrices = reqwest::get(url.clone())
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                Ok(Some(prices.most_confident_fees()?))
+            }
+            Self::Custom(fees) => Ok(Some(fees())),
+        }
+    }
+
+    /// Sets the fees returned by the oracle on `tx`. Legacy transactions get
+    /// the max fee as their gas price.
+    pub async fn populate(&self, tx: &mut TypedTransaction) -> anyhow::Result<()> {
+        if let Some(fees) = self.fees().await? {
+            apply_fees(tx, fees);
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_fees(tx: &mut TypedTransaction, fees: Eip1559Fees) {
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            tx.max_fee_per_gas = Some(fees.max_fee_per_gas);
+            tx.max_priority_fee_per_gas = Some(fees.max_priority_fee_per_gas);
+        }
+        tx => {
+            tx.set_gas_price(fees.max_fee_per_gas);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockPrices {
+    block_prices: Vec<BlockPrice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockPrice {
+    estimated_prices: Vec<EstimatedPrice>,
+}
+
+/// A fee estimate, in gwei.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EstimatedPrice {
+    confidence:               u32,
+    max_fee_per_gas:          f64,
+    max_priority_fee_per_gas: f64,
+}
+
+impl BlockPrices {
+    /// Returns the estimate for the next block that is most likely to be
+    /// included.
+    fn most_confident_fees(&self) -> anyhow::Result<Eip1559Fees> {
+        let price = self
+            .block_prices
+            .first()
+            .and_then(|block| block.estimated_prices.iter().max_by_key(|p| p.confidence))
+            .context("BlockNative returned no price estimates")?;
+
+        Ok(Eip1559Fees {
+            max_fee_per_gas:          gwei_to_wei(price.max_fee_per_gas),
+            max_priority_fee_per_gas: gwei_to_wei(price.max_priority_fee_per_gas),
+        })
+    }
+}
+
+fn gwei_to_wei(gwei: f64) -> U256 {
+    U256::from((gwei * WEI_PER_GWEI).round() as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest};
+    use serde_json::json;
+
+    use super::*;
+
+    fn fees(max_fee: u64, priority_fee: u64) -> Eip1559Fees {
+        Eip1559Fees {
+            max_fee_per_gas:          U256::from(max_fee),
+            max_priority_fee_per_gas: U256::from(priority_fee),
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_oracle_fees_are_applied() -> anyhow::Result<()> {
+        let oracle = GasOracle::Custom(Arc::new(|| fees(30, 2)));
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().into();
+
+        oracle.populate(&mut tx).await?;
+
+        let TypedTransaction::Eip1559(tx) = tx else {
+            panic!("Transaction type changed");
+        };
+        assert_eq!(tx.max_fee_per_gas, Some(U256::from(30)));
+        assert_eq!(tx.max_priority_fee_per_gas, Some(U256::from(2)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn provider_default_leaves_fees_unset() -> anyhow::Result<()> {
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new().into();
+
+        GasOracle::ProviderDefault.populate(&mut tx).await?;
+
+        assert_eq!(tx, Eip1559TransactionRequest::new().into());
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_transactions_get_the_max_fee_as_gas_price() {
+        let mut tx: TypedTransaction = TransactionRequest::new().into();
+
+        apply_fees(&mut tx, fees(30, 2));
+
+        assert_eq!(tx.gas_price(), Some(U256::from(30)));
+    }
+
+    #[test]
+    fn block_native_estimate_with_highest_confidence_is_used() -> anyhow::Result<()> {
+        let prices: BlockPrices = serde_json::from_value(json!({
+            "blockPrices": [{
+                "blockNumber": 17_000_000,
+                "estimatedPrices": [
+                    { "confidence": 90, "price": 20, "maxPriorityFeePerGas": 1.0, "maxFeePerGas": 25.0 },
+                    { "confidence": 99, "price": 21, "maxPriorityFeePerGas": 1.5, "maxFeePerGas": 30.25 },
+                    { "confidence": 70, "price": 19, "maxPriorityFeePerGas": 0.5, "maxFeePerGas": 22.0 },
+                ],
+            }],
+        }))?;
+
+        assert_eq!(
+            prices.most_confident_fees()?,
+            fees(30_250_000_000, 1_500_000_000)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_native_response_without_estimates_is_rejected() -> anyhow::Result<()> {
+        let prices: BlockPrices = serde_json::from_value(json!({ "blockPrices": [] }))?;
+
+        assert!(prices.most_confident_fees().is_err());
+
+        Ok(())
+    }
+}
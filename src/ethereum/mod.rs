@@ -1,38 +1,77 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+use anyhow::anyhow;
 use ethers::types::transaction::eip2718::TypedTransaction;
-use ethers::types::Address;
+use ethers::types::{Address, TransactionReceipt};
 pub use read::{EventError, ReadProvider};
-use tracing::instrument;
+use tracing::{instrument, warn};
 pub use write::TxError;
 
+use self::confirmations::await_receipt_confirmations;
+use self::gas_oracle::GasOracle;
 use self::write::TransactionId;
 use self::write_provider::WriteProvider;
-use crate::config::Config;
+use crate::config::{Config, NetworkConfig, ProvidersConfig, RelayerConfig};
+use crate::utils::retry::Backoff;
 
+pub mod gas_oracle;
 pub mod read;
 pub mod write;
 
+mod confirmations;
 mod write_provider;
 
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_REORG_RETRIES: u32 = 10;
+const MAX_REORG_RETRY_DELAY: Duration = Duration::from_secs(60);
+
 #[derive(Clone, Debug)]
 pub struct Ethereum {
     read_provider:            Arc<ReadProvider>,
     // Mapping of chain id to provider
     secondary_read_providers: HashMap<u64, Arc<ReadProvider>>,
     write_provider:           Arc<WriteProvider>,
+    gas_oracle:               GasOracle,
 }
 
 impl Ethereum {
     #[instrument(name = "Ethereum::new", level = "debug", skip_all)]
     pub async fn new(config: &Config) -> anyhow::Result<Self> {
+        Self::connect(&config.providers, &config.relayer, &config.network).await
+    }
+
+    pub(crate) async fn connect(
+        providers: &ProvidersConfig,
+        relayer: &RelayerConfig,
+        network: &NetworkConfig,
+    ) -> anyhow::Result<Self> {
         let read_provider =
-            ReadProvider::new(config.providers.primary_network_provider.clone().into()).await?;
+            ReadProvider::new(providers.primary_network_provider.clone().into()).await?;
+
+        // Transactions go through the read provider unless a separate write
+        // endpoint is configured
+        let write_rpc_provider = match &providers.write_network_provider {
+            Some(url) => {
+                let write_rpc_provider = ReadProvider::new(url.clone().into()).await?;
+
+                if write_rpc_provider.chain_id != read_provider.chain_id {
+                    return Err(anyhow!(
+                        "Write provider is connected to chain {}, expected chain {}",
+                        write_rpc_provider.chain_id,
+                        read_provider.chain_id
+                    ));
+                }
+
+                write_rpc_provider
+            }
+            None => read_provider.clone(),
+        };
 
         let mut secondary_read_providers = HashMap::new();
 
-        for secondary_url in &config.providers.relayed_network_providers.0 {
+        for secondary_url in &providers.relayed_network_providers.0 {
             let secondary_read_provider = ReadProvider::new(secondary_url.clone().into()).await?;
             secondary_read_providers.insert(
                 secondary_read_provider.chain_id.as_u64(),
@@ -41,13 +80,16 @@ impl Ethereum {
         }
 
         let write_provider: Arc<WriteProvider> = Arc::new(
-            write_provider::WriteProvider::new(read_provider.clone(), &config.relayer).await?,
+            write_provider::WriteProvider::new(write_rpc_provider, relayer, network).await?,
         );
 
+        let gas_oracle = GasOracle::from_config(&network.gas_price_oracle)?;
+
         Ok(Self {
             read_provider: Arc::new(read_provider),
             secondary_read_providers,
             write_provider,
+            gas_oracle,
         })
     }
 
@@ -66,11 +108,18 @@ impl Ethereum {
         self.write_provider.address()
     }
 
+    /// Sends the transaction through the write provider, with its fees taken
+    /// from the configured gas oracle.
     pub async fn send_transaction(
         &self,
-        tx: TypedTransaction,
+        mut tx: TypedTransaction,
         only_once: bool,
     ) -> Result<TransactionId, TxError> {
+        self.gas_oracle
+            .populate(&mut tx)
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+
         self.write_provider.send_transaction(tx, only_once).await
     }
 
@@ -81,4 +130,106 @@ impl Ethereum {
     pub async fn mine_transaction(&self, tx: TransactionId) -> Result<bool, TxError> {
         self.write_provider.mine_transaction(tx).await
     }
+
+    /// Waits until the transaction is buried under `confirmations` blocks and
+    /// returns its receipt. If the transaction is reorged out of the chain in
+    /// the meantime, we back off and wait for it to be mined again, up to
+    /// `MAX_REORG_RETRIES` times.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn await_confirmations(
+        &self,
+        tx_id: TransactionId,
+        confirmations: u64,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let backoff = Backoff::new(CONFIRMATION_POLL_INTERVAL, MAX_REORG_RETRY_DELAY, 2.0);
+
+        let mut attempt: u32 = 0;
+
+        loop {
+            attempt += 1;
+
+            let tx_hash = self
+                .write_provider
+                .mined_transaction_hash(tx_id.clone())
+                .await?;
+
+            let receipt = await_receipt_confirmations(
+                self.read_provider.as_ref(),
+                tx_hash,
+                confirmations,
+                CONFIRMATION_POLL_INTERVAL,
+            )
+            .await?;
+
+            if let Some(receipt) = receipt {
+                return Ok(receipt);
+            }
+
+            if attempt > MAX_REORG_RETRIES {
+                return Err(anyhow!(
+                    "Transaction {tx_id} was reorged out {attempt} times, giving up"
+                ));
+            }
+
+            warn!(
+                ?tx_id,
+                ?tx_hash,
+                attempt,
+                "Transaction was reorged out, awaiting it again"
+            );
+
+            tokio::time::sleep(backoff.delay(attempt)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Middleware;
+    use ethers::types::TransactionRequest;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::RpcMock;
+
+    // The first account of the default anvil and hardhat mnemonic
+    const PRIVATE_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    #[tokio::test]
+    async fn reads_and_writes_use_their_own_endpoints() -> anyhow::Result<()> {
+        let read_rpc = RpcMock::spawn().await?;
+        let write_rpc = RpcMock::spawn().await?;
+
+        let providers: ProvidersConfig = serde_json::from_value(json!({
+            "primary_network_provider": read_rpc.url(),
+            "write_network_provider": write_rpc.url(),
+        }))?;
+        let relayer: RelayerConfig = serde_json::from_value(json!({
+            "kind": "signer",
+            "signer": "local_key",
+            "private_key": PRIVATE_KEY,
+        }))?;
+        let network: NetworkConfig = serde_json::from_value(json!({
+            "identity_manager_address": Address::zero(),
+        }))?;
+
+        let ethereum = Ethereum::connect(&providers, &relayer, &network).await?;
+
+        ethereum.provider().get_block_number().await?;
+
+        let tx = TransactionRequest::new()
+            .to(Address::zero())
+            .gas(21_000)
+            .gas_price(1)
+            .nonce(0);
+        ethereum.send_transaction(tx.into(), false).await?;
+
+        assert!(read_rpc.called("eth_blockNumber"));
+        assert!(!read_rpc.called("eth_sendRawTransaction"));
+
+        assert!(write_rpc.called("eth_sendRawTransaction"));
+        assert!(!write_rpc.called("eth_blockNumber"));
+
+        Ok(())
+    }
 }
@@ -1,8 +1,9 @@
 use std::error::Error;
 use std::fmt;
+use std::ops::Range;
 
 use ethers::providers::ProviderError;
-use ethers::types::{TransactionReceipt, H256};
+use ethers::types::{TransactionReceipt, H256, U256};
 use thiserror::Error;
 
 #[derive(Clone, Debug)]
@@ -53,3 +54,152 @@ pub enum TxError {
     #[error("{0}")]
     Other(anyhow::Error),
 }
+
+/// A classification of the errors returned when submitting a transaction,
+/// based on the error messages commonly returned by providers and relayers.
+#[derive(Debug, Error)]
+pub enum SubmitError {
+    #[error("Insufficient funds: {0}")]
+    InsufficientFunds(TxError),
+
+    #[error("Nonce too low: {0}")]
+    NonceTooLow(TxError),
+
+    #[error("Replacement transaction underpriced: {0}")]
+    ReplacementUnderpriced(TxError),
+
+    #[error("Transaction already known: {0}")]
+    AlreadyKnown(TxError),
+
+    #[error("Gas limit exceeded: {0}")]
+    GasLimitExceeded(TxError),
+
+    /// The batch no longer matches the inputs its proof was generated for, so
+    /// the proof would be rejected on-chain.
+    #[error("Proof was generated for input hash {expected}, but the batch hashes to {actual}")]
+    InputHashMismatch { expected: U256, actual: U256 },
+
+    /// The identity manager only verifies Groth16 proofs.
+    #[error("Identity manager can't verify a PLONK proof of {words} words")]
+    UnsupportedProof { words: usize },
+
+    #[error(transparent)]
+    Other(TxError),
+}
+
+impl From<TxError> for SubmitError {
+    fn from(error: TxError) -> Self {
+        let message = error.to_string().to_lowercase();
+
+        if message.contains("insufficient funds") {
+            Self::InsufficientFunds(error)
+        } else if message.contains("nonce too low") {
+            Self::NonceTooLow(error)
+        } else if message.contains("underpriced") {
+            Self::ReplacementUnderpriced(error)
+        } else if message.contains("already known") {
+            Self::AlreadyKnown(error)
+        } else if message.contains("exceeds block gas limit") || message.contains("gas too low") {
+            Self::GasLimitExceeded(error)
+        } else {
+            Self::Other(error)
+        }
+    }
+}
+
+/// A failed submission of a batch, along with the leaf indices it covered so
+/// the affected identities can be identified.
+#[derive(Debug, Error)]
+#[error(
+    "Failed to submit batch for leaf indices {}..{}: {error}",
+    .leaf_indices.start,
+    .leaf_indices.end
+)]
+pub struct BatchSubmitError {
+    pub leaf_indices: Range<usize>,
+    pub error:        SubmitError,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn classify(message: &str) -> SubmitError {
+        SubmitError::from(TxError::Send(anyhow!("{message}")))
+    }
+
+    #[test]
+    fn batch_error_includes_leaf_index_range() {
+        let error = BatchSubmitError {
+            leaf_indices: 10..13,
+            error:        classify("nonce too low"),
+        };
+
+        let message = error.to_string();
+
+        assert!(
+            message.contains("10..13"),
+            "Missing index range in: {message}"
+        );
+        assert!(
+            message.contains("Nonce too low"),
+            "Missing cause in: {message}"
+        );
+    }
+
+    #[test_case("insufficient funds for gas * price + value" ; "insufficient funds")]
+    #[test_case("Insufficient Funds for transfer" ; "insufficient funds mixed case")]
+    fn insufficient_funds(message: &str) {
+        assert!(matches!(
+            classify(message),
+            SubmitError::InsufficientFunds(_)
+        ));
+    }
+
+    #[test_case("nonce too low" ; "nonce too low")]
+    #[test_case("Nonce too low: next nonce 5, tx nonce 4" ; "nonce too low with details")]
+    fn nonce_too_low(message: &str) {
+        assert!(matches!(classify(message), SubmitError::NonceTooLow(_)));
+    }
+
+    #[test_case("replacement transaction underpriced" ; "replacement underpriced")]
+    #[test_case("transaction underpriced" ; "underpriced")]
+    fn replacement_underpriced(message: &str) {
+        assert!(matches!(
+            classify(message),
+            SubmitError::ReplacementUnderpriced(_)
+        ));
+    }
+
+    #[test]
+    fn already_known() {
+        assert!(matches!(
+            classify("already known"),
+            SubmitError::AlreadyKnown(_)
+        ));
+    }
+
+    #[test_case("exceeds block gas limit" ; "exceeds block gas limit")]
+    #[test_case("intrinsic gas too low" ; "intrinsic gas too low")]
+    fn gas_limit_exceeded(message: &str) {
+        assert!(matches!(
+            classify(message),
+            SubmitError::GasLimitExceeded(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_errors_are_other() {
+        assert!(matches!(
+            classify("execution reverted"),
+            SubmitError::Other(_)
+        ));
+        assert!(matches!(
+            SubmitError::from(TxError::SendTimeout),
+            SubmitError::Other(_)
+        ));
+    }
+}
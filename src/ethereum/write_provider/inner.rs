@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use ethers::providers::Middleware;
 use ethers::types::transaction::eip2718::TypedTransaction;
 use ethers::types::H256;
 
@@ -12,6 +15,8 @@ pub trait Inner: Send + Sync + 'static {
         only_once: bool,
     ) -> Result<TransactionId, TxError>;
 
+    /// Returns the transactions that are not yet mined, sorted by nonce.
+    /// Transactions that were not assigned a nonce yet come last.
     async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError>;
 
     async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError>;
@@ -21,3 +26,69 @@ pub struct TransactionResult {
     pub transaction_id: String,
     pub hash:           Option<H256>,
 }
+
+const MINING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Polls `provider` for the receipt of a transaction signed by the sequencer
+/// until it is mined, or fails with [`TxError::ConfirmationTimeout`] once
+/// `timeout` has elapsed. The transaction hash doubles as its id.
+pub async fn await_signed_transaction<M: Middleware>(
+    provider: &M,
+    tx_hash: H256,
+    timeout: Duration,
+) -> Result<TransactionResult, TxError> {
+    let poll = async {
+        loop {
+            let receipt = provider
+                .get_transaction_receipt(tx_hash)
+                .await
+                .map_err(|err| TxError::Fetch(err.into()))?;
+
+            if receipt.is_some() {
+                return Ok(TransactionResult {
+                    transaction_id: format!("{tx_hash:?}"),
+                    hash:           Some(tx_hash),
+                });
+            }
+
+            tokio::time::sleep(MINING_POLL_INTERVAL).await;
+        }
+    };
+
+    tokio::time::timeout(timeout, poll)
+        .await
+        .map_err(|_| TxError::ConfirmationTimeout)
+        .and_then(|result| result)
+}
+
+/// Sorts transactions by nonce, keeping transactions without a nonce at the
+/// end in their original order.
+pub fn sort_by_nonce(mut txs: Vec<(Option<u64>, TransactionId)>) -> Vec<TransactionId> {
+    txs.sort_by_key(|(nonce, _)| (nonce.is_none(), *nonce));
+
+    txs.into_iter().map(|(_, tx_id)| tx_id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(id: &str) -> TransactionId {
+        TransactionId(id.to_string())
+    }
+
+    #[test]
+    fn sorts_out_of_order_transactions_by_nonce() {
+        let txs = vec![
+            (Some(7), tx("c")),
+            (None, tx("e")),
+            (Some(5), tx("a")),
+            (None, tx("f")),
+            (Some(6), tx("b")),
+        ];
+
+        let sorted: Vec<String> = sort_by_nonce(txs).into_iter().map(|tx| tx.0).collect();
+
+        assert_eq!(sorted, vec!["a", "b", "c", "e", "f"]);
+    }
+}
@@ -1,21 +1,27 @@
 use std::fmt;
 use std::sync::Arc;
 
+use anyhow::anyhow;
 use ethers::providers::Middleware;
+use ethers::signers::Signer;
 use ethers::types::transaction::eip2718::TypedTransaction;
-use ethers::types::{Address, U64};
+use ethers::types::{Address, H256, U64};
 use tracing::{info, warn};
 
 use self::inner::Inner;
 use self::openzeppelin::OzRelay;
+use self::private_relay::PrivateRelay;
+use self::signer::{SignerRelay, TxSigner};
 use self::tx_sitter::TxSitter;
 use super::write::TransactionId;
 use super::{ReadProvider, TxError};
-use crate::config::RelayerConfig;
+use crate::config::{NetworkConfig, RelayerConfig, SubmissionMode};
 
 mod error;
 mod inner;
 mod openzeppelin;
+mod private_relay;
+mod signer;
 mod tx_sitter;
 
 pub struct WriteProvider {
@@ -35,17 +41,60 @@ impl fmt::Debug for WriteProvider {
 }
 
 impl WriteProvider {
-    pub async fn new(read_provider: ReadProvider, config: &RelayerConfig) -> anyhow::Result<Self> {
-        let address = config.address();
-
-        let inner: Arc<dyn Inner> = match config {
-            RelayerConfig::OzDefender(oz_config) => {
+    pub async fn new(
+        read_provider: ReadProvider,
+        config: &RelayerConfig,
+        network: &NetworkConfig,
+    ) -> anyhow::Result<Self> {
+        let chain_id = read_provider.chain_id.as_u64();
+
+        // The signer is only loaded, and its address only known, for the
+        // `signer` relayer
+        let (inner, address): (Arc<dyn Inner>, Address) = match (&network.submission_mode, config) {
+            (SubmissionMode::Private(relay_url), RelayerConfig::Signer(kind)) => {
+                let signer = TxSigner::load(kind, chain_id).await?;
+                let address = signer.address();
+                let nonce_strategy = network.nonce_strategy.unwrap_or_default();
+                tracing::info!(relay_url, ?nonce_strategy, "Initializing private relay");
+                let relay = PrivateRelay::new(
+                    relay_url,
+                    read_provider.clone(),
+                    signer,
+                    nonce_strategy,
+                    network.mining_timeout,
+                )?;
+
+                (Arc::new(relay), address)
+            }
+            (SubmissionMode::Private(_), _) => {
+                return Err(anyhow!(
+                    "Private submission signs transactions locally and requires the `signer` \
+                     relayer"
+                ));
+            }
+            (SubmissionMode::Public, RelayerConfig::OzDefender(oz_config)) => {
                 tracing::info!("Initializing OZ Relayer");
-                Arc::new(OzRelay::new(oz_config).await?)
+
+                (
+                    Arc::new(OzRelay::new(oz_config).await?),
+                    oz_config.oz_address,
+                )
             }
-            RelayerConfig::TxSitter(tx_sitter_config) => {
+            (SubmissionMode::Public, RelayerConfig::TxSitter(tx_sitter_config)) => {
                 tracing::info!("Initializing TxSitter");
-                Arc::new(TxSitter::new(tx_sitter_config))
+
+                (
+                    Arc::new(TxSitter::new(tx_sitter_config)),
+                    tx_sitter_config.tx_sitter_address,
+                )
+            }
+            (SubmissionMode::Public, RelayerConfig::Signer(kind)) => {
+                let signer = TxSigner::load(kind, chain_id).await?;
+                let address = signer.address();
+                tracing::info!(?address, "Initializing local signer");
+                let relay = SignerRelay::new(read_provider.clone(), signer, network.mining_timeout);
+
+                (Arc::new(relay), address)
             }
         };
 
@@ -110,6 +159,18 @@ impl WriteProvider {
         }
     }
 
+    /// Waits for the relayer to mine the transaction and returns its hash
+    pub async fn mined_transaction_hash(&self, tx: TransactionId) -> Result<H256, TxError> {
+        let transaction = self.inner.mine_transaction(tx).await?;
+
+        transaction.hash.ok_or_else(|| {
+            TxError::Fetch(From::from(format!(
+                "Failed to get tx hash for transaction id {}",
+                transaction.transaction_id
+            )))
+        })
+    }
+
     pub fn address(&self) -> Address {
         self.address
     }
@@ -10,7 +10,7 @@ use tokio::time::timeout;
 use tracing::{error, info, info_span, Instrument};
 
 use super::error::Error;
-use super::inner::{Inner, TransactionResult};
+use super::inner::{sort_by_nonce, Inner, TransactionResult};
 use crate::config::OzDefenderConfig;
 use crate::ethereum::write::TransactionId;
 use crate::ethereum::TxError;
@@ -207,10 +207,10 @@ impl OzRelay {
 
         let pending_txs = recent_pending_txs
             .into_iter()
-            .map(|tx| TransactionId(tx.transaction_id))
+            .map(|tx| (tx.nonce, TransactionId(tx.transaction_id)))
             .collect();
 
-        Ok(pending_txs)
+        Ok(sort_by_nonce(pending_txs))
     }
 }
 
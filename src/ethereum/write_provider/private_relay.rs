@@ -0,0 +1,418 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Http, JsonRpcClient, Middleware, Provider};
+use ethers::signers::Signer;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, Bytes, H256, U256};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use super::inner::{await_signed_transaction, Inner, TransactionResult};
+use super::signer::TxSigner;
+use crate::config::NonceStrategy;
+use crate::ethereum::write::TransactionId;
+use crate::ethereum::{ReadProvider, TxError};
+
+/// Submits transactions through a private transaction relay instead of the
+/// public mempool, so they can't be frontrun.
+///
+/// Transactions are filled in from the chain, signed with the relayer's
+/// signer and sent as raw transactions with `eth_sendPrivateTransaction`.
+/// Receipts are polled from the chain, as the relay only accepts
+/// submissions. The transaction hash doubles as the transaction id.
+///
+/// With [`NonceStrategy::LocallyTracked`] the next nonce is kept in memory,
+/// seeded from the pending transaction count of the signer. It is reseeded
+/// from the chain whenever a submission fails or a transaction doesn't get
+/// mined, so no gaps are left behind.
+///
+/// Relays don't list the transactions they hold back, so the relay keeps
+/// track of the ones it submitted until they are mined or dropped. Those
+/// submitted before a restart are lost.
+pub struct PrivateRelay<P = Http, M = ReadProvider> {
+    relay:          Provider<P>,
+    client:         SignerMiddleware<M, TxSigner>,
+    nonce_strategy: NonceStrategy,
+    mining_timeout: Duration,
+    next_nonce:     Mutex<Option<U256>>,
+    submitted:      Mutex<Vec<H256>>,
+}
+
+#[derive(Serialize)]
+struct PrivateTransactionRequest {
+    tx: Bytes,
+}
+
+impl<M: Middleware + 'static> PrivateRelay<Http, M> {
+    pub fn new(
+        relay_url: &str,
+        chain: M,
+        signer: TxSigner,
+        nonce_strategy: NonceStrategy,
+        mining_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        Ok(Self::with_provider(
+            Provider::<Http>::try_from(relay_url)?,
+            chain,
+            signer,
+            nonce_strategy,
+            mining_timeout,
+        ))
+    }
+}
+
+impl<P: JsonRpcClient, M: Middleware + 'static> PrivateRelay<P, M> {
+    pub fn with_provider(
+        relay: Provider<P>,
+        chain: M,
+        signer: TxSigner,
+        nonce_strategy: NonceStrategy,
+        mining_timeout: Duration,
+    ) -> Self {
+        Self {
+            relay,
+            client: SignerMiddleware::new(chain, signer),
+            nonce_strategy,
+            mining_timeout,
+            next_nonce: Mutex::new(None),
+            submitted: Mutex::new(vec![]),
+        }
+    }
+
+    /// Fills in the rest of the transaction from the chain, signs it and sends
+    /// it to the relay as a raw transaction.
+    async fn send_private_transaction(&self, mut tx: TypedTransaction) -> Result<H256, TxError> {
+        if tx.nonce().is_none() {
+            tx.set_nonce(self.pending_nonce().await?);
+        }
+
+        self.client
+            .fill_transaction(&mut tx, None)
+            .await
+            .map_err(|err| TxError::Fill(err.into()))?;
+
+        let signature = self
+            .client
+            .signer()
+            .sign_transaction(&tx)
+            .await
+            .map_err(|err| TxError::Send(err.into()))?;
+
+        self.relay
+            .request("eth_sendPrivateTransaction", [PrivateTransactionRequest {
+                tx: tx.rlp_signed(&signature),
+            }])
+            .await
+            .map_err(|err| TxError::Send(err.into()))
+    }
+
+    /// Assigns the next locally tracked nonce to the transaction and sends it.
+    /// The lock is held for the whole submission so nonces are handed out in
+    /// the order the transactions reach the relay.
+    async fn send_with_tracked_nonce(&self, mut tx: TypedTransaction) -> Result<H256, TxError> {
+        let mut next_nonce = self.next_nonce.lock().await;
+
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.pending_nonce().await?,
+        };
+
+        tx.set_nonce(nonce);
+
+        match self.send_private_transaction(tx).await {
+            Ok(tx_hash) => {
+                *next_nonce = Some(nonce + 1);
+                Ok(tx_hash)
+            }
+            Err(error) => {
+                warn!(?nonce, "Submission failed, reseeding nonce from the chain");
+                *next_nonce = None;
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the next nonce of the signer, counting its pending
+    /// transactions.
+    async fn pending_nonce(&self) -> Result<U256, TxError> {
+        self.client
+            .get_transaction_count(self.client.address(), Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|err| TxError::Fill(err.into()))
+    }
+
+    async fn reset_nonce(&self) {
+        if self.nonce_strategy == NonceStrategy::LocallyTracked {
+            *self.next_nonce.lock().await = None;
+        }
+    }
+}
+
+#[async_trait]
+impl<P, M> Inner for PrivateRelay<P, M>
+where
+    P: JsonRpcClient + 'static,
+    M: Middleware + 'static,
+{
+    async fn send_transaction(
+        &self,
+        mut tx: TypedTransaction,
+        _only_once: bool,
+    ) -> Result<TransactionId, TxError> {
+        tx.set_from(self.client.address());
+
+        let tx_hash = match self.nonce_strategy {
+            NonceStrategy::ProviderManaged => self.send_private_transaction(tx).await?,
+            NonceStrategy::LocallyTracked => self.send_with_tracked_nonce(tx).await?,
+        };
+
+        info!(?tx_hash, "Transaction submitted to private relay");
+        self.submitted.lock().await.push(tx_hash);
+
+        Ok(TransactionId(format!("{tx_hash:?}")))
+    }
+
+    async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
+        warn!(
+            "Private relays don't list the transactions they hold back, transactions submitted \
+             before a restart can't be awaited"
+        );
+
+        // In the order they were submitted
+        Ok(self
+            .submitted
+            .lock()
+            .await
+            .iter()
+            .map(|tx_hash| TransactionId(format!("{tx_hash:?}")))
+            .collect())
+    }
+
+    async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError> {
+        let tx_hash: H256 = tx.0.parse().map_err(|err| TxError::Parse(Box::new(err)))?;
+
+        let result = await_signed_transaction(&self.client, tx_hash, self.mining_timeout).await;
+
+        // Either mined or dropped by the relay
+        if matches!(result, Ok(_) | Err(TxError::ConfirmationTimeout)) {
+            self.submitted.lock().await.retain(|hash| *hash != tx_hash);
+        }
+
+        if result.is_err() {
+            self.reset_nonce().await;
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse};
+    use ethers::signers::LocalWallet;
+    use ethers::types::{Address, Bytes, TransactionReceipt, TransactionRequest};
+    use ethers::utils::rlp::Rlp;
+    use serde_json::json;
+
+    use super::*;
+
+    // The first account of the default anvil and hardhat mnemonic
+    const PRIVATE_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const CHAIN_ID: u64 = 5;
+
+    type MockRelay = PrivateRelay<MockProvider, Provider<MockProvider>>;
+
+    /// Returns a relay along with the mocks of the relay and of the chain.
+    fn relay(nonce_strategy: NonceStrategy) -> (MockRelay, MockProvider, MockProvider) {
+        let (relay, relay_mock) = Provider::mocked();
+        let (chain, chain_mock) = Provider::mocked();
+        let wallet: LocalWallet = PRIVATE_KEY.parse().unwrap();
+        let signer = TxSigner::Local(wallet.with_chain_id(CHAIN_ID));
+
+        (
+            PrivateRelay::with_provider(
+                relay,
+                chain,
+                signer,
+                nonce_strategy,
+                Duration::from_secs(60),
+            ),
+            relay_mock,
+            chain_mock,
+        )
+    }
+
+    /// A transaction with its gas already set, so that filling it in only
+    /// needs a nonce
+    fn register_tx() -> TypedTransaction {
+        TransactionRequest::new()
+            .to(Address::repeat_byte(3))
+            .data(Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]))
+            .gas(1_000_000)
+            .gas_price(1_000)
+            .chain_id(CHAIN_ID)
+            .into()
+    }
+
+    fn address() -> Address {
+        PRIVATE_KEY.parse::<LocalWallet>().unwrap().address()
+    }
+
+    fn assert_nonce_query(mock: &MockProvider) {
+        mock.assert_request("eth_getTransactionCount", (address(), "pending"))
+            .unwrap();
+    }
+
+    /// Asserts that the relay received `register_tx` with the given nonce,
+    /// signed by the relayer's key.
+    fn assert_sent_with_nonce(mock: &MockProvider, nonce: u64) {
+        let mut expected = register_tx();
+        expected.set_from(address());
+        expected.set_nonce(nonce);
+
+        let wallet = PRIVATE_KEY
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(CHAIN_ID);
+        let signature = wallet.sign_transaction_sync(&expected).unwrap();
+
+        mock.assert_request("eth_sendPrivateTransaction", [json!({
+            "tx": expected.rlp_signed(&signature)
+        })])
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn signed_transactions_are_sent_to_the_private_relay() {
+        let (relay, relay_mock, chain_mock) = relay(NonceStrategy::ProviderManaged);
+
+        let tx_hash = H256::repeat_byte(2);
+        relay_mock.push(tx_hash).unwrap();
+        chain_mock.push(U256::from(7)).unwrap();
+
+        let tx_id = relay.send_transaction(register_tx(), false).await.unwrap();
+
+        assert_nonce_query(&chain_mock);
+        assert_sent_with_nonce(&relay_mock, 7);
+        assert_eq!(tx_id.0, format!("{tx_hash:?}"));
+    }
+
+    #[test]
+    fn raw_transaction_recovers_to_the_relayer() {
+        let mut tx = register_tx();
+        tx.set_from(address());
+        tx.set_nonce(7);
+
+        let wallet = PRIVATE_KEY
+            .parse::<LocalWallet>()
+            .unwrap()
+            .with_chain_id(CHAIN_ID);
+        let raw = tx.rlp_signed(&wallet.sign_transaction_sync(&tx).unwrap());
+
+        let (decoded, signature) = TypedTransaction::decode_signed(&Rlp::new(&raw)).unwrap();
+        assert_eq!(signature.recover(decoded.sighash()).unwrap(), address());
+        assert_eq!(decoded.nonce(), Some(&U256::from(7)));
+    }
+
+    #[tokio::test]
+    async fn receipts_are_polled_from_the_chain() {
+        let (relay, relay_mock, chain_mock) = relay(NonceStrategy::ProviderManaged);
+
+        let tx_hash = H256::repeat_byte(2);
+        chain_mock
+            .push(TransactionReceipt {
+                transaction_hash: tx_hash,
+                ..TransactionReceipt::default()
+            })
+            .unwrap();
+
+        let result = relay
+            .mine_transaction(TransactionId(format!("{tx_hash:?}")))
+            .await
+            .unwrap();
+
+        assert_eq!(result.hash, Some(tx_hash));
+        chain_mock
+            .assert_request("eth_getTransactionReceipt", [tx_hash])
+            .unwrap();
+        assert!(relay_mock
+            .assert_request("eth_getTransactionReceipt", [tx_hash])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn submitted_transactions_are_pending_until_mined() {
+        let (relay, relay_mock, chain_mock) = relay(NonceStrategy::LocallyTracked);
+
+        let tx_hash = H256::repeat_byte(2);
+        relay_mock.push(tx_hash).unwrap();
+        chain_mock.push(U256::from(7)).unwrap();
+
+        let tx_id = relay.send_transaction(register_tx(), false).await.unwrap();
+        let pending = relay.fetch_pending_transactions().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, tx_id.0);
+
+        chain_mock
+            .push(TransactionReceipt {
+                transaction_hash: tx_hash,
+                ..TransactionReceipt::default()
+            })
+            .unwrap();
+        relay.mine_transaction(tx_id).await.unwrap();
+
+        assert!(relay.fetch_pending_transactions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tracked_nonces_are_sequential() {
+        let (relay, relay_mock, chain_mock) = relay(NonceStrategy::LocallyTracked);
+
+        // Responses are popped from the back
+        for byte in (1..=3).rev() {
+            relay_mock.push(H256::repeat_byte(byte)).unwrap();
+        }
+        chain_mock.push(U256::from(5)).unwrap();
+
+        for _ in 0..3 {
+            relay.send_transaction(register_tx(), false).await.unwrap();
+        }
+
+        assert_nonce_query(&chain_mock);
+        for nonce in 5..8 {
+            assert_sent_with_nonce(&relay_mock, nonce);
+        }
+    }
+
+    #[tokio::test]
+    async fn nonce_is_reseeded_after_a_failed_submission() {
+        let (relay, relay_mock, chain_mock) = relay(NonceStrategy::LocallyTracked);
+
+        // Responses are popped from the back
+        relay_mock.push(H256::repeat_byte(2)).unwrap();
+        relay_mock.push_response(MockResponse::Error(JsonRpcError {
+            code:    -32000,
+            message: "nonce too low".to_string(),
+            data:    None,
+        }));
+        relay_mock.push(H256::repeat_byte(1)).unwrap();
+        chain_mock.push(U256::from(6)).unwrap();
+        chain_mock.push(U256::from(5)).unwrap();
+
+        relay.send_transaction(register_tx(), false).await.unwrap();
+        relay
+            .send_transaction(register_tx(), false)
+            .await
+            .unwrap_err();
+        relay.send_transaction(register_tx(), false).await.unwrap();
+
+        assert_nonce_query(&chain_mock);
+        assert_sent_with_nonce(&relay_mock, 5);
+        assert_sent_with_nonce(&relay_mock, 6);
+        assert_nonce_query(&chain_mock);
+        assert_sent_with_nonce(&relay_mock, 6);
+    }
+}
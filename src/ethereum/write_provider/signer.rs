@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::Middleware;
+#[cfg(feature = "aws-kms")]
+use ethers::signers::{AwsSigner, AwsSignerError};
+use ethers::signers::{LocalWallet, Signer, WalletError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip712::Eip712;
+use ethers::types::{Address, Signature, H256};
+use thiserror::Error;
+use tracing::info;
+
+use super::inner::{await_signed_transaction, Inner, TransactionResult};
+use crate::config::SignerKind;
+use crate::ethereum::write::TransactionId;
+use crate::ethereum::TxError;
+
+/// A signer for any of the supported [`SignerKind`]s.
+#[derive(Debug)]
+pub enum TxSigner {
+    Local(LocalWallet),
+    #[cfg(feature = "aws-kms")]
+    AwsKms(AwsSigner),
+}
+
+#[derive(Debug, Error)]
+pub enum TxSignerError {
+    #[error(transparent)]
+    Local(#[from] WalletError),
+    #[cfg(feature = "aws-kms")]
+    #[error(transparent)]
+    AwsKms(#[from] AwsSignerError),
+}
+
+impl TxSigner {
+    /// Loads the signer for `kind`, signing for the chain with the given id.
+    pub async fn load(kind: &SignerKind, chain_id: u64) -> anyhow::Result<Self> {
+        match kind {
+            SignerKind::LocalKey { private_key } => {
+                let wallet: LocalWallet = private_key.trim_start_matches("0x").parse()?;
+
+                Ok(Self::Local(wallet.with_chain_id(chain_id)))
+            }
+            #[cfg(feature = "aws-kms")]
+            SignerKind::AwsKms { key_id, region } => {
+                let region: rusoto_core::Region = region.parse()?;
+                let client = rusoto_kms::KmsClient::new(region);
+
+                Ok(Self::AwsKms(
+                    AwsSigner::new(client, key_id, chain_id).await?,
+                ))
+            }
+            #[cfg(not(feature = "aws-kms"))]
+            SignerKind::AwsKms { .. } => Err(anyhow::anyhow!(
+                "Signing with AWS KMS requires the `aws-kms` feature"
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for TxSigner {
+    type Error = TxSignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(signer) => Ok(signer.sign_message(message).await?),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => Ok(signer.sign_message(message).await?),
+        }
+    }
+
+    async fn sign_transaction(&self, message: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(signer) => Ok(signer.sign_transaction(message).await?),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => Ok(signer.sign_transaction(message).await?),
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            Self::Local(signer) => Ok(signer.sign_typed_data(payload).await?),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => Ok(signer.sign_typed_data(payload).await?),
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            Self::Local(signer) => signer.address(),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => signer.address(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            Self::Local(signer) => signer.chain_id(),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => signer.chain_id(),
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            Self::Local(signer) => Self::Local(signer.with_chain_id(chain_id)),
+            #[cfg(feature = "aws-kms")]
+            Self::AwsKms(signer) => Self::AwsKms(signer.with_chain_id(chain_id)),
+        }
+    }
+}
+
+/// Signs transactions locally and sends them through the given provider.
+///
+/// The provider fills in the nonce and fees of every transaction. The
+/// transaction hash doubles as the transaction id.
+pub struct SignerRelay<M> {
+    client:         SignerMiddleware<M, TxSigner>,
+    mining_timeout: Duration,
+}
+
+impl<M: Middleware + 'static> SignerRelay<M> {
+    pub fn new(provider: M, signer: TxSigner, mining_timeout: Duration) -> Self {
+        Self {
+            client: SignerMiddleware::new(provider, signer),
+            mining_timeout,
+        }
+    }
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> Inner for SignerRelay<M> {
+    async fn send_transaction(
+        &self,
+        tx: TypedTransaction,
+        _only_once: bool,
+    ) -> Result<TransactionId, TxError> {
+        let pending = self
+            .client
+            .send_transaction(tx, None)
+            .await
+            .map_err(|err| TxError::Send(err.into()))?;
+        let tx_hash = pending.tx_hash();
+
+        info!(?tx_hash, "Signed transaction submitted");
+
+        Ok(TransactionId(format!("{tx_hash:?}")))
+    }
+
+    async fn fetch_pending_transactions(&self) -> Result<Vec<TransactionId>, TxError> {
+        // Transactions are not tracked once they are handed to the provider
+        Ok(vec![])
+    }
+
+    async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError> {
+        let tx_hash: H256 = tx.0.parse().map_err(|err| TxError::Parse(Box::new(err)))?;
+
+        await_signed_transaction(&self.client, tx_hash, self.mining_timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The first account of the default anvil and hardhat mnemonic
+    const PRIVATE_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const ADDRESS: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    #[tokio::test]
+    async fn local_key_signer_is_loaded() {
+        let kind = SignerKind::LocalKey {
+            private_key: PRIVATE_KEY.to_string(),
+        };
+
+        let signer = TxSigner::load(&kind, 5).await.unwrap();
+
+        assert!(matches!(signer, TxSigner::Local(_)));
+        assert_eq!(signer.address(), ADDRESS.parse::<Address>().unwrap());
+        assert_eq!(signer.chain_id(), 5);
+    }
+
+    #[tokio::test]
+    async fn invalid_local_key_is_rejected() {
+        let kind = SignerKind::LocalKey {
+            private_key: "0x1234".to_string(),
+        };
+
+        assert!(TxSigner::load(&kind, 5).await.is_err());
+    }
+
+    #[cfg(not(feature = "aws-kms"))]
+    #[tokio::test]
+    async fn aws_kms_signer_requires_feature() {
+        let kind = SignerKind::AwsKms {
+            key_id: "alias/sequencer".to_string(),
+            region: "eu-central-1".to_string(),
+        };
+
+        let error = TxSigner::load(&kind, 5).await.unwrap_err();
+
+        assert!(error.to_string().contains("aws-kms"));
+    }
+
+    #[cfg(feature = "aws-kms")]
+    #[tokio::test]
+    async fn aws_kms_signer_rejects_unknown_region() {
+        let kind = SignerKind::AwsKms {
+            key_id: "alias/sequencer".to_string(),
+            region: "moon-central-1".to_string(),
+        };
+
+        assert!(TxSigner::load(&kind, 5).await.is_err());
+    }
+}
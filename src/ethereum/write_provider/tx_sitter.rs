@@ -7,7 +7,7 @@ use ethers::types::U256;
 use tx_sitter_client::data::{SendTxRequest, TransactionPriority, TxStatus};
 use tx_sitter_client::TxSitterClient;
 
-use super::inner::{Inner, TransactionResult};
+use super::inner::{sort_by_nonce, Inner, TransactionResult};
 use crate::config::TxSitterConfig;
 use crate::ethereum::write::TransactionId;
 use crate::ethereum::TxError;
@@ -100,10 +100,10 @@ impl Inner for TxSitter {
         let mut txs = vec![];
 
         for tx in unsent_txs.into_iter().chain(pending_txs) {
-            txs.push(TransactionId(tx.tx_id));
+            txs.push((Some(tx.nonce), TransactionId(tx.tx_id)));
         }
 
-        Ok(txs)
+        Ok(sort_by_nonce(txs))
     }
 
     async fn mine_transaction(&self, tx: TransactionId) -> Result<TransactionResult, TxError> {
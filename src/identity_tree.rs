@@ -1,10 +1,12 @@
 use std::cmp::min;
+use std::ops::Range;
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use chrono::Utc;
+use ethers::types::U256;
 use semaphore::lazy_merkle_tree::{Derived, LazyMerkleTree};
 use semaphore::merkle_tree::Hasher;
-use semaphore::poseidon_tree::{PoseidonHash, Proof};
+use semaphore::poseidon_tree::{LazyPoseidonTree, PoseidonHash, Proof};
 use semaphore::{lazy_merkle_tree, Field};
 use serde::Serialize;
 use tracing::{info, warn};
@@ -14,7 +16,16 @@ mod status;
 pub type PoseidonTree<Version> = LazyMerkleTree<PoseidonHash, Version>;
 pub type Hash = <PoseidonHash as Hasher>::Hash;
 
-pub use self::status::{ProcessedStatus, Status, UnknownStatus, UnprocessedStatus};
+pub use self::status::{DeletionStatus, ProcessedStatus, Status, UnknownStatus, UnprocessedStatus};
+use crate::database::Database;
+use crate::utils::field_util::{field_to_u256, u256_to_field};
+
+/// Returns the root of a tree of depth `tree_depth` whose leaves are all
+/// `initial_leaf_value`, e.g. the root of a freshly deployed identity manager.
+#[must_use]
+pub fn empty_tree_root(tree_depth: usize, initial_leaf_value: Field) -> U256 {
+    field_to_u256(LazyPoseidonTree::new(tree_depth, initial_leaf_value).root())
+}
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct TreeUpdate {
@@ -32,6 +43,35 @@ impl TreeUpdate {
     }
 }
 
+/// How full the tree is relative to its depth.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeOccupancy {
+    pub used:     u64,
+    pub capacity: u64,
+    pub fraction: f64,
+}
+
+impl TreeOccupancy {
+    /// Computes the occupancy of a tree of depth `tree_depth` whose first
+    /// `used` leaves have been filled.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(used: usize, tree_depth: usize) -> Self {
+        let used = used as u64;
+        let capacity = u32::try_from(tree_depth)
+            .ok()
+            .and_then(|depth| 1u64.checked_shl(depth))
+            .unwrap_or(u64::MAX);
+
+        Self {
+            used,
+            capacity,
+            fraction: used as f64 / capacity as f64,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct TreeItem {
     pub status:     ProcessedStatus,
@@ -50,10 +90,14 @@ pub struct RootItem {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InclusionProof {
-    pub status:  Status,
-    pub root:    Option<Field>,
-    pub proof:   Option<Proof>,
-    pub message: Option<String>,
+    pub status:      Status,
+    pub root:        Option<Field>,
+    pub proof:       Option<Proof>,
+    pub message:     Option<String>,
+    /// The status of `root` when `status` is about the identity rather than
+    /// the root, i.e. while the identity is pending deletion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_status: Option<ProcessedStatus>,
 }
 
 /// Additional data held by the canonical tree version. It includes data
@@ -64,9 +108,11 @@ pub struct CanonicalTreeMetadata {
 }
 
 /// Additional data held by any derived tree version. Includes the list of
-/// updates performed since previous version.
+/// updates performed since previous version, and the tree they were applied
+/// to.
 pub struct DerivedTreeMetadata {
     diff: Vec<AppliedTreeUpdate>,
+    base: PoseidonTree<lazy_merkle_tree::Derived>,
 }
 
 #[derive(Clone)]
@@ -191,6 +237,7 @@ where
             };
 
             let applied_updates: Vec<_> = next.metadata.diff.drain(..=index_of_root).collect();
+            next.metadata.base = applied_updates[index_of_root].result.clone();
 
             num_updates = applied_updates.len();
 
@@ -243,7 +290,23 @@ impl BasicTreeOps for TreeVersionData<lazy_merkle_tree::Canonical> {
 }
 
 impl TreeVersionData<lazy_merkle_tree::Derived> {
+    /// The number of updates in the diff that are kept when rolling back to
+    /// `root`, or `None` if `root` was never reached by this version.
+    fn updates_retained_at(&self, root: Hash) -> Option<usize> {
+        if self.tree.root() == root {
+            return Some(self.metadata.diff.len());
+        }
+
+        self.metadata
+            .diff
+            .iter()
+            .rposition(|update| update.result.root() == root)
+            .map(|index| index + 1)
+            .or_else(|| (self.metadata.base.root() == root).then_some(0))
+    }
+
     fn rebuild_on(&mut self, mut tree: PoseidonTree<lazy_merkle_tree::Derived>) {
+        self.metadata.base = tree.clone();
         for update in &mut self.metadata.diff {
             tree = tree.update(update.update.leaf_index, &update.update.element);
             update.result = tree.clone();
@@ -360,6 +423,31 @@ impl<V: Version<TreeVersion = lazy_merkle_tree::Derived>> TreeVersion<V> {
     fn as_derived(&self) -> TreeVersion<AnyDerived> {
         TreeVersion(self.0.clone())
     }
+
+    /// Returns the current root and the root after appending `commitments`
+    /// from the next free leaf on. The commitments are applied to a clone of
+    /// the tree, which is left untouched.
+    pub fn roots_after_append(&self, commitments: &[Hash]) -> anyhow::Result<(Hash, Hash)> {
+        let (mut tree, next_leaf) = {
+            let data = self.get_data();
+            (data.tree.clone(), data.next_leaf)
+        };
+        let pre_root = tree.root();
+
+        let capacity = 1_usize.checked_shl(tree.depth() as u32);
+        if capacity.is_some_and(|capacity| next_leaf + commitments.len() > capacity) {
+            return Err(anyhow::anyhow!(
+                "Batch of {} commitments does not fit into the tree after leaf {next_leaf}",
+                commitments.len()
+            ));
+        }
+
+        for (leaf_index, commitment) in (next_leaf..).zip(commitments) {
+            tree = tree.update(leaf_index, commitment);
+        }
+
+        Ok((pre_root, tree.root()))
+    }
 }
 
 /// The public-facing API for reading from a tree version. It is implemented for
@@ -376,6 +464,9 @@ pub trait TreeVersionReadOps {
     fn get_proof(&self, leaf: usize) -> (Hash, Proof);
     /// Gets the leaf value at a given index.
     fn get_leaf(&self, leaf: usize) -> Hash;
+    /// Returns the root of the tree and the proofs for a range of leaves, all
+    /// taken from the same snapshot of the tree.
+    fn get_proofs(&self, leaves: Range<usize>) -> (Hash, Vec<Proof>);
 }
 
 impl<V: Version> TreeVersionReadOps for TreeVersion<V>
@@ -408,6 +499,13 @@ where
         let tree = self.get_data();
         tree.get_leaf(leaf)
     }
+
+    fn get_proofs(&self, leaves: Range<usize>) -> (Hash, Vec<Proof>) {
+        let tree = self.get_data();
+        let proofs = leaves.map(|leaf| tree.get_proof(leaf).1).collect();
+
+        (tree.get_root(), proofs)
+    }
 }
 
 impl<V: Version> TreeVersion<V> {
@@ -438,6 +536,46 @@ impl TreeVersion<Latest> {
         output
     }
 
+    /// Inserts a batch of commitments starting at leaf `start_index`, which
+    /// must be the next free leaf, and returns the new root.
+    ///
+    /// The batch is applied all-or-nothing: it is checked as a whole before
+    /// any leaf is touched, and the tree stays locked while it is applied, so
+    /// no partially applied batch is ever observed.
+    pub fn apply_batch(&self, start_index: usize, commitments: &[U256]) -> anyhow::Result<U256> {
+        let mut data = self.get_data();
+
+        if start_index != data.next_leaf {
+            return Err(anyhow::anyhow!(
+                "Batch starts at leaf {start_index}, but the next free leaf is {}",
+                data.next_leaf
+            ));
+        }
+
+        let capacity = 1_usize.checked_shl(data.tree.depth() as u32);
+        let end_index = start_index + commitments.len();
+        if capacity.is_some_and(|capacity| end_index > capacity) {
+            return Err(anyhow::anyhow!(
+                "Batch of {} commitments does not fit into the tree after leaf {start_index}",
+                commitments.len()
+            ));
+        }
+
+        // A zero commitment would be taken for a deletion
+        if let Some(offset) = commitments.iter().position(U256::is_zero) {
+            return Err(anyhow::anyhow!(
+                "Commitment at leaf {} of the batch is zero",
+                start_index + offset
+            ));
+        }
+
+        for (leaf_index, commitment) in (start_index..).zip(commitments) {
+            data.update(leaf_index, u256_to_field(*commitment));
+        }
+
+        Ok(field_to_u256(data.get_root()))
+    }
+
     /// Deletes many identities from the tree, returns a list with the root
     /// and proof of inclusion
     #[must_use]
@@ -455,6 +593,69 @@ impl TreeVersion<Latest> {
 
         output
     }
+
+    /// Reverts the updates applied after `root` was reached, e.g. when a batch
+    /// turns out to be invalid, and removes the pending identities they
+    /// recorded in the database.
+    ///
+    /// Only roots reached since the previous version was last updated can be
+    /// rolled back to, including the root of the previous version itself.
+    /// Errors if `root` isn't one of them. The tree is checked and rolled back
+    /// under a single lock before the database is touched, and is restored if
+    /// removing the identities fails, so that a failure leaves both untouched.
+    pub async fn rollback_to(&self, database: &Database, root: U256) -> anyhow::Result<()> {
+        let root: Hash = root.into();
+
+        let (reverted, previous_tree, previous_next_leaf) = {
+            let mut data = self.get_data();
+
+            let retained = data
+                .updates_retained_at(root)
+                .ok_or_else(|| anyhow::anyhow!("Root {root:?} not found in the tree history"))?;
+
+            let previous_tree = data.tree.clone();
+            let previous_next_leaf = data.next_leaf;
+            let reverted: Vec<_> = data.metadata.diff.drain(retained..).collect();
+
+            // Insertions are appended in order, so the first reverted one marks the
+            // next free leaf
+            if let Some(first_insertion) = reverted
+                .iter()
+                .find(|applied| applied.update.element != Hash::ZERO)
+            {
+                data.next_leaf = first_insertion.update.leaf_index;
+            }
+
+            data.tree = match data.metadata.diff.last() {
+                Some(last) => last.result.clone(),
+                None => data.metadata.base.clone(),
+            };
+
+            (reverted, previous_tree, previous_next_leaf)
+        };
+
+        if let Err(error) = database.remove_pending_identities_after(&root).await {
+            let mut data = self.get_data();
+
+            // Updates applied since were built on the rolled back tree, in which
+            // case the reverted ones can't be put back
+            if data.tree.root() == root {
+                data.metadata.diff.extend(reverted);
+                data.tree = previous_tree;
+                data.next_leaf = previous_next_leaf;
+            }
+
+            return Err(error.into());
+        }
+
+        info!(
+            ?root,
+            reverted = reverted.len(),
+            "Rolled back the latest tree"
+        );
+
+        Ok(())
+    }
 }
 
 impl<T> TreeVersion<T>
@@ -564,14 +765,75 @@ impl TreeState {
         };
 
         let proof = InclusionProof {
-            status:  item.status.into(),
-            root:    Some(root),
-            proof:   Some(proof),
-            message: None,
+            status:      item.status.into(),
+            root:        Some(root),
+            proof:       Some(proof),
+            message:     None,
+            root_status: None,
         };
 
         (leaf, proof)
     }
+
+    /// Returns inclusion proofs for up to `count` consecutive leaves starting
+    /// at `start`. Leaves past the end of the latest tree are left out, but
+    /// `start` itself has to be a leaf index of the tree.
+    ///
+    /// Every leaf is proven against the most final tree version that contains
+    /// it, and each version is locked only once for the whole range. The trees
+    /// store every node, so a proof is only a lookup along its path and
+    /// nothing is hashed for the part the paths share.
+    pub fn inclusion_proofs_range(
+        &self,
+        start: usize,
+        count: usize,
+    ) -> anyhow::Result<Vec<InclusionProof>> {
+        let depth = self.latest.get_data().tree.depth();
+        if 1_usize
+            .checked_shl(depth as u32)
+            .is_some_and(|capacity| start >= capacity)
+        {
+            return Err(anyhow::anyhow!(
+                "Leaf {start} is out of the bounds of a tree of depth {depth}"
+            ));
+        }
+
+        let end = min(start.saturating_add(count), self.latest.next_leaf());
+        let mined_end = min(end, self.mined.next_leaf()).max(start);
+        let processed_end = min(end, self.processed.next_leaf()).max(mined_end);
+
+        let versions: [(&dyn TreeVersionReadOps, Range<usize>, ProcessedStatus); 3] = [
+            (&self.mined, start..mined_end, ProcessedStatus::Mined),
+            (
+                &self.processed,
+                mined_end..processed_end,
+                ProcessedStatus::Processed,
+            ),
+            (
+                &self.latest,
+                processed_end..end.max(processed_end),
+                ProcessedStatus::Pending,
+            ),
+        ];
+
+        let mut proofs = vec![];
+        for (tree, leaves, status) in versions {
+            if leaves.is_empty() {
+                continue;
+            }
+
+            let (root, leaf_proofs) = tree.get_proofs(leaves);
+            proofs.extend(leaf_proofs.into_iter().map(|proof| InclusionProof {
+                status:      status.into(),
+                root:        Some(root),
+                proof:       Some(proof),
+                message:     None,
+                root_status: None,
+            }));
+        }
+
+        Ok(proofs)
+    }
 }
 
 /// A helper for building the first tree version. Exposes a type-safe API over
@@ -704,7 +966,10 @@ impl<P: Version> DerivedTreeBuilder<P> {
         next_leaf: usize,
         prev: TreeVersion<Prev>,
     ) -> DerivedTreeBuilder<Prev> {
-        let metadata = DerivedTreeMetadata { diff: vec![] };
+        let metadata = DerivedTreeMetadata {
+            diff: vec![],
+            base: tree.clone(),
+        };
         DerivedTreeBuilder {
             prev,
             current: TreeVersionData {
@@ -746,7 +1011,112 @@ impl<P: Version> DerivedTreeBuilder<P> {
 #[cfg(test)]
 mod tests {
 
-    use super::{CanonicalTreeBuilder, Hash, TreeWithNextVersion};
+    use ethers::types::U256;
+    use semaphore::poseidon_tree::LazyPoseidonTree;
+    use semaphore::Field;
+    use test_case::test_case;
+
+    use super::{
+        empty_tree_root, CanonicalTreeBuilder, Hash, Latest, ProcessedStatus, Status,
+        TreeOccupancy, TreeState, TreeUpdate, TreeVersion, TreeVersionReadOps, TreeWithNextVersion,
+    };
+    use crate::database::Database;
+    use crate::test_utils::{empty_trees, setup_db};
+    use crate::utils::field_util::field_to_u256;
+
+    #[test_case(0, "0x0" ; "depth 0")]
+    #[test_case(1, "0x2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864" ; "depth 1")]
+    #[test_case(2, "0x1069673dcdb12263df301a6ff584a7ec261a44cb9dc68df067a4774460b1f1e1" ; "depth 2")]
+    #[test_case(3, "0x18f43331537ee2af2e3d758d50f72106467c6eea50371dd528d57eb2b856d238" ; "depth 3")]
+    #[test_case(5, "0x2b94cf5e8746b3f5c9631f4c5df32907a699c58c94b2ad4d7b5cec1639183f55" ; "depth 5")]
+    fn empty_tree_root_matches_known_zero_hashes(tree_depth: usize, expected: &'static str) {
+        assert_eq!(
+            empty_tree_root(tree_depth, Field::ZERO),
+            U256::from(expected)
+        );
+    }
+
+    #[test]
+    fn empty_tree_root_depends_on_the_initial_leaf_value() {
+        let initial_leaf_value = Field::from(7);
+
+        assert_eq!(empty_tree_root(0, initial_leaf_value), U256::from(7));
+        assert_ne!(
+            empty_tree_root(10, initial_leaf_value),
+            empty_tree_root(10, Field::ZERO)
+        );
+    }
+
+    /// Builds a tree state with leaves `0..2` mined, leaf `2` processed and
+    /// leaves `3..5` pending.
+    fn tree_state(temp_dir: &tempfile::TempDir) -> TreeState {
+        let (mined, mut processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[Hash::from(1), Hash::from(2)],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        processed_builder.update(&TreeUpdate::new(2, Hash::from(3)));
+
+        let (processed, batching_builder) = processed_builder.seal_and_continue();
+        let (batching, mut latest_builder) = batching_builder.seal_and_continue();
+        latest_builder.update(&TreeUpdate::new(3, Hash::from(4)));
+        latest_builder.update(&TreeUpdate::new(4, Hash::from(5)));
+
+        TreeState::new(mined, processed, batching, latest_builder.seal())
+    }
+
+    #[test]
+    fn inclusion_proofs_range_within_the_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tree_state = tree_state(&temp_dir);
+
+        let proofs = tree_state.inclusion_proofs_range(1, 3).unwrap();
+
+        let statuses: Vec<Status> = proofs.iter().map(|proof| proof.status).collect();
+        assert_eq!(statuses, vec![
+            ProcessedStatus::Mined.into(),
+            ProcessedStatus::Processed.into(),
+            ProcessedStatus::Pending.into(),
+        ]);
+
+        for (leaf, proof) in (2..=4).map(Hash::from).zip(&proofs) {
+            let merkle_proof = proof.proof.as_ref().unwrap();
+            assert_eq!(Some(merkle_proof.root(leaf)), proof.root);
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_range_past_the_end_of_the_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tree_state = tree_state(&temp_dir);
+
+        let proofs = tree_state.inclusion_proofs_range(3, 10).unwrap();
+        assert_eq!(proofs.len(), 2);
+        assert_eq!(proofs[1].root, Some(tree_state.latest_tree().get_root()));
+
+        assert_eq!(
+            tree_state
+                .inclusion_proofs_range(0, usize::MAX)
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(tree_state.inclusion_proofs_range(5, 10).unwrap().is_empty());
+        assert!(tree_state.inclusion_proofs_range(2, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn inclusion_proofs_range_outside_the_tree_should_fail() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tree_state = tree_state(&temp_dir);
+
+        assert!(tree_state.inclusion_proofs_range(1 << 10, 1).is_err());
+        assert!(tree_state.inclusion_proofs_range(usize::MAX, 1).is_err());
+    }
 
     #[test]
     fn test_peek_next_updates() {
@@ -795,4 +1165,192 @@ mod tests {
 
         assert_eq!(next_updates.len(), 3);
     }
+
+    /// Appends the commitments to the tree and records them as pending
+    /// identities, as the identity processor does.
+    async fn append_pending(
+        db: &Database,
+        tree: &TreeVersion<Latest>,
+        commitments: &[Hash],
+    ) -> anyhow::Result<Hash> {
+        for ((root, _, leaf_index), commitment) in
+            tree.append_many(commitments).iter().zip(commitments)
+        {
+            db.insert_pending_identity(*leaf_index, commitment, root)
+                .await?;
+        }
+
+        Ok(tree.get_root())
+    }
+
+    #[tokio::test]
+    async fn rollback_to_intermediate_root() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let temp_dir = tempfile::tempdir()?;
+        let (_, latest_tree) = empty_trees(10, &temp_dir);
+
+        let intermediate_root =
+            append_pending(&db, &latest_tree, &[Hash::from(1), Hash::from(2)]).await?;
+
+        append_pending(&db, &latest_tree, &[Hash::from(3), Hash::from(4)]).await?;
+        let (deletion_root, _) = latest_tree.delete_many(&[0])[0].clone();
+        db.insert_pending_identity(0, &Hash::ZERO, &deletion_root)
+            .await?;
+        assert_ne!(latest_tree.get_root(), intermediate_root);
+
+        latest_tree
+            .rollback_to(&db, intermediate_root.into())
+            .await?;
+
+        assert_eq!(latest_tree.get_root(), intermediate_root);
+        assert_eq!(latest_tree.next_leaf(), 2);
+        assert_eq!(latest_tree.get_leaf(0), Hash::from(1));
+        assert_eq!(latest_tree.get_leaf(2), Hash::ZERO);
+        assert_eq!(db.count_pending_identities().await?, 2);
+
+        // Appending continues from the rolled back state
+        let replayed = latest_tree.append_many(&[Hash::from(3)]);
+        assert_eq!(replayed[0].2, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rollback_to_base_root() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let temp_dir = tempfile::tempdir()?;
+        let (canonical_tree, latest_tree) = empty_trees(10, &temp_dir);
+        let empty_root = latest_tree.get_root();
+
+        append_pending(&db, &latest_tree, &[Hash::from(1), Hash::from(2)]).await?;
+        append_pending(&db, &latest_tree, &[Hash::from(3)]).await?;
+
+        // Rolling back every update restores the empty tree
+        latest_tree.rollback_to(&db, empty_root.into()).await?;
+
+        assert_eq!(latest_tree.get_root(), empty_root);
+        assert_eq!(latest_tree.next_leaf(), 0);
+        assert_eq!(db.count_pending_identities().await?, 0);
+
+        // Once the previous version took updates, its root is the new base
+        let first_root = append_pending(&db, &latest_tree, &[Hash::from(1), Hash::from(2)]).await?;
+        append_pending(&db, &latest_tree, &[Hash::from(3)]).await?;
+        assert_eq!(canonical_tree.apply_updates_up_to(first_root), 2);
+
+        latest_tree.rollback_to(&db, first_root.into()).await?;
+
+        assert_eq!(latest_tree.get_root(), first_root);
+        assert_eq!(latest_tree.next_leaf(), 2);
+        assert_eq!(db.count_pending_identities().await?, 2);
+        assert!(latest_tree
+            .rollback_to(&db, empty_root.into())
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rollback_to_unknown_root_should_fail() -> anyhow::Result<()> {
+        let (db, _db_container) = setup_db().await?;
+        let temp_dir = tempfile::tempdir()?;
+        let (_, latest_tree) = empty_trees(10, &temp_dir);
+
+        let root = append_pending(&db, &latest_tree, &[Hash::from(1), Hash::from(2)]).await?;
+
+        assert!(latest_tree.rollback_to(&db, U256::from(42)).await.is_err());
+        assert_eq!(latest_tree.get_root(), root);
+        assert_eq!(db.count_pending_identities().await?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch_is_applied_as_a_whole() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let latest_tree = processed_builder.seal();
+        let _ = latest_tree.append_many(&[Hash::from(1)]);
+
+        let root = latest_tree
+            .apply_batch(1, &[U256::from(2), U256::from(3)])
+            .unwrap();
+
+        let expected = LazyPoseidonTree::new(10, Hash::ZERO)
+            .update(0, &Hash::from(1))
+            .update(1, &Hash::from(2))
+            .update(2, &Hash::from(3));
+        assert_eq!(root, field_to_u256(expected.root()));
+        assert_eq!(latest_tree.get_root(), expected.root());
+        assert_eq!(latest_tree.next_leaf(), 3);
+    }
+
+    #[test_case(2, &[2, 3] ; "wrong start index")]
+    #[test_case(1, &[2, 0, 3] ; "zero commitment")]
+    #[test_case(1, &[2, 3, 4, 5] ; "batch beyond capacity")]
+    fn failing_batch_leaves_the_tree_unchanged(start_index: usize, commitments: &[u64]) {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            2,
+            2,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let latest_tree = processed_builder.seal();
+        let _ = latest_tree.append_many(&[Hash::from(1)]);
+        let root = latest_tree.get_root();
+
+        let commitments: Vec<U256> = commitments.iter().map(|c| U256::from(*c)).collect();
+
+        assert!(latest_tree.apply_batch(start_index, &commitments).is_err());
+        assert_eq!(latest_tree.get_root(), root);
+        assert_eq!(latest_tree.next_leaf(), 1);
+        assert_eq!(latest_tree.get_leaf(1), Hash::ZERO);
+    }
+
+    #[test]
+    fn occupancy_of_partially_filled_tree() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            2,
+            2,
+            0,
+            Hash::ZERO,
+            &[],
+            temp_dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let latest_tree = processed_builder.seal();
+
+        let _ = latest_tree.append_many(&[Hash::from(1), Hash::from(2), Hash::from(3)]);
+
+        let occupancy = TreeOccupancy::new(latest_tree.next_leaf(), 2);
+
+        assert_eq!(occupancy, TreeOccupancy {
+            used:     3,
+            capacity: 4,
+            fraction: 0.75,
+        });
+    }
+
+    #[test]
+    fn occupancy_of_empty_and_deep_trees() {
+        assert_eq!(TreeOccupancy::new(0, 30).fraction, 0.0);
+        assert_eq!(TreeOccupancy::new(1 << 29, 30).fraction, 0.5);
+        assert_eq!(TreeOccupancy::new(0, 64).capacity, u64::MAX);
+    }
 }
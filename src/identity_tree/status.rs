@@ -36,6 +36,15 @@ pub enum UnprocessedStatus {
     New,
 }
 
+/// Status of identity commitments which are queued to be deleted from the tree
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum DeletionStatus {
+    /// The identity is still in the tree but a deletion has been requested
+    /// and is waiting to be batched.
+    PendingDeletion,
+}
+
 /// A status type visible on the API level - contains both the processed and
 /// unprocessed statuses
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -44,6 +53,7 @@ pub enum UnprocessedStatus {
 pub enum Status {
     Unprocessed(UnprocessedStatus),
     Processed(ProcessedStatus),
+    Deletion(DeletionStatus),
 }
 
 #[derive(Debug, Error)]
@@ -81,6 +91,8 @@ impl FromStr for Status {
             Ok(Self::Unprocessed(s))
         } else if let Ok(s) = ProcessedStatus::from_str(s) {
             Ok(Self::Processed(s))
+        } else if let Ok(s) = DeletionStatus::from_str(s) {
+            Ok(Self::Deletion(s))
         } else {
             Err(UnknownStatus)
         }
@@ -106,6 +118,25 @@ impl From<UnprocessedStatus> for &str {
     }
 }
 
+impl FromStr for DeletionStatus {
+    type Err = UnknownStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pendingDeletion" => Ok(Self::PendingDeletion),
+            _ => Err(UnknownStatus),
+        }
+    }
+}
+
+impl From<DeletionStatus> for &str {
+    fn from(scope: DeletionStatus) -> Self {
+        match scope {
+            DeletionStatus::PendingDeletion => "pendingDeletion",
+        }
+    }
+}
+
 impl From<UnprocessedStatus> for Status {
     fn from(status: UnprocessedStatus) -> Self {
         Self::Unprocessed(status)
@@ -118,6 +149,12 @@ impl From<ProcessedStatus> for Status {
     }
 }
 
+impl From<DeletionStatus> for Status {
+    fn from(status: DeletionStatus) -> Self {
+        Self::Deletion(status)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use test_case::test_case;
@@ -127,6 +164,7 @@ mod tests {
     #[test_case(Status::Processed(ProcessedStatus::Pending) => "pending")]
     #[test_case(Status::Processed(ProcessedStatus::Mined) => "mined")]
     #[test_case(Status::Unprocessed(UnprocessedStatus::New) => "new")]
+    #[test_case(Status::Deletion(DeletionStatus::PendingDeletion) => "pendingDeletion")]
     fn serialize_status(api_status: Status) -> &'static str {
         let s = serde_json::to_string(&api_status).unwrap();
 
@@ -139,6 +177,7 @@ mod tests {
     #[test_case("pending" => Status::Processed(ProcessedStatus::Pending))]
     #[test_case("mined" => Status::Processed(ProcessedStatus::Mined))]
     #[test_case("new" => Status::Unprocessed(UnprocessedStatus::New))]
+    #[test_case("pendingDeletion" => Status::Deletion(DeletionStatus::PendingDeletion))]
     fn deserialize_status(s: &str) -> Status {
         // Wrapped because JSON expected `"something"` and not `something`
         let wrapped = format!("\"{s}\"");
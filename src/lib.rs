@@ -10,5 +10,8 @@ mod ethereum;
 pub mod identity_tree;
 pub mod prover;
 pub mod server;
+pub mod stats;
 pub mod task_monitor;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
@@ -35,6 +35,7 @@ async fn sequencer_app(args: Args) -> anyhow::Result<()> {
         .build()?;
 
     let config = settings.try_deserialize::<Config>()?;
+    config.validate()?;
 
     let server_config = config.server.clone();
 
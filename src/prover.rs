@@ -7,29 +7,54 @@
 //! APIs are designed to be imported for use qualified (e.g.
 //! `batch_insertion::Prover`, `batch_insertion::Identity` and so on).
 
+pub mod circuit_breaker;
 pub mod identity;
 pub mod map;
 pub mod proof;
+pub mod proof_cache;
+pub mod queue;
+pub mod rate_limiter;
+pub mod transition;
 
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
-use std::mem::size_of;
-use std::time::Duration;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use ethers::types::U256;
 use ethers::utils::keccak256;
 pub use map::ProverMap;
 use once_cell::sync::Lazy;
 use prometheus::{exponential_buckets, register_histogram, Histogram};
 pub use proof::Proof;
+use semaphore::merkle_tree::Hasher as _;
+use semaphore::poseidon_tree::{LazyPoseidonTree, PoseidonHash};
+use semaphore::Field;
 use serde::{Deserialize, Serialize};
+use tracing::{debug, trace, warn};
 use url::Url;
 
+use crate::config::{AppConfig, Endianness, HashFunction, OnRootMismatch, ProofFormat};
+use crate::prover::circuit_breaker::CircuitBreaker;
 use crate::prover::identity::Identity;
+use crate::prover::proof::SnarkJsProof;
+use crate::prover::proof_cache::ProofCache;
+use crate::prover::rate_limiter::RateLimiter;
+use crate::utils::field_util::{
+    field_to_u256, merkle_proof_siblings, u256_to_be_bytes, u256_to_field, u256_to_le_bytes,
+};
 use crate::utils::index_packing::pack_indices;
 
 /// The endpoint used for proving operations.
 const MTB_PROVE_ENDPOINT: &str = "prove";
+/// The endpoint the prover reports its circuit parameters on.
+const MTB_INFO_ENDPOINT: &str = "info";
+
+/// The User-Agent sent to the provers unless configured otherwise.
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 static TOTAL_PROVING_TIME: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
@@ -100,14 +125,113 @@ impl PartialEq for ProverConfig {
     }
 }
 
+/// Settings that apply to every prover, regardless of its batch size or type.
+#[derive(Clone, Debug, Default)]
+pub struct ProverSettings {
+    /// The maximum number of proof requests sent to a single prover per
+    /// second. Unlimited if not set.
+    pub max_requests_per_second: Option<NonZeroU32>,
+
+    /// A domain separator prepended to the insertion proof input hash
+    /// preimage. The preimage is left untouched if not set.
+    pub input_hash_domain: Option<[u8; 32]>,
+
+    /// The hash function used to compute the proof input hashes.
+    pub input_hash_function: HashFunction,
+
+    /// The byte order of the 256-bit words in the keccak256 input hashes.
+    pub input_hash_endianness: Endianness,
+
+    /// The format the prover returns proofs in.
+    pub proof_format: ProofFormat,
+
+    /// The number of consecutive failures after which a prover is skipped.
+    /// Provers are never skipped if not set.
+    pub failure_threshold: Option<NonZeroU32>,
+
+    /// How long a prover is skipped for after reaching the failure threshold.
+    pub failure_cooldown: Duration,
+
+    /// The User-Agent sent with every request. Defaults to
+    /// `signup-sequencer/<version>` if not set.
+    pub user_agent: Option<String>,
+
+    /// Whether proof requests carry the tree depth and batch size, for
+    /// provers that check them against their circuit.
+    pub send_circuit_parameters: bool,
+
+    /// Whether the public inputs a prover echoes with a proof must hash to the
+    /// input hash the proof was requested for.
+    pub verify_echoed_inputs: bool,
+
+    /// Whether a prover is skipped while the batch size it reports doesn't
+    /// match the configured one.
+    pub pause_on_drift: bool,
+
+    /// The number of merkle proof elements logged per identity when proof
+    /// requests are traced.
+    pub log_truncate_elements: usize,
+
+    /// The largest response body accepted from the prover, in bytes.
+    /// Unbounded if not set.
+    pub max_response_bytes: Option<usize>,
+
+    /// The number of proofs cached by their input hash. Proofs aren't cached
+    /// if not set.
+    pub proof_cache_capacity: Option<usize>,
+
+    /// Which post root a proof is submitted with if the prover reports a
+    /// different one than the sequencer computed.
+    pub on_root_mismatch: OnRootMismatch,
+}
+
+impl From<&AppConfig> for ProverSettings {
+    fn from(config: &AppConfig) -> Self {
+        Self {
+            max_requests_per_second: config.prover_max_requests_per_second,
+            input_hash_domain:       config.input_hash_domain.map(|domain| domain.0),
+            input_hash_function:     config.input_hash_function,
+            input_hash_endianness:   config.input_hash_endianness,
+            proof_format:            config.prover_proof_format,
+            failure_threshold:       config.prover_failure_threshold,
+            failure_cooldown:        config.prover_failure_cooldown,
+            user_agent:              config.prover_user_agent.clone(),
+            send_circuit_parameters: config.prover_circuit_validation,
+            verify_echoed_inputs:    config.verify_echoed_inputs,
+            pause_on_drift:          config.pause_drifted_provers,
+            log_truncate_elements:   config.log_proof_truncate_elements,
+            max_response_bytes:      Some(config.prover_max_response_bytes),
+            proof_cache_capacity:    config.prover_proof_cache_capacity,
+            on_root_mismatch:        config.on_root_mismatch,
+        }
+    }
+}
+
 /// A representation of the connection to the MTB prover service.
 #[derive(Clone, Debug)]
 pub struct Prover {
-    target_url:  Url,
-    client:      reqwest::Client,
-    batch_size:  usize,
-    timeout_s:   u64,
-    prover_type: ProverType,
+    target_url:        Url,
+    client:            Arc<RwLock<reqwest::Client>>,
+    user_agent:        String,
+    batch_size:        usize,
+    timeout_s:         Arc<AtomicU64>,
+    prover_type:       ProverType,
+    rate_limiter:      Option<Arc<RateLimiter>>,
+    input_hash_domain: Option<[u8; 32]>,
+    hash_function:     HashFunction,
+    endianness:        Endianness,
+    proof_format:      ProofFormat,
+    circuit_breaker:   Option<Arc<CircuitBreaker>>,
+    backend:           Option<Arc<dyn ProveBackend>>,
+
+    send_circuit_parameters: bool,
+    verify_echoed_inputs:    bool,
+    pause_on_drift:          bool,
+    batch_size_drifted:      Arc<AtomicBool>,
+    log_truncate_elements:   usize,
+    max_response_bytes:      Option<usize>,
+    proof_cache:             Option<Arc<ProofCache>>,
+    on_root_mismatch:        OnRootMismatch,
 }
 
 impl Prover {
@@ -115,42 +239,76 @@ impl Prover {
     ///
     /// # Arguments
     /// - `options`: The prover configuration options.
-    pub fn new(options: &ProverConfig) -> anyhow::Result<Self> {
+    /// - `settings`: The settings shared by all provers.
+    pub fn new(options: &ProverConfig, settings: &ProverSettings) -> anyhow::Result<Self> {
+        if settings.input_hash_function == HashFunction::Poseidon
+            && settings.input_hash_domain.is_some()
+        {
+            return Err(anyhow::anyhow!(
+                "An input hash domain can only be used with the keccak256 input hash"
+            ));
+        }
+
         let target_url = Url::parse(&options.url)?;
-        let timeout_duration = Duration::from_secs(options.timeout_s);
-        let client = reqwest::Client::builder()
-            .connect_timeout(timeout_duration)
-            .https_only(false)
-            .build()?;
+        let user_agent = settings
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        let client = build_client(options.timeout_s, &user_agent)?;
 
         let mtb = Self {
             target_url,
-            client,
+            client: Arc::new(RwLock::new(client)),
+            user_agent,
             batch_size: options.batch_size,
-            timeout_s: options.timeout_s,
+            timeout_s: Arc::new(AtomicU64::new(options.timeout_s)),
             prover_type: options.prover_type,
+            rate_limiter: settings
+                .max_requests_per_second
+                .map(|rate| Arc::new(RateLimiter::new(rate))),
+            input_hash_domain: settings.input_hash_domain,
+            hash_function: settings.input_hash_function,
+            endianness: settings.input_hash_endianness,
+            proof_format: settings.proof_format,
+            circuit_breaker: settings.failure_threshold.map(|threshold| {
+                Arc::new(CircuitBreaker::new(threshold, settings.failure_cooldown))
+            }),
+            backend: None,
+            send_circuit_parameters: settings.send_circuit_parameters,
+            verify_echoed_inputs: settings.verify_echoed_inputs,
+            pause_on_drift: settings.pause_on_drift,
+            batch_size_drifted: Arc::new(AtomicBool::new(false)),
+            log_truncate_elements: settings.log_truncate_elements,
+            max_response_bytes: settings.max_response_bytes,
+            proof_cache: settings
+                .proof_cache_capacity
+                .map(|capacity| Arc::new(ProofCache::new(capacity))),
+            on_root_mismatch: settings.on_root_mismatch,
         };
 
         Ok(mtb)
     }
 
+    /// Constructs a prover that sends its proof requests to the provided
+    /// backend instead of the prover service at `options.url`.
+    pub fn with_backend(
+        options: &ProverConfig,
+        settings: &ProverSettings,
+        backend: Arc<dyn ProveBackend>,
+    ) -> anyhow::Result<Self> {
+        let mut prover = Self::new(options, settings)?;
+        prover.backend = Some(backend);
+
+        Ok(prover)
+    }
+
     /// Creates a new batch insertion prover from the prover taken from the
     /// database
-    pub fn from_prover_conf(prover_conf: &ProverConfig) -> anyhow::Result<Self> {
-        let target_url = Url::parse(&prover_conf.url)?;
-        let timeout_duration = Duration::from_secs(prover_conf.timeout_s);
-        let client = reqwest::Client::builder()
-            .connect_timeout(timeout_duration)
-            .https_only(false)
-            .build()?;
-
-        Ok(Self {
-            target_url,
-            client,
-            batch_size: prover_conf.batch_size,
-            timeout_s: prover_conf.timeout_s,
-            prover_type: prover_conf.prover_type,
-        })
+    pub fn from_prover_conf(
+        prover_conf: &ProverConfig,
+        settings: &ProverSettings,
+    ) -> anyhow::Result<Self> {
+        Self::new(prover_conf, settings)
     }
 
     pub fn batch_size(&self) -> usize {
@@ -161,8 +319,102 @@ impl Prover {
         self.prover_type
     }
 
+    pub fn on_root_mismatch(&self) -> OnRootMismatch {
+        self.on_root_mismatch
+    }
+
     pub fn timeout_s(&self) -> u64 {
-        self.timeout_s
+        self.timeout_s.load(Ordering::Relaxed)
+    }
+
+    /// Sets the connect timeout applied to every subsequent request to the
+    /// prover service.
+    ///
+    /// The timeout is shared by all clones of this prover. The batch size on
+    /// the other hand is tied to the prover's circuit and can't be changed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the timeout is zero.
+    pub fn set_timeout(&self, timeout_s: u64) -> anyhow::Result<()> {
+        let client = build_client(timeout_s, &self.user_agent)?;
+
+        *self.client.write().expect("Prover client lock poisoned") = client;
+        self.timeout_s.store(timeout_s, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn client(&self) -> reqwest::Client {
+        self.client
+            .read()
+            .expect("Prover client lock poisoned")
+            .clone()
+    }
+
+    /// Returns `false` if the prover has failed too many times in a row and
+    /// should be skipped until its cooldown elapses, or if it is paused
+    /// because its batch size drifted.
+    pub fn is_available(&self) -> bool {
+        if self.pause_on_drift && self.batch_size_drifted.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        self.circuit_breaker
+            .as_ref()
+            .map_or(true, |breaker| breaker.is_available())
+    }
+
+    /// Returns whether the prover service can be reached at all. Any response,
+    /// even an error status, counts. Provers with an in-process backend are
+    /// always reachable.
+    pub async fn is_reachable(&self) -> bool {
+        if self.backend.is_some() {
+            return true;
+        }
+
+        self.client()
+            .get(self.target_url.clone())
+            .send()
+            .await
+            .is_ok()
+    }
+
+    /// Fetches the circuit parameters the prover reports about itself.
+    pub async fn info(&self) -> anyhow::Result<ProverInfo> {
+        let response = self
+            .client()
+            .get(self.target_url.join(MTB_INFO_ENDPOINT)?)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json().await?)
+    }
+
+    /// Compares the batch size the prover reports with the configured one,
+    /// returning the reported batch size if they differ.
+    ///
+    /// The outcome is kept until the next check, so a prover that drifted is
+    /// unavailable until a later check finds it fixed if
+    /// [`ProverSettings::pause_on_drift`] is set.
+    pub async fn check_batch_size(&self) -> anyhow::Result<Option<usize>> {
+        let reported = self.info().await?.batch_size;
+        let drifted = reported != self.batch_size;
+
+        self.batch_size_drifted.store(drifted, Ordering::Relaxed);
+
+        Ok(drifted.then_some(reported))
+    }
+
+    /// Returns a summary of the prover's configuration and availability.
+    pub fn health(&self) -> ProverHealth {
+        ProverHealth {
+            url:         self.url(),
+            batch_size:  self.batch_size,
+            prover_type: self.prover_type,
+            available:   self.is_available(),
+        }
     }
 
     /// Generates a proof term for the provided identity insertions into the
@@ -184,57 +436,80 @@ impl Prover {
         post_root: U256,
         identities: &[Identity],
     ) -> anyhow::Result<Proof> {
+        let proved = self
+            .prove_insertion(start_index, pre_root, post_root, identities)
+            .await?;
+
+        Ok(proved.proof)
+    }
+
+    /// Like [`Self::generate_insertion_proof`], but also returns the timing of
+    /// the proof if the prover reports how long it spent proving.
+    pub async fn generate_insertion_proof_with_stats(
+        &self,
+        start_index: u32,
+        pre_root: U256,
+        post_root: U256,
+        identities: &[Identity],
+    ) -> anyhow::Result<(Proof, Option<ProofStats>)> {
+        let proved = self
+            .prove_insertion(start_index, pre_root, post_root, identities)
+            .await?;
+
+        Ok((proved.proof, proved.stats))
+    }
+
+    /// Like [`Self::generate_insertion_proof`], but also returns the post root
+    /// to submit the proof with. It only differs from `post_root` if the
+    /// prover reports another one and [`OnRootMismatch::TrustProver`] is set.
+    pub async fn generate_insertion_proof_with_root(
+        &self,
+        start_index: u32,
+        pre_root: U256,
+        post_root: U256,
+        identities: &[Identity],
+    ) -> anyhow::Result<(Proof, U256)> {
+        let proved = self
+            .prove_insertion(start_index, pre_root, post_root, identities)
+            .await?;
+
+        Ok((proved.proof, proved.post_root))
+    }
+
+    async fn prove_insertion(
+        &self,
+        start_index: u32,
+        pre_root: U256,
+        post_root: U256,
+        identities: &[Identity],
+    ) -> anyhow::Result<ProvedBatch> {
         if identities.len() != self.batch_size {
             return Err(anyhow::Error::msg(
                 "Provided batch does not match prover batch size.",
             ));
         }
 
+        validate_merkle_proof_lengths(identities)?;
+
         let total_proving_time_timer = TOTAL_PROVING_TIME.start_timer();
 
-        let identity_commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
-        let input_hash = compute_insertion_proof_input_hash(
+        let proof_input = build_insertion_proof_input(
             start_index,
             pre_root,
             post_root,
-            &identity_commitments,
+            identities,
+            self.hash_function,
+            self.endianness,
+            self.input_hash_domain.as_ref(),
         );
-        let merkle_proofs = identities
-            .iter()
-            .map(|id| id.merkle_proof.clone())
-            .collect();
-
-        let proof_input = InsertionProofInput {
-            input_hash,
-            start_index,
-            pre_root,
-            post_root,
-            identity_commitments,
-            merkle_proofs,
-        };
-
-        let request = self
-            .client
-            .post(self.target_url.join(MTB_PROVE_ENDPOINT)?)
-            .body("OH MY GOD")
-            .json(&proof_input)
-            .build()?;
-
-        let prover_proving_time_timer = PROVER_PROVING_TIME.start_timer();
-        let proof_term = self.client.execute(request).await?;
-        let proof_term = proof_term.error_for_status()?;
-        prover_proving_time_timer.observe_duration();
-
-        let json = proof_term.text().await?;
 
-        let Ok(proof) = serde_json::from_str::<Proof>(&json) else {
-            let error: ProverError = serde_json::from_str(&json)?;
-            return Err(anyhow::Error::msg(format!("{error}")));
-        };
+        let proved = self
+            .send_proof_request(ProofInput::Insertion(proof_input))
+            .await?;
 
         total_proving_time_timer.observe_duration();
 
-        Ok(proof)
+        Ok(proved)
     }
 
     pub async fn generate_deletion_proof(
@@ -250,6 +525,8 @@ impl Prover {
             ));
         }
 
+        validate_merkle_proof_lengths(&identities)?;
+
         let total_proving_time_timer = TOTAL_PROVING_TIME.start_timer();
 
         let (identity_commitments, merkle_proofs): (Vec<U256>, Vec<Vec<U256>>) = identities
@@ -257,8 +534,13 @@ impl Prover {
             .map(|id| (id.commitment, id.merkle_proof))
             .unzip();
 
-        let input_hash =
-            compute_deletion_proof_input_hash(deletion_indices.clone(), pre_root, post_root);
+        let input_hash = compute_deletion_input_hash(
+            pre_root,
+            post_root,
+            &deletion_indices,
+            self.hash_function,
+            self.endianness,
+        );
 
         let proof_input = DeletionProofInput {
             input_hash,
@@ -267,30 +549,280 @@ impl Prover {
             deletion_indices,
             identity_commitments,
             merkle_proofs,
+            tree_depth: None,
+            batch_size: None,
+        };
+
+        let proof = self
+            .send_proof_request(ProofInput::Deletion(proof_input))
+            .await?
+            .proof;
+
+        total_proving_time_timer.observe_duration();
+
+        Ok(proof)
+    }
+
+    /// Sends a fully-formed proof input to the prover as is, e.g. to replay a
+    /// persisted input of a batch that failed to prove.
+    ///
+    /// Unlike [`Self::generate_insertion_proof`] nothing is recomputed, so the
+    /// request is exactly the one that was stored.
+    pub async fn prove_from_input(&self, input: ProofInput) -> anyhow::Result<Proof> {
+        let (input_type, batch_size) = match &input {
+            ProofInput::Insertion(input) => {
+                (ProverType::Insertion, input.identity_commitments.len())
+            }
+            ProofInput::Deletion(input) => (ProverType::Deletion, input.identity_commitments.len()),
+        };
+
+        if input_type != self.prover_type {
+            return Err(anyhow::anyhow!(
+                "Cannot send {input_type} proof input to {} prover",
+                self.prover_type
+            ));
+        }
+
+        if batch_size != self.batch_size {
+            return Err(anyhow::Error::msg(
+                "Provided batch does not match prover batch size.",
+            ));
+        }
+
+        let proved = self.send_proof_request(input).await?;
+
+        Ok(proved.proof)
+    }
+
+    /// Sends a proof request to the backend, recording the outcome with the
+    /// circuit breaker. A proof cached for the same input hash is returned
+    /// without sending a request, and without stats.
+    async fn send_proof_request(&self, input: ProofInput) -> anyhow::Result<ProvedBatch> {
+        let input_hash = input.input_hash();
+        let post_root = input.post_root();
+        if let Some(proof) = self
+            .proof_cache
+            .as_ref()
+            .and_then(|cache| cache.get(input_hash))
+        {
+            debug!(?input_hash, "Reusing cached proof");
+            return Ok(ProvedBatch {
+                proof,
+                post_root,
+                stats: None,
+            });
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+
+        if let Some(breaker) = &self.circuit_breaker {
+            if !breaker.try_acquire() {
+                return Err(anyhow::anyhow!(
+                    "Prover is skipped after repeated failures, or its trial request is still in \
+                     flight"
+                ));
+            }
+        }
+
+        let input = if self.send_circuit_parameters {
+            input.with_circuit_parameters(self.batch_size)
+        } else {
+            input
         };
 
-        let request = self
-            .client
+        trace!(
+            input = %TruncatedProofInput::new(&input, self.log_truncate_elements),
+            "Sending proof request"
+        );
+
+        let prover_proving_time_timer = PROVER_PROVING_TIME.start_timer();
+        let sent_at = Instant::now();
+        let result = self
+            .prove_with_metadata(input)
+            .await
+            .map_err(anyhow::Error::from);
+        let round_trip_time = sent_at.elapsed();
+        prover_proving_time_timer.observe_duration();
+
+        // A degenerate proof serializes fine but reverts on-chain, so it is
+        // treated as a prover failure
+        let result = result.and_then(|(proof, metadata)| {
+            if proof.is_trivially_invalid() {
+                return Err(anyhow::anyhow!(
+                    "Prover returned a trivially invalid proof: {proof:?}"
+                ));
+            }
+
+            if self.verify_echoed_inputs {
+                if let Some(echoed) = &metadata.echoed_inputs {
+                    self.check_echoed_inputs(input_hash, echoed)?;
+                }
+            }
+
+            let post_root =
+                resolve_post_root(self.on_root_mismatch, post_root, metadata.post_root)?;
+            let stats = metadata.proving_time_ms.map(|proving_time_ms| ProofStats {
+                proving_time: Duration::from_millis(proving_time_ms),
+                round_trip_time,
+            });
+
+            Ok(ProvedBatch {
+                proof,
+                post_root,
+                stats,
+            })
+        });
+
+        if let Some(breaker) = &self.circuit_breaker {
+            match result {
+                Ok(_) => breaker.record_success(),
+                Err(_) => breaker.record_failure(),
+            }
+        }
+
+        // A proof for another post root than requested isn't what the input
+        // hash stands for
+        if let (Ok(proved), Some(cache)) = (&result, &self.proof_cache) {
+            if proved.post_root == post_root {
+                cache.insert(input_hash, proved.proof.clone());
+            }
+        }
+
+        result
+    }
+
+    /// Generates the proof with the configured backend, or by calling the
+    /// prover service over HTTP if there is none. Returns what the prover
+    /// service reports about the proof besides the proof itself.
+    async fn prove_with_metadata(
+        &self,
+        input: ProofInput,
+    ) -> Result<(Proof, ResponseMetadata), ProverError> {
+        if let Some(backend) = &self.backend {
+            return Ok((backend.prove(input).await?, ResponseMetadata::default()));
+        }
+
+        self.prove_over_http(&input)
+            .await
+            .map_err(|err| match err.downcast::<ProverError>() {
+                Ok(error) => error,
+                Err(err) => ProverError {
+                    code:    "REQUEST_FAILED".into(),
+                    message: format!("{err:#}"),
+                },
+            })
+    }
+
+    async fn prove_over_http(
+        &self,
+        input: &ProofInput,
+    ) -> anyhow::Result<(Proof, ResponseMetadata)> {
+        let client = self.client();
+        let request = client
             .post(self.target_url.join(MTB_PROVE_ENDPOINT)?)
             .body("OH MY GOD")
-            .json(&proof_input)
+            .json(input)
             .build()?;
 
-        let prover_proving_time_timer = PROVER_PROVING_TIME.start_timer();
-        let proof_term = self.client.execute(request).await?;
+        let proof_term = client.execute(request).await?;
         let proof_term = proof_term.error_for_status()?;
-        prover_proving_time_timer.observe_duration();
 
-        let json = proof_term.text().await?;
+        let json = read_response_body(proof_term, self.max_response_bytes).await?;
 
-        let Ok(proof) = serde_json::from_str::<Proof>(&json) else {
+        let proof = match self.proof_format {
+            ProofFormat::Flat => serde_json::from_str::<Proof>(&json).ok(),
+            ProofFormat::SnarkJs => serde_json::from_str::<SnarkJsProof>(&json)
+                .ok()
+                .map(Proof::try_from)
+                .transpose()?,
+        };
+
+        let Some(proof) = proof else {
             let error: ProverError = serde_json::from_str(&json)?;
-            return Err(anyhow::Error::msg(format!("{error}")));
+            return Err(error.into());
         };
 
-        total_proving_time_timer.observe_duration();
+        let metadata = serde_json::from_str(&json).unwrap_or_default();
 
-        Ok(proof)
+        Ok((proof, metadata))
+    }
+
+    /// Computes the input hash of the public inputs a prover echoed with a
+    /// proof, the same way it is computed for proof requests of this prover's
+    /// type.
+    fn echoed_input_hash(&self, echoed: &EchoedInputs) -> U256 {
+        match self.prover_type {
+            ProverType::Insertion => compute_insertion_proof_input_hash(
+                echoed.start_index,
+                echoed.pre_root,
+                echoed.post_root,
+                &echoed.identity_commitments,
+                self.hash_function,
+                self.endianness,
+                self.input_hash_domain.as_ref(),
+            ),
+            ProverType::Deletion => compute_deletion_input_hash(
+                echoed.pre_root,
+                echoed.post_root,
+                &echoed.deletion_indices,
+                self.hash_function,
+                self.endianness,
+            ),
+        }
+    }
+
+    /// Checks that the public inputs a prover echoed with a proof are the ones
+    /// hashing to `expected`, the input hash the proof was requested for.
+    fn check_echoed_inputs(&self, expected: U256, echoed: &EchoedInputs) -> anyhow::Result<()> {
+        let echoed_hash = self.echoed_input_hash(echoed);
+        if echoed_hash != expected {
+            return Err(anyhow::anyhow!(
+                "Prover echoed inputs hashing to {echoed_hash:#x}, expected {expected:#x}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Sends a synthetic proof request to prime the prover's caches.
+    ///
+    /// The request is valid for an empty tree of depth `tree_depth` whose
+    /// leaves are all `initial_leaf_value`: insertions write the initial leaf
+    /// value back to the first leaves, and deletions only use padding indices.
+    pub async fn warmup(&self, tree_depth: usize, initial_leaf_value: Field) -> anyhow::Result<()> {
+        let tree = LazyPoseidonTree::new(tree_depth, initial_leaf_value);
+        let root = field_to_u256(tree.root());
+
+        match self.prover_type {
+            ProverType::Insertion => {
+                let identities: Vec<Identity> = (0..self.batch_size)
+                    .map(|leaf_index| {
+                        let merkle_proof = merkle_proof_siblings(&tree.proof(leaf_index));
+
+                        Identity::new(field_to_u256(initial_leaf_value), merkle_proof)
+                    })
+                    .collect();
+
+                self.generate_insertion_proof(0, root, root, &identities)
+                    .await?;
+            }
+            ProverType::Deletion => {
+                // The deletion prover skips any index beyond the tree's capacity
+                let pad_index = 2_u32.pow(tree_depth as u32);
+                let deletion_indices = vec![pad_index; self.batch_size];
+                let identities = vec![
+                    Identity::new(U256::zero(), vec![U256::zero(); tree_depth]);
+                    self.batch_size
+                ];
+
+                self.generate_deletion_proof(root, root, deletion_indices, identities)
+                    .await?;
+            }
+        }
+
+        Ok(())
     }
 
     pub fn url(&self) -> String {
@@ -298,6 +830,98 @@ impl Prover {
     }
 }
 
+/// A service capable of generating proofs for batches of tree updates.
+#[async_trait]
+pub trait ProveBackend: std::fmt::Debug + Send + Sync {
+    async fn prove(&self, input: ProofInput) -> Result<Proof, ProverError>;
+}
+
+#[async_trait]
+impl ProveBackend for Prover {
+    /// Generates the proof with the configured backend, or by calling the
+    /// prover service over HTTP if there is none.
+    async fn prove(&self, input: ProofInput) -> Result<Proof, ProverError> {
+        let (proof, _) = self.prove_with_metadata(input).await?;
+
+        Ok(proof)
+    }
+}
+
+/// Builds the client used to talk to a prover service. As proving a batch can
+/// take arbitrarily long, the timeout only bounds establishing the connection.
+fn build_client(timeout_s: u64, user_agent: &str) -> anyhow::Result<reqwest::Client> {
+    if timeout_s == 0 {
+        return Err(anyhow::anyhow!(
+            "The prover timeout must be at least a second"
+        ));
+    }
+
+    Ok(reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(timeout_s))
+        .user_agent(user_agent)
+        .https_only(false)
+        .build()?)
+}
+
+/// Checks that every identity in a batch carries a merkle proof of the same
+/// length, so that commitments and proofs line up when sent to the prover.
+fn validate_merkle_proof_lengths(identities: &[Identity]) -> anyhow::Result<()> {
+    let Some(expected_len) = identities.first().map(|id| id.merkle_proof.len()) else {
+        return Ok(());
+    };
+
+    for (idx, identity) in identities.iter().enumerate() {
+        let proof_len = identity.merkle_proof.len();
+
+        if proof_len != expected_len {
+            return Err(anyhow::anyhow!(
+                "Inconsistent merkle proofs in batch: identity at position {idx} has a proof of \
+                 length {proof_len}, expected {expected_len}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the input of an insertion proof request, in the format expected by
+/// the prover.
+pub fn build_insertion_proof_input(
+    start_index: u32,
+    pre_root: U256,
+    post_root: U256,
+    identities: &[Identity],
+    hash_function: HashFunction,
+    endianness: Endianness,
+    input_hash_domain: Option<&[u8; 32]>,
+) -> InsertionProofInput {
+    let identity_commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
+    let input_hash = compute_insertion_proof_input_hash(
+        start_index,
+        pre_root,
+        post_root,
+        &identity_commitments,
+        hash_function,
+        endianness,
+        input_hash_domain,
+    );
+    let merkle_proofs = identities
+        .iter()
+        .map(|id| id.merkle_proof.clone())
+        .collect();
+
+    InsertionProofInput {
+        input_hash,
+        start_index,
+        pre_root,
+        post_root,
+        identity_commitments,
+        merkle_proofs,
+        tree_depth: None,
+        batch_size: None,
+    }
+}
+
 /// Computes the input hash to the prover.
 ///
 /// The input hash is specified as the `keccak256` hash of the inputs arranged
@@ -318,63 +942,124 @@ impl Prover {
 /// - `IdComms` is `identity_commitments`, the list of identity commitments
 ///   provided in the order that they were inserted into the tree.
 ///
-/// The result is computed using the inputs in _big-endian_ byte ordering.
+/// If `input_hash_domain` is provided, its 256 bits are prepended to the
+/// above as a domain separator.
+///
+/// `StartIndex` is always encoded _big-endian_, the 256-bit words are encoded
+/// in the byte order given by `endianness`. The domain separator is used as
+/// is.
+///
+/// With [`HashFunction::Poseidon`] the same inputs are instead hashed as field
+/// elements, see [`poseidon_input_hash`]. The domain separator is not used in
+/// that case.
 pub fn compute_insertion_proof_input_hash(
     start_index: u32,
     pre_root: U256,
     post_root: U256,
     identity_commitments: &[U256],
+    hash_function: HashFunction,
+    endianness: Endianness,
+    input_hash_domain: Option<&[u8; 32]>,
 ) -> U256 {
-    let mut pre_root_bytes: [u8; size_of::<U256>()] = Default::default();
-    pre_root.to_big_endian(pre_root_bytes.as_mut_slice());
-    let mut post_root_bytes: [u8; size_of::<U256>()] = Default::default();
-    post_root.to_big_endian(post_root_bytes.as_mut_slice());
+    if hash_function == HashFunction::Poseidon {
+        let inputs = [U256::from(start_index), pre_root, post_root];
+
+        return poseidon_input_hash(
+            inputs
+                .into_iter()
+                .chain(identity_commitments.iter().copied()),
+        );
+    }
 
     let mut bytes: Vec<u8> = vec![];
+    if let Some(domain) = input_hash_domain {
+        bytes.extend_from_slice(domain);
+    }
     bytes.extend_from_slice(&start_index.to_be_bytes());
-    bytes.extend(pre_root_bytes.iter());
-    bytes.extend(post_root_bytes.iter());
+    bytes.extend(encode_word(pre_root, endianness));
+    bytes.extend(encode_word(post_root, endianness));
 
     for commitment in identity_commitments {
-        let mut commitment_bytes: [u8; size_of::<U256>()] = Default::default();
-        commitment.to_big_endian(commitment_bytes.as_mut_slice());
-        bytes.extend(commitment_bytes.iter());
+        bytes.extend(encode_word(*commitment, endianness));
     }
 
     keccak256(bytes).into()
 }
 
-// TODO: check this and update docs
-
-pub fn compute_deletion_proof_input_hash(
-    deletion_indices: Vec<u32>,
+/// Computes the input hash to the deletion prover.
+///
+/// The input hash is specified as the `keccak256` hash of the inputs arranged
+/// as follows:
+///
+/// ```md
+/// DeletionIndices[0] || DeletionIndices[1] || ... || DeletionIndices[batchSize-1] || PreRoot || PostRoot
+///         32         ||         32         || ... ||              32              ||   256   ||   256    bits
+/// ```
+///
+/// where:
+/// - `DeletionIndices` is `deletion_indices`, the leaf indices of the deleted
+///   identities, packed as in the verifier contract.
+/// - `PreRoot` is `pre_root`, the root value of the merkle tree before the
+///   deletions were made.
+/// - `PostRoot` is `post_root`, the root value of the merkle tree after the
+///   deletions were made.
+///
+/// `DeletionIndices` are always packed _big-endian_, the 256-bit words are
+/// encoded in the byte order given by `endianness`.
+///
+/// With [`HashFunction::Poseidon`] the same inputs are instead hashed as field
+/// elements, see [`poseidon_input_hash`].
+pub fn compute_deletion_input_hash(
     pre_root: U256,
     post_root: U256,
+    deletion_indices: &[u32],
+    hash_function: HashFunction,
+    endianness: Endianness,
 ) -> U256 {
-    // Convert pre_root and post_root to bytes
-    let mut pre_root_bytes = vec![0u8; 32];
-    pre_root.to_big_endian(&mut pre_root_bytes);
+    if hash_function == HashFunction::Poseidon {
+        let indices = deletion_indices.iter().map(|index| U256::from(*index));
 
-    let mut post_root_bytes = vec![0u8; 32];
-    post_root.to_big_endian(&mut post_root_bytes);
+        return poseidon_input_hash(indices.chain([pre_root, post_root]));
+    }
 
-    let mut bytes = vec![];
+    let mut bytes: Vec<u8> = pack_indices(deletion_indices);
+    bytes.extend(encode_word(pre_root, endianness));
+    bytes.extend(encode_word(post_root, endianness));
 
-    // Append packed_deletion_indices
-    let packed_indices = pack_indices(&deletion_indices);
-    bytes.extend_from_slice(&packed_indices);
+    keccak256(bytes).into()
+}
 
-    // Append pre_root and post_root bytes
-    bytes.extend_from_slice(&pre_root_bytes);
-    bytes.extend_from_slice(&post_root_bytes);
+/// Encodes a 256-bit word of a keccak256 input hash preimage.
+fn encode_word(value: U256, endianness: Endianness) -> [u8; 32] {
+    match endianness {
+        Endianness::Big => u256_to_be_bytes(value),
+        Endianness::Little => u256_to_le_bytes(value),
+    }
+}
 
-    // Compute and return the Keccak-256 hash
-    keccak256(bytes).into()
+/// Hashes the inputs as field elements by chaining the two-to-one poseidon
+/// hash used by the merkle tree:
+///
+/// ```md
+/// H(... H(H(Inputs[0], Inputs[1]), Inputs[2]) ..., Inputs[n-1])
+/// ```
+///
+/// `H` is circomlib's `Poseidon(2)` over BN254, i.e. a width 3 permutation
+/// with 8 full and 57 partial rounds and a zero capacity element, which is
+/// the gadget the poseidon variants of the circuits chain in the same order.
+fn poseidon_input_hash(inputs: impl IntoIterator<Item = U256>) -> U256 {
+    let mut inputs = inputs.into_iter().map(u256_to_field);
+
+    let first = inputs.next().unwrap_or(Field::ZERO);
+    let hash = inputs.fold(first, |acc, input| PoseidonHash::hash_node(&acc, &input));
+
+    field_to_u256(hash)
 }
 
+/// An error reported by a prover.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct ProverError {
+pub struct ProverError {
     pub code:    String,
     pub message: String,
 }
@@ -389,44 +1074,290 @@ impl Display for ProverError {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+impl std::error::Error for ProverError {}
+
+/// The availability of a single prover, as reported by [`Prover::health`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "camelCase")]
-struct InsertionProofInput {
-    input_hash:           U256,
-    start_index:          u32,
-    pre_root:             U256,
-    post_root:            U256,
-    identity_commitments: Vec<U256>,
-    merkle_proofs:        Vec<Vec<U256>>,
+pub struct ProverHealth {
+    pub url:         String,
+    pub batch_size:  usize,
+    pub prover_type: ProverType,
+    pub available:   bool,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct DeletionProofInput {
-    input_hash:           U256,
-    pre_root:             U256,
-    post_root:            U256,
-    deletion_indices:     Vec<u32>,
+/// The time a proof took, as reported by the prover and as seen by the
+/// sequencer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofStats {
+    /// The time the prover reports it spent generating the proof.
+    pub proving_time:    Duration,
+    /// The time from sending the proof request to receiving the proof.
+    pub round_trip_time: Duration,
+}
+
+impl ProofStats {
+    /// Returns the part of the round trip not spent proving, i.e. the network
+    /// latency and the prover's queueing time.
+    #[must_use]
+    pub fn overhead(&self) -> Duration {
+        self.round_trip_time.saturating_sub(self.proving_time)
+    }
+}
+
+/// What some provers add to their proof responses.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResponseMetadata {
+    proving_time_ms: Option<u64>,
+    /// The post root the prover generated the proof for
+    post_root:       Option<U256>,
+    #[serde(rename = "publicInputs")]
+    echoed_inputs:   Option<EchoedInputs>,
+}
+
+/// The public inputs of a proof, as some provers echo them back with it.
+/// Insertions leave out the deletion indices, and deletions the start index and
+/// the commitments.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EchoedInputs {
+    #[serde(default)]
+    start_index:          u32,
+    pre_root:             U256,
+    post_root:            U256,
+    #[serde(default)]
     identity_commitments: Vec<U256>,
-    merkle_proofs:        Vec<Vec<U256>>,
+    #[serde(default)]
+    deletion_indices:     Vec<u32>,
+}
+
+/// A proof, along with the post root to submit it with and its timing if the
+/// prover reports it.
+struct ProvedBatch {
+    proof:     Proof,
+    post_root: U256,
+    stats:     Option<ProofStats>,
+}
+
+/// Picks the post root a proof is submitted with, given the one computed by
+/// the sequencer and the one reported by the prover, if any.
+fn resolve_post_root(
+    policy: OnRootMismatch,
+    computed: U256,
+    reported: Option<U256>,
+) -> anyhow::Result<U256> {
+    let Some(reported) = reported.filter(|reported| *reported != computed) else {
+        return Ok(computed);
+    };
+
+    match policy {
+        OnRootMismatch::Error => Err(anyhow::anyhow!(
+            "Prover returned a proof for post root {reported:#x}, expected {computed:#x}"
+        )),
+        OnRootMismatch::TrustProver => {
+            warn!(
+                ?computed,
+                ?reported,
+                "Post root mismatch, using the prover's"
+            );
+            Ok(reported)
+        }
+        OnRootMismatch::TrustLocal => {
+            warn!(
+                ?computed,
+                ?reported,
+                "Post root mismatch, using the computed one"
+            );
+            Ok(computed)
+        }
+    }
+}
+
+/// The circuit parameters a prover reports on its `info` endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProverInfo {
+    pub batch_size: usize,
+}
+
+/// The input of a single proof request.
+///
+/// It serializes to the JSON body the prover expects, so it can also be
+/// written to a file and passed to the prover binary directly.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProofInput {
+    Insertion(InsertionProofInput),
+    Deletion(DeletionProofInput),
+}
+
+impl ProofInput {
+    pub fn input_hash(&self) -> U256 {
+        match self {
+            Self::Insertion(input) => input.input_hash,
+            Self::Deletion(input) => input.input_hash,
+        }
+    }
+
+    pub fn post_root(&self) -> U256 {
+        match self {
+            Self::Insertion(input) => input.post_root,
+            Self::Deletion(input) => input.post_root,
+        }
+    }
+
+    /// Adds the tree depth and the batch size to the request, so that the
+    /// prover can reject it if they don't match its circuit. The tree depth is
+    /// the length of the merkle proofs in the batch.
+    #[must_use]
+    pub fn with_circuit_parameters(self, batch_size: usize) -> Self {
+        fn tree_depth(merkle_proofs: &[Vec<U256>]) -> Option<usize> {
+            merkle_proofs.first().map(Vec::len)
+        }
+
+        match self {
+            Self::Insertion(input) => Self::Insertion(InsertionProofInput {
+                tree_depth: tree_depth(&input.merkle_proofs),
+                batch_size: Some(batch_size),
+                ..input
+            }),
+            Self::Deletion(input) => Self::Deletion(DeletionProofInput {
+                tree_depth: tree_depth(&input.merkle_proofs),
+                batch_size: Some(batch_size),
+                ..input
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InsertionProofInput {
+    pub input_hash:           U256,
+    pub start_index:          u32,
+    pub pre_root:             U256,
+    pub post_root:            U256,
+    pub identity_commitments: Vec<U256>,
+    pub merkle_proofs:        Vec<Vec<U256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_depth:           Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size:           Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionProofInput {
+    pub input_hash:           U256,
+    pub pre_root:             U256,
+    pub post_root:            U256,
+    pub deletion_indices:     Vec<u32>,
+    pub identity_commitments: Vec<U256>,
+    pub merkle_proofs:        Vec<Vec<U256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_depth:           Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size:           Option<usize>,
+}
+
+/// Reads the body of `response` as text, failing as soon as it grows past
+/// `max_bytes` instead of buffering all of it.
+async fn read_response_body(
+    mut response: reqwest::Response,
+    max_bytes: Option<usize>,
+) -> anyhow::Result<String> {
+    let Some(max_bytes) = max_bytes else {
+        return Ok(response.text().await?);
+    };
+
+    let too_large = || anyhow::anyhow!("Prover response exceeds the limit of {max_bytes} bytes");
+
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8(body)?)
+}
+
+/// Formats a proof input for logging, with every merkle proof cut down to its
+/// first `max_elements` elements.
+pub struct TruncatedProofInput<'a> {
+    input:        &'a ProofInput,
+    max_elements: usize,
+}
+
+impl<'a> TruncatedProofInput<'a> {
+    pub fn new(input: &'a ProofInput, max_elements: usize) -> Self {
+        Self {
+            input,
+            max_elements,
+        }
+    }
+}
+
+impl Display for TruncatedProofInput<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (pre_root, post_root, commitments, merkle_proofs) = match self.input {
+            ProofInput::Insertion(input) => (
+                input.pre_root,
+                input.post_root,
+                &input.identity_commitments,
+                &input.merkle_proofs,
+            ),
+            ProofInput::Deletion(input) => (
+                input.pre_root,
+                input.post_root,
+                &input.identity_commitments,
+                &input.merkle_proofs,
+            ),
+        };
+
+        write!(f, "{pre_root:#x} -> {post_root:#x}:")?;
+
+        for (commitment, merkle_proof) in commitments.iter().zip(merkle_proofs) {
+            let elements: Vec<String> = merkle_proof
+                .iter()
+                .take(self.max_elements)
+                .map(|element| format!("{element:#x}"))
+                .collect();
+
+            write!(f, " {commitment:#x} [{}", elements.join(", "))?;
+
+            if merkle_proof.len() > self.max_elements {
+                let separator = if elements.is_empty() { "" } else { ", " };
+                write!(f, "{separator}…")?;
+            }
+
+            write!(f, "]")?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use super::mock::{get_default_proof_output, mock_prover, mock_prover_config};
     use super::*;
 
     #[tokio::test]
     async fn mtb_should_generate_proof_with_correct_inputs() -> anyhow::Result<()> {
-        let mock_url: String = "0.0.0.0:3001".into();
-        let mock_service = mock::Service::new(mock_url.clone()).await?;
+        let mock_service = mock::Service::new().await?;
 
-        let options = ProverConfig {
-            url:         "http://localhost:3001".into(),
-            timeout_s:   30,
-            batch_size:  3,
-            prover_type: ProverType::Insertion,
-        };
-        let mtb = Prover::new(&options).unwrap();
+        let mtb = mock_prover(mock_service.url(), &ProverSettings::default());
         let input_data = get_default_proof_input();
         let identities: Vec<Identity> = extract_identities_from(&input_data);
 
@@ -438,32 +1369,662 @@ mod test {
                 input_data.post_root,
                 &identities,
             )
-            .await?;
+            .await?;
+
+        mock_service.stop();
+
+        assert_eq!(proof, expected_proof);
+
+        Ok(())
+    }
+
+    /// A backend that always returns an all-zero proof.
+    struct ZeroProofBackend;
+
+    #[async_trait]
+    impl ProveBackend for ZeroProofBackend {
+        async fn prove(&self, _input: ProofInput) -> Result<Proof, ProverError> {
+            Ok(Proof {
+                ar:  [U256::zero(); 2],
+                bs:  [[U256::zero(); 2]; 2],
+                krs: [U256::zero(); 2],
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn all_zero_proof_is_rejected() -> anyhow::Result<()> {
+        let options = mock_prover_config("http://localhost");
+        let mtb = Prover::with_backend(
+            &options,
+            &ProverSettings::default(),
+            Arc::new(ZeroProofBackend),
+        )?;
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        let error = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("trivially invalid proof"));
+
+        Ok(())
+    }
+
+    /// A backend that returns the same response to every request, whatever
+    /// the input.
+    struct FixedResponseBackend(Result<Proof, ProverError>);
+
+    #[async_trait]
+    impl ProveBackend for FixedResponseBackend {
+        async fn prove(&self, _input: ProofInput) -> Result<Proof, ProverError> {
+            self.0.clone()
+        }
+    }
+
+    /// Generates an insertion proof for the default input with its post root
+    /// replaced by `post_root`.
+    async fn prove_with_post_root(
+        backend: FixedResponseBackend,
+        post_root: U256,
+    ) -> anyhow::Result<Proof> {
+        let options = mock_prover_config("http://localhost");
+        let mtb = Prover::with_backend(&options, &ProverSettings::default(), Arc::new(backend))?;
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        mtb.generate_insertion_proof(
+            input_data.start_index,
+            input_data.pre_root,
+            post_root,
+            &identities,
+        )
+        .await
+    }
+
+    // The mock prover services fail every request with an even post root.
+    // Make sure that never leaks into the prover itself, which has to go by
+    // the response of the actual prover alone.
+    #[tokio::test]
+    async fn even_post_root_is_accepted_if_the_prover_succeeds() -> anyhow::Result<()> {
+        let expected = get_default_proof_output();
+        let backend = FixedResponseBackend(Ok(expected.clone()));
+
+        let proof = prove_with_post_root(backend, U256::from(2)).await?;
+
+        assert_eq!(proof, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn odd_post_root_is_rejected_if_the_prover_fails() {
+        let backend = FixedResponseBackend(Err(ProverError {
+            code:    "FAILED".into(),
+            message: "Proving failed".into(),
+        }));
+
+        let error = prove_with_post_root(backend, U256::from(3))
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("Proving failed"));
+    }
+
+    #[tokio::test]
+    async fn in_process_backend_generates_proof() -> anyhow::Result<()> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = mock_prover_config("http://localhost");
+        let mtb = Prover::with_backend(&options, &ProverSettings::default(), backend.clone())?;
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        let proof = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await?;
+
+        assert_eq!(proof, get_default_proof_output());
+        assert_eq!(backend.request_count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_proof_skips_the_prover() -> anyhow::Result<()> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = mock_prover_config("http://localhost");
+        let settings = ProverSettings {
+            proof_cache_capacity: Some(4),
+            ..Default::default()
+        };
+        let mtb = Prover::with_backend(&options, &settings, backend.clone())?;
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        for _ in 0..2 {
+            let proof = mtb
+                .generate_insertion_proof(
+                    input_data.start_index,
+                    input_data.pre_root,
+                    input_data.post_root,
+                    &identities,
+                )
+                .await?;
+
+            assert_eq!(proof, get_default_proof_output());
+        }
+
+        assert_eq!(backend.request_count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_proofs_are_not_cached() -> anyhow::Result<()> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = mock_prover_config("http://localhost");
+        let settings = ProverSettings {
+            proof_cache_capacity: Some(4),
+            ..Default::default()
+        };
+        let mtb = Prover::with_backend(&options, &settings, backend.clone())?;
+        let mut input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+        input_data.post_root = U256::from(2);
+
+        for _ in 0..2 {
+            assert!(mtb
+                .generate_insertion_proof(
+                    input_data.start_index,
+                    input_data.pre_root,
+                    input_data.post_root,
+                    &identities,
+                )
+                .await
+                .is_err());
+        }
+
+        assert_eq!(backend.request_count(), 2);
+
+        Ok(())
+    }
+
+    async fn sent_insertion_input(settings: &ProverSettings) -> anyhow::Result<ProofInput> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = mock_prover_config("http://localhost");
+        let mtb = Prover::with_backend(&options, settings, backend.clone())?;
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        mtb.generate_insertion_proof(
+            input_data.start_index,
+            input_data.pre_root,
+            input_data.post_root,
+            &identities,
+        )
+        .await?;
+
+        backend
+            .last_input()
+            .ok_or_else(|| anyhow::anyhow!("No proof request was sent"))
+    }
+
+    #[tokio::test]
+    async fn circuit_parameters_are_sent_when_enabled() -> anyhow::Result<()> {
+        let settings = ProverSettings {
+            send_circuit_parameters: true,
+            ..Default::default()
+        };
+
+        let input = serde_json::to_value(sent_insertion_input(&settings).await?)?;
+
+        assert_eq!(input["treeDepth"], 10);
+        assert_eq!(input["batchSize"], 3);
+
+        Ok(())
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn logged_merkle_proofs_are_truncated() -> anyhow::Result<()> {
+        let settings = ProverSettings {
+            log_truncate_elements: 2,
+            ..Default::default()
+        };
+
+        sent_insertion_input(&settings).await?;
+
+        assert!(logs_contain("Sending proof request"));
+        assert!(logs_contain(
+            "0x2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864, …]"
+        ));
+        assert!(!logs_contain(
+            "0x1069673dcdb12263df301a6ff584a7ec261a44cb9dc68df067a4774460b1f1e1"
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn circuit_parameters_are_omitted_by_default() -> anyhow::Result<()> {
+        let input = sent_insertion_input(&ProverSettings::default()).await?;
+
+        let serialized = serde_json::to_value(&input)?;
+        let expected: serde_json::Value = serde_json::from_str(EXPECTED_JSON)?;
+        assert_eq!(serialized, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deletion_input_carries_circuit_parameters() {
+        let input = ProofInput::Deletion(DeletionProofInput {
+            input_hash:           U256::one(),
+            pre_root:             U256::one(),
+            post_root:            U256::one(),
+            deletion_indices:     vec![0, 1 << 20],
+            identity_commitments: vec![U256::one(), U256::zero()],
+            merkle_proofs:        vec![vec![U256::zero(); 20]; 2],
+            tree_depth:           None,
+            batch_size:           None,
+        })
+        .with_circuit_parameters(2);
+
+        let ProofInput::Deletion(input) = input else {
+            panic!("Expected a deletion input");
+        };
+
+        assert_eq!(input.tree_depth, Some(20));
+        assert_eq!(input.batch_size, Some(2));
+    }
+
+    fn replay_prover(
+        prover_type: ProverType,
+        batch_size: usize,
+    ) -> anyhow::Result<(Prover, Arc<mock::MockProver>)> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = ProverConfig {
+            batch_size,
+            prover_type,
+            ..mock_prover_config("http://localhost")
+        };
+        let prover = Prover::with_backend(&options, &ProverSettings::default(), backend.clone())?;
+
+        Ok((prover, backend))
+    }
+
+    #[tokio::test]
+    async fn stored_input_is_replayed_verbatim() -> anyhow::Result<()> {
+        let (prover, backend) = replay_prover(ProverType::Insertion, 3)?;
+        let input: ProofInput = serde_json::from_str(EXPECTED_JSON)?;
+
+        let proof = prover.prove_from_input(input.clone()).await?;
+
+        assert_eq!(proof, get_default_proof_output());
+        assert_eq!(backend.last_input(), Some(input));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_rejects_mismatched_prover() -> anyhow::Result<()> {
+        let input: ProofInput = serde_json::from_str(EXPECTED_JSON)?;
+
+        let (deletion_prover, backend) = replay_prover(ProverType::Deletion, 3)?;
+        assert!(deletion_prover
+            .prove_from_input(input.clone())
+            .await
+            .is_err());
+        assert_eq!(backend.request_count(), 0);
+
+        let (larger_prover, backend) = replay_prover(ProverType::Insertion, 10)?;
+        assert!(larger_prover.prove_from_input(input).await.is_err());
+        assert_eq!(backend.request_count(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_process_backend_reports_prover_errors() -> anyhow::Result<()> {
+        let backend = Arc::new(mock::MockProver::default());
+
+        let options = mock_prover_config("http://localhost");
+        let mtb = Prover::with_backend(&options, &ProverSettings::default(), backend.clone())?;
+        let mut input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+        input_data.post_root = U256::from(2);
+
+        let error = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await
+            .expect_err("An even post root should be rejected");
+
+        assert_eq!(
+            error.downcast_ref::<ProverError>(),
+            Some(&ProverError {
+                code:    "Oh no!".into(),
+                message: "Things went wrong.".into(),
+            })
+        );
+        assert_eq!(backend.request_count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_can_be_adjusted_at_runtime() {
+        let mtb = mock_prover("http://localhost:3001", &ProverSettings::default());
+        let clone = mtb.clone();
+
+        assert_eq!(mtb.timeout_s(), 30);
+
+        mtb.set_timeout(120).unwrap();
+
+        assert_eq!(mtb.timeout_s(), 120);
+        assert_eq!(clone.timeout_s(), 120, "Clones share the timeout");
+        assert_eq!(mtb.batch_size(), 3);
+
+        assert!(mtb.set_timeout(0).is_err());
+        assert_eq!(mtb.timeout_s(), 120);
+    }
+
+    #[test]
+    fn zero_timeout_is_rejected() {
+        let options = ProverConfig {
+            timeout_s: 0,
+            ..mock_prover_config("http://localhost:3001")
+        };
+
+        assert!(Prover::new(&options, &ProverSettings::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn mtb_should_respond_with_error_if_inputs_incorrect() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        let mtb = mock_prover(mock_service.url(), &ProverSettings::default());
+        let mut input_data = get_default_proof_input();
+        let identities = extract_identities_from(&input_data);
+        input_data.post_root = U256::from(2);
+
+        let prover_result = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await;
+
+        mock_service.stop();
+        assert!(prover_result.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_should_error_if_batch_size_wrong() -> anyhow::Result<()> {
+        let options = ProverConfig {
+            batch_size: 10,
+            ..mock_prover_config("http://localhost:3002")
+        };
+        let mtb = Prover::new(&options, &ProverSettings::default()).unwrap();
+        let input_data = get_default_proof_input();
+        let identities = extract_identities_from(&input_data);
+
+        let prover_result = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await;
+
+        assert!(prover_result.is_err());
+        assert_eq!(
+            prover_result.unwrap_err().to_string(),
+            anyhow::Error::msg("Provided batch does not match prover batch size.").to_string()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_should_error_if_merkle_proof_lengths_differ() -> anyhow::Result<()> {
+        let mtb = mock_prover("http://localhost:3002", &ProverSettings::default());
+        let input_data = get_default_proof_input();
+        let mut identities = extract_identities_from(&input_data);
+        identities[1].merkle_proof.pop();
+
+        let prover_result = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await;
+
+        assert_eq!(
+            prover_result.unwrap_err().to_string(),
+            "Inconsistent merkle proofs in batch: identity at position 1 has a proof of length 9, \
+             expected 10"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_warmup_should_send_request() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        let mtb = mock_prover(mock_service.url(), &ProverSettings::default());
+
+        // The mock only succeeds for some roots, we only care that the request
+        // reached it
+        let _ = mtb.warmup(10, Field::ZERO).await;
+
+        assert_eq!(mock_service.request_count(), 1);
+
+        mock_service.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_should_send_configured_user_agent() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        let settings = ProverSettings {
+            user_agent: Some("test-sequencer/1.2.3".into()),
+            ..Default::default()
+        };
+        let mtb = mock_prover(mock_service.url(), &settings);
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        mtb.generate_insertion_proof(
+            input_data.start_index,
+            input_data.pre_root,
+            input_data.post_root,
+            &identities,
+        )
+        .await?;
+
+        let user_agent = mock_service.last_user_agent();
+        mock_service.stop();
+
+        assert_eq!(user_agent.as_deref(), Some("test-sequencer/1.2.3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prover_is_reachable_only_while_its_service_is_up() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+        let address = mock_service.address();
+        mock_service.stop_and_wait().await?;
+
+        let mtb = mock_prover(format!("http://{address}"), &ProverSettings::default());
+
+        assert!(!mtb.is_reachable().await);
+
+        let mock_service = mock::Service::bind(address).await?;
+
+        // The service has no route at the root, but it responds
+        assert!(mtb.is_reachable().await);
+
+        mock_service.stop_and_wait().await?;
+
+        assert!(!mtb.is_reachable().await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oversized_prover_response_is_rejected() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        // Far smaller than the serialized default proof
+        let settings = ProverSettings {
+            max_response_bytes: Some(64),
+            ..Default::default()
+        };
+        let mtb = mock_prover(mock_service.url(), &settings);
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        let error = mtb
+            .generate_insertion_proof(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await
+            .unwrap_err();
+
+        mock_service.stop();
+
+        assert!(error.to_string().contains("exceeds the limit of 64 bytes"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reported_proving_time_is_returned_with_the_proof() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        let mtb = mock_prover(mock_service.url(), &ProverSettings::default());
+        let input_data = get_default_proof_input();
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        let (_, stats) = mtb
+            .generate_insertion_proof_with_stats(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await?;
+        assert_eq!(stats, None);
+
+        mock_service.set_proving_time_ms(0);
+
+        let (proof, stats) = mtb
+            .generate_insertion_proof_with_stats(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await?;
+
+        mock_service.stop();
+
+        let stats = stats.expect("The reported proving time should be returned");
+        assert_eq!(proof, get_default_proof_output());
+        assert_eq!(stats.proving_time, Duration::ZERO);
+        assert_eq!(stats.overhead(), stats.round_trip_time);
+
+        Ok(())
+    }
+
+    async fn prove_with_divergent_post_root(
+        on_root_mismatch: OnRootMismatch,
+    ) -> anyhow::Result<(Proof, U256)> {
+        let mock_service = mock::Service::new().await?;
+        let input_data = get_default_proof_input();
+        mock_service.set_post_root(input_data.post_root + 2);
+
+        let settings = ProverSettings {
+            on_root_mismatch,
+            ..Default::default()
+        };
+        let mtb = mock_prover(mock_service.url(), &settings);
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
+
+        let result = mtb
+            .generate_insertion_proof_with_root(
+                input_data.start_index,
+                input_data.pre_root,
+                input_data.post_root,
+                &identities,
+            )
+            .await;
 
         mock_service.stop();
 
-        assert_eq!(proof, expected_proof);
-
-        Ok(())
+        result
     }
 
-    #[tokio::test]
-    async fn mtb_should_respond_with_error_if_inputs_incorrect() -> anyhow::Result<()> {
-        let mock_url: String = "0.0.0.0:3002".into();
-        let mock_service = mock::Service::new(mock_url.clone()).await?;
-
-        let options = ProverConfig {
-            url:         "http://localhost:3002".into(),
-            timeout_s:   30,
-            batch_size:  3,
-            prover_type: ProverType::Insertion,
+    /// Proves the default input with a mock service echoing it back, with the
+    /// post root replaced by `echoed_post_root`.
+    async fn prove_with_echoed_inputs(
+        verify_echoed_inputs: bool,
+        echoed_post_root: U256,
+    ) -> anyhow::Result<Proof> {
+        let mock_service = mock::Service::new().await?;
+        let input_data = get_default_proof_input();
+        mock_service.set_echoed_inputs(EchoedInputs {
+            start_index: input_data.start_index,
+            pre_root: input_data.pre_root,
+            post_root: echoed_post_root,
+            identity_commitments: input_data.identity_commitments.clone(),
+            ..Default::default()
+        });
+
+        let settings = ProverSettings {
+            verify_echoed_inputs,
+            ..Default::default()
         };
-        let mtb = Prover::new(&options).unwrap();
-        let mut input_data = get_default_proof_input();
-        let identities = extract_identities_from(&input_data);
-        input_data.post_root = U256::from(2);
+        let mtb = mock_prover(mock_service.url(), &settings);
+        let identities: Vec<Identity> = extract_identities_from(&input_data);
 
-        let prover_result = mtb
+        let result = mtb
             .generate_insertion_proof(
                 input_data.start_index,
                 input_data.pre_root,
@@ -473,37 +2034,127 @@ mod test {
             .await;
 
         mock_service.stop();
-        assert!(prover_result.is_err());
+
+        result
+    }
+
+    #[tokio::test]
+    async fn matching_echoed_inputs_are_accepted() -> anyhow::Result<()> {
+        let post_root = get_default_proof_input().post_root;
+
+        let proof = prove_with_echoed_inputs(true, post_root).await?;
+
+        assert_eq!(proof, get_default_proof_output());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn prover_should_error_if_batch_size_wrong() -> anyhow::Result<()> {
-        let options = ProverConfig {
-            url:         "http://localhost:3002".into(),
-            timeout_s:   30,
-            batch_size:  10,
-            prover_type: ProverType::Insertion,
-        };
-        let mtb = Prover::new(&options).unwrap();
+    async fn mismatching_echoed_inputs_fail_the_proof() -> anyhow::Result<()> {
+        let post_root = get_default_proof_input().post_root + 2;
+
+        let error = prove_with_echoed_inputs(true, post_root)
+            .await
+            .expect_err("Echoed inputs for another batch should fail the proof");
+
+        assert!(error.to_string().contains("echoed inputs"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn echoed_inputs_are_ignored_unless_verified() -> anyhow::Result<()> {
+        let post_root = get_default_proof_input().post_root + 2;
+
+        prove_with_echoed_inputs(false, post_root).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn divergent_post_root_fails_the_proof_by_default() -> anyhow::Result<()> {
+        let error = prove_with_divergent_post_root(OnRootMismatch::Error)
+            .await
+            .expect_err("A divergent post root should fail the proof");
+
+        assert!(error.to_string().contains("post root"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn divergent_post_root_from_a_trusted_prover_is_used() -> anyhow::Result<()> {
         let input_data = get_default_proof_input();
-        let identities = extract_identities_from(&input_data);
 
-        let prover_result = mtb
-            .generate_insertion_proof(
-                input_data.start_index,
-                input_data.pre_root,
-                input_data.post_root,
-                &identities,
-            )
-            .await;
+        let (proof, post_root) =
+            prove_with_divergent_post_root(OnRootMismatch::TrustProver).await?;
 
-        assert!(prover_result.is_err());
-        assert_eq!(
-            prover_result.unwrap_err().to_string(),
-            anyhow::Error::msg("Provided batch does not match prover batch size.").to_string()
-        );
+        assert_eq!(proof, get_default_proof_output());
+        assert_eq!(post_root, input_data.post_root + 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn divergent_post_root_is_ignored_when_trusting_local_root() -> anyhow::Result<()> {
+        let input_data = get_default_proof_input();
+
+        let (proof, post_root) = prove_with_divergent_post_root(OnRootMismatch::TrustLocal).await?;
+
+        assert_eq!(proof, get_default_proof_output());
+        assert_eq!(post_root, input_data.post_root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn proof_stats_overhead_excludes_proving_time() {
+        let stats = ProofStats {
+            proving_time:    Duration::from_millis(800),
+            round_trip_time: Duration::from_millis(1000),
+        };
+
+        assert_eq!(stats.overhead(), Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn batch_size_drift_is_detected_on_a_later_check() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+
+        let settings = ProverSettings {
+            pause_on_drift: true,
+            ..Default::default()
+        };
+        let mtb = mock_prover(mock_service.url(), &settings);
+
+        assert_eq!(mtb.check_batch_size().await?, None);
+        assert!(mtb.is_available());
+
+        // The prover is redeployed with a different circuit
+        mock_service.set_batch_size(10);
+
+        assert_eq!(mtb.check_batch_size().await?, Some(10));
+        assert!(!mtb.is_available());
+
+        // ... and rolled back
+        mock_service.set_batch_size(3);
+
+        assert_eq!(mtb.check_batch_size().await?, None);
+        assert!(mtb.is_available());
+
+        mock_service.stop();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn port_can_be_reused_after_stop_and_wait() -> anyhow::Result<()> {
+        let mock_service = mock::Service::new().await?;
+        let address = mock_service.address();
+        mock_service.stop_and_wait().await?;
+
+        let mock_service = mock::Service::bind(address).await?;
+        mock_service.stop_and_wait().await?;
 
         Ok(())
     }
@@ -517,12 +2168,201 @@ mod test {
                 input.start_index,
                 input.pre_root,
                 input.post_root,
-                &input.identity_commitments
+                &input.identity_commitments,
+                HashFunction::Keccak256,
+                Endianness::Big,
+                None,
             ),
             input.input_hash
         );
     }
 
+    // Fixtures are the roots of empty subtrees, where each level is the
+    // poseidon hash of two copies of the level below, starting from zero
+    const EMPTY_SUBTREE_ROOTS: [&str; 3] = [
+        "0x2098f5fb9e239eab3ceac3f27b81e481dc3124d55ffed523a839ee8446b64864",
+        "0x1069673dcdb12263df301a6ff584a7ec261a44cb9dc68df067a4774460b1f1e1",
+        "0x18f43331537ee2af2e3d758d50f72106467c6eea50371dd528d57eb2b856d238",
+    ];
+
+    #[test]
+    fn compute_poseidon_input_hash_should_succeed() {
+        let [z1, z2, z3] = EMPTY_SUBTREE_ROOTS.map(U256::from);
+
+        // H(H(H(0, 0), z1), z2) = H(H(z1, z1), z2) = H(z2, z2)
+        assert_eq!(
+            compute_insertion_proof_input_hash(
+                0,
+                U256::zero(),
+                z1,
+                &[z2],
+                HashFunction::Poseidon,
+                Endianness::Big,
+                None
+            ),
+            z3
+        );
+    }
+
+    #[test]
+    fn compute_poseidon_deletion_input_hash_should_succeed() {
+        let [z1, z2, _] = EMPTY_SUBTREE_ROOTS.map(U256::from);
+
+        // H(H(0, 0), z1) = H(z1, z1)
+        assert_eq!(
+            compute_deletion_input_hash(
+                U256::zero(),
+                z1,
+                &[0],
+                HashFunction::Poseidon,
+                Endianness::Big
+            ),
+            z2
+        );
+    }
+
+    #[test]
+    fn poseidon_input_hash_matches_circomlib() {
+        // Known answers of circomlib's `Poseidon(2)`
+        assert_eq!(
+            poseidon_input_hash([U256::from(1), U256::from(2)]),
+            U256::from("0x115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a")
+        );
+        assert_eq!(
+            poseidon_input_hash([U256::zero(), U256::zero()]),
+            U256::from(EMPTY_SUBTREE_ROOTS[0])
+        );
+    }
+
+    #[test]
+    fn compute_poseidon_input_hash_matches_fixture() {
+        let input = get_default_proof_input();
+        let expected: U256 =
+            "0x2a0e046499839c612f26afc9978face4974891036a44467a34fc35e288ca6d47".into();
+
+        let hash = compute_insertion_proof_input_hash(
+            input.start_index,
+            input.pre_root,
+            input.post_root,
+            &input.identity_commitments,
+            HashFunction::Poseidon,
+            Endianness::Big,
+            None,
+        );
+
+        assert_eq!(hash, expected);
+    }
+
+    #[test]
+    fn compute_poseidon_deletion_input_hash_matches_fixture() {
+        let pre_root: U256 =
+            "0x7b248024e18c30f6c8a6c63dad3748d72cd13d1197bfd79a1323216d6ac6e99".into();
+        let post_root: U256 =
+            "0x1b7201da72494f1e28717ad1a52eb469f95892f957713533de6175e5da190af2".into();
+        let expected: U256 =
+            "0x2753444cd272c6d95b686a82b47748916a133427363057f4cbeaa39a551e1f0c".into();
+
+        assert_eq!(
+            compute_deletion_input_hash(
+                pre_root,
+                post_root,
+                &[0, 2, 1 << 20],
+                HashFunction::Poseidon,
+                Endianness::Big
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn compute_input_hash_with_domain_should_differ() {
+        let input = get_default_proof_input();
+        let domain = [0x42; 32];
+        let expected: U256 =
+            "0xd1c87bf8553611064a2c2b14b4976c093c660ca4e82799cfe07ad9ed6b5a155c".into();
+
+        let hash = compute_insertion_proof_input_hash(
+            input.start_index,
+            input.pre_root,
+            input.post_root,
+            &input.identity_commitments,
+            HashFunction::Keccak256,
+            Endianness::Big,
+            Some(&domain),
+        );
+
+        assert_ne!(hash, input.input_hash);
+        assert_eq!(hash, expected);
+    }
+
+    #[test]
+    fn compute_little_endian_input_hash_should_differ() {
+        let input = get_default_proof_input();
+        let expected: U256 =
+            "0xf96ff4017e7227e49be1d42a7f7b425b2a8584efbdcf312160cd96fd2714bad5".into();
+
+        let hash = compute_insertion_proof_input_hash(
+            input.start_index,
+            input.pre_root,
+            input.post_root,
+            &input.identity_commitments,
+            HashFunction::Keccak256,
+            Endianness::Little,
+            None,
+        );
+
+        assert_ne!(hash, input.input_hash);
+        assert_eq!(hash, expected);
+    }
+
+    #[test]
+    fn compute_deletion_input_hash_should_succeed() {
+        let pre_root: U256 =
+            "0x7b248024e18c30f6c8a6c63dad3748d72cd13d1197bfd79a1323216d6ac6e99".into();
+        let post_root: U256 =
+            "0x1b7201da72494f1e28717ad1a52eb469f95892f957713533de6175e5da190af2".into();
+        let deletion_indices = [0, 2, 1 << 20];
+
+        let expected: U256 =
+            "0x5ee1085d363ee8970f62d162e13d32917f5714316dae88e868c610d7844ed05b".into();
+
+        assert_eq!(
+            compute_deletion_input_hash(
+                pre_root,
+                post_root,
+                &deletion_indices,
+                HashFunction::Keccak256,
+                Endianness::Big
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn compute_little_endian_deletion_input_hash_should_differ() {
+        let pre_root: U256 =
+            "0x7b248024e18c30f6c8a6c63dad3748d72cd13d1197bfd79a1323216d6ac6e99".into();
+        let post_root: U256 =
+            "0x1b7201da72494f1e28717ad1a52eb469f95892f957713533de6175e5da190af2".into();
+        let deletion_indices = [0, 2, 1 << 20];
+
+        let big_endian: U256 =
+            "0x5ee1085d363ee8970f62d162e13d32917f5714316dae88e868c610d7844ed05b".into();
+        let expected: U256 =
+            "0xd29d32df9bb28ad33df70278e5aca9752c9ad53c79fd9ce66ca586e1d3869814".into();
+
+        let hash = compute_deletion_input_hash(
+            pre_root,
+            post_root,
+            &deletion_indices,
+            HashFunction::Keccak256,
+            Endianness::Little,
+        );
+
+        assert_ne!(hash, big_endian);
+        assert_eq!(hash, expected);
+    }
+
     #[test]
     fn proof_input_should_serde() {
         let expected_data: InsertionProofInput = serde_json::from_str(EXPECTED_JSON).unwrap();
@@ -531,6 +2371,29 @@ mod test {
         assert_eq!(proof_input, expected_data);
     }
 
+    #[test]
+    fn built_proof_input_should_serialize() {
+        let input_data = get_default_proof_input();
+        let identities = extract_identities_from(&input_data);
+
+        let proof_input = ProofInput::Insertion(build_insertion_proof_input(
+            input_data.start_index,
+            input_data.pre_root,
+            input_data.post_root,
+            &identities,
+            HashFunction::Keccak256,
+            Endianness::Big,
+            None,
+        ));
+
+        let serialized = serde_json::to_value(&proof_input).unwrap();
+        let expected: serde_json::Value = serde_json::from_str(EXPECTED_JSON).unwrap();
+        assert_eq!(serialized, expected);
+
+        let deserialized: ProofInput = serde_json::from_value(serialized).unwrap();
+        assert_eq!(deserialized, proof_input);
+    }
+
     fn extract_identities_from(proof_input: &InsertionProofInput) -> Vec<Identity> {
         proof_input
             .identity_commitments
@@ -540,19 +2403,6 @@ mod test {
             .collect()
     }
 
-    pub fn get_default_proof_output() -> Proof {
-        Proof::from([
-            "0x12bba8b5a46139c819d83544f024828ece34f4f46be933a377a07c1904e96ec4".into(),
-            "0x112c8d7c63b6c431cef23e9c0d9ffff39d1d660f514030d4f2787960b437a1d5".into(),
-            "0x2413396a2af3add6fbe8137cfe7657917e31a5cdab0b7d1d645bd5eeb47ba601".into(),
-            "0x1ad029539528b32ba70964ce43dbf9bba2501cdb3aaa04e4d58982e2f6c34752".into(),
-            "0x5bb975296032b135458bd49f92d5e9d363367804440d4692708de92e887cf17".into(),
-            "0x14932600f53a1ceb11d79a7bdd9688a2f8d1919176f257f132587b2b3274c41e".into(),
-            "0x13d7b19c7b67bf5d3adf2ac2d3885fd5d49435b6069c0656939cd1fb7bef9dc9".into(),
-            "0x142e14f90c49c79b4edf5f6b7acbcdb0b0f376a4311fc036f1006679bd53ca9e".into(),
-        ])
-    }
-
     fn get_default_proof_input() -> InsertionProofInput {
         let start_index: u32 = 0;
         let pre_root: U256 =
@@ -608,6 +2458,8 @@ mod test {
             post_root,
             identity_commitments: identities,
             merkle_proofs,
+            tree_depth: None,
+            batch_size: None,
         }
     }
 
@@ -663,52 +2515,162 @@ mod test {
 "#;
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "mock-prover"))]
 pub mod mock {
-    use std::net::SocketAddr;
-
-    use axum::routing::post;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use anyhow::Context;
+    use axum::http::header::USER_AGENT;
+    use axum::http::HeaderMap;
+    use axum::routing::{get, post};
     use axum::{Json, Router};
     use axum_server::Handle;
+    use tokio::task::JoinHandle;
 
     use super::*;
 
+    /// The config of an insertion prover at `url` for batches of 3, the size
+    /// the mocks answer by default.
+    pub fn mock_prover_config(url: impl Into<String>) -> ProverConfig {
+        ProverConfig {
+            url:         url.into(),
+            timeout_s:   30,
+            batch_size:  3,
+            prover_type: ProverType::Insertion,
+        }
+    }
+
+    /// An insertion prover for batches of 3 talking to the mock service at
+    /// `url`.
+    pub fn mock_prover(url: impl Into<String>, settings: &ProverSettings) -> Prover {
+        Prover::new(&mock_prover_config(url), settings).expect("the mock prover config is valid")
+    }
+
+    /// The proof both mocks return for a batch they accept.
+    pub fn get_default_proof_output() -> Proof {
+        Proof::from([
+            "0x12bba8b5a46139c819d83544f024828ece34f4f46be933a377a07c1904e96ec4".into(),
+            "0x112c8d7c63b6c431cef23e9c0d9ffff39d1d660f514030d4f2787960b437a1d5".into(),
+            "0x2413396a2af3add6fbe8137cfe7657917e31a5cdab0b7d1d645bd5eeb47ba601".into(),
+            "0x1ad029539528b32ba70964ce43dbf9bba2501cdb3aaa04e4d58982e2f6c34752".into(),
+            "0x5bb975296032b135458bd49f92d5e9d363367804440d4692708de92e887cf17".into(),
+            "0x14932600f53a1ceb11d79a7bdd9688a2f8d1919176f257f132587b2b3274c41e".into(),
+            "0x13d7b19c7b67bf5d3adf2ac2d3885fd5d49435b6069c0656939cd1fb7bef9dc9".into(),
+            "0x142e14f90c49c79b4edf5f6b7acbcdb0b0f376a4311fc036f1006679bd53ca9e".into(),
+        ])
+    }
+
+    /// A mock prover service on a local port, answering proof requests for
+    /// odd post roots and failing the others.
     pub struct Service {
-        server: Handle,
+        server:          Handle,
+        task:            JoinHandle<()>,
+        address:         SocketAddr,
+        requests:        Arc<AtomicUsize>,
+        last_user_agent: Arc<Mutex<Option<String>>>,
+        batch_size:      Arc<AtomicUsize>,
+        proving_time_ms: Arc<Mutex<Option<u64>>>,
+        post_root:       Arc<Mutex<Option<U256>>>,
+        echoed_inputs:   Arc<Mutex<Option<EchoedInputs>>>,
     }
 
     #[derive(Serialize, Deserialize)]
     #[serde(untagged)]
     #[allow(clippy::large_enum_variant)]
     enum ProveResponse {
+        ProofSuccessWithMetadata {
+            #[serde(flatten)]
+            proof:           Proof,
+            #[serde(rename = "provingTimeMs", skip_serializing_if = "Option::is_none")]
+            proving_time_ms: Option<u64>,
+            #[serde(rename = "postRoot", skip_serializing_if = "Option::is_none")]
+            post_root:       Option<U256>,
+            #[serde(rename = "publicInputs", skip_serializing_if = "Option::is_none")]
+            echoed_inputs:   Option<EchoedInputs>,
+        },
         ProofSuccess(Proof),
         ProofFailure(ProverError),
     }
 
     impl Service {
-        pub async fn new(url: String) -> anyhow::Result<Self> {
-            let prove = |Json(payload): Json<InsertionProofInput>| async move {
-                match payload.post_root.div_mod(U256::from(2)) {
-                    (_, y) if y != U256::zero() => {
-                        Json(ProveResponse::ProofSuccess(test::get_default_proof_output()))
-                    }
-                    _ => {
-                        let error = ProverError {
-                            code:    "Oh no!".into(),
-                            message: "Things went wrong.".into(),
-                        };
-                        Json(ProveResponse::ProofFailure(error))
+        /// Starts the service on a free local port, see [`Service::url`].
+        pub async fn new() -> anyhow::Result<Self> {
+            Self::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).await
+        }
+
+        /// Starts the service on the given address.
+        pub async fn bind(addr: SocketAddr) -> anyhow::Result<Self> {
+            let requests = Arc::new(AtomicUsize::new(0));
+            let request_counter = requests.clone();
+            let last_user_agent = Arc::new(Mutex::new(None));
+            let user_agent_recorder = last_user_agent.clone();
+            let proving_time_ms = Arc::new(Mutex::new(None));
+            let reported_proving_time_ms = proving_time_ms.clone();
+            let post_root = Arc::new(Mutex::new(None));
+            let reported_post_root = post_root.clone();
+            let echoed_inputs = Arc::new(Mutex::new(None));
+            let reported_echoed_inputs = echoed_inputs.clone();
+
+            let prove = move |headers: HeaderMap, Json(payload): Json<InsertionProofInput>| {
+                request_counter.fetch_add(1, Ordering::SeqCst);
+                *user_agent_recorder.lock().unwrap() = headers
+                    .get(USER_AGENT)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string);
+                let proving_time_ms = *reported_proving_time_ms.lock().unwrap();
+                let post_root = *reported_post_root.lock().unwrap();
+                let echoed_inputs = reported_echoed_inputs.lock().unwrap().clone();
+
+                async move {
+                    match payload.post_root.div_mod(U256::from(2)) {
+                        (_, y) if y != U256::zero() => {
+                            let proof = get_default_proof_output();
+
+                            Json(
+                                if proving_time_ms.is_none()
+                                    && post_root.is_none()
+                                    && echoed_inputs.is_none()
+                                {
+                                    ProveResponse::ProofSuccess(proof)
+                                } else {
+                                    ProveResponse::ProofSuccessWithMetadata {
+                                        proof,
+                                        proving_time_ms,
+                                        post_root,
+                                        echoed_inputs,
+                                    }
+                                },
+                            )
+                        }
+                        _ => {
+                            let error = ProverError {
+                                code:    "Oh no!".into(),
+                                message: "Things went wrong.".into(),
+                            };
+                            Json(ProveResponse::ProofFailure(error))
+                        }
                     }
                 }
             };
-            let app = Router::new().route("/prove", post(prove));
+            // Matches the batch size of the default proof fixture
+            let batch_size = Arc::new(AtomicUsize::new(3));
+            let reported_batch_size = batch_size.clone();
+            let info = move || {
+                let batch_size = reported_batch_size.load(Ordering::SeqCst);
+                async move { Json(ProverInfo { batch_size }) }
+            };
+
+            let app = Router::new()
+                .route("/prove", post(prove))
+                .route("/info", get(info));
 
-            let addr: SocketAddr = url.parse()?;
             let server = Handle::new();
             let serverside_handle = server.clone();
             let service = app.into_make_service();
 
-            tokio::spawn(async move {
+            let task = tokio::spawn(async move {
                 axum_server::bind(addr)
                     .handle(serverside_handle)
                     .serve(service)
@@ -716,12 +2678,119 @@ pub mod mock {
                     .unwrap();
             });
 
-            let service = Self { server };
+            let address = server
+                .listening()
+                .await
+                .context("Failed to bind the mock prover service")?;
+
+            let service = Self {
+                server,
+                task,
+                address,
+                requests,
+                last_user_agent,
+                batch_size,
+                proving_time_ms,
+                post_root,
+                echoed_inputs,
+            };
             Ok(service)
         }
 
+        /// Returns the address the service listens on.
+        pub const fn address(&self) -> SocketAddr {
+            self.address
+        }
+
+        /// Returns the URL to configure a prover with to reach the service.
+        pub fn url(&self) -> String {
+            format!("http://{}", self.address)
+        }
+
+        /// Returns the number of proof requests the service has received.
+        pub fn request_count(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+
+        /// Returns the User-Agent of the last proof request received.
+        pub fn last_user_agent(&self) -> Option<String> {
+            self.last_user_agent.lock().unwrap().clone()
+        }
+
+        /// Sets the batch size reported on the `info` endpoint, e.g. to
+        /// simulate a prover redeployed with a different circuit.
+        pub fn set_batch_size(&self, batch_size: usize) {
+            self.batch_size.store(batch_size, Ordering::SeqCst);
+        }
+
+        /// Makes the service report the given proving time with every proof.
+        pub fn set_proving_time_ms(&self, proving_time_ms: u64) {
+            *self.proving_time_ms.lock().unwrap() = Some(proving_time_ms);
+        }
+
+        /// Makes the service report the given post root with every proof,
+        /// regardless of the one it was asked to prove.
+        pub fn set_post_root(&self, post_root: U256) {
+            *self.post_root.lock().unwrap() = Some(post_root);
+        }
+
+        /// Makes the service echo the given public inputs with every proof,
+        /// regardless of the ones it was asked to prove.
+        pub fn set_echoed_inputs(&self, echoed_inputs: EchoedInputs) {
+            *self.echoed_inputs.lock().unwrap() = Some(echoed_inputs);
+        }
+
         pub fn stop(self) {
             self.server.shutdown();
         }
+
+        /// Shuts the service down and waits until its port is released.
+        pub async fn stop_and_wait(self) -> anyhow::Result<()> {
+            self.server.shutdown();
+            self.task.await?;
+
+            Ok(())
+        }
+    }
+
+    /// An in-process prover backend mirroring the behaviour of [`Service`]
+    /// without binding to a port.
+    #[derive(Debug, Default)]
+    pub struct MockProver {
+        requests:   AtomicUsize,
+        last_input: Mutex<Option<ProofInput>>,
+    }
+
+    impl MockProver {
+        /// Returns the number of proof requests the backend has received.
+        pub fn request_count(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+
+        /// Returns the input of the latest proof request, if any.
+        pub fn last_input(&self) -> Option<ProofInput> {
+            self.last_input.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl ProveBackend for MockProver {
+        async fn prove(&self, input: ProofInput) -> Result<Proof, ProverError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            *self.last_input.lock().unwrap() = Some(input.clone());
+
+            let post_root = match input {
+                ProofInput::Insertion(input) => input.post_root,
+                ProofInput::Deletion(input) => input.post_root,
+            };
+
+            match post_root.div_mod(U256::from(2)) {
+                (_, y) if y != U256::zero() => Ok(get_default_proof_output()),
+                _ => Err(ProverError {
+                    code:    "Oh no!".into(),
+                    message: "Things went wrong.".into(),
+                }),
+            }
+        }
     }
 }
@@ -0,0 +1,174 @@
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Tracks consecutive failures of a prover and temporarily takes it out of
+/// rotation when it keeps failing.
+///
+/// The breaker opens after `failure_threshold` consecutive failures. Once the
+/// cooldown has elapsed it is half-open: a single trial request is let
+/// through, and its success closes the breaker while its failure opens it for
+/// another cooldown period.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: NonZeroU32,
+    cooldown:          Duration,
+    state:             Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: u32,
+    opened_at:            Option<Instant>,
+    trial_in_flight:      bool,
+}
+
+impl State {
+    fn is_open(&self, cooldown: Duration) -> bool {
+        self.opened_at
+            .map_or(false, |opened_at| opened_at.elapsed() < cooldown)
+    }
+
+    fn is_half_open(&self, cooldown: Duration) -> bool {
+        self.opened_at.is_some() && !self.is_open(cooldown)
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a circuit breaker that opens after `failure_threshold`
+    /// consecutive failures and stays open for `cooldown`.
+    pub fn new(failure_threshold: NonZeroU32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns `true` if a request may be sent to the prover, i.e. the
+    /// breaker is closed, or half-open with no trial request in flight yet.
+    pub fn is_available(&self) -> bool {
+        let state = self.state.lock().expect("Circuit breaker lock poisoned");
+
+        !state.is_open(self.cooldown)
+            && !(state.is_half_open(self.cooldown) && state.trial_in_flight)
+    }
+
+    /// Claims the right to send a request. While half-open only the first
+    /// caller gets it, until the outcome of its trial is recorded.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().expect("Circuit breaker lock poisoned");
+
+        if state.is_open(self.cooldown) {
+            return false;
+        }
+
+        if state.is_half_open(self.cooldown) {
+            if state.trial_in_flight {
+                return false;
+            }
+
+            state.trial_in_flight = true;
+        }
+
+        true
+    }
+
+    /// Closes the breaker and resets the failure count.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().expect("Circuit breaker lock poisoned");
+
+        *state = State::default();
+    }
+
+    /// Records a failure, opening the breaker if the threshold is reached.
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().expect("Circuit breaker lock poisoned");
+
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        state.trial_in_flight = false;
+
+        if state.consecutive_failures >= self.failure_threshold.get() {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_secs(10);
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(NonZeroU32::new(failure_threshold).unwrap(), COOLDOWN)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_after_consecutive_failures() {
+        let breaker = breaker(3);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.is_available());
+
+        breaker.record_failure();
+        assert!(!breaker.is_available());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn success_resets_failure_count() {
+        let breaker = breaker(2);
+
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+
+        assert!(breaker.is_available());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_open_after_cooldown() {
+        let breaker = breaker(1);
+
+        breaker.record_failure();
+        assert!(!breaker.is_available());
+        assert!(!breaker.try_acquire());
+
+        tokio::time::advance(COOLDOWN).await;
+        assert!(breaker.is_available());
+
+        // A single failure while half-open opens the breaker again
+        assert!(breaker.try_acquire());
+        breaker.record_failure();
+        assert!(!breaker.is_available());
+
+        tokio::time::advance(COOLDOWN).await;
+        assert!(breaker.is_available());
+
+        // A single success while half-open closes the breaker
+        assert!(breaker.try_acquire());
+        breaker.record_success();
+        assert!(breaker.is_available());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_open_admits_a_single_trial() {
+        let breaker = breaker(1);
+
+        breaker.record_failure();
+        tokio::time::advance(COOLDOWN).await;
+
+        assert!(breaker.try_acquire());
+        assert!(!breaker.is_available());
+        assert!(!breaker.try_acquire());
+
+        breaker.record_success();
+
+        // Closed again, so every request is let through
+        assert!(breaker.try_acquire());
+        assert!(breaker.try_acquire());
+    }
+}
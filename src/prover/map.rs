@@ -1,6 +1,9 @@
 use std::collections::HashSet;
 
-use crate::prover::{Prover, ProverConfig, ProverType};
+use tracing::{error, info, warn};
+
+use crate::config::ProverStartupCheck;
+use crate::prover::{Prover, ProverConfig, ProverSettings, ProverType};
 use crate::utils::min_map::MinMap;
 
 /// A map that contains a prover for each batch size.
@@ -12,9 +15,17 @@ pub struct ProverMap {
 }
 
 impl ProverMap {
-    /// Get the smallest prover that can handle the given batch size.
+    /// Get the smallest available prover that can handle the given batch
+    /// size.
+    ///
+    /// Provers whose circuit breaker is open are skipped in favour of the next
+    /// larger prover.
     pub fn get(&self, batch_size: usize) -> Option<&Prover> {
-        self.map.get(batch_size)
+        self.map
+            .iter()
+            .filter(|(size, _)| batch_size <= **size)
+            .map(|(_, prover)| prover)
+            .find(|prover| prover.is_available())
     }
 
     /// Registers the provided `prover` for the given `batch_size` in the map.
@@ -22,6 +33,14 @@ impl ProverMap {
         self.map.add(batch_size, prover);
     }
 
+    /// Get the prover registered for exactly the given batch size.
+    pub fn get_exact(&self, batch_size: usize) -> Option<&Prover> {
+        self.map
+            .iter()
+            .find(|(size, _)| **size == batch_size)
+            .map(|(_, prover)| prover)
+    }
+
     /// Removes the prover for the provided `batch_size` from the prover map.
     pub fn remove(&mut self, batch_size: usize) -> Option<Prover> {
         self.map.remove(batch_size)
@@ -39,10 +58,76 @@ impl ProverMap {
         self.map.max_key().unwrap_or(0)
     }
 
+    /// Returns how many identities have to be added to `queued_identities`
+    /// to fill the smallest prover, or `None` if there are no provers.
+    pub fn identities_until_next_batch(&self, queued_identities: usize) -> Option<usize> {
+        self.map
+            .min_key()
+            .map(|min_batch_size| min_batch_size.saturating_sub(queued_identities))
+    }
+
     pub fn batch_size_exists(&self, batch_size: usize) -> bool {
         self.map.key_exists(batch_size)
     }
 
+    /// Splits `num_identities` into the sizes of consecutive batches, filling
+    /// the largest prover that fits first.
+    ///
+    /// Every batch but the last exactly matches a registered batch size. The
+    /// last one holds whatever is smaller than the smallest prover, if
+    /// anything.
+    pub fn split_into_batches(&self, num_identities: usize) -> Vec<usize> {
+        let mut batch_sizes: Vec<usize> = self.map.iter().map(|(size, _)| *size).collect();
+        batch_sizes.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut batches = vec![];
+        let mut remaining = num_identities;
+
+        while remaining > 0 {
+            let batch = batch_sizes
+                .iter()
+                .copied()
+                .find(|size| *size <= remaining)
+                .unwrap_or(remaining);
+
+            batches.push(batch);
+            remaining -= batch;
+        }
+
+        batches
+    }
+
+    /// Returns an iterator over all the registered provers.
+    pub fn provers(&self) -> impl Iterator<Item = &Prover> {
+        self.map.iter().map(|(_, prover)| prover)
+    }
+
+    /// Checks that every registered prover can be reached and reports the
+    /// batch size it is configured with.
+    pub async fn verify(&self) -> ProverReport {
+        let mut checks = vec![];
+
+        for prover in self.provers() {
+            let reachable = prover.is_reachable().await;
+            // Not every prover serves its circuit parameters
+            let reported_batch_size = if reachable {
+                prover.info().await.ok().map(|info| info.batch_size)
+            } else {
+                None
+            };
+
+            checks.push(ProverCheck {
+                url: prover.url(),
+                batch_size: prover.batch_size(),
+                prover_type: prover.prover_type(),
+                reachable,
+                reported_batch_size,
+            });
+        }
+
+        ProverReport { checks }
+    }
+
     pub fn as_configuration_vec(&self) -> Vec<ProverConfig> {
         self.map
             .iter()
@@ -56,9 +141,94 @@ impl ProverMap {
     }
 }
 
+/// The outcome of checking a single prover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProverCheck {
+    pub url:                 String,
+    pub batch_size:          usize,
+    pub prover_type:         ProverType,
+    pub reachable:           bool,
+    /// The batch size the prover reports, if it serves its circuit parameters
+    pub reported_batch_size: Option<usize>,
+}
+
+impl ProverCheck {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.reachable
+            && self
+                .reported_batch_size
+                .map_or(true, |reported| reported == self.batch_size)
+    }
+}
+
+/// The outcome of checking a set of provers, e.g. at startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProverReport {
+    pub checks: Vec<ProverCheck>,
+}
+
+impl ProverReport {
+    pub fn extend(&mut self, other: Self) {
+        self.checks.extend(other.checks);
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &ProverCheck> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+
+    /// Logs the outcome of every check. Returns an error if any check failed
+    /// and `policy` is [`ProverStartupCheck::Error`].
+    pub fn enforce(&self, policy: ProverStartupCheck) -> anyhow::Result<()> {
+        for check in &self.checks {
+            let url = check.url.as_str();
+            let batch_size = check.batch_size;
+            let prover_type = check.prover_type;
+
+            if check.passed() {
+                info!(url, batch_size, %prover_type, "Prover verified");
+                continue;
+            }
+
+            let reachable = check.reachable;
+            let reported_batch_size = check.reported_batch_size;
+            if policy == ProverStartupCheck::Error {
+                error!(
+                    url,
+                    batch_size,
+                    %prover_type,
+                    reachable,
+                    ?reported_batch_size,
+                    "Prover failed verification"
+                );
+            } else {
+                warn!(
+                    url,
+                    batch_size,
+                    %prover_type,
+                    reachable,
+                    ?reported_batch_size,
+                    "Prover failed verification"
+                );
+            }
+        }
+
+        let failed = self.failed().count();
+        if failed > 0 && policy == ProverStartupCheck::Error {
+            return Err(anyhow::anyhow!(
+                "{failed} of {} provers failed verification",
+                self.checks.len()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 /// Builds an insertion prover map from the provided configuration.
 pub fn initialize_prover_maps(
     db_provers: HashSet<ProverConfig>,
+    settings: &ProverSettings,
 ) -> anyhow::Result<(ProverMap, ProverMap)> {
     let mut insertion_map = ProverMap::default();
     let mut deletion_map = ProverMap::default();
@@ -66,14 +236,166 @@ pub fn initialize_prover_maps(
     for prover in db_provers {
         match prover.prover_type {
             ProverType::Insertion => {
-                insertion_map.add(prover.batch_size, Prover::from_prover_conf(&prover)?);
+                insertion_map.add(
+                    prover.batch_size,
+                    Prover::from_prover_conf(&prover, settings)?,
+                );
             }
 
             ProverType::Deletion => {
-                deletion_map.add(prover.batch_size, Prover::from_prover_conf(&prover)?);
+                deletion_map.add(
+                    prover.batch_size,
+                    Prover::from_prover_conf(&prover, settings)?,
+                );
             }
         }
     }
 
     Ok((insertion_map, deletion_map))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    use test_case::test_case;
+
+    use super::*;
+    use crate::prover::mock;
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    fn prover(batch_size: usize, settings: &ProverSettings) -> Prover {
+        let config = ProverConfig {
+            batch_size,
+            ..mock::mock_prover_config("http://localhost:3001")
+        };
+
+        Prover::new(&config, settings).unwrap()
+    }
+
+    fn map_with_batch_sizes(batch_sizes: &[usize]) -> ProverMap {
+        let settings = ProverSettings::default();
+
+        let mut map = ProverMap::default();
+        for batch_size in batch_sizes {
+            map.add(*batch_size, prover(*batch_size, &settings));
+        }
+
+        map
+    }
+
+    #[test]
+    fn large_queue_is_split_largest_first() {
+        let map = map_with_batch_sizes(&[10, 30, 100]);
+
+        assert_eq!(map.split_into_batches(130), vec![100, 30]);
+        assert_eq!(map.split_into_batches(260), vec![100, 100, 30, 30]);
+        assert_eq!(map.split_into_batches(100), vec![100]);
+    }
+
+    #[test]
+    fn remainder_smaller_than_every_prover_is_last() {
+        let map = map_with_batch_sizes(&[10, 30, 100]);
+
+        assert_eq!(map.split_into_batches(135), vec![100, 30, 5]);
+        assert_eq!(map.split_into_batches(7), vec![7]);
+        assert!(map.split_into_batches(0).is_empty());
+    }
+
+    #[test_case(&[10, 30, 100], 0 => Some(10))]
+    #[test_case(&[10, 30, 100], 7 => Some(3))]
+    #[test_case(&[10, 30, 100], 10 => Some(0))]
+    #[test_case(&[10, 30, 100], 45 => Some(0))]
+    #[test_case(&[30, 100], 12 => Some(18))]
+    #[test_case(&[1], 0 => Some(1))]
+    #[test_case(&[], 5 => None)]
+    fn identities_until_next_batch(batch_sizes: &[usize], queued: usize) -> Option<usize> {
+        map_with_batch_sizes(batch_sizes).identities_until_next_batch(queued)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_prover_is_skipped_until_cooldown() {
+        let settings = ProverSettings {
+            failure_threshold: NonZeroU32::new(2),
+            failure_cooldown: COOLDOWN,
+            ..Default::default()
+        };
+
+        let mut map = ProverMap::default();
+        map.add(3, prover(3, &settings));
+        map.add(10, prover(10, &settings));
+
+        let breaker = map.get(3).unwrap().circuit_breaker.clone().unwrap();
+
+        breaker.record_failure();
+        assert_eq!(map.get(3).unwrap().batch_size(), 3);
+
+        breaker.record_failure();
+        assert_eq!(map.get(3).unwrap().batch_size(), 10);
+
+        tokio::time::advance(COOLDOWN).await;
+        assert_eq!(map.get(3).unwrap().batch_size(), 3);
+
+        breaker.record_success();
+        assert_eq!(map.get(3).unwrap().batch_size(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_prover_when_all_are_failing() {
+        let settings = ProverSettings {
+            failure_threshold: NonZeroU32::new(1),
+            failure_cooldown: COOLDOWN,
+            ..Default::default()
+        };
+
+        let mut map = ProverMap::default();
+        map.add(3, prover(3, &settings));
+
+        map.get(3)
+            .unwrap()
+            .circuit_breaker
+            .as_ref()
+            .unwrap()
+            .record_failure();
+
+        assert!(map.get(3).is_none());
+    }
+
+    fn prover_at(url: &str, batch_size: usize) -> Prover {
+        let config = ProverConfig {
+            timeout_s: 1,
+            batch_size,
+            ..mock::mock_prover_config(url)
+        };
+
+        Prover::new(&config, &ProverSettings::default()).unwrap()
+    }
+
+    #[test_case(ProverStartupCheck::Warn => true ; "warn")]
+    #[test_case(ProverStartupCheck::Error => false ; "error")]
+    #[tokio::test]
+    async fn unreachable_prover_is_reported(policy: ProverStartupCheck) -> bool {
+        let mock_service = mock::Service::new().await.unwrap();
+        // Nothing listens on this port once the service is stopped
+        let stopped_service = mock::Service::new().await.unwrap();
+        let unreachable_url = stopped_service.url();
+        stopped_service.stop_and_wait().await.unwrap();
+
+        let mut map = ProverMap::default();
+        map.add(3, prover_at(&mock_service.url(), 3));
+        map.add(10, prover_at(&unreachable_url, 10));
+
+        let report = map.verify().await;
+        mock_service.stop();
+
+        let failed: Vec<&ProverCheck> = report.failed().collect();
+        assert_eq!(report.checks.len(), 2);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].url, unreachable_url);
+        assert!(!failed[0].reachable);
+
+        report.enforce(policy).is_ok()
+    }
+}
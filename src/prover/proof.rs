@@ -1,6 +1,13 @@
+use anyhow::{anyhow, Context};
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G2Affine};
+use ark_ff::{BigInteger256, PrimeField};
+use ark_groth16::{prepare_verifying_key, verify_proof};
 use ethers::types::U256;
 use serde::{Deserialize, Serialize};
 
+/// A Groth16 verifying key over BN254.
+pub type VerifyingKey = ark_groth16::VerifyingKey<Bn254>;
+
 /// The proof term returned from the `semaphore-mtb` proof generation service.
 ///
 /// The names of the data fields match those from the JSON response exactly.
@@ -11,6 +18,73 @@ pub struct Proof {
     pub krs: [U256; 2],
 }
 
+impl Proof {
+    /// Verifies the proof against the provided `public_inputs` locally, without
+    /// a round trip to the verifier contract.
+    ///
+    /// Returns `false` if the proof is invalid or its points are malformed.
+    #[must_use]
+    pub fn verify(&self, vk: &VerifyingKey, public_inputs: &[U256]) -> bool {
+        let Some(proof) = self.to_ark() else {
+            return false;
+        };
+
+        let public_inputs: Vec<Fr> = public_inputs
+            .iter()
+            .map(|input| {
+                let mut bytes = [0u8; 32];
+                input.to_big_endian(&mut bytes);
+                Fr::from_be_bytes_mod_order(&bytes)
+            })
+            .collect();
+
+        let pvk = prepare_verifying_key(vk);
+
+        verify_proof(&pvk, &proof, &public_inputs).unwrap_or(false)
+    }
+
+    /// Returns whether the proof is obviously unusable, i.e. any of its points
+    /// is all zeros. The verifier contract reads an all-zero point as the
+    /// point at infinity, which no honest prover produces.
+    #[must_use]
+    pub fn is_trivially_invalid(&self) -> bool {
+        let ar_is_zero = self.ar.iter().all(U256::is_zero);
+        let bs_is_zero = self.bs.iter().flatten().all(U256::is_zero);
+        let krs_is_zero = self.krs.iter().all(U256::is_zero);
+
+        ar_is_zero || bs_is_zero || krs_is_zero
+    }
+
+    /// Converts the proof to its arkworks representation. The `G2` point is
+    /// encoded as in the verifier contract, with the imaginary part of each
+    /// coordinate first.
+    fn to_ark(&self) -> Option<ark_groth16::Proof<Bn254>> {
+        let a = g1_from_coordinates(self.ar)?;
+        let b = g2_from_coordinates(self.bs)?;
+        let c = g1_from_coordinates(self.krs)?;
+
+        Some(ark_groth16::Proof { a, b, c })
+    }
+}
+
+fn fq_from_u256(value: U256) -> Option<Fq> {
+    Fq::from_repr(BigInteger256(value.0))
+}
+
+fn g1_from_coordinates([x, y]: [U256; 2]) -> Option<G1Affine> {
+    let point = G1Affine::new(fq_from_u256(x)?, fq_from_u256(y)?, false);
+
+    (point.is_on_curve() && point.is_in_correct_subgroup_assuming_on_curve()).then_some(point)
+}
+
+fn g2_from_coordinates([[x1, x0], [y1, y0]]: [[U256; 2]; 2]) -> Option<G2Affine> {
+    let x = Fq2::new(fq_from_u256(x0)?, fq_from_u256(x1)?);
+    let y = Fq2::new(fq_from_u256(y0)?, fq_from_u256(y1)?);
+    let point = G2Affine::new(x, y, false);
+
+    (point.is_on_curve() && point.is_in_correct_subgroup_assuming_on_curve()).then_some(point)
+}
+
 impl From<[U256; 8]> for Proof {
     fn from(value: [U256; 8]) -> Self {
         Self {
@@ -21,6 +95,70 @@ impl From<[U256; 8]> for Proof {
     }
 }
 
+/// A proof in the JSON format produced by snarkjs.
+///
+/// The points are in projective coordinates and every coordinate is a decimal
+/// string. The `G2` point lists the real part of each coordinate first, the
+/// reverse of the verifier contract's order. Only points normalized to
+/// `z = 1` are accepted, as their `x` and `y` are then the affine coordinates
+/// the verifier contract takes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnarkJsProof {
+    pub pi_a: Vec<String>,
+    pub pi_b: Vec<Vec<String>>,
+    pub pi_c: Vec<String>,
+}
+
+impl TryFrom<SnarkJsProof> for Proof {
+    type Error = anyhow::Error;
+
+    fn try_from(proof: SnarkJsProof) -> anyhow::Result<Self> {
+        let [b_x, b_y, b_z] = proof.pi_b.as_slice() else {
+            return Err(anyhow!("Malformed pi_b: expected three coordinate pairs"));
+        };
+        let [b_x0, b_x1] = fq2_from_snarkjs(b_x)?;
+        let [b_y0, b_y1] = fq2_from_snarkjs(b_y)?;
+
+        if fq2_from_snarkjs(b_z)? != [U256::one(), U256::zero()] {
+            return Err(anyhow!("Malformed pi_b: expected z = 1"));
+        }
+
+        Ok(Self {
+            ar:  g1_from_snarkjs("pi_a", &proof.pi_a)?,
+            bs:  [[b_x1, b_x0], [b_y1, b_y0]],
+            krs: g1_from_snarkjs("pi_c", &proof.pi_c)?,
+        })
+    }
+}
+
+fn g1_from_snarkjs(name: &str, point: &[String]) -> anyhow::Result<[U256; 2]> {
+    let [x, y, z] = point else {
+        return Err(anyhow!("Malformed {name}: expected three coordinates"));
+    };
+
+    if parse_decimal(z)? != U256::one() {
+        return Err(anyhow!("Malformed {name}: expected z = 1"));
+    }
+
+    Ok([parse_decimal(x)?, parse_decimal(y)?])
+}
+
+/// Parses a coordinate of a `G2` point, real part first.
+fn fq2_from_snarkjs(coordinate: &[String]) -> anyhow::Result<[U256; 2]> {
+    let [c0, c1] = coordinate else {
+        return Err(anyhow!(
+            "Malformed pi_b: expected coordinate pairs, got {} values",
+            coordinate.len()
+        ));
+    };
+
+    Ok([parse_decimal(c0)?, parse_decimal(c1)?])
+}
+
+fn parse_decimal(value: &str) -> anyhow::Result<U256> {
+    U256::from_dec_str(value).with_context(|| format!("Invalid proof coordinate {value:?}"))
+}
+
 impl From<Proof> for [U256; 8] {
     fn from(value: Proof) -> Self {
         [
@@ -35,3 +173,265 @@ impl From<Proof> for [U256; 8] {
         ]
     }
 }
+
+/// A proof in the shape of the proving system that produced it.
+///
+/// Groth16 proofs are always the eight words the verifier contracts take,
+/// while the length of a PLONK proof depends on the circuit.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "points", rename_all = "lowercase")]
+pub enum ProofKind {
+    Groth16([U256; 8]),
+    Plonk(Vec<U256>),
+}
+
+impl ProofKind {
+    /// Returns the words of the proof in the order they are passed on-chain.
+    #[must_use]
+    pub fn points(&self) -> &[U256] {
+        match self {
+            Self::Groth16(points) => points,
+            Self::Plonk(points) => points,
+        }
+    }
+}
+
+impl From<Proof> for ProofKind {
+    fn from(proof: Proof) -> Self {
+        Self::Groth16(proof.into())
+    }
+}
+
+impl TryFrom<ProofKind> for Proof {
+    type Error = anyhow::Error;
+
+    fn try_from(proof: ProofKind) -> anyhow::Result<Self> {
+        match proof {
+            ProofKind::Groth16(points) => Ok(Self::from(points)),
+            ProofKind::Plonk(points) => Err(anyhow!(
+                "Expected a Groth16 proof, got a PLONK proof of {} words",
+                points.len()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use ark_ec::{AffineCurve, ProjectiveCurve};
+
+    use super::*;
+    use crate::prover::mock::get_default_proof_output;
+
+    fn g1(scalar: u64) -> G1Affine {
+        G1Affine::prime_subgroup_generator()
+            .mul(Fr::from(scalar).into_repr())
+            .into_affine()
+    }
+
+    fn g2(scalar: u64) -> G2Affine {
+        G2Affine::prime_subgroup_generator()
+            .mul(Fr::from(scalar).into_repr())
+            .into_affine()
+    }
+
+    fn to_u256(value: Fq) -> U256 {
+        U256(value.into_repr().0)
+    }
+
+    /// Builds a verifying key and a matching proof for the given public input.
+    ///
+    /// With every `G2` element of the key set to the generator, the pairing
+    /// check reduces to `A = alpha + L + C` where `L = gamma_abc[0] + input *
+    /// gamma_abc[1]`.
+    pub fn key_and_proof_for(input: U256) -> (VerifyingKey, Proof) {
+        let vk = VerifyingKey {
+            alpha_g1:     g1(1),
+            beta_g2:      g2(1),
+            gamma_g2:     g2(1),
+            delta_g2:     g2(1),
+            gamma_abc_g1: vec![g1(1), g1(1)],
+        };
+
+        let mut bytes = [0u8; 32];
+        input.to_big_endian(&mut bytes);
+        let a_scalar = Fr::from(3u64) + Fr::from_be_bytes_mod_order(&bytes);
+        let a = G1Affine::prime_subgroup_generator()
+            .mul(a_scalar.into_repr())
+            .into_affine();
+        let b = g2(1);
+        let c = g1(1);
+
+        let proof = Proof {
+            ar:  [to_u256(a.x), to_u256(a.y)],
+            bs:  [[to_u256(b.x.c1), to_u256(b.x.c0)], [
+                to_u256(b.y.c1),
+                to_u256(b.y.c0),
+            ]],
+            krs: [to_u256(c.x), to_u256(c.y)],
+        };
+
+        (vk, proof)
+    }
+
+    const SNARKJS_PROOF_JSON: &str = r#"{
+        "pi_a": [
+            "8473196066060172600014997931094904448157664613864067686514389823863189499588",
+            "7768036196535164314075782529381215518495531455074803059166929203079260119509",
+            "1"
+        ],
+        "pi_b": [
+            [
+                "12127923477622896244987399516583981999107151034595945749960215334206335829842",
+                "16317228905047853065500278290472280091211199019717191499041067311819669612033"
+            ],
+            [
+                "9306245782374346050038455248550779512910570188861861177151682478046168007710",
+                "2593009034242028991353641816374313261297027842951659832721823671618711834391"
+            ],
+            ["1", "0"]
+        ],
+        "pi_c": [
+            "8975042069860567143207752820977530876489590137606418439193505330129030585801",
+            "9127676685892888092161696523089599572570822394278258323878124614005275478686",
+            "1"
+        ],
+        "protocol": "groth16",
+        "curve": "bn128"
+    }"#;
+
+    #[test]
+    fn snarkjs_fixture_converts_to_flat_proof() -> anyhow::Result<()> {
+        let snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON)?;
+
+        assert_eq!(Proof::try_from(snarkjs)?, get_default_proof_output());
+
+        Ok(())
+    }
+
+    #[test]
+    fn converted_snarkjs_proof_should_verify() -> anyhow::Result<()> {
+        let (vk, proof) = key_and_proof_for(U256::from(5));
+
+        let decimal = |value: U256| value.to_string();
+        let snarkjs = SnarkJsProof {
+            pi_a: vec![decimal(proof.ar[0]), decimal(proof.ar[1]), "1".into()],
+            pi_b: vec![
+                vec![decimal(proof.bs[0][1]), decimal(proof.bs[0][0])],
+                vec![decimal(proof.bs[1][1]), decimal(proof.bs[1][0])],
+                vec!["1".into(), "0".into()],
+            ],
+            pi_c: vec![decimal(proof.krs[0]), decimal(proof.krs[1]), "1".into()],
+        };
+
+        let converted = Proof::try_from(snarkjs)?;
+
+        assert_eq!(converted, proof);
+        assert!(converted.verify(&vk, &[U256::from(5)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_snarkjs_proof_is_rejected() {
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_b.truncate(1);
+        assert!(Proof::try_from(snarkjs).is_err());
+
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_a[0] = "0x12".into();
+        assert!(Proof::try_from(snarkjs).is_err());
+    }
+
+    #[test]
+    fn projective_snarkjs_proof_is_rejected() {
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_a[2] = "2".into();
+        assert!(Proof::try_from(snarkjs).is_err());
+
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_b[2] = vec!["1".into(), "1".into()];
+        assert!(Proof::try_from(snarkjs).is_err());
+
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_c[2] = "0".into();
+        assert!(Proof::try_from(snarkjs).is_err());
+
+        let mut snarkjs: SnarkJsProof = serde_json::from_str(SNARKJS_PROOF_JSON).unwrap();
+        snarkjs.pi_c.truncate(2);
+        assert!(Proof::try_from(snarkjs).is_err());
+    }
+
+    #[test]
+    fn all_zero_proof_is_trivially_invalid() {
+        let proof = Proof {
+            ar:  [U256::zero(); 2],
+            bs:  [[U256::zero(); 2]; 2],
+            krs: [U256::zero(); 2],
+        };
+
+        assert!(proof.is_trivially_invalid());
+    }
+
+    #[test]
+    fn proof_with_a_zero_point_is_trivially_invalid() {
+        let mut proof = get_default_proof_output();
+        assert!(!proof.is_trivially_invalid());
+
+        proof.krs = [U256::zero(); 2];
+        assert!(proof.is_trivially_invalid());
+    }
+
+    #[test]
+    fn valid_proof_should_verify() {
+        let (vk, proof) = key_and_proof_for(U256::from(5));
+
+        assert!(proof.verify(&vk, &[U256::from(5)]));
+    }
+
+    #[test]
+    fn proof_with_wrong_inputs_should_not_verify() {
+        let (vk, proof) = key_and_proof_for(U256::from(5));
+
+        assert!(!proof.verify(&vk, &[U256::from(6)]));
+    }
+
+    #[test]
+    fn fixture_proof_should_not_verify_against_unrelated_key() {
+        let (vk, _) = key_and_proof_for(U256::from(5));
+        let proof = get_default_proof_output();
+
+        assert!(!proof.verify(&vk, &[U256::from(5)]));
+    }
+
+    #[test]
+    fn groth16_proof_kind_round_trips_through_json() -> anyhow::Result<()> {
+        let proof = ProofKind::from(get_default_proof_output());
+
+        let json = serde_json::to_value(&proof)?;
+        assert_eq!(json["kind"], "groth16");
+        assert_eq!(json["points"].as_array().map(Vec::len), Some(8));
+
+        let decoded: ProofKind = serde_json::from_value(json)?;
+        assert_eq!(decoded, proof);
+        assert_eq!(Proof::try_from(decoded)?, get_default_proof_output());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plonk_proof_kind_round_trips_through_json() -> anyhow::Result<()> {
+        let proof = ProofKind::Plonk((1..=24).map(U256::from).collect());
+
+        let json = serde_json::to_value(&proof)?;
+        assert_eq!(json["kind"], "plonk");
+        assert_eq!(json["points"].as_array().map(Vec::len), Some(24));
+
+        let decoded: ProofKind = serde_json::from_value(json)?;
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.points().len(), 24);
+        assert!(Proof::try_from(decoded).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use ethers::types::U256;
+
+use crate::prover::Proof;
+
+/// Keeps the most recently generated proofs, keyed by the input hash of the
+/// batch they prove, so that a batch proved again, e.g. after a reorg, doesn't
+/// have to be sent to the prover a second time.
+///
+/// Once full, the least recently used proof is evicted.
+#[derive(Debug)]
+pub struct ProofCache {
+    capacity: usize,
+    state:    Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    proofs: HashMap<U256, Proof>,
+    /// The cached input hashes, least recently used first
+    order:  VecDeque<U256>,
+}
+
+impl State {
+    fn touch(&mut self, input_hash: U256) {
+        self.order.retain(|hash| *hash != input_hash);
+        self.order.push_back(input_hash);
+    }
+}
+
+impl ProofCache {
+    /// Creates a cache holding up to `capacity` proofs. A cache with no
+    /// capacity never holds any proof.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the cached proof of the batch with the given input hash.
+    pub fn get(&self, input_hash: U256) -> Option<Proof> {
+        let mut state = self.state.lock().expect("Proof cache lock poisoned");
+
+        let proof = state.proofs.get(&input_hash).cloned()?;
+        state.touch(input_hash);
+
+        Some(proof)
+    }
+
+    /// Caches the proof of the batch with the given input hash.
+    pub fn insert(&self, input_hash: U256, proof: Proof) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("Proof cache lock poisoned");
+
+        state.proofs.insert(input_hash, proof);
+        state.touch(input_hash);
+
+        while state.order.len() > self.capacity {
+            if let Some(evicted) = state.order.pop_front() {
+                state.proofs.remove(&evicted);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof(value: u64) -> Proof {
+        Proof {
+            ar:  [U256::from(value); 2],
+            bs:  [[U256::from(value); 2]; 2],
+            krs: [U256::from(value); 2],
+        }
+    }
+
+    #[test]
+    fn cached_proof_is_returned() {
+        let cache = ProofCache::new(2);
+
+        cache.insert(U256::from(1), proof(1));
+
+        assert_eq!(cache.get(U256::from(1)), Some(proof(1)));
+        assert_eq!(cache.get(U256::from(2)), None);
+    }
+
+    #[test]
+    fn least_recently_used_proof_is_evicted() {
+        let cache = ProofCache::new(2);
+
+        cache.insert(U256::from(1), proof(1));
+        cache.insert(U256::from(2), proof(2));
+
+        // Reading the first proof makes the second one the least recently used
+        cache.get(U256::from(1));
+        cache.insert(U256::from(3), proof(3));
+
+        assert_eq!(cache.get(U256::from(1)), Some(proof(1)));
+        assert_eq!(cache.get(U256::from(2)), None);
+        assert_eq!(cache.get(U256::from(3)), Some(proof(3)));
+    }
+
+    #[test]
+    fn cache_without_capacity_holds_nothing() {
+        let cache = ProofCache::new(0);
+
+        cache.insert(U256::from(1), proof(1));
+
+        assert_eq!(cache.get(U256::from(1)), None);
+    }
+}
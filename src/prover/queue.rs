@@ -0,0 +1,230 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::database::types::DeletionEntry;
+use crate::identity_tree::Hash;
+use crate::utils::batch_type::BatchType;
+
+/// How urgently a queued operation should be proved and submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Normal,
+    /// Jumps ahead of every normal operation, e.g. the deletion of a
+    /// compromised identity
+    High,
+}
+
+impl From<BatchType> for Priority {
+    fn from(batch_type: BatchType) -> Self {
+        match batch_type {
+            BatchType::Insertion => Self::Normal,
+            BatchType::Deletion => Self::High,
+        }
+    }
+}
+
+/// An identity operation waiting to be applied to the latest tree, from where
+/// it is batched and proved.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TreeOperation {
+    Insertion(Hash),
+    Deletion(DeletionEntry),
+}
+
+impl TreeOperation {
+    #[must_use]
+    pub const fn batch_type(&self) -> BatchType {
+        match self {
+            Self::Insertion(_) => BatchType::Insertion,
+            Self::Deletion(_) => BatchType::Deletion,
+        }
+    }
+
+    #[must_use]
+    pub fn priority(&self) -> Priority {
+        self.batch_type().into()
+    }
+}
+
+/// A queue of operations waiting to be proved.
+///
+/// Operations are popped by priority first and in the order they were pushed
+/// second, so operations of the same priority are processed first in, first
+/// out.
+#[derive(Debug)]
+pub struct ProvingQueue<T> {
+    heap:          BinaryHeap<Entry<T>>,
+    next_sequence: u64,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    priority: Priority,
+    /// The submission order, earlier submissions are popped first
+    sequence: u64,
+    item:     T,
+}
+
+impl<T> Entry<T> {
+    fn key(&self) -> (Priority, Reverse<u64>) {
+        (self.priority, Reverse(self.sequence))
+    }
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl<T> Default for ProvingQueue<T> {
+    fn default() -> Self {
+        Self {
+            heap:          BinaryHeap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl<T> ProvingQueue<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues an operation and returns the id it can be cancelled by.
+    pub fn push(&mut self, priority: Priority, item: T) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        self.heap.push(Entry {
+            priority,
+            sequence,
+            item,
+        });
+
+        sequence
+    }
+
+    /// Removes the operation with the given id from the queue, e.g. because it
+    /// contains a commitment that must not be inserted after all. Returns
+    /// `false` if the operation was already popped for proving or never
+    /// queued.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let len = self.heap.len();
+        self.heap.retain(|entry| entry.sequence != id);
+
+        self.heap.len() != len
+    }
+
+    /// Removes the most urgent operation from the queue.
+    pub fn pop(&mut self) -> Option<T> {
+        self.heap.pop().map(|entry| entry.item)
+    }
+
+    /// Removes every queued operation, most urgent first, e.g. to persist them
+    /// on shutdown.
+    pub fn drain(&mut self) -> Vec<T> {
+        std::iter::from_fn(|| self.pop()).collect()
+    }
+
+    /// Returns the queued operations, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter().map(|entry| &entry.item)
+    }
+
+    /// Returns the priority of the operation that would be popped next.
+    #[must_use]
+    pub fn peek_priority(&self) -> Option<Priority> {
+        self.heap.peek().map(|entry| entry.priority)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deletion_jumps_ahead_of_earlier_insertions() {
+        let mut queue = ProvingQueue::new();
+
+        queue.push(BatchType::Insertion.into(), "insertion 1");
+        queue.push(BatchType::Insertion.into(), "insertion 2");
+        queue.push(BatchType::Deletion.into(), "deletion");
+        queue.push(BatchType::Insertion.into(), "insertion 3");
+
+        assert_eq!(queue.peek_priority(), Some(Priority::High));
+        assert_eq!(queue.drain(), vec![
+            "deletion",
+            "insertion 1",
+            "insertion 2",
+            "insertion 3"
+        ]);
+    }
+
+    #[test]
+    fn same_priority_is_first_in_first_out() {
+        let mut queue = ProvingQueue::new();
+
+        for i in 0..10 {
+            let priority = if i % 3 == 0 {
+                Priority::High
+            } else {
+                Priority::Normal
+            };
+            queue.push(priority, i);
+        }
+
+        assert_eq!(queue.len(), 10);
+        assert_eq!(queue.drain(), vec![0, 3, 6, 9, 1, 2, 4, 5, 7, 8]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queued_operation_can_be_cancelled() {
+        let mut queue = ProvingQueue::new();
+
+        queue.push(Priority::Normal, "insertion 1");
+        let id = queue.push(Priority::Normal, "insertion 2");
+        queue.push(Priority::High, "deletion");
+
+        assert!(queue.cancel(id));
+        assert_eq!(queue.drain(), vec!["deletion", "insertion 1"]);
+    }
+
+    #[test]
+    fn popped_operation_cannot_be_cancelled() {
+        let mut queue = ProvingQueue::new();
+
+        let id = queue.push(Priority::Normal, "insertion 1");
+        queue.push(Priority::Normal, "insertion 2");
+
+        assert_eq!(queue.pop(), Some("insertion 1"));
+        assert!(!queue.cancel(id));
+        assert_eq!(queue.len(), 1);
+    }
+}
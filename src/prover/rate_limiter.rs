@@ -0,0 +1,90 @@
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// A token bucket used to limit the rate of requests, e.g. those sent to a
+/// prover.
+///
+/// The bucket holds at most a single token, so bursts of requests are spaced
+/// out evenly instead of being sent all at once.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    bucket:   Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens:      f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter allowing `max_requests_per_second` requests.
+    pub fn new(max_requests_per_second: NonZeroU32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / max_requests_per_second.get(),
+            bucket:   Mutex::new(Bucket {
+                tokens:      1.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a token is available and consumes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().expect("Rate limiter lock poisoned");
+
+                let now = Instant::now();
+                let refilled = now.duration_since(bucket.last_refill).as_secs_f64()
+                    / self.interval.as_secs_f64();
+                bucket.tokens = (bucket.tokens + refilled).min(1.0);
+                bucket.last_refill = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                self.interval.mul_f64(1.0 - bucket.tokens)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_spaced_according_to_rate() {
+        let limiter = RateLimiter::new(NonZeroU32::new(4).unwrap());
+        let start = Instant::now();
+
+        let mut elapsed = vec![];
+        for _ in 0..5 {
+            limiter.acquire().await;
+            elapsed.push(start.elapsed());
+        }
+
+        let expected: Vec<Duration> = (0..5).map(|i| Duration::from_millis(250 * i)).collect();
+
+        for (actual, expected) in elapsed.iter().zip(&expected) {
+            let diff = if actual > expected {
+                *actual - *expected
+            } else {
+                *expected - *actual
+            };
+            assert!(
+                diff < Duration::from_millis(5),
+                "expected {expected:?}, got {actual:?}"
+            );
+        }
+    }
+}
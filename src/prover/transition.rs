@@ -0,0 +1,365 @@
+use anyhow::{anyhow, bail};
+use ethers::types::U256;
+use semaphore::merkle_tree::Hasher;
+use semaphore::poseidon_tree::{Branch, PoseidonHash, Proof as MerkleProof};
+use semaphore::Field;
+
+use crate::config::{Endianness, HashFunction};
+use crate::prover::identity::Identity;
+use crate::prover::proof::VerifyingKey;
+use crate::prover::{compute_insertion_proof_input_hash, Proof};
+use crate::utils::field_util::{field_to_u256, u256_to_field};
+
+/// Checks that an insertion batch is internally consistent, without a round
+/// trip to the prover or the chain.
+///
+/// Starting from `pre_root`, every merkle proof must place an `empty_leaf` at
+/// its index in the current root, and inserting all the commitments must
+/// yield `post_root`. If a `verifying_key` is provided, `proof` must also
+/// verify against the input hash recomputed from the batch.
+pub fn verify_insertion_transition(
+    start_index: u32,
+    pre_root: U256,
+    post_root: U256,
+    identities: &[Identity],
+    empty_leaf: Field,
+    hash_function: HashFunction,
+    endianness: Endianness,
+    input_hash_domain: Option<&[u8; 32]>,
+    proof: &Proof,
+    verifying_key: Option<&VerifyingKey>,
+) -> anyhow::Result<()> {
+    let root = apply_insertions(
+        start_index as usize,
+        u256_to_field(pre_root),
+        identities,
+        empty_leaf,
+    )?;
+
+    let computed_post_root = field_to_u256(root);
+    if computed_post_root != post_root {
+        return Err(anyhow!(
+            "Computed post root {computed_post_root:?} does not match the expected post root \
+             {post_root:?}"
+        ));
+    }
+
+    let Some(verifying_key) = verifying_key else {
+        return Ok(());
+    };
+
+    let commitments: Vec<U256> = identities.iter().map(|id| id.commitment).collect();
+    let input_hash = compute_insertion_proof_input_hash(
+        start_index,
+        pre_root,
+        post_root,
+        &commitments,
+        hash_function,
+        endianness,
+        input_hash_domain,
+    );
+
+    if !proof.verify(verifying_key, &[input_hash]) {
+        bail!("Proof does not verify against the input hash {input_hash:?}");
+    }
+
+    Ok(())
+}
+
+/// Computes the root before and after inserting an insertion batch starting
+/// at `start_index`, using only the merkle proofs of the batch.
+///
+/// The pre root is the root the first merkle proof places an `empty_leaf` in,
+/// so that no tree has to be cloned or mutated to stage a proof.
+pub fn compute_insertion_roots(
+    start_index: usize,
+    identities: &[Identity],
+    empty_leaf: Field,
+) -> anyhow::Result<(U256, U256)> {
+    let Some(first) = identities.first() else {
+        bail!("Cannot compute the roots of an empty batch");
+    };
+
+    let pre_root = to_merkle_proof(start_index, &first.merkle_proof).root(empty_leaf);
+    let post_root = apply_insertions(start_index, pre_root, identities, empty_leaf)?;
+
+    Ok((field_to_u256(pre_root), field_to_u256(post_root)))
+}
+
+/// Rebuilds the insertion batch of `batch_size` that inserts `commitment` at
+/// `leaf_index`, followed by zero padding, as if `leaf_index` were the next
+/// free leaf of the tree.
+///
+/// `siblings` is the merkle proof of the leaf in any later state of the tree.
+/// The siblings to its left are unaffected by later insertions, while the ones
+/// to its right are replaced by the roots of empty subtrees.
+pub fn rebuild_insertion_batch(
+    leaf_index: usize,
+    siblings: &[U256],
+    commitment: U256,
+    batch_size: usize,
+    empty_leaf: Field,
+) -> Vec<Identity> {
+    let mut empty_subtrees = Vec::with_capacity(siblings.len());
+    let mut empty_subtree = empty_leaf;
+    for _ in siblings {
+        empty_subtrees.push(empty_subtree);
+        empty_subtree = PoseidonHash::hash_node(&empty_subtree, &empty_subtree);
+    }
+
+    // The left siblings of the path to the next leaf, only the entries of the
+    // levels where that path goes right are read
+    let mut frontier: Vec<Field> = siblings.iter().map(|s| u256_to_field(*s)).collect();
+
+    std::iter::once(commitment)
+        .chain(std::iter::repeat(U256::zero()))
+        .take(batch_size)
+        .enumerate()
+        .map(|(offset, commitment)| {
+            let leaf_index = leaf_index + offset;
+            let goes_left = |depth: usize| (leaf_index >> depth) & 1 == 0;
+
+            let merkle_proof = (0..frontier.len())
+                .map(|depth| {
+                    if goes_left(depth) {
+                        field_to_u256(empty_subtrees[depth])
+                    } else {
+                        field_to_u256(frontier[depth])
+                    }
+                })
+                .collect();
+
+            let mut node = u256_to_field(commitment);
+            for depth in 0..frontier.len() {
+                node = if goes_left(depth) {
+                    frontier[depth] = node;
+                    PoseidonHash::hash_node(&node, &empty_subtrees[depth])
+                } else {
+                    PoseidonHash::hash_node(&frontier[depth], &node)
+                };
+            }
+
+            Identity::new(commitment, merkle_proof)
+        })
+        .collect()
+}
+
+/// Inserts every commitment of the batch in turn, starting from `root`, and
+/// returns the resulting root. Every merkle proof must place an `empty_leaf`
+/// at its index in the root preceding it.
+fn apply_insertions(
+    start_index: usize,
+    mut root: Field,
+    identities: &[Identity],
+    empty_leaf: Field,
+) -> anyhow::Result<Field> {
+    for (offset, identity) in identities.iter().enumerate() {
+        let leaf_index = start_index + offset;
+        let merkle_proof = to_merkle_proof(leaf_index, &identity.merkle_proof);
+
+        if merkle_proof.root(empty_leaf) != root {
+            bail!("Merkle proof for leaf index {leaf_index} does not match the preceding root");
+        }
+
+        root = merkle_proof.root(u256_to_field(identity.commitment));
+    }
+
+    Ok(root)
+}
+
+/// Rebuilds a merkle proof from the sibling values of the leaf at
+/// `leaf_index`, ordered from the leaf up.
+fn to_merkle_proof(leaf_index: usize, siblings: &[U256]) -> MerkleProof {
+    let branches = siblings
+        .iter()
+        .enumerate()
+        .map(|(depth, sibling)| {
+            let sibling = u256_to_field(*sibling);
+
+            if (leaf_index >> depth) & 1 == 0 {
+                Branch::Left(sibling)
+            } else {
+                Branch::Right(sibling)
+            }
+        })
+        .collect();
+
+    MerkleProof(branches)
+}
+
+#[cfg(test)]
+mod tests {
+    use semaphore::poseidon_tree::LazyPoseidonTree;
+
+    use super::*;
+    use crate::prover::proof::tests::key_and_proof_for;
+    use crate::utils::field_util::merkle_proof_siblings;
+
+    const TREE_DEPTH: usize = 4;
+
+    struct Transition {
+        start_index: u32,
+        pre_root:    U256,
+        post_root:   U256,
+        identities:  Vec<Identity>,
+    }
+
+    /// Inserts `commitments` into a tree that already holds `start_index`
+    /// leaves.
+    fn transition(start_index: u32, commitments: &[u64]) -> Transition {
+        let mut tree = LazyPoseidonTree::new(TREE_DEPTH, Field::ZERO).derived();
+        for leaf_index in 0..start_index as usize {
+            tree = tree.update(leaf_index, &Field::from(100 + leaf_index));
+        }
+
+        let pre_root = field_to_u256(tree.root());
+
+        let mut identities = vec![];
+        for (offset, commitment) in commitments.iter().enumerate() {
+            let leaf_index = start_index as usize + offset;
+            tree = tree.update(leaf_index, &Field::from(*commitment));
+
+            let merkle_proof = merkle_proof_siblings(&tree.proof(leaf_index));
+
+            identities.push(Identity::new(U256::from(*commitment), merkle_proof));
+        }
+
+        Transition {
+            start_index,
+            pre_root,
+            post_root: field_to_u256(tree.root()),
+            identities,
+        }
+    }
+
+    fn input_hash_of(transition: &Transition) -> U256 {
+        let commitments: Vec<U256> = transition
+            .identities
+            .iter()
+            .map(|id| id.commitment)
+            .collect();
+
+        compute_insertion_proof_input_hash(
+            transition.start_index,
+            transition.pre_root,
+            transition.post_root,
+            &commitments,
+            HashFunction::Keccak256,
+            Endianness::Big,
+            None,
+        )
+    }
+
+    fn verify(transition: &Transition, proof: &Proof, vk: Option<&VerifyingKey>) -> bool {
+        verify_insertion_transition(
+            transition.start_index,
+            transition.pre_root,
+            transition.post_root,
+            &transition.identities,
+            Field::ZERO,
+            HashFunction::Keccak256,
+            Endianness::Big,
+            None,
+            proof,
+            vk,
+        )
+        .is_ok()
+    }
+
+    #[test]
+    fn consistent_transition_should_verify() {
+        let transition = transition(3, &[1, 2, 3]);
+        let (vk, proof) = key_and_proof_for(input_hash_of(&transition));
+
+        assert!(verify(&transition, &proof, Some(&vk)));
+    }
+
+    #[test]
+    fn merkle_checks_run_without_verifying_key() {
+        let transition = transition(0, &[1, 2]);
+        let (_, proof) = key_and_proof_for(U256::zero());
+
+        assert!(verify(&transition, &proof, None));
+    }
+
+    #[test]
+    fn wrong_pre_root_should_fail() {
+        let mut transition = transition(3, &[1, 2, 3]);
+        let (vk, proof) = key_and_proof_for(input_hash_of(&transition));
+        transition.pre_root = U256::from(42);
+
+        assert!(!verify(&transition, &proof, Some(&vk)));
+    }
+
+    #[test]
+    fn wrong_post_root_should_fail() {
+        let mut transition = transition(3, &[1, 2, 3]);
+        transition.post_root = U256::from(42);
+        let (vk, proof) = key_and_proof_for(input_hash_of(&transition));
+
+        assert!(!verify(&transition, &proof, Some(&vk)));
+    }
+
+    #[test]
+    fn wrong_start_index_should_fail() {
+        let mut transition = transition(3, &[1, 2, 3]);
+        transition.start_index = 4;
+        let (vk, proof) = key_and_proof_for(input_hash_of(&transition));
+
+        assert!(!verify(&transition, &proof, Some(&vk)));
+    }
+
+    #[test]
+    fn tampered_commitment_should_fail() {
+        let mut transition = transition(3, &[1, 2, 3]);
+        transition.identities[1].commitment = U256::from(7);
+        let (vk, proof) = key_and_proof_for(input_hash_of(&transition));
+
+        assert!(!verify(&transition, &proof, Some(&vk)));
+    }
+
+    #[test]
+    fn insertion_roots_match_the_tree() -> anyhow::Result<()> {
+        let transition = transition(3, &[1, 2, 3]);
+
+        let roots = compute_insertion_roots(3, &transition.identities, Field::ZERO)?;
+
+        assert_eq!(roots, (transition.pre_root, transition.post_root));
+
+        Ok(())
+    }
+
+    #[test]
+    fn insertion_roots_of_inconsistent_batch_fail() {
+        let mut transition = transition(0, &[1, 2, 3]);
+        transition.identities[1].merkle_proof[0] = U256::from(42);
+
+        assert!(compute_insertion_roots(0, &transition.identities, Field::ZERO).is_err());
+    }
+
+    #[test]
+    fn insertion_roots_of_empty_batch_fail() {
+        assert!(compute_insertion_roots(0, &[], Field::ZERO).is_err());
+    }
+
+    #[test]
+    fn rebuilt_batch_ignores_later_insertions() {
+        let mut tree = LazyPoseidonTree::new(TREE_DEPTH, Field::ZERO).derived();
+        for leaf_index in 0..10 {
+            tree = tree.update(leaf_index, &Field::from(100 + leaf_index));
+        }
+        let siblings: Vec<U256> = merkle_proof_siblings(&tree.proof(3));
+
+        let rebuilt = rebuild_insertion_batch(3, &siblings, U256::from(103), 4, Field::ZERO);
+
+        assert_eq!(rebuilt, transition(3, &[103, 0, 0, 0]).identities);
+    }
+
+    #[test]
+    fn proof_for_other_inputs_should_fail() {
+        let transition = transition(3, &[1, 2, 3]);
+        let (vk, proof) = key_and_proof_for(U256::from(5));
+
+        assert!(!verify(&transition, &proof, Some(&vk)));
+    }
+}
@@ -14,6 +14,7 @@ use tracing::info;
 
 use crate::app::App;
 use crate::config::ServerConfig;
+use crate::stats::SequencerStats;
 
 mod custom_middleware;
 pub mod data;
@@ -21,8 +22,9 @@ pub mod data;
 use self::data::{
     AddBatchSizeRequest, DeletionRequest, IdentityHistoryRequest, IdentityHistoryResponse,
     InclusionProofRequest, InclusionProofResponse, InsertCommitmentRequest, ListBatchSizesResponse,
-    RecoveryRequest, RemoveBatchSizeRequest, ToResponseCode, VerifySemaphoreProofQuery,
-    VerifySemaphoreProofRequest, VerifySemaphoreProofResponse,
+    QueueStateResponse, RecoveryRequest, RemoveBatchSizeRequest, SetProverTimeoutRequest,
+    ToResponseCode, VerifySemaphoreProofQuery, VerifySemaphoreProofRequest,
+    VerifySemaphoreProofResponse,
 };
 
 async fn inclusion_proof(
@@ -120,6 +122,16 @@ async fn remove_batch_size(
     Ok(())
 }
 
+async fn set_prover_timeout(
+    State(app): State<Arc<App>>,
+    Json(req): Json<SetProverTimeoutRequest>,
+) -> Result<(), Error> {
+    app.set_prover_timeout(req.batch_size, req.prover_type, req.timeout_seconds)
+        .await?;
+
+    Ok(())
+}
+
 async fn list_batch_sizes(
     State(app): State<Arc<App>>,
 ) -> Result<(StatusCode, Json<ListBatchSizesResponse>), Error> {
@@ -128,6 +140,26 @@ async fn list_batch_sizes(
     Ok((result.to_response_code(), Json(result)))
 }
 
+async fn pause(State(app): State<Arc<App>>) {
+    app.pause();
+}
+
+async fn resume(State(app): State<Arc<App>>) {
+    app.resume();
+}
+
+async fn queue_state(State(app): State<Arc<App>>) -> Result<Json<QueueStateResponse>, Error> {
+    let result = app.queue_state().await?;
+
+    Ok(Json(result))
+}
+
+async fn sequencer_stats(State(app): State<Arc<App>>) -> Result<Json<SequencerStats>, Error> {
+    let result = app.sequencer_stats().await?;
+
+    Ok(Json(result))
+}
+
 /// # Errors
 ///
 /// Will return `Err` if `options.server` URI is not http, incorrectly includes
@@ -162,6 +194,13 @@ pub async fn bind_from_listener(
         .route("/addBatchSize", post(add_batch_size))
         .route("/removeBatchSize", post(remove_batch_size))
         .route("/listBatchSizes", get(list_batch_sizes))
+        .route("/setProverTimeout", post(set_prover_timeout))
+        .route("/queue", get(queue_state))
+        .route("/stats", get(sequencer_stats))
+        // Pause and resume the pipeline. These are not authenticated and must
+        // be kept from public access at the ingress
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
         .layer(middleware::from_fn(
             custom_middleware::api_metrics_layer::middleware,
         ))
@@ -3,8 +3,9 @@ use semaphore::protocol::Proof;
 use semaphore::Field;
 use serde::{Deserialize, Serialize};
 
+use crate::database::types::QueueState;
 use crate::identity_tree::{
-    Hash, InclusionProof, ProcessedStatus, RootItem, Status, UnprocessedStatus,
+    DeletionStatus, Hash, InclusionProof, ProcessedStatus, RootItem, Status, UnprocessedStatus,
 };
 use crate::prover::{ProverConfig, ProverType};
 
@@ -20,6 +21,10 @@ pub struct ListBatchSizesResponse(pub Vec<ProverConfig>);
 #[serde(transparent)]
 pub struct VerifySemaphoreProofResponse(pub RootItem);
 
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct QueueStateResponse(pub QueueState);
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
@@ -59,6 +64,8 @@ pub enum IdentityHistoryEntryStatus {
     Mined,
     // Present in the batching tree (mined on chain)
     Bridged,
+    // Still present in the tree, but queued for deletion
+    PendingDeletion,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -92,6 +99,18 @@ pub struct RemoveBatchSizeRequest {
     pub prover_type: ProverType,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct SetProverTimeoutRequest {
+    /// The batch size of the prover to update. Batch sizes can't be changed.
+    pub batch_size:      usize,
+    /// The new timeout for connecting to the prover service. Must not be zero.
+    pub timeout_seconds: u64,
+    /// The type of the prover to update.
+    pub prover_type:     ProverType,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
@@ -167,9 +186,8 @@ impl ToResponseCode for InclusionProofResponse {
         match self.0.status {
             Status::Unprocessed(UnprocessedStatus::New)
             | Status::Processed(ProcessedStatus::Pending) => StatusCode::ACCEPTED,
-            Status::Processed(ProcessedStatus::Mined | ProcessedStatus::Processed) => {
-                StatusCode::OK
-            }
+            Status::Processed(ProcessedStatus::Mined | ProcessedStatus::Processed)
+            | Status::Deletion(DeletionStatus::PendingDeletion) => StatusCode::OK,
         }
     }
 }
@@ -186,6 +204,12 @@ impl ToResponseCode for ListBatchSizesResponse {
     }
 }
 
+impl From<QueueState> for QueueStateResponse {
+    fn from(value: QueueState) -> Self {
+        Self(value)
+    }
+}
+
 impl VerifySemaphoreProofResponse {
     #[must_use]
     pub fn hide_processed_status(mut self) -> Self {
@@ -240,6 +264,7 @@ mod tests {
             IdentityHistoryEntryStatus::Batched,
             IdentityHistoryEntryStatus::Mined,
             IdentityHistoryEntryStatus::Bridged,
+            IdentityHistoryEntryStatus::PendingDeletion,
         ];
 
         let mut statuses = expected.clone();
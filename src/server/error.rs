@@ -59,6 +59,8 @@ pub enum Error {
     NoSuchBatchSize,
     #[error("The last batch size cannot be removed")]
     CannotRemoveLastBatchSize,
+    #[error("The prover timeout must be at least a second")]
+    InvalidTimeout,
     #[error("Identity Manager had no provers on point of identity insertion.")]
     NoProversOnIdInsert,
     #[error("Identity Manager had no provers on point of identity deletion.")]
@@ -84,6 +86,7 @@ impl Error {
             | IdentityCommitmentNotFound
             | InvalidCommitment
             | DuplicateCommitment
+            | InvalidTimeout
             | InvalidSerialization(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -103,6 +106,7 @@ impl Error {
             Self::IndexOutOfBounds
             | Self::IdentityCommitmentNotFound
             | Self::InvalidCommitment
+            | Self::InvalidTimeout
             | Self::InvalidSerialization(_) => StatusCode::BAD_REQUEST,
             Self::IdentityAlreadyDeleted
             | Self::IdentityQueuedForDeletion
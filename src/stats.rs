@@ -0,0 +1,111 @@
+use ethers::types::U256;
+use futures::TryFutureExt;
+use serde::Serialize;
+
+use crate::contracts::IdentityManagerTrait;
+use crate::database::Database;
+use crate::identity_tree::{
+    Hash, Latest, ProcessedStatus, TreeOccupancy, TreeVersion, TreeVersionReadOps,
+};
+use crate::prover::ProverHealth;
+
+/// A snapshot of the sequencer's state, meant for debugging and monitoring.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequencerStats {
+    /// The number of identities that are not yet mined.
+    pub pending_identities: i32,
+    /// The latest root of the identity manager contract.
+    pub latest_root:        U256,
+    /// The latest root that is mined on every chain.
+    pub finalized_root:     Option<Hash>,
+    pub tree_occupancy:     TreeOccupancy,
+    pub provers:            Vec<ProverHealth>,
+}
+
+/// Collects a [`SequencerStats`] snapshot. The database and the chain are
+/// queried concurrently, with a single call to the chain.
+pub(crate) async fn collect_sequencer_stats(
+    database: &Database,
+    identity_manager: &dyn IdentityManagerTrait,
+    latest_tree: &TreeVersion<Latest>,
+    tree_depth: usize,
+    provers: Vec<ProverHealth>,
+) -> anyhow::Result<SequencerStats> {
+    let (pending_identities, finalized_root, latest_root) = tokio::try_join!(
+        database
+            .count_pending_identities()
+            .err_into::<anyhow::Error>(),
+        database
+            .get_latest_root_by_status(ProcessedStatus::Mined)
+            .err_into::<anyhow::Error>(),
+        identity_manager.latest_root(),
+    )?;
+
+    Ok(SequencerStats {
+        pending_identities,
+        latest_root,
+        finalized_root,
+        tree_occupancy: TreeOccupancy::new(latest_tree.next_leaf(), tree_depth),
+        provers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prover::ProverType;
+    use crate::test_utils::{empty_trees, setup_db, MockIdentityManager};
+
+    const TREE_DEPTH: usize = 10;
+
+    #[tokio::test]
+    async fn snapshot_aggregates_database_chain_and_tree_state() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (_, latest_tree) = empty_trees(TREE_DEPTH, &temp_dir);
+
+        let identities = [Hash::from(1), Hash::from(2), Hash::from(3)];
+        let updates = latest_tree.append_many(&identities);
+
+        for (identity, (root, _, leaf_index)) in identities.iter().zip(&updates) {
+            database
+                .insert_pending_identity(*leaf_index, identity, root)
+                .await?;
+        }
+
+        let finalized_root = updates[0].0;
+        database.mark_root_as_mined(&finalized_root).await?;
+
+        let identity_manager = MockIdentityManager {
+            latest_root: updates[1].0.into(),
+            ..Default::default()
+        };
+        let provers = vec![ProverHealth {
+            url:         "http://localhost:3001/".to_string(),
+            batch_size:  3,
+            prover_type: ProverType::Insertion,
+            available:   true,
+        }];
+
+        let stats = collect_sequencer_stats(
+            &database,
+            &identity_manager,
+            &latest_tree,
+            TREE_DEPTH,
+            provers.clone(),
+        )
+        .await?;
+
+        assert_eq!(stats, SequencerStats {
+            pending_identities: 2,
+            latest_root: updates[1].0.into(),
+            finalized_root: Some(finalized_root),
+            tree_occupancy: TreeOccupancy::new(3, TREE_DEPTH),
+            provers,
+        });
+
+        Ok(())
+    }
+}
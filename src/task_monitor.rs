@@ -9,6 +9,8 @@ use tracing::{info, instrument, warn};
 
 use crate::app::App;
 use crate::database::Database;
+use crate::identity_tree::TreeOccupancy;
+use crate::task_monitor::tasks::finalize_identities::FinalizationLag;
 
 pub mod tasks;
 
@@ -17,6 +19,7 @@ const PROCESS_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
 const FINALIZE_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
 const INSERT_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
 const DELETE_IDENTITIES_BACKOFF: Duration = Duration::from_secs(5);
+const CHECK_PROVERS_BACKOFF: Duration = Duration::from_secs(5);
 
 struct RunningInstance {
     handles:         Vec<JoinHandle<()>>,
@@ -35,6 +38,14 @@ static UNPROCESSED_IDENTITIES: Lazy<Gauge> = Lazy::new(|| {
     .unwrap()
 });
 
+static TREE_OCCUPANCY: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "tree_occupancy",
+        "Fraction of the tree's leaves that have been filled"
+    )
+    .unwrap()
+});
+
 static BATCH_SIZES: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
         "submitted_batch_sizes",
@@ -107,6 +118,8 @@ impl TaskMonitor {
         // in the database
         base_wake_up_notify.notify_one();
 
+        let finalization_lag = Arc::new(FinalizationLag::default());
+
         let mut handles = Vec::new();
 
         // Initialize the Tree
@@ -122,7 +135,9 @@ impl TaskMonitor {
 
         // Finalize identities
         let app = self.app.clone();
-        let finalize_identities = move || tasks::finalize_identities::finalize_roots(app.clone());
+        let lag = finalization_lag.clone();
+        let finalize_identities =
+            move || tasks::finalize_identities::finalize_roots(app.clone(), lag.clone());
         let finalize_identities_handle = crate::utils::spawn_monitored_with_backoff(
             finalize_identities,
             shutdown_sender.clone(),
@@ -138,6 +153,7 @@ impl TaskMonitor {
                 app.clone(),
                 monitored_txs_sender.clone(),
                 wake_up_notify.clone(),
+                finalization_lag.clone(),
             )
         };
         let process_identities_handle = crate::utils::spawn_monitored_with_backoff(
@@ -184,6 +200,19 @@ impl TaskMonitor {
         );
         handles.push(delete_identities_handle);
 
+        // Check prover batch sizes
+        if let Some(interval) = self.app.config.app.prover_check_interval {
+            let app = self.app.clone();
+            let check_provers =
+                move || self::tasks::check_provers::check_provers(app.clone(), interval);
+            let check_provers_handle = crate::utils::spawn_monitored_with_backoff(
+                check_provers,
+                shutdown_sender.clone(),
+                CHECK_PROVERS_BACKOFF,
+            );
+            handles.push(check_provers_handle);
+        }
+
         // Create the instance
         *instance = Some(RunningInstance {
             handles,
@@ -214,6 +243,10 @@ impl TaskMonitor {
         BATCH_SIZES.observe(size as f64);
     }
 
+    pub(crate) fn log_tree_occupancy(occupancy: &TreeOccupancy) {
+        TREE_OCCUPANCY.set(occupancy.fraction);
+    }
+
     /// # Errors
     ///
     /// Will return an Error if the committer thread cannot be shut down
@@ -222,6 +255,8 @@ impl TaskMonitor {
         let mut instance = self.instance.write().await;
         if let Some(instance) = instance.take() {
             instance.shutdown().await?;
+            // With the tasks stopped nothing is taken off the queue anymore
+            self.app.drain_to_database().await?;
         } else {
             info!("Committer not running.");
         }
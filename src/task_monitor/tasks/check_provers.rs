@@ -0,0 +1,26 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, IntCounter};
+
+use crate::app::App;
+
+static PROVER_BATCH_SIZE_DRIFTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "prover_batch_size_drifts",
+        "The number of prover checks that found a batch size different from the configured one."
+    )
+    .unwrap()
+});
+
+pub async fn check_provers(app: Arc<App>, interval: Duration) -> anyhow::Result<()> {
+    let mut timer = tokio::time::interval(interval);
+
+    loop {
+        timer.tick().await;
+
+        let drifted = app.identity_manager.check_prover_batch_sizes().await;
+        PROVER_BATCH_SIZE_DRIFTS.inc_by(drifted as u64);
+    }
+}
@@ -4,12 +4,17 @@ use std::sync::Arc;
 use anyhow::Context;
 use chrono::Utc;
 use tokio::sync::Notify;
-use tracing::info;
+use tracing::{info, instrument};
 
 use crate::app::App;
 use crate::database::types::DeletionEntry;
-use crate::identity_tree::Hash;
+use crate::database::Database;
+use crate::identity_tree::{Hash, Latest, TreeVersion};
+use crate::prover::queue::TreeOperation;
 
+/// Queues the requested deletions once enough of them are waiting or the batch
+/// deletion timeout has elapsed. They're applied to the tree by the identity
+/// processor, ahead of any queued insertion.
 pub async fn delete_identities(app: Arc<App>, wake_up_notify: Arc<Notify>) -> anyhow::Result<()> {
     info!("Starting deletion processor.");
 
@@ -17,48 +22,82 @@ pub async fn delete_identities(app: Arc<App>, wake_up_notify: Arc<Notify>) -> an
         .context("Invalid batch deletion timeout duration")?;
 
     loop {
-        let deletions = app.database.get_deletions().await?;
-        if deletions.is_empty() {
+        let queued = {
+            // The queue is locked while reading the database, so that a deletion
+            // being applied to the tree isn't queued again
+            let mut queue = app.proving_queue.lock().await;
+
+            let already_queued: HashSet<&TreeOperation> = queue.iter().collect();
+            let operations: HashSet<TreeOperation> = app
+                .database
+                .get_deletions()
+                .await?
+                .into_iter()
+                .map(TreeOperation::Deletion)
+                .filter(|operation| !already_queued.contains(operation))
+                .collect();
+
+            let ready = !operations.is_empty()
+                && (operations.len() >= app.config.app.min_batch_deletion_size
+                    || Utc::now() - app.database.get_latest_deletion().await?.timestamp
+                        > batch_deletion_timeout);
+
+            // If the minimum deletions batch size is reached or the deletion time
+            // interval has elapsed, queue a batch of deletions
+            if ready {
+                for operation in &operations {
+                    queue.push(operation.priority(), operation.clone());
+                }
+            }
+
+            ready
+        };
+
+        if !queued {
             tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
             continue;
         }
 
-        let last_deletion_timestamp = app.database.get_latest_deletion().await?.timestamp;
-
-        // If the minimum deletions batch size is reached or the deletion time interval
-        // has elapsed, run a batch of deletions
-        if deletions.len() >= app.config.app.min_batch_deletion_size
-            || Utc::now() - last_deletion_timestamp > batch_deletion_timeout
-        {
-            // Dedup deletion entries
-            let deletions = deletions.into_iter().collect::<HashSet<DeletionEntry>>();
-
-            let (leaf_indices, previous_commitments): (Vec<usize>, Vec<Hash>) = deletions
-                .iter()
-                .map(|d| (d.leaf_index, d.commitment))
-                .unzip();
-
-            // Delete the commitments at the target leaf indices in the latest tree,
-            // generating the proof for each update
-            let data = app.tree_state()?.latest_tree().delete_many(&leaf_indices);
-
-            assert_eq!(
-                data.len(),
-                leaf_indices.len(),
-                "Length mismatch when appending identities to tree"
-            );
-
-            // Insert the new items into pending identities
-            let items = data.into_iter().zip(leaf_indices);
-            for ((root, _proof), leaf_index) in items {
-                app.database
-                    .insert_pending_identity(leaf_index, &Hash::ZERO, &root)
-                    .await?;
-            }
+        wake_up_notify.notify_one();
+    }
+}
 
-            // Remove the previous commitments from the deletions table
-            app.database.remove_deletions(previous_commitments).await?;
-            wake_up_notify.notify_one();
-        }
+/// Deletes the identities from the latest tree, records the deletions as
+/// pending identities and removes them from the deletions table.
+#[instrument(level = "info", skip_all)]
+pub async fn delete_identities_batch(
+    database: &Database,
+    latest_tree: &TreeVersion<Latest>,
+    deletions: Vec<DeletionEntry>,
+) -> anyhow::Result<()> {
+    // Dedup deletion entries
+    let deletions = deletions.into_iter().collect::<HashSet<DeletionEntry>>();
+
+    let (leaf_indices, previous_commitments): (Vec<usize>, Vec<Hash>) = deletions
+        .iter()
+        .map(|d| (d.leaf_index, d.commitment))
+        .unzip();
+
+    // Delete the commitments at the target leaf indices in the latest tree,
+    // generating the proof for each update
+    let data = latest_tree.delete_many(&leaf_indices);
+
+    assert_eq!(
+        data.len(),
+        leaf_indices.len(),
+        "Length mismatch when appending identities to tree"
+    );
+
+    // Insert the new items into pending identities
+    let items = data.into_iter().zip(leaf_indices);
+    for ((root, _proof), leaf_index) in items {
+        database
+            .insert_pending_identity(leaf_index, &Hash::ZERO, &root)
+            .await?;
     }
+
+    // Remove the previous commitments from the deletions table
+    database.remove_deletions(previous_commitments).await?;
+
+    Ok(())
 }
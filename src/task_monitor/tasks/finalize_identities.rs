@@ -1,24 +1,155 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
+use async_trait::async_trait;
 use chrono::Utc;
 use ethers::abi::RawLog;
 use ethers::contract::EthEvent;
 use ethers::providers::Middleware;
-use ethers::types::{Address, Log, Topic, ValueOrArray, U256};
-use tracing::{info, instrument};
+use ethers::types::{Address, Log, Topic, ValueOrArray, H256, U256};
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use rand::Rng;
+use tokio::time::Instant;
+use tracing::{debug, error, info, instrument};
 
 use crate::app::App;
 use crate::contracts::abi::{BridgedWorldId, RootAddedFilter, TreeChangeKind, TreeChangedFilter};
 use crate::contracts::scanner::BlockScanner;
-use crate::contracts::IdentityManager;
+use crate::contracts::{CleanSlateReport, IdentityManagerTrait};
 use crate::database::Database;
-use crate::identity_tree::{Canonical, Intermediate, TreeVersion, TreeWithNextVersion};
+use crate::identity_tree::{
+    Canonical, Intermediate, ProcessedStatus, TreeVersion, TreeWithNextVersion,
+};
+use crate::prover::rate_limiter::RateLimiter;
 use crate::task_monitor::TaskMonitor;
+use crate::utils::webhook::{FinalizationNotification, FinalizationWebhook};
+
+static FINALIZATION_ALERTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "finalization_alerts",
+        "The number of roots that took longer than the alert threshold to be finalized."
+    )
+    .unwrap()
+});
+
+static UNFINALIZED_ROOTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "unfinalized_roots",
+        "The number of roots waiting to be finalized on every chain."
+    )
+    .unwrap()
+});
+
+/// How far finalization lags behind, shared between the finalization task
+/// and the task submitting batches so that the latter can hold off while
+/// roots pile up.
+#[derive(Debug, Default)]
+pub struct FinalizationLag {
+    unfinalized_roots: AtomicUsize,
+}
+
+impl FinalizationLag {
+    /// The number of roots that were waiting to be finalized on every chain
+    /// at the end of the last finalization pass.
+    pub fn unfinalized_roots(&self) -> usize {
+        self.unfinalized_roots.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether more than `max_unfinalized_roots` roots are waiting to
+    /// be finalized. Never true if no limit is set.
+    pub fn exceeds(&self, max_unfinalized_roots: Option<usize>) -> bool {
+        max_unfinalized_roots.map_or(false, |max| self.unfinalized_roots() > max)
+    }
+
+    fn record(&self, waiting_roots: &[U256]) {
+        self.unfinalized_roots
+            .store(waiting_roots.len(), Ordering::Relaxed);
+        UNFINALIZED_ROOTS.set(waiting_roots.len().try_into().unwrap_or(i64::MAX));
+    }
+}
+
+/// An identity manager whose RPC calls share a single rate limit, so that
+/// polling many roots at once doesn't flood the provider.
+struct RateLimitedIdentityManager<'a> {
+    inner:   &'a dyn IdentityManagerTrait,
+    limiter: Option<RateLimiter>,
+}
+
+impl<'a> RateLimitedIdentityManager<'a> {
+    /// Limits the calls to `inner` to `max_requests_per_second`, or doesn't
+    /// limit them at all if not set.
+    fn new(
+        inner: &'a dyn IdentityManagerTrait,
+        max_requests_per_second: Option<NonZeroU32>,
+    ) -> Self {
+        Self {
+            inner,
+            limiter: max_requests_per_second.map(RateLimiter::new),
+        }
+    }
+
+    async fn acquire(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityManagerTrait for RateLimitedIdentityManager<'_> {
+    async fn latest_root(&self) -> anyhow::Result<U256> {
+        self.acquire().await;
+        self.inner.latest_root().await
+    }
+
+    async fn latest_block_number(&self) -> anyhow::Result<u64> {
+        self.acquire().await;
+        self.inner.latest_block_number().await
+    }
+
+    async fn root_history_expiry(&self) -> anyhow::Result<U256> {
+        self.acquire().await;
+        self.inner.root_history_expiry().await
+    }
+
+    async fn is_root_mined(&self, root: U256) -> anyhow::Result<bool> {
+        self.acquire().await;
+        self.inner.is_root_mined(root).await
+    }
+
+    async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>> {
+        self.acquire().await;
+        self.inner.are_roots_mined(roots).await
+    }
+
+    async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
+        self.acquire().await;
+        self.inner.is_root_mined_multi_chain(root).await
+    }
+
+    async fn chains_with_root(&self, root: U256) -> anyhow::Result<Vec<u64>> {
+        self.acquire().await;
+        self.inner.chains_with_root(root).await
+    }
+
+    async fn fetch_deletion_indices_from_tx(&self, tx_hash: H256) -> anyhow::Result<Vec<usize>> {
+        self.acquire().await;
+        self.inner.fetch_deletion_indices_from_tx(tx_hash).await
+    }
+
+    async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport> {
+        self.inner.await_clean_slate().await
+    }
+}
+
+pub async fn finalize_roots(app: Arc<App>, lag: Arc<FinalizationLag>) -> anyhow::Result<()> {
+    wait_for_startup_jitter(app.config.app.finalization_startup_jitter).await;
 
-pub async fn finalize_roots(app: Arc<App>) -> anyhow::Result<()> {
     let mainnet_abi = app.identity_manager.abi();
     let secondary_abis = app.identity_manager.secondary_abis();
 
@@ -34,33 +165,122 @@ pub async fn finalize_roots(app: Arc<App>) -> anyhow::Result<()> {
 
     let mainnet_address = mainnet_abi.address();
 
+    let identity_manager = RateLimitedIdentityManager::new(
+        app.identity_manager.as_ref(),
+        app.config.app.finalization_rpc_rate_limit,
+    );
+
+    // The root to resume finalization from is only enqueued once, ahead of
+    // any roots fetched from the logs
+    let mut resumed_root = app.config.app.resume_finalization_from_root;
+
+    // Roots that are not yet finalized on every chain are retried on the next
+    // iteration
+    let mut waiting_roots: Vec<U256> = vec![];
+    // The mainnet blocks the waiting roots were mined in, reported to the
+    // webhook once they are finalized
+    let mut root_blocks: HashMap<U256, u64> = HashMap::new();
+    let mut alerts = FinalizationAlerts::new(app.config.app.finalization_alert_after);
+
+    let webhook = app
+        .config
+        .app
+        .finalization_webhook_url
+        .clone()
+        .map(|url| FinalizationWebhook::new(url, app.config.app.finalization_webhook_retries))
+        .transpose()?;
+
     loop {
+        // The scanners aren't advanced while paused, so no logs are skipped
+        if app.is_paused() {
+            tokio::time::sleep(app.config.app.time_between_scans).await;
+            continue;
+        }
+
         let mainnet_logs = fetch_mainnet_logs(&mut mainnet_scanner, mainnet_address).await?;
 
         finalize_mainnet_roots(
             &app.database,
-            &app.identity_manager,
+            &identity_manager,
             app.tree_state()?.processed_tree(),
             &mainnet_logs,
             app.config.app.max_epoch_duration,
+            app.config.app.min_confirmations,
+            app.config.app.time_between_scans,
         )
         .await?;
 
-        let mut roots = extract_roots_from_mainnet_logs(mainnet_logs);
-        roots.extend(fetch_secondary_logs(&mut secondary_scanners).await?);
+        let mut new_roots: Vec<U256> = resumed_root.take().into_iter().collect();
+        if let Some(root) = new_roots.first() {
+            info!(?root, "Resuming finalization");
+        }
 
-        finalize_secondary_roots(
+        root_blocks.extend(extract_root_blocks_from_mainnet_logs(&mainnet_logs));
+        new_roots.extend(extract_roots_from_mainnet_logs(mainnet_logs));
+        new_roots.extend(
+            fetch_secondary_logs(
+                &mut secondary_scanners,
+                app.config.app.max_bridge_clock_skew,
+            )
+            .await?,
+        );
+
+        let mut seen: HashSet<U256> = waiting_roots.iter().copied().collect();
+        new_roots.retain(|root| seen.insert(*root));
+
+        let roots: Vec<U256> = waiting_roots.iter().chain(&new_roots).copied().collect();
+
+        waiting_roots = finalize_secondary_roots(
             &app.database,
-            &app.identity_manager,
+            &identity_manager,
             app.tree_state()?.mined_tree(),
-            roots,
+            webhook.as_ref(),
+            &root_blocks,
+            std::mem::take(&mut waiting_roots),
+            new_roots,
         )
         .await?;
 
+        root_blocks.retain(|root, _| waiting_roots.contains(root));
+        lag.record(&waiting_roots);
+
+        // Close the trace and record the latency of every batch whose root has
+        // just been finalized
+        for root in roots.iter().filter(|root| !waiting_roots.contains(root)) {
+            if let Some(batch) = app.batch_spans.finish(root) {
+                batch.finalize(root);
+            }
+        }
+
+        for (root, waiting_for) in alerts.track(&waiting_roots) {
+            error!(
+                ?root,
+                ?waiting_for,
+                "Root has not been finalized on every chain in time"
+            );
+            FINALIZATION_ALERTS.inc();
+        }
+
         tokio::time::sleep(app.config.app.time_between_scans).await;
     }
 }
 
+/// Waits a random time of up to `max_jitter`, so that instances started at
+/// once don't start scanning in lockstep.
+async fn wait_for_startup_jitter(max_jitter: Duration) {
+    let delay = startup_delay(max_jitter, &mut rand::thread_rng());
+    if delay.is_zero() {
+        return;
+    }
+
+    info!(?delay, "Delaying the first finalization scan");
+    tokio::time::sleep(delay).await;
+}
+
+fn startup_delay(max_jitter: Duration, rng: &mut impl Rng) -> Duration {
+    max_jitter.mul_f64(rng.gen::<f64>())
+}
+
 async fn fetch_mainnet_logs<M>(
     mainnet_scanner: &mut BlockScanner<M>,
     mainnet_address: Address,
@@ -87,6 +307,7 @@ where
 
 async fn fetch_secondary_logs<M>(
     secondary_scanners: &mut HashMap<Address, BlockScanner<M>>,
+    max_clock_skew: Duration,
 ) -> anyhow::Result<Vec<U256>>
 where
     M: Middleware,
@@ -111,30 +332,53 @@ where
 
     let roots = extract_roots_from_secondary_logs(&secondary_logs);
 
-    Ok(roots)
+    Ok(order_bridged_roots(roots, max_clock_skew))
 }
 
+/// Marks the roots of the batches in `logs` as processed and applies them to
+/// the processed tree.
+///
+/// Once the last log is confirmed, every earlier one is too, so the mined
+/// status of all the roots is double checked at once.
 #[instrument(level = "info", skip_all)]
 async fn finalize_mainnet_roots(
     database: &Database,
-    identity_manager: &IdentityManager,
+    identity_manager: &dyn IdentityManagerTrait,
     processed_tree: &TreeVersion<Intermediate>,
     logs: &[Log],
     max_epoch_duration: Duration,
+    min_confirmations: u64,
+    confirmation_poll_interval: Duration,
 ) -> Result<(), anyhow::Error> {
-    for log in logs {
-        let Some(event) = raw_log_to_tree_changed(log) else {
-            continue;
-        };
+    let batches: Vec<(&Log, TreeChangedFilter)> = logs
+        .iter()
+        .filter_map(|log| Some((log, raw_log_to_tree_changed(log)?)))
+        .collect();
 
+    let Some((last_log, _)) = batches.last() else {
+        return Ok(());
+    };
+
+    wait_for_confirmations(
+        identity_manager,
+        last_log,
+        min_confirmations,
+        confirmation_poll_interval,
+    )
+    .await?;
+
+    // Double check
+    let post_roots: Vec<U256> = batches.iter().map(|(_, event)| event.post_root).collect();
+    let mined = identity_manager.are_roots_mined(&post_roots).await?;
+
+    for ((log, event), mined) in batches.into_iter().zip(mined) {
         let pre_root = event.pre_root;
         let post_root = event.post_root;
         let kind = TreeChangeKind::from(event.kind);
 
         info!(?pre_root, ?post_root, ?kind, "Mining batch");
 
-        // Double check
-        if !identity_manager.is_root_mined(post_root).await? {
+        if !mined {
             continue;
         }
 
@@ -166,28 +410,205 @@ async fn finalize_mainnet_roots(
     Ok(())
 }
 
+/// Waits until the block containing `log` is buried under at least
+/// `min_confirmations` blocks
+async fn wait_for_confirmations(
+    identity_manager: &dyn IdentityManagerTrait,
+    log: &Log,
+    min_confirmations: u64,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    if min_confirmations == 0 {
+        return Ok(());
+    }
+
+    let block_number = log.block_number.context("Missing block number")?.as_u64();
+
+    loop {
+        let head = identity_manager.latest_block_number().await?;
+
+        if has_enough_confirmations(block_number, head, min_confirmations) {
+            return Ok(());
+        }
+
+        info!(
+            block_number,
+            head, min_confirmations, "Waiting for root confirmations"
+        );
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+fn has_enough_confirmations(block_number: u64, head: u64, min_confirmations: u64) -> bool {
+    if min_confirmations == 0 {
+        return true;
+    }
+
+    // The block containing the root counts as the first confirmation
+    head.checked_sub(block_number)
+        .map_or(false, |depth| depth + 1 >= min_confirmations)
+}
+
+/// Finalizes the roots that are mined on every chain. Returns the roots that
+/// are still waiting to be mined somewhere.
+///
+/// Every root in `new_roots` is polled, but of the `waiting_roots` left over
+/// from earlier passes only the newest one is. Bridges only relay the latest
+/// root, so the older ones are finalized along with a later root rather than
+/// on their own, and polling them on every pass would only load the providers
+/// more the longer the backlog grows.
+///
+/// If a `webhook` is given, it is notified of every root finalized, along with
+/// the mainnet block it was mined in if found in `root_blocks`.
 #[instrument(level = "info", skip_all)]
 async fn finalize_secondary_roots(
     database: &Database,
-    identity_manager: &IdentityManager,
+    identity_manager: &dyn IdentityManagerTrait,
     finalized_tree: &TreeVersion<Canonical>,
-    roots: Vec<U256>,
-) -> Result<(), anyhow::Error> {
-    for root in roots {
+    webhook: Option<&FinalizationWebhook>,
+    root_blocks: &HashMap<U256, u64>,
+    waiting_roots: Vec<U256>,
+    new_roots: Vec<U256>,
+) -> Result<Vec<U256>, anyhow::Error> {
+    let waiting_hashes: Vec<Hash> = waiting_roots.iter().map(|root| (*root).into()).collect();
+    let newest_waiting_root: Option<U256> = database
+        .get_newest_root(&waiting_hashes)
+        .await?
+        .map(Into::into);
+
+    let (polled_roots, unpolled_roots): (Vec<U256>, Vec<U256>) = waiting_roots
+        .into_iter()
+        .partition(|root| Some(*root) == newest_waiting_root);
+
+    let mut waiting_roots = vec![];
+    let mut any_finalized = false;
+
+    for root in polled_roots.into_iter().chain(new_roots) {
+        if is_root_finalized(database, root).await? {
+            debug!(?root, "Root already finalized, skipping");
+            continue;
+        }
+
         info!(?root, "Finalizing root");
 
         // Check if mined on all L2s
         if !identity_manager.is_root_mined_multi_chain(root).await? {
+            // Keep track of the chains the root already reached, to report
+            // the finalization backlog
+            let chain_ids = identity_manager.chains_with_root(root).await?;
+            database
+                .record_root_on_chains(&root.into(), &chain_ids)
+                .await?;
+
+            waiting_roots.push(root);
             continue;
         }
 
-        database.mark_root_as_mined(&root.into()).await?;
+        let newly_mined = database.mark_root_as_mined(&root.into()).await?;
+        any_finalized = true;
+
+        // The tree is brought up to the root even if the database already had
+        // it as mined, so the two can't drift apart
         finalized_tree.apply_updates_up_to(root.into());
 
+        // During recovery the same root may be seen more than once, it's only
+        // announced the first time
+        if !newly_mined {
+            debug!(?root, "Root already finalized, not notifying");
+            continue;
+        }
+
         info!(?root, "Root finalized");
+
+        if let Some(webhook) = webhook {
+            webhook.notify(FinalizationNotification {
+                root,
+                block_number: root_blocks.get(&root).copied(),
+                timestamp: Utc::now(),
+            });
+        }
     }
 
-    Ok(())
+    // Bridges only relay the latest root, so the roots they skip over are
+    // never seen on the secondary chains. Those are finalized along with the
+    // first later root that is, and are no longer waited for.
+    if !any_finalized {
+        return Ok(unpolled_roots.into_iter().chain(waiting_roots).collect());
+    }
+
+    let mut still_waiting = vec![];
+    for root in unpolled_roots.into_iter().chain(waiting_roots) {
+        if is_root_finalized(database, root).await? {
+            info!(?root, "Root superseded by a later finalized root");
+        } else {
+            still_waiting.push(root);
+        }
+    }
+
+    Ok(still_waiting)
+}
+
+/// Returns whether the root has been finalized, either on its own or along
+/// with a later root.
+async fn is_root_finalized(database: &Database, root: U256) -> anyhow::Result<bool> {
+    let state = database.get_root_state(&root.into()).await?;
+
+    Ok(state.is_some_and(|state| state.status == ProcessedStatus::Mined))
+}
+
+/// Keeps track of how long roots have been waiting to be finalized, so that
+/// stuck bridges can be surfaced.
+#[derive(Debug)]
+struct FinalizationAlerts {
+    threshold: Option<Duration>,
+    waiting:   HashMap<U256, WaitingRoot>,
+}
+
+#[derive(Debug)]
+struct WaitingRoot {
+    since:   Instant,
+    alerted: bool,
+}
+
+impl FinalizationAlerts {
+    fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// Updates the set of roots waiting to be finalized. Roots missing from
+    /// `waiting_roots` are considered finalized.
+    ///
+    /// Returns the roots that have just exceeded the alert threshold, along
+    /// with how long they have been waiting. Each root is alerted on once.
+    fn track(&mut self, waiting_roots: &[U256]) -> Vec<(U256, Duration)> {
+        let Some(threshold) = self.threshold else {
+            return vec![];
+        };
+
+        let now = Instant::now();
+
+        self.waiting.retain(|root, _| waiting_roots.contains(root));
+
+        let mut alerts = vec![];
+        for root in waiting_roots {
+            let waiting = self.waiting.entry(*root).or_insert(WaitingRoot {
+                since:   now,
+                alerted: false,
+            });
+
+            let waiting_for = now.duration_since(waiting.since);
+            if !waiting.alerted && waiting_for >= threshold {
+                waiting.alerted = true;
+                alerts.push((*root, waiting_for));
+            }
+        }
+
+        alerts
+    }
 }
 
 async fn init_secondary_scanners<T>(
@@ -226,30 +647,73 @@ fn extract_roots_from_mainnet_logs(mainnet_logs: Vec<Log>) -> Vec<U256> {
     roots
 }
 
+/// Maps the post root of every batch in `mainnet_logs` to the block it was
+/// mined in
+fn extract_root_blocks_from_mainnet_logs(mainnet_logs: &[Log]) -> HashMap<U256, u64> {
+    mainnet_logs
+        .iter()
+        .filter_map(|log| {
+            let event = raw_log_to_tree_changed(log)?;
+            let block_number = log.block_number?;
+
+            Some((event.post_root, block_number.as_u64()))
+        })
+        .collect()
+}
+
 fn raw_log_to_tree_changed(log: &Log) -> Option<TreeChangedFilter> {
     let raw_log = RawLog::from((log.topics.clone(), log.data.to_vec()));
 
     TreeChangedFilter::decode_log(&raw_log).ok()
 }
 
-fn extract_roots_from_secondary_logs(logs: &[Log]) -> Vec<U256> {
+/// A root added to a bridged chain, along with the chain's timestamp of when
+/// it was added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BridgedRoot {
+    root:      U256,
+    timestamp: u128,
+}
+
+fn extract_roots_from_secondary_logs(logs: &[Log]) -> Vec<BridgedRoot> {
     let mut roots = vec![];
 
     for log in logs {
         let raw_log = RawLog::from((log.topics.clone(), log.data.to_vec()));
         if let Ok(event) = RootAddedFilter::decode_log(&raw_log) {
-            roots.push(event.root);
+            roots.push(BridgedRoot {
+                root:      event.root,
+                timestamp: event.timestamp,
+            });
         }
     }
 
     roots
 }
 
+/// Orders the roots seen on the bridged chains by their timestamps. As the
+/// clocks of different chains may drift apart, a root is only moved ahead of
+/// the roots seen before it if it was added more than `max_clock_skew`
+/// earlier than them.
+fn order_bridged_roots(roots: Vec<BridgedRoot>, max_clock_skew: Duration) -> Vec<U256> {
+    let max_clock_skew = u128::from(max_clock_skew.as_secs());
+    let mut ordered: Vec<BridgedRoot> = Vec::with_capacity(roots.len());
+
+    for root in roots {
+        let is_later = |other: &BridgedRoot| other.timestamp > root.timestamp + max_clock_skew;
+        let position = ordered.len() - ordered.iter().rev().take_while(|r| is_later(r)).count();
+
+        ordered.insert(position, root);
+    }
+
+    ordered.into_iter().map(|root| root.root).collect()
+}
+
 use crate::identity_tree::Hash;
 
 async fn update_eligible_recoveries(
     database: &Database,
-    identity_manager: &IdentityManager,
+    identity_manager: &dyn IdentityManagerTrait,
     processed_tree: &TreeVersion<Intermediate>,
     log: &Log,
     max_epoch_duration: Duration,
@@ -301,3 +765,372 @@ async fn update_eligible_recoveries(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::identity_tree::TreeVersionReadOps;
+    use crate::test_utils::{empty_trees, setup_db, MockIdentityManager};
+
+    #[tokio::test]
+    async fn finalizes_only_roots_mined_on_all_chains() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (finalized_tree, processed_tree) = empty_trees(10, &temp_dir);
+
+        let identities = [Hash::from(1), Hash::from(2)];
+        let updates = processed_tree.append_many(&identities);
+
+        for (identity, (root, _, leaf_index)) in identities.iter().zip(&updates) {
+            database
+                .insert_pending_identity(*leaf_index, identity, root)
+                .await?;
+        }
+
+        let first_root: U256 = updates[0].0.into();
+        let second_root: U256 = updates[1].0.into();
+
+        let identity_manager = MockIdentityManager {
+            mined_roots: HashSet::from([first_root]),
+            ..Default::default()
+        };
+
+        let waiting_roots = finalize_secondary_roots(
+            &database,
+            &identity_manager,
+            &finalized_tree,
+            None,
+            &HashMap::new(),
+            vec![],
+            vec![first_root, second_root],
+        )
+        .await?;
+
+        assert_eq!(waiting_roots, vec![second_root]);
+
+        assert_eq!(finalized_tree.get_root(), updates[0].0);
+
+        let first_state = database
+            .get_root_state(&updates[0].0)
+            .await?
+            .context("Fetching root state")?;
+        assert_eq!(first_state.status, ProcessedStatus::Mined);
+
+        let second_state = database
+            .get_root_state(&updates[1].0)
+            .await?
+            .context("Fetching root state")?;
+        assert_eq!(second_state.status, ProcessedStatus::Pending);
+
+        // Seeing the same root again is a no-op
+        finalize_secondary_roots(
+            &database,
+            &identity_manager,
+            &finalized_tree,
+            None,
+            &HashMap::new(),
+            vec![],
+            vec![first_root],
+        )
+        .await?;
+
+        assert_eq!(finalized_tree.get_root(), updates[0].0);
+
+        // The waiting root is reported with the chains it reached so far
+        database.mark_root_as_processed(&updates[1].0).await?;
+        let pending = database.pending_finalization_roots().await?;
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].root, updates[1].0);
+        assert_eq!(pending[0].chains.len(), 1);
+        assert_eq!(pending[0].chains[0].chain_id, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn roots_skipped_by_the_bridges_are_no_longer_waited_for() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (finalized_tree, processed_tree) = empty_trees(10, &temp_dir);
+
+        let identities = [Hash::from(1), Hash::from(2)];
+        let updates = processed_tree.append_many(&identities);
+
+        for (identity, (root, _, leaf_index)) in identities.iter().zip(&updates) {
+            database
+                .insert_pending_identity(*leaf_index, identity, root)
+                .await?;
+        }
+
+        let first_root: U256 = updates[0].0.into();
+        let second_root: U256 = updates[1].0.into();
+
+        // The bridges skip the first root and only ever relay the second one
+        let identity_manager = MockIdentityManager {
+            mined_roots: HashSet::from([second_root]),
+            ..Default::default()
+        };
+
+        let waiting_roots = finalize_secondary_roots(
+            &database,
+            &identity_manager,
+            &finalized_tree,
+            None,
+            &HashMap::new(),
+            vec![],
+            vec![first_root, second_root],
+        )
+        .await?;
+
+        assert!(waiting_roots.is_empty());
+        assert_eq!(finalized_tree.get_root(), updates[1].0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_the_newest_waiting_root_is_polled_again() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (finalized_tree, processed_tree) = empty_trees(10, &temp_dir);
+
+        let identities = [Hash::from(1), Hash::from(2), Hash::from(3)];
+        let updates = processed_tree.append_many(&identities);
+
+        for (identity, (root, _, leaf_index)) in identities.iter().zip(&updates) {
+            database
+                .insert_pending_identity(*leaf_index, identity, root)
+                .await?;
+        }
+
+        let roots: Vec<U256> = updates.iter().map(|(root, ..)| (*root).into()).collect();
+
+        // None of the roots reached every chain yet
+        let identity_manager = MockIdentityManager::default();
+
+        let waiting_roots = finalize_secondary_roots(
+            &database,
+            &identity_manager,
+            &finalized_tree,
+            None,
+            &HashMap::new(),
+            vec![roots[0], roots[1]],
+            vec![roots[2]],
+        )
+        .await?;
+
+        // The oldest root waits for a later root to be finalized
+        assert_eq!(waiting_roots, roots);
+        assert_eq!(*identity_manager.polled.lock().unwrap(), vec![
+            roots[1], roots[2]
+        ]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn finalization_lag_rises_as_roots_accumulate() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (finalized_tree, _) = empty_trees(10, &temp_dir);
+
+        // None of the roots ever reach every chain
+        let identity_manager = MockIdentityManager {
+            mined_roots: HashSet::new(),
+            ..Default::default()
+        };
+        let lag = FinalizationLag::default();
+        assert_eq!(lag.unfinalized_roots(), 0);
+
+        let mut waiting_roots = vec![];
+        for root in 1..=3 {
+            waiting_roots = finalize_secondary_roots(
+                &database,
+                &identity_manager,
+                &finalized_tree,
+                None,
+                &HashMap::new(),
+                waiting_roots,
+                vec![U256::from(root)],
+            )
+            .await?;
+            lag.record(&waiting_roots);
+
+            assert_eq!(lag.unfinalized_roots(), root);
+        }
+
+        assert!(lag.exceeds(Some(2)));
+        assert!(!lag.exceeds(Some(3)));
+        assert!(!lag.exceeds(None));
+
+        Ok(())
+    }
+
+    #[test]
+    fn startup_delays_are_jittered_within_the_window() {
+        let window = Duration::from_secs(10);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let delays: Vec<_> = (0..100).map(|_| startup_delay(window, &mut rng)).collect();
+
+        assert!(delays.iter().all(|delay| *delay <= window));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn first_scan_waits_within_the_startup_window() {
+        let start = Instant::now();
+        wait_for_startup_jitter(Duration::from_secs(10)).await;
+
+        assert!(start.elapsed() <= Duration::from_secs(10));
+
+        // Without a window the first scan starts right away
+        let start = Instant::now();
+        wait_for_startup_jitter(Duration::ZERO).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn alerts_once_root_waits_past_threshold() {
+        let mut alerts = FinalizationAlerts::new(Some(Duration::from_secs(60)));
+        let root = U256::from(1);
+
+        assert!(alerts.track(&[root]).is_empty());
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(alerts.track(&[root]).is_empty());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(alerts.track(&[root]), vec![(root, Duration::from_secs(60))]);
+
+        // The alert only fires once per root
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(alerts.track(&[root]).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finalized_roots_are_no_longer_tracked() {
+        let mut alerts = FinalizationAlerts::new(Some(Duration::from_secs(60)));
+        let root = U256::from(1);
+
+        alerts.track(&[root]);
+        tokio::time::advance(Duration::from_secs(30)).await;
+        alerts.track(&[]);
+
+        // The root starts waiting anew once it shows up again
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(alerts.track(&[root]).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_alerts_without_threshold() {
+        let mut alerts = FinalizationAlerts::new(None);
+        let root = U256::from(1);
+
+        alerts.track(&[root]);
+        tokio::time::advance(Duration::from_secs(3600)).await;
+
+        assert!(alerts.track(&[root]).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_finalization_polls_share_the_rate_limit() -> anyhow::Result<()> {
+        let mock = MockIdentityManager {
+            mined_roots: HashSet::new(),
+            ..Default::default()
+        };
+        let identity_manager = RateLimitedIdentityManager::new(&mock, NonZeroU32::new(10));
+
+        // Every root is polled at once, as when catching up on a backlog
+        let start = Instant::now();
+        let polls =
+            (0..50).map(|root| identity_manager.is_root_mined_multi_chain(U256::from(root)));
+        for mined in futures::future::join_all(polls).await {
+            assert!(!mined?);
+        }
+
+        // The first poll goes through right away, every later one waits for
+        // its own slot
+        assert!(start.elapsed() >= Duration::from_millis(4900));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finalization_polls_are_unlimited_by_default() -> anyhow::Result<()> {
+        let mock = MockIdentityManager {
+            mined_roots: HashSet::new(),
+            ..Default::default()
+        };
+        let identity_manager = RateLimitedIdentityManager::new(&mock, None);
+
+        let start = Instant::now();
+        let polls =
+            (0..50).map(|root| identity_manager.is_root_mined_multi_chain(U256::from(root)));
+        futures::future::join_all(polls).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        Ok(())
+    }
+
+    #[test_case(100, 100, 0 => true ; "no confirmations required")]
+    #[test_case(100, 99, 0 => true ; "no confirmations required with lagging head")]
+    #[test_case(100, 100, 1 => true ; "included block is the first confirmation")]
+    #[test_case(100, 99, 1 => false ; "head behind the included block")]
+    #[test_case(100, 103, 5 => false ; "not buried deep enough")]
+    #[test_case(100, 104, 5 => true ; "buried exactly deep enough")]
+    #[test_case(100, 200, 5 => true ; "buried deeper than required")]
+    fn confirmations(block_number: u64, head: u64, min_confirmations: u64) -> bool {
+        has_enough_confirmations(block_number, head, min_confirmations)
+    }
+
+    fn bridged_root(root: u64, timestamp: u128) -> BridgedRoot {
+        BridgedRoot {
+            root: U256::from(root),
+            timestamp,
+        }
+    }
+
+    #[test_case(0 => vec![2, 1] ; "strictly by timestamp without tolerance")]
+    #[test_case(5 => vec![2, 1] ; "skew beyond the tolerance")]
+    #[test_case(10 => vec![1, 2] ; "skew exactly at the tolerance")]
+    #[test_case(60 => vec![1, 2] ; "skew within the tolerance")]
+    fn bridged_roots_are_ordered_within_clock_skew(max_clock_skew_secs: u64) -> Vec<u64> {
+        let roots = vec![bridged_root(1, 1_010), bridged_root(2, 1_000)];
+
+        order_bridged_roots(roots, Duration::from_secs(max_clock_skew_secs))
+            .into_iter()
+            .map(|root| root.as_u64())
+            .collect()
+    }
+
+    #[test]
+    fn bridged_roots_only_move_past_roots_beyond_the_tolerance() {
+        let roots = vec![
+            bridged_root(1, 1_000),
+            bridged_root(2, 1_030),
+            bridged_root(3, 1_025),
+            bridged_root(4, 1_010),
+        ];
+
+        let ordered = order_bridged_roots(roots, Duration::from_secs(10));
+
+        assert_eq!(ordered, vec![
+            U256::from(1),
+            U256::from(4),
+            U256::from(2),
+            U256::from(3)
+        ]);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -6,49 +7,83 @@ use tokio::time::sleep;
 use tracing::instrument;
 
 use crate::app::App;
+use crate::config::OrderingStrategy;
 use crate::database::types::UnprocessedCommitment;
 use crate::database::Database;
-use crate::identity_tree::{Latest, TreeVersion, TreeVersionReadOps, UnprocessedStatus};
+use crate::identity_tree::{Hash, Latest, TreeVersion, TreeVersionReadOps, UnprocessedStatus};
+use crate::prover::queue::TreeOperation;
 
+/// Queues the eligible unprocessed identities for insertion. They're applied
+/// to the tree by the identity processor, after any queued deletion.
 pub async fn insert_identities(app: Arc<App>, wake_up_notify: Arc<Notify>) -> anyhow::Result<()> {
     loop {
-        // get commits from database
-        let unprocessed = app
-            .database
-            .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
-            .await?;
-        if unprocessed.is_empty() {
+        let queued = {
+            // The queue is locked while reading the database, so that an identity
+            // being applied to the tree isn't queued again
+            let mut queue = app.proving_queue.lock().await;
+
+            // get commits from database
+            let unprocessed = app
+                .database
+                .get_eligible_unprocessed_commitments(UnprocessedStatus::New)
+                .await?;
+
+            let already_queued: HashSet<&TreeOperation> = queue.iter().collect();
+            let operations: Vec<TreeOperation> =
+                order_commitments(unprocessed, app.config.app.insertion_ordering)
+                    .into_iter()
+                    .map(|identity| TreeOperation::Insertion(identity.commitment))
+                    .filter(|operation| !already_queued.contains(operation))
+                    .collect();
+
+            for operation in &operations {
+                queue.push(operation.priority(), operation.clone());
+            }
+
+            operations.len()
+        };
+
+        if queued == 0 {
             sleep(Duration::from_secs(5)).await;
             continue;
         }
 
-        insert_identities_batch(&app.database, app.tree_state()?.latest_tree(), unprocessed)
-            .await?;
         // Notify the identity processing task, that there are new identities
         wake_up_notify.notify_one();
     }
 }
 
+/// Puts the identities in the order they are inserted into the tree in. The
+/// batches and their proofs are built from the tree, so they follow the same
+/// order.
+fn order_commitments(
+    mut identities: Vec<UnprocessedCommitment>,
+    strategy: OrderingStrategy,
+) -> Vec<UnprocessedCommitment> {
+    match strategy {
+        OrderingStrategy::Fifo => identities.sort_by_key(|identity| identity.created_at),
+        OrderingStrategy::ByCommitment => identities.sort_by_key(|identity| identity.commitment),
+    }
+
+    identities
+}
+
+/// Appends the identities to the latest tree and moves them from the
+/// unprocessed queue to the pending identities.
 #[instrument(level = "info", skip_all)]
-async fn insert_identities_batch(
+pub async fn insert_identities_batch(
     database: &Database,
     latest_tree: &TreeVersion<Latest>,
-    identities: Vec<UnprocessedCommitment>,
+    identities: Vec<Hash>,
 ) -> anyhow::Result<()> {
     // Filter out any identities that are already in the `identities` table
     let mut filtered_identities = vec![];
     for identity in identities {
-        if database
-            .get_identity_leaf_index(&identity.commitment)
-            .await?
-            .is_some()
-        {
-            tracing::warn!(?identity.commitment, "Duplicate identity");
-            database
-                .remove_unprocessed_identity(&identity.commitment)
-                .await?;
+        if database.get_identity_leaf_index(&identity).await?.is_some() {
+            tracing::warn!(commitment = ?identity, "Duplicate identity");
+            database.remove_unprocessed_identity(&identity).await?;
         } else {
-            filtered_identities.push(identity.commitment);
+            filtered_identities.push(identity);
         }
     }
 
@@ -81,3 +116,81 @@ async fn insert_identities_batch(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use ethers::types::U256;
+    use semaphore::Field;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::identity_tree::{Hash, TreeVersionReadOps};
+    use crate::prover::identity::Identity;
+    use crate::prover::transition::compute_insertion_roots;
+    use crate::test_utils::empty_trees;
+    use crate::utils::field_util::merkle_proof_siblings;
+
+    /// Commitments in the order they were queued in
+    const QUEUED: [u64; 4] = [30, 10, 40, 20];
+
+    fn queued_commitments() -> Vec<UnprocessedCommitment> {
+        QUEUED
+            .iter()
+            .zip(1_700_000_000..)
+            .map(|(commitment, created_at)| {
+                let created_at = Utc.timestamp_opt(created_at, 0).unwrap();
+
+                UnprocessedCommitment {
+                    commitment: Hash::from(*commitment),
+                    status: UnprocessedStatus::New,
+                    created_at,
+                    processed_at: None,
+                    error_message: None,
+                    eligibility_timestamp: created_at,
+                }
+            })
+            // Rows aren't returned in any particular order
+            .rev()
+            .collect()
+    }
+
+    #[test_case(OrderingStrategy::Fifo => vec![30, 10, 40, 20] ; "fifo")]
+    #[test_case(OrderingStrategy::ByCommitment => vec![10, 20, 30, 40] ; "by commitment")]
+    fn commitments_are_ordered_by_strategy(strategy: OrderingStrategy) -> Vec<u64> {
+        order_commitments(queued_commitments(), strategy)
+            .into_iter()
+            .map(|identity| identity.commitment.as_limbs()[0])
+            .collect()
+    }
+
+    #[test_case(OrderingStrategy::Fifo ; "fifo")]
+    #[test_case(OrderingStrategy::ByCommitment ; "by commitment")]
+    fn ordered_batch_is_a_consistent_transition(strategy: OrderingStrategy) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (_, latest_tree) = empty_trees(10, &temp_dir);
+        let pre_root = latest_tree.get_root();
+
+        let commitments: Vec<Hash> = order_commitments(queued_commitments(), strategy)
+            .into_iter()
+            .map(|identity| identity.commitment)
+            .collect();
+        let updates = latest_tree.append_many(&commitments);
+
+        // The proof input is built from the merkle proofs of the tree updates
+        let identities: Vec<Identity> = commitments
+            .iter()
+            .zip(&updates)
+            .map(|(commitment, (_, merkle_proof, _))| {
+                let merkle_proof = merkle_proof_siblings(&merkle_proof);
+
+                Identity::new(U256::from(*commitment), merkle_proof)
+            })
+            .collect();
+
+        let roots = compute_insertion_roots(0, &identities, Field::ZERO).unwrap();
+
+        assert_eq!(roots, (pre_root.into(), latest_tree.get_root().into()));
+        assert_eq!(latest_tree.get_root(), updates.last().unwrap().0);
+    }
+}
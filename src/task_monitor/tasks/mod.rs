@@ -1,3 +1,4 @@
+pub mod check_provers;
 pub mod delete_identities;
 pub mod finalize_identities;
 pub mod insert_identities;
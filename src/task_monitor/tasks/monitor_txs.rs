@@ -1,19 +1,29 @@
 use std::sync::Arc;
 
 use tokio::sync::{mpsc, Mutex};
+use tracing::{info_span, Instrument};
 
 use crate::app::App;
-use crate::ethereum::write::TransactionId;
+use crate::utils::batch_trace::MonitoredTx;
 
 pub async fn monitor_txs(
     app: Arc<App>,
-    monitored_txs_receiver: Arc<Mutex<mpsc::Receiver<TransactionId>>>,
+    monitored_txs_receiver: Arc<Mutex<mpsc::Receiver<MonitoredTx>>>,
 ) -> anyhow::Result<()> {
     let mut monitored_txs_receiver = monitored_txs_receiver.lock().await;
 
-    while let Some(tx) = monitored_txs_receiver.recv().await {
+    while let Some(MonitoredTx {
+        transaction_id: tx,
+        batch_span,
+    }) = monitored_txs_receiver.recv().await
+    {
+        let span = info_span!(parent: &batch_span, "mine_transaction", transaction_id = %tx);
+
         assert!(
-            (app.identity_manager.mine_transaction(tx.clone()).await?),
+            (app.identity_manager
+                .mine_transaction(tx.clone())
+                .instrument(span)
+                .await?),
             "Failed to mine transaction: {}",
             tx
         );
@@ -1,38 +1,70 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
 use ethers::types::U256;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_gauge, IntGauge};
 use ruint::Uint;
 use semaphore::merkle_tree::Proof;
 use semaphore::poseidon_tree::{Branch, PoseidonHash};
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::time::Instant;
 use tokio::{select, time};
-use tracing::instrument;
+use tracing::{info_span, instrument, Instrument};
 
 use crate::app::App;
-use crate::contracts::IdentityManager;
+use crate::config::OnRootMismatch;
+use crate::contracts::{settle_pending_transactions, IdentityManager};
+use crate::database::types::PendingProof;
+use crate::database::Database;
 use crate::ethereum::write::TransactionId;
 use crate::identity_tree::{
-    AppliedTreeUpdate, Hash, Intermediate, TreeVersion, TreeVersionReadOps, TreeWithNextVersion,
+    AppliedTreeUpdate, Hash, Intermediate, Latest, TreeVersion, TreeVersionReadOps,
+    TreeWithNextVersion,
 };
 use crate::prover::identity::Identity;
-use crate::prover::Prover;
+use crate::prover::queue::{ProvingQueue, TreeOperation};
+use crate::prover::{Prover, ProverType};
+use crate::task_monitor::tasks::delete_identities::delete_identities_batch;
+use crate::task_monitor::tasks::finalize_identities::FinalizationLag;
+use crate::task_monitor::tasks::insert_identities::insert_identities_batch;
 use crate::task_monitor::TaskMonitor;
+use crate::utils::batch_trace::{BatchSpans, MonitoredTx, PendingBatch};
 use crate::utils::batch_type::BatchType;
+use crate::utils::field_util::merkle_proof_siblings;
 use crate::utils::index_packing::pack_indices;
 
 /// The number of seconds either side of the timer tick to treat as enough to
 /// trigger a forced batch insertion.
 const DEBOUNCE_THRESHOLD_SECS: i64 = 1;
 
+static PROVING_PAUSED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "proving_paused",
+        "Set to 1 while proving is paused because no prover can be reached."
+    )
+    .unwrap()
+});
+
 pub async fn process_identities(
     app: Arc<App>,
-    monitored_txs_sender: Arc<mpsc::Sender<TransactionId>>,
+    monitored_txs_sender: Arc<mpsc::Sender<MonitoredTx>>,
     wake_up_notify: Arc<Notify>,
+    finalization_lag: Arc<FinalizationLag>,
 ) -> anyhow::Result<()> {
     tracing::info!("Awaiting for a clean slate");
-    app.identity_manager.await_clean_slate().await?;
+    settle_pending_transactions(
+        app.identity_manager.as_ref(),
+        app.config.app.skip_clean_slate,
+    )
+    .await?;
+
+    let mut throttle = SubmissionThrottle::new(app.config.app.inter_batch_submission_delay);
+
+    resume_pending_proofs(&app, &mut throttle, &monitored_txs_sender).await?;
 
     tracing::info!("Starting identity processor.");
 
@@ -55,6 +87,8 @@ pub async fn process_identities(
         .await?
         .unwrap_or(Utc::now());
 
+    let mut proving_pause = ProvingPause::default();
+
     loop {
         // We wait either for a timer tick or a full batch
         select! {
@@ -66,32 +100,62 @@ pub async fn process_identities(
             },
         }
 
+        if app.is_paused() {
+            tracing::trace!("Pipeline paused, skipping batch");
+            continue;
+        }
+
+        // Proofs left pending by a pause are submitted before any new batch
+        resume_pending_proofs(&app, &mut throttle, &monitored_txs_sender).await?;
+
+        apply_queued_operations(
+            &app.database,
+            app.tree_state()?.latest_tree(),
+            &app.proving_queue,
+        )
+        .await?;
+
         let Some(batch_type) = determine_batch_type(app.tree_state()?.batching_tree()) else {
             continue;
         };
 
-        let batch_size = if batch_type.is_deletion() {
-            app.identity_manager.max_deletion_batch_size().await
+        if finalization_lag.exceeds(app.config.app.max_unfinalized_roots) {
+            tracing::warn!(
+                unfinalized_roots = finalization_lag.unfinalized_roots(),
+                "Too many roots waiting to be finalized, holding off new batches",
+            );
+
+            continue;
+        }
+
+        let (batch_size, prover_type) = if batch_type.is_deletion() {
+            (
+                app.identity_manager.max_deletion_batch_size().await,
+                ProverType::Deletion,
+            )
         } else {
-            app.identity_manager.max_insertion_batch_size().await
+            (
+                app.identity_manager.max_insertion_batch_size().await,
+                ProverType::Insertion,
+            )
         };
 
+        // Everything that is pending is peeked so that a backlog larger than the
+        // largest prover can be committed as several batches in one go
         let updates = app
             .tree_state()?
             .batching_tree()
-            .peek_next_updates(batch_size);
-
-        let current_time = Utc::now();
-        let batch_insertion_timeout =
-            chrono::Duration::from_std(app.config.app.batch_insertion_timeout)?;
-
-        let timeout_batch_time = last_batch_time
-            + batch_insertion_timeout
-            + chrono::Duration::seconds(DEBOUNCE_THRESHOLD_SECS);
-
-        let can_skip_batch = current_time < timeout_batch_time;
+            .peek_next_updates(usize::MAX);
+
+        let readiness = batch_readiness(
+            updates.len(),
+            batch_size,
+            last_batch_time,
+            Utc::now(),
+            chrono::Duration::from_std(app.config.app.batch_insertion_timeout)?,
+        );
 
-        if updates.len() < batch_size && can_skip_batch {
+        if readiness == BatchReadiness::Wait {
             tracing::trace!(
                 num_updates = updates.len(),
                 batch_size,
@@ -102,13 +166,62 @@ pub async fn process_identities(
             continue;
         }
 
-        commit_identities(
-            &app.identity_manager,
-            app.tree_state()?.batching_tree(),
-            &monitored_txs_sender,
-            &updates,
-        )
-        .await?;
+        // Identities keep being queued while no prover can be reached, they are
+        // committed once a later health check succeeds
+        let reachable = app.identity_manager.any_prover_reachable(prover_type).await;
+        if !proving_pause.update(reachable) {
+            continue;
+        }
+
+        // A trailing batch that doesn't fill a prover is only committed once the
+        // batch insertion timeout has elapsed, it is padded to the size of the
+        // smallest prover that fits it
+        let batch_sizes = app
+            .identity_manager
+            .split_into_batches(
+                prover_type,
+                updates.len(),
+                readiness == BatchReadiness::TimedOut,
+            )
+            .await;
+
+        tracing::info!(
+            num_updates = updates.len(),
+            ?batch_sizes,
+            "Committing pending updates"
+        );
+
+        // Each batch starts from the root the previous one left the batching
+        // tree at
+        let mut start = 0;
+        for batch_size in batch_sizes {
+            // A pause requested while the previous batch was being proved keeps
+            // the remaining ones pending until the pipeline is resumed
+            if app.is_paused() {
+                tracing::info!(
+                    num_updates = updates.len() - start,
+                    "Pipeline paused, leaving the remaining updates pending"
+                );
+                break;
+            }
+
+            commit_identities(
+                &app.database,
+                &app.identity_manager,
+                app.tree_state()?.batching_tree(),
+                &monitored_txs_sender,
+                &app.batch_spans,
+                &mut throttle,
+                &updates[start..start + batch_size],
+            )
+            .await?;
+
+            start += batch_size;
+        }
+
+        if start == 0 {
+            continue;
+        }
 
         timer.reset();
         last_batch_time = Utc::now();
@@ -121,20 +234,128 @@ pub async fn process_identities(
     }
 }
 
+/// Applies the queued insertions and deletions to the latest tree, most urgent
+/// first. Deletions queued while a batch was being proved are thereby batched
+/// and proved ahead of the insertions queued before them.
+///
+/// The queue stays locked until the operations are recorded in the database,
+/// so that they aren't queued again in the meantime.
+async fn apply_queued_operations(
+    database: &Database,
+    latest_tree: &TreeVersion<Latest>,
+    proving_queue: &Mutex<ProvingQueue<TreeOperation>>,
+) -> anyhow::Result<()> {
+    let mut queue = proving_queue.lock().await;
+
+    while let Some(priority) = queue.peek_priority() {
+        // Consecutive operations of the same priority are of the same kind and
+        // are applied together
+        let mut commitments = vec![];
+        let mut deletions = vec![];
+        while queue.peek_priority() == Some(priority) {
+            match queue.pop() {
+                Some(TreeOperation::Insertion(commitment)) => commitments.push(commitment),
+                Some(TreeOperation::Deletion(deletion)) => deletions.push(deletion),
+                None => break,
+            }
+        }
+
+        if !deletions.is_empty() {
+            delete_identities_batch(database, latest_tree, deletions).await?;
+        }
+
+        if !commitments.is_empty() {
+            insert_identities_batch(database, latest_tree, commitments).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Pauses proving while no prover can be reached, rather than failing every
+/// batch until the provers come back.
+#[derive(Debug, Default)]
+struct ProvingPause {
+    paused: bool,
+}
+
+impl ProvingPause {
+    /// Records the outcome of the latest prover health check. Returns whether
+    /// proving can go ahead.
+    fn update(&mut self, reachable: bool) -> bool {
+        // Proving is paused exactly while no prover is reachable
+        if reachable == self.paused {
+            if reachable {
+                tracing::info!("A prover is reachable again, resuming proving");
+            } else {
+                tracing::warn!("No prover is reachable, pausing proving");
+            }
+
+            self.paused = !reachable;
+            PROVING_PAUSED.set(i64::from(self.paused));
+        }
+
+        reachable
+    }
+}
+
+/// Whether the pending updates should be committed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchReadiness {
+    /// Not enough updates to fill the largest prover and the batch insertion
+    /// timeout hasn't elapsed yet
+    Wait,
+    /// The largest prover can be filled, only full batches are committed
+    Full,
+    /// The batch insertion timeout has elapsed, everything pending is
+    /// committed, including a partial batch
+    TimedOut,
+}
+
+fn batch_readiness(
+    num_updates: usize,
+    batch_size: usize,
+    last_batch_time: DateTime<Utc>,
+    current_time: DateTime<Utc>,
+    batch_insertion_timeout: chrono::Duration,
+) -> BatchReadiness {
+    let timeout_batch_time = last_batch_time
+        + batch_insertion_timeout
+        + chrono::Duration::seconds(DEBOUNCE_THRESHOLD_SECS);
+
+    if current_time >= timeout_batch_time {
+        BatchReadiness::TimedOut
+    } else if num_updates >= batch_size {
+        BatchReadiness::Full
+    } else {
+        BatchReadiness::Wait
+    }
+}
+
 async fn commit_identities(
+    database: &Database,
     identity_manager: &IdentityManager,
     batching_tree: &TreeVersion<Intermediate>,
-    monitored_txs_sender: &mpsc::Sender<TransactionId>,
+    monitored_txs_sender: &mpsc::Sender<MonitoredTx>,
+    batch_spans: &BatchSpans,
+    throttle: &mut SubmissionThrottle,
     updates: &[AppliedTreeUpdate],
 ) -> anyhow::Result<()> {
+    let first_update = updates.first().context("Updates should be > 1")?;
+    let last_update = updates.last().context("Updates should be > 1")?;
+
+    // The batch span is carried along with the transactions and kept until
+    // the batch's root is finalized, so that the whole lifecycle is one trace.
+    // Its latency is measured from here, before proving.
+    let batch = PendingBatch::new(info_span!(
+        "batch",
+        start_index = first_update.update.leaf_index,
+        num_updates = updates.len(),
+    ));
+    let batch_span = batch.span.clone();
+
     // If the update is an insertion
-    let tx_id = if updates
-        .first()
-        .context("Updates should be > 1")?
-        .update
-        .element
-        != Hash::ZERO
-    {
+    let transaction_id = if first_update.update.element != Hash::ZERO {
         let prover = identity_manager
             .get_suitable_insertion_prover(updates.len())
             .await?;
@@ -145,7 +366,16 @@ async fn commit_identities(
             "Insertion batch",
         );
 
-        insert_identities(identity_manager, batching_tree, updates, &prover).await?
+        insert_identities(
+            database,
+            identity_manager,
+            batching_tree,
+            throttle,
+            updates,
+            &prover,
+        )
+        .instrument(batch_span.clone())
+        .await?
     } else {
         let prover = identity_manager
             .get_suitable_deletion_prover(updates.len())
@@ -157,23 +387,32 @@ async fn commit_identities(
             "Deletion batch"
         );
 
-        delete_identities(identity_manager, batching_tree, updates, &prover).await?
+        delete_identities(identity_manager, batching_tree, throttle, updates, &prover)
+            .instrument(batch_span.clone())
+            .await?
     };
 
-    if let Some(tx_id) = tx_id {
-        monitored_txs_sender.send(tx_id).await?;
-    }
+    batch_spans.insert(last_update.result.root().into(), batch);
+
+    monitored_txs_sender
+        .send(MonitoredTx {
+            transaction_id,
+            batch_span,
+        })
+        .await?;
 
     Ok(())
 }
 
 #[instrument(level = "info", skip_all)]
 pub async fn insert_identities(
+    database: &Database,
     identity_manager: &IdentityManager,
     batching_tree: &TreeVersion<Intermediate>,
+    throttle: &mut SubmissionThrottle,
     updates: &[AppliedTreeUpdate],
     prover: &Prover,
-) -> anyhow::Result<Option<TransactionId>> {
+) -> anyhow::Result<TransactionId> {
     assert_updates_are_consecutive(updates);
 
     let start_index = updates[0].update.leaf_index;
@@ -189,6 +428,8 @@ pub async fn insert_identities(
         .result
         .clone();
 
+    ensure_no_duplicate_commitments(database, start_index, &commitments).await?;
+
     // Next get merkle proofs for each update - note the proofs are acquired from
     // intermediate versions of the tree
     let mut merkle_proofs: Vec<_> = updates
@@ -247,8 +488,10 @@ pub async fn insert_identities(
 
     identity_manager.validate_merkle_proofs(&identity_commitments)?;
 
-    // We prepare the proof before reserving a slot in the pending identities
-    let proof = IdentityManager::prepare_insertion_proof(
+    // We prepare the proof before reserving a slot in the pending identities.
+    // The root to submit only differs from ours if the prover reports another one
+    // and is trusted over us.
+    let (proof, submitted_root) = IdentityManager::prepare_insertion_proof(
         prover,
         start_index,
         pre_root,
@@ -257,22 +500,77 @@ pub async fn insert_identities(
     )
     .await?;
 
+    // The batching tree can only move to the root computed from the updates. A
+    // batch submitted with any other root would leave the chain at a root the
+    // tree never reaches, and every later batch would revert.
+    anyhow::ensure!(
+        submitted_root == post_root,
+        "Batch at {start_index} was proved for post root {submitted_root:#x}, but the batching \
+         tree would be at {post_root:#x}"
+    );
+
+    // Submitting our root over the prover's only works out if the proof verifies
+    // for it, so the identity manager is asked before anything is sent
+    if prover.on_root_mismatch() == OnRootMismatch::TrustLocal {
+        identity_manager
+            .check_insertion_batch(
+                start_index,
+                pre_root,
+                post_root,
+                &identity_commitments,
+                &proof,
+            )
+            .await?;
+    }
+
+    // The input hash the proof is generated for, so that the batch can be checked
+    // against it right before submission
+    let proof_input_hash = identity_manager
+        .build_proof_input(start_index, pre_root, submitted_root, &identity_commitments)?
+        .input_hash();
+
+    // Persisted until the batch is submitted, so that the proof survives a crash
+    let pending_proof = PendingProof {
+        input_hash: proof_input_hash,
+        start_index,
+        pre_root: pre_root.into(),
+        post_root: submitted_root.into(),
+        commitments: identity_commitments
+            .iter()
+            .map(|identity| identity.commitment.into())
+            .collect(),
+        proof: proof.clone(),
+    };
+    if let Err(error) = database.insert_pending_proof(&pending_proof).await {
+        tracing::error!(?error, start_index, "Failed to persist the batch proof");
+    }
+
     tracing::info!(
         start_index,
         ?pre_root,
-        ?post_root,
+        post_root = ?submitted_root,
         "Submitting insertion batch"
     );
 
+    throttle.wait().await;
+
+    // Kept to record what the batch was submitted with
+    let audited_commitments: Vec<Hash> = identity_commitments
+        .iter()
+        .map(|identity| identity.commitment.into())
+        .collect();
+    let audited_proof = proof.clone();
+
     // With all the data prepared we can submit the identities to the on-chain
     // identity manager and wait for that transaction to be mined.
     let transaction_id = identity_manager
         .register_identities(
             start_index,
             pre_root,
-            post_root,
+            submitted_root,
             identity_commitments,
             proof,
+            Some(proof_input_hash),
         )
         .await
         .map_err(|e| {
@@ -283,19 +581,213 @@ pub async fn insert_identities(
     tracing::info!(
         start_index,
         ?pre_root,
-        ?post_root,
+        post_root = ?submitted_root,
         ?transaction_id,
         "Insertion batch submitted"
     );
 
+    if let Err(error) = database.remove_pending_proof(&proof_input_hash).await {
+        tracing::error!(
+            ?error,
+            start_index,
+            "Failed to remove the submitted batch proof"
+        );
+    }
+
+    // The batch is on its way, so a failure to record it must not fail the
+    // submission
+    let audited_transaction_ids = [transaction_id.0.clone()];
+    if let Err(error) = database
+        .insert_batch_audit(
+            start_index,
+            &pre_root.into(),
+            &submitted_root.into(),
+            &audited_commitments,
+            &audited_proof,
+            &audited_transaction_ids,
+        )
+        .await
+    {
+        tracing::error!(?error, post_root = ?submitted_root, "Failed to record the batch audit");
+    }
+
     // Update the batching tree only after submitting the identities to the chain
     batching_tree.apply_updates_up_to(post_root.into());
 
     tracing::info!(start_index, ?pre_root, ?post_root, "Tree updated");
 
     TaskMonitor::log_batch_size(updates.len());
+    TaskMonitor::log_tree_occupancy(&identity_manager.tree_occupancy(batching_tree.next_leaf()));
+
+    Ok(transaction_id)
+}
+
+/// Submits the insertion batches that were proved but not submitted before
+/// the last shutdown or pause, so that their proofs don't have to be generated
+/// again. Batches that no longer extend both the chain and the batching tree
+/// are discarded and proved again as usual.
+#[instrument(level = "info", skip_all)]
+async fn resume_pending_proofs(
+    app: &App,
+    throttle: &mut SubmissionThrottle,
+    monitored_txs_sender: &mpsc::Sender<MonitoredTx>,
+) -> anyhow::Result<()> {
+    if app.is_paused() || app.database.get_pending_proofs().await?.is_empty() {
+        return Ok(());
+    }
+
+    let tree_state = app.tree_state()?;
+    let batching_tree = tree_state.batching_tree();
+    let chain_root: Hash = app.identity_manager.latest_root().await?.into();
+
+    submit_pending_proofs(
+        &app.database,
+        &app.identity_manager,
+        batching_tree,
+        &app.batch_spans,
+        throttle,
+        monitored_txs_sender,
+        chain_root,
+        || app.is_paused(),
+    )
+    .await?;
+
+    TaskMonitor::log_tree_occupancy(
+        &app.identity_manager
+            .tree_occupancy(batching_tree.next_leaf()),
+    );
+
+    Ok(())
+}
+
+/// Submits the persisted proofs in order, as long as they extend both the
+/// chain, starting at `chain_root`, and the batching tree. Every persisted
+/// proof is removed, submitted or not, except for those left once `is_paused`
+/// returns true.
+async fn submit_pending_proofs(
+    database: &Database,
+    identity_manager: &IdentityManager,
+    batching_tree: &TreeVersion<Intermediate>,
+    batch_spans: &BatchSpans,
+    throttle: &mut SubmissionThrottle,
+    monitored_txs_sender: &mpsc::Sender<MonitoredTx>,
+    mut chain_root: Hash,
+    is_paused: impl Fn() -> bool,
+) -> anyhow::Result<()> {
+    for pending_proof in database.get_pending_proofs().await? {
+        if is_paused() {
+            tracing::info!(
+                start_index = pending_proof.start_index,
+                "Pipeline paused, leaving the remaining proved batches pending"
+            );
+            break;
+        }
+
+        let updates = batching_tree.peek_next_updates(pending_proof.commitments.len());
+        let tree_root = batching_tree.get_root();
+
+        match resumable_batch_root(&pending_proof, chain_root, tree_root, &updates) {
+            Some(batch_root) => {
+                let batch = PendingBatch::new(info_span!(
+                    "batch",
+                    start_index = pending_proof.start_index,
+                    num_updates = pending_proof.commitments.len(),
+                ));
+                let batch_span = batch.span.clone();
+
+                // Only the commitments are submitted, the merkle proofs were
+                // already checked when the batch was proved
+                let identities = pending_proof
+                    .commitments
+                    .iter()
+                    .map(|commitment| Identity::new((*commitment).into(), vec![]))
+                    .collect();
+
+                throttle.wait().await;
+
+                let transaction_id = identity_manager
+                    .register_identities(
+                        pending_proof.start_index,
+                        pending_proof.pre_root.into(),
+                        pending_proof.post_root.into(),
+                        identities,
+                        pending_proof.proof.clone(),
+                        Some(pending_proof.input_hash),
+                    )
+                    .instrument(batch_span.clone())
+                    .await?;
+
+                tracing::info!(
+                    start_index = pending_proof.start_index,
+                    ?transaction_id,
+                    "Resumed submission of a proved batch"
+                );
+
+                batch_spans.insert(batch_root.into(), batch);
+
+                monitored_txs_sender
+                    .send(MonitoredTx {
+                        transaction_id,
+                        batch_span,
+                    })
+                    .await?;
+
+                batching_tree.apply_updates_up_to(batch_root);
+                chain_root = batch_root;
+            }
+            None => {
+                tracing::info!(
+                    start_index = pending_proof.start_index,
+                    "Discarding the proof of a batch that no longer applies"
+                );
+            }
+        }
+
+        database
+            .remove_pending_proof(&pending_proof.input_hash)
+            .await?;
+    }
 
-    Ok(Some(transaction_id))
+    Ok(())
+}
+
+/// Checks that a persisted batch still extends both the chain, at
+/// `chain_root`, and the batching tree, at `tree_root` with `updates` pending
+/// next, and that it was proved for the root the batching tree reaches with
+/// it. Returns that root if so.
+fn resumable_batch_root(
+    pending_proof: &PendingProof,
+    chain_root: Hash,
+    tree_root: Hash,
+    updates: &[AppliedTreeUpdate],
+) -> Option<Hash> {
+    if pending_proof.pre_root != chain_root || pending_proof.pre_root != tree_root {
+        return None;
+    }
+
+    // Everything after the identities of the batch is padding
+    let identity_count = pending_proof
+        .commitments
+        .iter()
+        .take_while(|commitment| **commitment != Hash::ZERO)
+        .count();
+    if identity_count == 0 || updates.len() < identity_count {
+        return None;
+    }
+
+    let batch_updates = &updates[..identity_count];
+    let matches = batch_updates
+        .iter()
+        .zip(&pending_proof.commitments)
+        .enumerate()
+        .all(|(offset, (update, commitment))| {
+            update.update.leaf_index == pending_proof.start_index + offset
+                && update.update.element == *commitment
+        });
+
+    let batch_root = batch_updates[identity_count - 1].result.root();
+
+    (matches && batch_root == pending_proof.post_root).then_some(batch_root)
 }
 
 fn assert_updates_are_consecutive(updates: &[AppliedTreeUpdate]) {
@@ -325,9 +817,10 @@ fn assert_updates_are_consecutive(updates: &[AppliedTreeUpdate]) {
 pub async fn delete_identities(
     identity_manager: &IdentityManager,
     batching_tree: &TreeVersion<Intermediate>,
+    throttle: &mut SubmissionThrottle,
     updates: &[AppliedTreeUpdate],
     prover: &Prover,
-) -> anyhow::Result<Option<TransactionId>> {
+) -> anyhow::Result<TransactionId> {
     // Grab the initial conditions before the updates are applied to the tree.
     let pre_root: U256 = batching_tree.get_root().into();
 
@@ -416,6 +909,8 @@ pub async fn delete_identities(
 
     tracing::info!(?pre_root, ?post_root, "Submitting deletion batch");
 
+    throttle.wait().await;
+
     // With all the data prepared we can submit the identities to the on-chain
     // identity manager and wait for that transaction to be mined.
     let transaction_id = identity_manager
@@ -439,8 +934,9 @@ pub async fn delete_identities(
     tracing::info!(?pre_root, ?post_root, "Tree updated");
 
     TaskMonitor::log_batch_size(updates.len());
+    TaskMonitor::log_tree_occupancy(&identity_manager.tree_occupancy(batching_tree.next_leaf()));
 
-    Ok(Some(transaction_id))
+    Ok(transaction_id)
 }
 
 fn zip_commitments_and_proofs(
@@ -452,13 +948,7 @@ fn zip_commitments_and_proofs(
         .zip(merkle_proofs)
         .map(|(id, prf)| {
             let commitment: U256 = id.into();
-            let proof: Vec<U256> = prf
-                .0
-                .iter()
-                .map(|branch| match branch {
-                    Branch::Left(v) | Branch::Right(v) => U256::from(*v),
-                })
-                .collect();
+            let proof: Vec<U256> = merkle_proof_siblings(&prf);
             Identity::new(commitment, proof)
         })
         .collect()
@@ -478,3 +968,517 @@ fn determine_batch_type(tree: &TreeVersion<Intermediate>) -> Option<BatchType> {
 
     Some(batch_type)
 }
+
+/// Rejects a batch containing a commitment that appears more than once in the
+/// batch or that was already inserted at an earlier leaf.
+///
+/// The prover would reject such a batch anyway, but only after a round trip.
+async fn ensure_no_duplicate_commitments(
+    database: &Database,
+    start_index: usize,
+    commitments: &[U256],
+) -> anyhow::Result<()> {
+    if let Some((first, second)) = find_duplicate_commitment(commitments) {
+        anyhow::bail!(
+            "Commitment {:?} appears twice in the batch, at leaf indices {} and {}",
+            commitments[first],
+            start_index + first,
+            start_index + second
+        );
+    }
+
+    let inserted: Vec<Hash> = commitments
+        .iter()
+        .filter(|commitment| !commitment.is_zero())
+        .map(|commitment| (*commitment).into())
+        .collect();
+
+    let earlier: HashMap<Hash, usize> = database
+        .get_earlier_leaf_indices(&inserted, start_index)
+        .await?
+        .into_iter()
+        .collect();
+
+    for (offset, commitment) in commitments.iter().enumerate() {
+        if let Some(earlier) = earlier.get(&(*commitment).into()) {
+            let leaf_index = start_index + offset;
+            anyhow::bail!(
+                "Commitment {commitment:?} at leaf index {leaf_index} was already inserted at \
+                 leaf index {earlier}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the positions of the first commitment that appears twice in the
+/// batch. Zero commitments are padding and are ignored.
+fn find_duplicate_commitment(commitments: &[U256]) -> Option<(usize, usize)> {
+    let mut seen = HashMap::new();
+
+    for (position, commitment) in commitments.iter().enumerate() {
+        if commitment.is_zero() {
+            continue;
+        }
+
+        if let Some(first) = seen.insert(*commitment, position) {
+            return Some((first, position));
+        }
+    }
+
+    None
+}
+
+/// Spaces out consecutive batch submissions so that they don't overwhelm the
+/// RPC provider.
+struct SubmissionThrottle {
+    delay:           Duration,
+    last_submission: Option<Instant>,
+}
+
+impl SubmissionThrottle {
+    const fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            last_submission: None,
+        }
+    }
+
+    /// Waits until at least the configured delay has passed since the
+    /// previous submission and marks the start of a new one.
+    async fn wait(&mut self) {
+        if let Some(last_submission) = self.last_submission {
+            time::sleep_until(last_submission + self.delay).await;
+        }
+
+        self.last_submission = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semaphore::poseidon_tree::LazyPoseidonTree;
+
+    use super::*;
+    use crate::database::types::DeletionEntry;
+    use crate::identity_tree::{CanonicalTreeBuilder, ProcessedStatus, TreeUpdate};
+    use crate::prover::{mock, ProverSettings};
+    use crate::test_utils::{empty_trees, setup_db, RpcMock};
+
+    #[test]
+    fn detects_duplicate_commitments_within_batch() {
+        let commitments = [U256::from(1), U256::from(2), U256::from(3), U256::from(2)];
+
+        assert_eq!(find_duplicate_commitment(&commitments), Some((1, 3)));
+    }
+
+    #[test]
+    fn unique_commitments_and_padding_are_accepted() {
+        let commitments = [U256::from(1), U256::from(2), U256::zero(), U256::zero()];
+
+        assert_eq!(find_duplicate_commitment(&commitments), None);
+    }
+
+    #[test]
+    fn proving_pauses_until_a_prover_is_reachable() {
+        let mut pause = ProvingPause::default();
+
+        assert!(pause.update(true));
+        assert!(!pause.paused);
+
+        assert!(!pause.update(false));
+        assert!(!pause.update(false));
+        assert!(pause.paused);
+
+        assert!(pause.update(true));
+        assert!(!pause.paused);
+    }
+
+    fn readiness_after(num_updates: usize, elapsed: chrono::Duration) -> BatchReadiness {
+        let last_batch_time = Utc::now();
+
+        batch_readiness(
+            num_updates,
+            10,
+            last_batch_time,
+            last_batch_time + elapsed,
+            chrono::Duration::seconds(60),
+        )
+    }
+
+    #[test]
+    fn partial_batch_waits_for_the_timeout() {
+        assert_eq!(
+            readiness_after(3, chrono::Duration::seconds(30)),
+            BatchReadiness::Wait
+        );
+        assert_eq!(
+            readiness_after(0, chrono::Duration::seconds(30)),
+            BatchReadiness::Wait
+        );
+    }
+
+    #[test]
+    fn partial_batch_is_committed_after_the_timeout() {
+        assert_eq!(
+            readiness_after(3, chrono::Duration::seconds(61)),
+            BatchReadiness::TimedOut
+        );
+        assert_eq!(
+            readiness_after(3, chrono::Duration::seconds(600)),
+            BatchReadiness::TimedOut
+        );
+    }
+
+    #[test]
+    fn full_batch_is_committed_immediately() {
+        assert_eq!(
+            readiness_after(10, chrono::Duration::zero()),
+            BatchReadiness::Full
+        );
+        assert_eq!(
+            readiness_after(25, chrono::Duration::seconds(30)),
+            BatchReadiness::Full
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn submissions_are_spaced_by_the_delay() {
+        let delay = Duration::from_millis(500);
+        let mut throttle = SubmissionThrottle::new(delay);
+        let start = Instant::now();
+
+        let mut submitted_at = vec![];
+        for _ in 0..3 {
+            throttle.wait().await;
+            submitted_at.push(start.elapsed());
+
+            // Time spent proving counts towards the delay
+            time::advance(Duration::from_millis(200)).await;
+        }
+
+        assert_eq!(submitted_at, vec![
+            Duration::ZERO,
+            Duration::from_millis(500),
+            Duration::from_millis(1000),
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_delay_does_not_wait() {
+        let mut throttle = SubmissionThrottle::new(Duration::ZERO);
+        let start = Instant::now();
+
+        throttle.wait().await;
+        throttle.wait().await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    /// Inserts `commitments` after `start_index` leaves, returning the root
+    /// before the insertions and the pending updates.
+    fn pending_insertions(
+        start_index: usize,
+        commitments: &[u64],
+    ) -> (Hash, Vec<AppliedTreeUpdate>) {
+        let mut tree = LazyPoseidonTree::new(10, Hash::ZERO).derived();
+        for leaf_index in 0..start_index {
+            tree = tree.update(leaf_index, &Hash::from(100 + leaf_index));
+        }
+        let pre_root = tree.root();
+
+        let updates = commitments
+            .iter()
+            .enumerate()
+            .map(|(offset, commitment)| {
+                let leaf_index = start_index + offset;
+                tree = tree.update(leaf_index, &Hash::from(*commitment));
+
+                AppliedTreeUpdate {
+                    update: TreeUpdate::new(leaf_index, Hash::from(*commitment)),
+                    result: tree.clone(),
+                }
+            })
+            .collect();
+
+        (pre_root, updates)
+    }
+
+    fn pending_proof(
+        start_index: usize,
+        pre_root: Hash,
+        post_root: Hash,
+        commitments: &[u64],
+    ) -> PendingProof {
+        PendingProof {
+            input_hash: U256::from(1),
+            start_index,
+            pre_root,
+            post_root,
+            commitments: commitments.iter().map(|c| Hash::from(*c)).collect(),
+            proof: crate::prover::Proof::from([U256::zero(); 8]),
+        }
+    }
+
+    #[test]
+    fn persisted_proof_is_reused_after_a_crash() {
+        // The batch of 1 and 2 was proved before the crash, 3 arrived after it
+        let (pre_root, updates) = pending_insertions(5, &[1, 2, 3]);
+        let post_root = updates[1].result.root();
+        let pending_proof = pending_proof(5, pre_root, post_root, &[1, 2, 0, 0]);
+
+        let batch_root = resumable_batch_root(&pending_proof, pre_root, pre_root, &updates);
+
+        assert_eq!(batch_root, Some(post_root));
+    }
+
+    #[test]
+    fn persisted_proof_is_discarded_if_proved_for_another_root() {
+        let (pre_root, updates) = pending_insertions(5, &[1, 2]);
+        let pending_proof = pending_proof(5, pre_root, Hash::from(42), &[1, 2, 0, 0]);
+
+        let batch_root = resumable_batch_root(&pending_proof, pre_root, pre_root, &updates);
+
+        assert_eq!(batch_root, None);
+    }
+
+    #[test]
+    fn persisted_proof_is_discarded_once_the_chain_moved_on() {
+        let (pre_root, updates) = pending_insertions(5, &[1, 2]);
+        let pending_proof = pending_proof(5, pre_root, updates[1].result.root(), &[1, 2, 0, 0]);
+        let chain_root = Hash::from(7);
+
+        let batch_root = resumable_batch_root(&pending_proof, chain_root, pre_root, &updates);
+
+        assert_eq!(batch_root, None);
+    }
+
+    #[test]
+    fn persisted_proof_is_discarded_if_the_batch_changed() {
+        let (pre_root, updates) = pending_insertions(5, &[1, 3]);
+        let pending_proof = pending_proof(5, pre_root, updates[1].result.root(), &[1, 2, 0, 0]);
+
+        let batch_root = resumable_batch_root(&pending_proof, pre_root, pre_root, &updates);
+
+        assert_eq!(batch_root, None);
+    }
+
+    #[tokio::test]
+    async fn queued_deletion_is_applied_before_earlier_insertions() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (_, latest_tree) = empty_trees(10, &temp_dir);
+
+        // The identity to delete is already in the tree
+        let existing = Hash::from(1);
+        let (root, _, leaf_index) = latest_tree.append_many(&[existing]).remove(0);
+        database
+            .insert_pending_identity(leaf_index, &existing, &root)
+            .await?;
+
+        let proving_queue = Mutex::new(ProvingQueue::new());
+        {
+            let mut queue = proving_queue.lock().await;
+
+            let operations = [
+                TreeOperation::Insertion(Hash::from(2)),
+                TreeOperation::Insertion(Hash::from(3)),
+                TreeOperation::Deletion(DeletionEntry {
+                    leaf_index,
+                    commitment: existing,
+                }),
+            ];
+            for operation in operations {
+                queue.push(operation.priority(), operation);
+            }
+        }
+
+        apply_queued_operations(&database, &latest_tree, &proving_queue).await?;
+
+        let pending = database
+            .get_commitments_by_status(ProcessedStatus::Pending)
+            .await?;
+        assert_eq!(pending, vec![
+            TreeUpdate::new(0, existing),
+            TreeUpdate::new(0, Hash::ZERO),
+            TreeUpdate::new(1, Hash::from(2)),
+            TreeUpdate::new(2, Hash::from(3)),
+        ]);
+        assert!(proving_queue.lock().await.is_empty());
+
+        Ok(())
+    }
+
+    /// Builds an empty tree of depth 10 and returns its batching and latest
+    /// versions.
+    fn empty_batching_trees(
+        dir: &tempfile::TempDir,
+    ) -> (TreeVersion<Intermediate>, TreeVersion<Latest>) {
+        let (_, processed_builder) = CanonicalTreeBuilder::new(
+            10,
+            10,
+            0,
+            Hash::ZERO,
+            &[],
+            dir.path().join("testfile").to_str().unwrap(),
+        )
+        .seal();
+        let (_, batching_builder) = processed_builder.seal_and_continue();
+        let (batching_tree, latest_builder) = batching_builder.seal_and_continue();
+
+        (batching_tree, latest_builder.seal())
+    }
+
+    #[tokio::test]
+    async fn batch_proved_for_another_root_is_not_submitted() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+        let rpc = RpcMock::spawn().await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 10).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (batching_tree, latest_tree) = empty_batching_trees(&temp_dir);
+        let _ = latest_tree.append_many(&[Hash::from(1), Hash::from(2)]);
+
+        let mock_service = mock::Service::new().await?;
+        mock_service.set_post_root(U256::from(7));
+        let prover = mock::mock_prover(mock_service.url(), &ProverSettings {
+            on_root_mismatch: OnRootMismatch::TrustProver,
+            ..Default::default()
+        });
+        let mut throttle = SubmissionThrottle::new(Duration::ZERO);
+
+        let pre_root = batching_tree.get_root();
+        let updates = batching_tree.peek_next_updates(3);
+        let post_root = latest_tree.get_root();
+
+        let error = insert_identities(
+            &database,
+            &identity_manager,
+            &batching_tree,
+            &mut throttle,
+            &updates,
+            &prover,
+        )
+        .await
+        .expect_err("A batch proved for another root should not be submitted");
+
+        assert!(error.to_string().contains("batching tree"));
+        assert!(!rpc.called("eth_sendRawTransaction"));
+        assert!(database.get_pending_proofs().await?.is_empty());
+        assert_eq!(batching_tree.get_root(), pre_root);
+
+        // The batch is proved again from the same root, which the chain is still at
+        mock_service.set_post_root(post_root.into());
+        let updates = batching_tree.peek_next_updates(3);
+
+        insert_identities(
+            &database,
+            &identity_manager,
+            &batching_tree,
+            &mut throttle,
+            &updates,
+            &prover,
+        )
+        .await?;
+
+        mock_service.stop();
+
+        assert_eq!(updates[0].update.leaf_index, 0);
+        assert!(rpc.called("eth_sendRawTransaction"));
+        assert_eq!(batching_tree.get_root(), post_root);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proof_persisted_before_a_crash_is_submitted_on_restart() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+        let rpc = RpcMock::spawn().await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 10).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (batching_tree, latest_tree) = empty_batching_trees(&temp_dir);
+        let _ = latest_tree.append_many(&[Hash::from(1), Hash::from(2)]);
+
+        // The batch was proved and persisted, but the process stopped before
+        // submitting it
+        let pre_root = batching_tree.get_root();
+        let post_root = latest_tree.get_root();
+        let commitments = [Hash::from(1), Hash::from(2), Hash::ZERO];
+        let identities: Vec<Identity> = commitments
+            .iter()
+            .map(|commitment| Identity::new((*commitment).into(), vec![]))
+            .collect();
+        let pending_proof = PendingProof {
+            input_hash: identity_manager
+                .build_proof_input(0, pre_root.into(), post_root.into(), &identities)?
+                .input_hash(),
+            start_index: 0,
+            pre_root,
+            post_root,
+            commitments: commitments.to_vec(),
+            proof: mock::get_default_proof_output(),
+        };
+        database.insert_pending_proof(&pending_proof).await?;
+
+        let batch_spans = BatchSpans::default();
+        let (monitored_txs_sender, mut monitored_txs_receiver) = mpsc::channel(1);
+
+        submit_pending_proofs(
+            &database,
+            &identity_manager,
+            &batching_tree,
+            &batch_spans,
+            &mut SubmissionThrottle::new(Duration::ZERO),
+            &monitored_txs_sender,
+            pre_root,
+            || false,
+        )
+        .await?;
+
+        assert!(rpc.called("eth_sendRawTransaction"));
+        assert!(monitored_txs_receiver.try_recv().is_ok());
+        assert!(database.get_pending_proofs().await?.is_empty());
+        assert!(batch_spans.finish(&post_root.into()).is_some());
+        assert_eq!(batching_tree.get_root(), post_root);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn persisted_proof_is_kept_while_paused() -> anyhow::Result<()> {
+        let (database, _db_container) = setup_db().await?;
+        let rpc = RpcMock::spawn().await?;
+        let identity_manager = IdentityManager::connect_to_mock(&rpc, 10).await?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let (batching_tree, latest_tree) = empty_batching_trees(&temp_dir);
+        let _ = latest_tree.append_many(&[Hash::from(1), Hash::from(2)]);
+
+        let pre_root = batching_tree.get_root();
+        let pending_proof = pending_proof(0, pre_root, latest_tree.get_root(), &[1, 2, 0]);
+        database.insert_pending_proof(&pending_proof).await?;
+
+        let (monitored_txs_sender, _monitored_txs_receiver) = mpsc::channel(1);
+
+        submit_pending_proofs(
+            &database,
+            &identity_manager,
+            &batching_tree,
+            &BatchSpans::default(),
+            &mut SubmissionThrottle::new(Duration::ZERO),
+            &monitored_txs_sender,
+            pre_root,
+            || true,
+        )
+        .await?;
+
+        assert!(!rpc.called("eth_sendRawTransaction"));
+        assert_eq!(database.get_pending_proofs().await?, vec![pending_proof]);
+        assert_eq!(batching_tree.get_root(), pre_root);
+
+        Ok(())
+    }
+}
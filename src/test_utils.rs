@@ -0,0 +1,231 @@
+//! Fixtures shared by the unit tests.
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::routing::post;
+use axum::{Json, Router};
+use axum_server::Handle;
+use chrono::Utc;
+use ethers::types::{H256, U256};
+use postgres_docker_utils::DockerContainerGuard;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+use crate::config::DatabaseConfig;
+use crate::contracts::{CleanSlateReport, IdentityManagerTrait};
+use crate::database::Database;
+use crate::identity_tree::{Canonical, CanonicalTreeBuilder, Hash, Latest, TreeVersion};
+use crate::utils::secret::SecretUrl;
+
+/// Starts a migrated database in a fresh container. The database is only
+/// usable while the returned guard is alive.
+pub async fn setup_db() -> anyhow::Result<(Database, DockerContainerGuard)> {
+    let db_container = postgres_docker_utils::setup().await?;
+    let db_socket_addr = db_container.address();
+    let url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let db = Database::new(&DatabaseConfig {
+        database:        SecretUrl::from_str(&url)?,
+        migrate:         true,
+        max_connections: 1,
+    })
+    .await?;
+
+    Ok((db, db_container))
+}
+
+/// Builds an empty, fully dense tree of the given depth, backed by a file in
+/// `dir`. Returns its canonical version and a latest version derived from it.
+pub fn empty_trees(depth: usize, dir: &TempDir) -> (TreeVersion<Canonical>, TreeVersion<Latest>) {
+    let (canonical_tree, latest_builder) = CanonicalTreeBuilder::new(
+        depth,
+        depth,
+        0,
+        Hash::ZERO,
+        &[],
+        dir.path().join("testfile").to_str().unwrap(),
+    )
+    .seal();
+
+    (canonical_tree, latest_builder.seal())
+}
+
+/// An identity manager that reports a fixed latest root and considers a fixed
+/// set of roots mined, and records the roots it is polled for.
+#[derive(Default)]
+pub struct MockIdentityManager {
+    pub latest_root: U256,
+    pub mined_roots: HashSet<U256>,
+    pub polled:      Mutex<Vec<U256>>,
+}
+
+#[async_trait]
+impl IdentityManagerTrait for MockIdentityManager {
+    async fn latest_root(&self) -> anyhow::Result<U256> {
+        Ok(self.latest_root)
+    }
+
+    async fn latest_block_number(&self) -> anyhow::Result<u64> {
+        Ok(0)
+    }
+
+    async fn root_history_expiry(&self) -> anyhow::Result<U256> {
+        Ok(U256::zero())
+    }
+
+    async fn is_root_mined(&self, root: U256) -> anyhow::Result<bool> {
+        Ok(self.mined_roots.contains(&root))
+    }
+
+    async fn are_roots_mined(&self, roots: &[U256]) -> anyhow::Result<Vec<bool>> {
+        Ok(roots
+            .iter()
+            .map(|root| self.mined_roots.contains(root))
+            .collect())
+    }
+
+    async fn is_root_mined_multi_chain(&self, root: U256) -> anyhow::Result<bool> {
+        self.polled.lock().unwrap().push(root);
+        Ok(self.mined_roots.contains(&root))
+    }
+
+    async fn chains_with_root(&self, root: U256) -> anyhow::Result<Vec<u64>> {
+        Ok(if self.mined_roots.contains(&root) {
+            vec![1, 10]
+        } else {
+            vec![1]
+        })
+    }
+
+    async fn fetch_deletion_indices_from_tx(&self, _tx_hash: H256) -> anyhow::Result<Vec<usize>> {
+        Ok(vec![])
+    }
+
+    async fn await_clean_slate(&self) -> anyhow::Result<CleanSlateReport> {
+        Ok(CleanSlateReport::default())
+    }
+}
+
+/// A JSON-RPC endpoint that answers just enough methods to connect to it and
+/// fill and send a transaction, recording the methods it is called with.
+pub struct RpcMock {
+    server:  Handle,
+    address: SocketAddr,
+    methods: Arc<Mutex<Vec<String>>>,
+}
+
+impl RpcMock {
+    /// Starts a mock that accepts every transaction.
+    pub async fn spawn() -> anyhow::Result<Self> {
+        Self::start(None).await
+    }
+
+    /// Starts a mock that rejects every transaction with `message`, the way a
+    /// node rejects an invalid one.
+    pub async fn rejecting_transactions(message: &str) -> anyhow::Result<Self> {
+        Self::start(Some(message.to_string())).await
+    }
+
+    async fn start(rejection: Option<String>) -> anyhow::Result<Self> {
+        let methods = Arc::new(Mutex::new(vec![]));
+        let recorded_methods = methods.clone();
+
+        let rpc = move |Json(request): Json<Value>| {
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            recorded_methods.lock().unwrap().push(method.clone());
+            let rejection = rejection.clone();
+
+            async move {
+                let result = match (method.as_str(), rejection) {
+                    ("web3_clientVersion", _) => json!("mock"),
+                    ("eth_chainId", _) => json!("0x1"),
+                    ("eth_blockNumber", _) => json!("0x1"),
+                    ("eth_getBlockByNumber", _) => json!({
+                        "hash": format!("0x{}", "11".repeat(32)),
+                        "parentHash": format!("0x{}", "00".repeat(32)),
+                        "number": "0x1",
+                        "gasUsed": "0x0",
+                        "gasLimit": "0x1c9c380",
+                        "baseFeePerGas": "0x1",
+                        "extraData": "0x",
+                        "timestamp": format!("{:#x}", Utc::now().timestamp()),
+                        "difficulty": "0x0",
+                        "uncles": [],
+                        "transactions": [],
+                    }),
+                    ("eth_getTransactionCount", _) => json!("0x0"),
+                    ("eth_estimateGas", _) => json!("0x5208"),
+                    ("eth_feeHistory", _) => json!({
+                        "oldestBlock": "0x1",
+                        "baseFeePerGas": ["0x1", "0x1"],
+                        "gasUsedRatio": [0.5],
+                        "reward": [["0x1"]],
+                    }),
+                    ("eth_sendRawTransaction", None) => json!(format!("0x{}", "22".repeat(32))),
+                    ("eth_sendRawTransaction", Some(message)) => {
+                        return Json(json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "error": { "code": -32000, "message": message },
+                        }))
+                    }
+                    _ => {
+                        return Json(json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "error": { "code": -32601, "message": "Method not found" },
+                        }))
+                    }
+                };
+
+                Json(json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "result": result,
+                }))
+            }
+        };
+
+        let server = Handle::new();
+        let serverside_handle = server.clone();
+        let service = Router::new().route("/", post(rpc)).into_make_service();
+
+        tokio::spawn(async move {
+            axum_server::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .handle(serverside_handle)
+                .serve(service)
+                .await
+                .unwrap();
+        });
+
+        let address = server
+            .listening()
+            .await
+            .context("RPC mock failed to start")?;
+
+        Ok(Self {
+            server,
+            address,
+            methods,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn called(&self, method: &str) -> bool {
+        self.methods.lock().unwrap().iter().any(|m| m == method)
+    }
+}
+
+impl Drop for RpcMock {
+    fn drop(&mut self) {
+        self.server.shutdown();
+    }
+}
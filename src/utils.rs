@@ -7,12 +7,16 @@ use tokio::sync::broadcast;
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
+pub mod batch_trace;
 pub mod batch_type;
+pub mod field_util;
 pub mod index_packing;
 pub mod min_map;
+pub mod retry;
 pub mod secret;
 pub mod serde_utils;
 pub mod tree_updates;
+pub mod webhook;
 
 pub fn spawn_monitored_with_backoff<S, F>(
     future_spawner: S,
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::types::U256;
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, register_histogram, Histogram};
+use tracing::{info, Span};
+
+use crate::ethereum::write::TransactionId;
+
+/// The number of batch spans kept for roots that are not finalized yet. The
+/// oldest span is dropped once a batch beyond it is submitted, e.g. if its
+/// transaction failed and the root is never finalized.
+const MAX_PENDING_BATCH_SPANS: usize = 1024;
+
+static BATCH_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "batch_latency_seconds",
+        "The time from committing a batch to finalizing its root, in seconds.",
+        exponential_buckets(1.0, 1.5, 25).unwrap()
+    )
+    .unwrap()
+});
+
+/// A transaction submitted for a batch, along with the span of the batch, so
+/// that awaiting it is traced as part of the batch.
+#[derive(Clone, Debug)]
+pub struct MonitoredTx {
+    pub transaction_id: TransactionId,
+    pub batch_span:     Span,
+}
+
+/// A submitted batch awaiting the finalization of its root.
+#[derive(Clone, Debug)]
+pub struct PendingBatch {
+    pub span:        Span,
+    /// When the batch was committed
+    pub enqueued_at: Instant,
+}
+
+impl PendingBatch {
+    #[must_use]
+    pub fn new(span: Span) -> Self {
+        Self {
+            span,
+            enqueued_at: Instant::now(),
+        }
+    }
+
+    /// Records the finalization of the batch's root in its span and in the
+    /// batch latency histogram. Returns the latency of the batch.
+    pub fn finalize(self, root: &U256) -> Duration {
+        let latency = self.enqueued_at.elapsed();
+        BATCH_LATENCY.observe(latency.as_secs_f64());
+
+        self.span
+            .in_scope(|| info!(?root, ?latency, "Batch finalized"));
+
+        latency
+    }
+}
+
+/// The spans of submitted batches by their post root, kept until the root is
+/// finalized so that finalization is traced as part of the batch too.
+#[derive(Debug, Default)]
+pub struct BatchSpans {
+    spans: Mutex<VecDeque<(U256, PendingBatch)>>,
+}
+
+impl BatchSpans {
+    /// Keeps the batch with the given post root until the root is finalized.
+    pub fn insert(&self, root: U256, batch: PendingBatch) {
+        let mut spans = self.spans.lock().expect("Batch spans lock poisoned");
+
+        if spans.len() == MAX_PENDING_BATCH_SPANS {
+            spans.pop_front();
+        }
+
+        spans.push_back((root, batch));
+    }
+
+    /// Returns the batch with the given post root, which is no longer kept
+    /// afterwards.
+    pub fn finish(&self, root: &U256) -> Option<PendingBatch> {
+        let mut spans = self.spans.lock().expect("Batch spans lock poisoned");
+
+        let position = spans
+            .iter()
+            .position(|(batch_root, _)| batch_root == root)?;
+
+        spans.remove(position).map(|(_, batch)| batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tracing::info_span;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn batch_span_is_kept_from_submission_to_finalization() {
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry());
+
+        let batch_span = info_span!("batch");
+        let batch_id = batch_span.id().expect("Span should be enabled");
+        let root = U256::from(42);
+
+        let batch_spans = BatchSpans::default();
+        batch_spans.insert(root, PendingBatch::new(batch_span.clone()));
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        sender
+            .send(MonitoredTx {
+                transaction_id: TransactionId("tx".to_string()),
+                batch_span,
+            })
+            .await
+            .unwrap();
+
+        let monitored = receiver.recv().await.unwrap();
+        assert_eq!(monitored.batch_span.id(), Some(batch_id.clone()));
+
+        let finalized = batch_spans.finish(&root).expect("Span should be kept");
+        assert_eq!(finalized.span.id(), Some(batch_id));
+        assert!(batch_spans.finish(&root).is_none());
+    }
+
+    #[test]
+    fn oldest_batch_span_is_dropped_when_full() {
+        let batch_spans = BatchSpans::default();
+
+        for root in 0..=MAX_PENDING_BATCH_SPANS {
+            batch_spans.insert(U256::from(root), PendingBatch::new(Span::none()));
+        }
+
+        assert!(batch_spans.finish(&U256::zero()).is_none());
+        assert!(batch_spans.finish(&U256::one()).is_some());
+    }
+
+    #[test]
+    fn latency_is_recorded_on_finalization() {
+        let root = U256::from(42);
+        let enqueued_at = Instant::now() - Duration::from_secs(30);
+
+        let batch_spans = BatchSpans::default();
+        batch_spans.insert(root, PendingBatch {
+            span: Span::none(),
+            enqueued_at,
+        });
+
+        let samples = BATCH_LATENCY.get_sample_count();
+        let latency = batch_spans
+            .finish(&root)
+            .expect("Batch should be kept")
+            .finalize(&root);
+
+        assert!(latency >= Duration::from_secs(30));
+        assert!(latency < Duration::from_secs(60));
+        assert!(BATCH_LATENCY.get_sample_count() > samples);
+    }
+}
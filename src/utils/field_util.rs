@@ -0,0 +1,112 @@
+use ethers::types::U256;
+use semaphore::poseidon_tree::{Branch, Proof};
+use semaphore::Field;
+
+/// Converts a field element into the `U256` used by the contracts and the
+/// prover.
+#[must_use]
+pub fn field_to_u256(field: Field) -> U256 {
+    field.into()
+}
+
+/// Converts a `U256` into a field element.
+///
+/// The value is not reduced, so values above the field modulus are kept as
+/// they are. Callers are expected to validate untrusted values separately.
+#[must_use]
+pub fn u256_to_field(value: U256) -> Field {
+    value.into()
+}
+
+/// Returns the siblings along a merkle proof, starting next to the leaf, in
+/// the form the contracts and the prover take them.
+#[must_use]
+pub fn merkle_proof_siblings(proof: &Proof) -> Vec<U256> {
+    proof
+        .0
+        .iter()
+        .map(|branch| match branch {
+            Branch::Left(v) | Branch::Right(v) => field_to_u256(*v),
+        })
+        .collect()
+}
+
+/// Encodes a field element as 32 big-endian bytes.
+#[must_use]
+pub fn field_to_be_bytes(field: Field) -> [u8; 32] {
+    field.to_be_bytes()
+}
+
+/// Encodes a `U256` as 32 big-endian bytes, as the contracts hash it.
+#[must_use]
+pub fn u256_to_be_bytes(value: U256) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
+
+/// Encodes a `U256` as 32 little-endian bytes.
+#[must_use]
+pub fn u256_to_le_bytes(value: U256) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    value.to_little_endian(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// The largest element of the BN254 scalar field.
+    const MODULUS_MINUS_ONE: [u8; 32] =
+        hex!("30644e72e131a029b85045b68181585d2833e84879b9709143e1f593f0000000");
+
+    fn modulus_minus_one() -> Field {
+        Field::from_be_bytes(MODULUS_MINUS_ONE)
+    }
+
+    #[test]
+    fn zero_converts_to_zero() {
+        assert_eq!(field_to_u256(Field::ZERO), U256::zero());
+        assert_eq!(u256_to_field(U256::zero()), Field::ZERO);
+        assert_eq!(field_to_be_bytes(Field::ZERO), [0; 32]);
+        assert_eq!(u256_to_be_bytes(U256::zero()), [0; 32]);
+        assert_eq!(u256_to_le_bytes(U256::zero()), [0; 32]);
+    }
+
+    #[test]
+    fn one_is_encoded_in_the_last_byte() {
+        let mut expected = [0; 32];
+        expected[31] = 1;
+
+        assert_eq!(field_to_be_bytes(Field::from(1)), expected);
+        assert_eq!(u256_to_be_bytes(U256::one()), expected);
+
+        expected.reverse();
+        assert_eq!(u256_to_le_bytes(U256::one()), expected);
+    }
+
+    #[test]
+    fn modulus_minus_one_round_trips() {
+        let field = modulus_minus_one();
+        let value = field_to_u256(field);
+
+        assert_eq!(
+            value,
+            U256::from_dec_str(
+                "21888242871839275222246405745257275088548364400416034343698204186575808495616"
+            )
+            .unwrap()
+        );
+        assert_eq!(u256_to_field(value), field);
+        assert_eq!(field_to_be_bytes(field), MODULUS_MINUS_ONE);
+        assert_eq!(u256_to_be_bytes(value), MODULUS_MINUS_ONE);
+    }
+
+    #[test]
+    fn values_above_the_modulus_are_not_reduced() {
+        assert_eq!(field_to_u256(u256_to_field(U256::MAX)), U256::MAX);
+    }
+}
@@ -52,6 +52,10 @@ where
         self.map.is_empty()
     }
 
+    pub fn min_key(&self) -> Option<K> {
+        self.map.keys().next().copied()
+    }
+
     pub fn max_key(&self) -> Option<K> {
         self.map.keys().next_back().copied()
     }
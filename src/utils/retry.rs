@@ -0,0 +1,180 @@
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+use tracing::warn;
+
+/// Jittered exponential backoff between retries of a failing call.
+///
+/// The delay before retry `n` is drawn uniformly from the upper half of
+/// `base_delay * multiplier^(n - 1)`, capped at `max_delay`. The jitter keeps
+/// sequencers from hammering the RPC provider in lockstep once it recovers
+/// from an outage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub base_delay: Duration,
+    pub max_delay:  Duration,
+    pub multiplier: f64,
+}
+
+impl Backoff {
+    #[must_use]
+    pub fn new(base_delay: Duration, max_delay: Duration, multiplier: f64) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            multiplier,
+        }
+    }
+
+    /// The longest delay before retry `attempt`, counting from 1.
+    #[must_use]
+    pub fn ceiling(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = self.base_delay.as_secs_f64() * self.multiplier.powi(exponent);
+
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    /// The delay to wait before retry `attempt`, counting from 1.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.jittered_delay(attempt, &mut rand::thread_rng())
+    }
+
+    /// Calls `call` until it succeeds, retrying up to `retries` times with the
+    /// backoff delays in between. Returns the error of the last attempt if
+    /// every attempt failed.
+    pub async fn retry<T, E, F, Fut>(&self, retries: u32, mut call: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: fmt::Display,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match call().await {
+                Ok(value) => return Ok(value),
+                Err(error) if attempt < retries => {
+                    attempt += 1;
+                    let retry_delay = self.delay(attempt);
+                    warn!(
+                        %error,
+                        attempt,
+                        retries,
+                        "Call failed, retrying in {retry_delay:?}"
+                    );
+                    tokio::time::sleep(retry_delay).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn jittered_delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let ceiling = self.ceiling(attempt);
+        let floor = ceiling / 2;
+
+        floor + (ceiling - floor).mul_f64(rng.gen::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(2), 2.0)
+    }
+
+    #[test]
+    fn ceiling_grows_until_the_max_delay() {
+        let ceilings: Vec<_> = (1..=7).map(|attempt| backoff().ceiling(attempt)).collect();
+
+        assert_eq!(ceilings, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(400),
+            Duration::from_millis(800),
+            Duration::from_millis(1600),
+            Duration::from_secs(2),
+            Duration::from_secs(2),
+        ]);
+    }
+
+    #[test]
+    fn delays_grow_and_are_jittered_within_bounds() {
+        let backoff = backoff();
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for attempt in 1..=7 {
+            let ceiling = backoff.ceiling(attempt);
+            let delays: Vec<_> = (0..100)
+                .map(|_| backoff.jittered_delay(attempt, &mut rng))
+                .collect();
+
+            assert!(delays
+                .iter()
+                .all(|delay| *delay >= ceiling / 2 && *delay <= ceiling));
+            assert!(
+                delays.iter().any(|delay| *delay != delays[0]),
+                "delays for attempt {attempt} are not jittered"
+            );
+        }
+
+        // The shortest possible delay of a later attempt is at least the
+        // longest delay of an attempt two steps before it
+        for attempt in 1..=4 {
+            assert!(backoff.ceiling(attempt + 2) / 2 >= backoff.ceiling(attempt));
+        }
+    }
+
+    #[test]
+    fn huge_attempt_counts_are_capped() {
+        assert_eq!(backoff().ceiling(u32::MAX), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_back_off_until_the_call_succeeds() {
+        let calls = &AtomicU32::new(0);
+
+        let start = tokio::time::Instant::now();
+        let result = backoff()
+            .retry(3, || async move {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err("unavailable"),
+                    call => Ok(call),
+                }
+            })
+            .await;
+
+        assert_eq!(result, Ok(2));
+
+        // Two retries, jittered within the upper half of 100ms and 200ms
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(150), "{elapsed:?}");
+        assert!(elapsed <= Duration::from_millis(300), "{elapsed:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_give_up_with_the_last_error() {
+        let calls = &AtomicU32::new(0);
+
+        let result: Result<(), _> = backoff()
+            .retry(
+                2,
+                || async move { Err(calls.fetch_add(1, Ordering::SeqCst)) },
+            )
+            .await;
+
+        assert_eq!(result, Err(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
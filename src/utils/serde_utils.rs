@@ -2,8 +2,9 @@ use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
 
+use semaphore::Field;
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct JsonStrWrapper<T>(pub T);
@@ -62,9 +63,56 @@ impl<T> From<T> for JsonStrWrapper<T> {
     }
 }
 
+/// Parses a field element from a `0x`-prefixed hex, bare hex or `dec:`-prefixed
+/// decimal string.
+///
+/// Bare values are always read as hex, as they were before the other formats
+/// were accepted, so `"10"` is `0x10`.
+pub fn parse_field(s: &str) -> anyhow::Result<Field> {
+    let s = s.trim();
+
+    let invalid = |reason: &dyn fmt::Display| {
+        anyhow::anyhow!(
+            "Invalid field element {s:?}, expected a 0x-prefixed hex, bare hex or dec:-prefixed \
+             decimal number: {reason}"
+        )
+    };
+
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"));
+
+    let (digits, radix) = match (hex, s.strip_prefix("dec:")) {
+        (Some(hex), _) => (hex, 16),
+        (None, Some(decimal)) => (decimal, 10),
+        (None, None) => (s, 16),
+    };
+
+    if digits.is_empty() {
+        return Err(invalid(&"no digits"));
+    }
+
+    Field::from_str_radix(digits, radix).map_err(|err| invalid(&err))
+}
+
+/// Serde helpers for field elements that accept every format supported by
+/// [`parse_field`]. Values are serialized as `0x`-prefixed hex.
+pub mod lenient_field {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Field, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Field, D::Error> {
+        let s = Cow::<'static, str>::deserialize(deserializer)?;
+
+        parse_field(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::Value;
+    use test_case::test_case;
 
     use super::*;
 
@@ -93,4 +141,49 @@ mod tests {
 
         assert_eq!(wrapper.0, vec![1, 2, 3]);
     }
+
+    #[test_case("0x1f" ; "prefixed hex")]
+    #[test_case("0X1F" ; "uppercase prefixed hex")]
+    #[test_case("1f" ; "bare hex")]
+    #[test_case("dec:31" ; "decimal")]
+    #[test_case(" 0x1f " ; "surrounding whitespace")]
+    fn parse_field_formats(s: &str) {
+        assert_eq!(parse_field(s).unwrap(), Field::from(31));
+    }
+
+    #[test_case("" ; "empty")]
+    #[test_case("0x" ; "prefix only")]
+    #[test_case("0xzz" ; "invalid hex")]
+    #[test_case("-1" ; "negative")]
+    #[test_case("dec:" ; "decimal prefix only")]
+    #[test_case("dec:1f" ; "hex digits after decimal prefix")]
+    fn parse_field_rejects_invalid_input(s: &str) {
+        let error = parse_field(s).unwrap_err().to_string();
+
+        assert!(
+            error.contains("expected a 0x-prefixed hex, bare hex or dec:-prefixed"),
+            "Unexpected error: {error}"
+        );
+    }
+
+    #[test]
+    fn bare_digits_are_hex() {
+        assert_eq!(parse_field("10").unwrap(), Field::from(0x10));
+    }
+
+    #[test]
+    fn lenient_field_round_trip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Wrapper {
+            #[serde(with = "lenient_field")]
+            value: Field,
+        }
+
+        let wrapper: Wrapper = serde_json::from_str(r#"{ "value": "dec:255" }"#).unwrap();
+        assert_eq!(wrapper.value, Field::from(255));
+
+        let s = serde_json::to_string(&wrapper).unwrap();
+        let round_tripped: Wrapper = serde_json::from_str(&s).unwrap();
+        assert_eq!(round_tripped, wrapper);
+    }
 }
@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The payload POSTed to the finalization webhook for every finalized root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalizationNotification {
+    pub root:         U256,
+    /// The mainnet block the root was mined in. Unknown for a root that
+    /// finalization was resumed from
+    pub block_number: Option<u64>,
+    pub timestamp:    DateTime<Utc>,
+}
+
+/// Notifies an external service whenever a root is finalized.
+///
+/// Notifications are delivered in the background and retried a bounded number
+/// of times, so a slow or failing receiver never holds up finalization.
+#[derive(Clone, Debug)]
+pub struct FinalizationWebhook {
+    client:      reqwest::Client,
+    url:         Url,
+    retries:     u32,
+    retry_delay: Duration,
+}
+
+impl FinalizationWebhook {
+    pub fn new(url: Url, retries: u32) -> anyhow::Result<Self> {
+        Self::with_retry_delay(url, retries, RETRY_DELAY)
+    }
+
+    pub fn with_retry_delay(url: Url, retries: u32, retry_delay: Duration) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            client,
+            url,
+            retries,
+            retry_delay,
+        })
+    }
+
+    /// Delivers the notification in a background task.
+    pub fn notify(&self, notification: FinalizationNotification) {
+        let webhook = self.clone();
+
+        tokio::spawn(async move {
+            if let Err(error) = webhook.deliver(&notification).await {
+                warn!(
+                    root = ?notification.root,
+                    ?error,
+                    "Giving up on delivering finalization notification"
+                );
+            }
+        });
+    }
+
+    /// Delivers the notification, retrying up to the configured number of
+    /// times. Returns the error of the last attempt if every attempt failed.
+    pub async fn deliver(&self, notification: &FinalizationNotification) -> anyhow::Result<()> {
+        let mut attempt = 0;
+
+        loop {
+            match self.post(notification).await {
+                Ok(()) => {
+                    info!(root = ?notification.root, "Finalization notification delivered");
+                    return Ok(());
+                }
+                Err(error) if attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        root = ?notification.root,
+                        ?error,
+                        attempt,
+                        "Failed to deliver finalization notification, retrying"
+                    );
+                    tokio::time::sleep(self.retry_delay).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn post(&self, notification: &FinalizationNotification) -> anyhow::Result<()> {
+        self.client
+            .post(self.url.clone())
+            .json(notification)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Context;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use axum_server::Handle;
+
+    use super::*;
+
+    /// Starts a receiver that fails the first `failures` requests and records
+    /// every payload it accepts.
+    fn spawn_receiver(failures: usize) -> (Handle, Arc<Mutex<Vec<FinalizationNotification>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let remaining_failures = Arc::new(Mutex::new(failures));
+
+        let app = Router::new().route(
+            "/finalized",
+            post({
+                let received = received.clone();
+                move |Json(payload): Json<FinalizationNotification>| {
+                    let received = received.clone();
+                    let remaining_failures = remaining_failures.clone();
+
+                    async move {
+                        let mut remaining_failures = remaining_failures.lock().unwrap();
+                        if *remaining_failures > 0 {
+                            *remaining_failures -= 1;
+                            return StatusCode::SERVICE_UNAVAILABLE;
+                        }
+
+                        received.lock().unwrap().push(payload);
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+
+        let handle = Handle::new();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        tokio::spawn(
+            axum_server::bind(addr)
+                .handle(handle.clone())
+                .serve(app.into_make_service()),
+        );
+
+        (handle, received)
+    }
+
+    fn notification() -> FinalizationNotification {
+        FinalizationNotification {
+            root:         U256::from(42),
+            block_number: Some(1234),
+            timestamp:    "2023-10-01T12:00:00Z".parse().unwrap(),
+        }
+    }
+
+    fn webhook(port: u16, retries: u32) -> FinalizationWebhook {
+        let url = format!("http://127.0.0.1:{port}/finalized")
+            .parse()
+            .unwrap();
+
+        FinalizationWebhook::with_retry_delay(url, retries, Duration::from_millis(10)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn notification_is_delivered_after_retries() -> anyhow::Result<()> {
+        let (handle, received) = spawn_receiver(2);
+        let addr = handle
+            .listening()
+            .await
+            .context("Receiver failed to start")?;
+
+        webhook(addr.port(), 2).deliver(&notification()).await?;
+
+        assert_eq!(*received.lock().unwrap(), vec![notification()]);
+
+        handle.shutdown();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_retries() -> anyhow::Result<()> {
+        let (handle, received) = spawn_receiver(3);
+        let addr = handle
+            .listening()
+            .await
+            .context("Receiver failed to start")?;
+
+        assert!(webhook(addr.port(), 2)
+            .deliver(&notification())
+            .await
+            .is_err());
+        assert!(received.lock().unwrap().is_empty());
+
+        handle.shutdown();
+
+        Ok(())
+    }
+
+    #[test]
+    fn payload_uses_the_documented_fields() {
+        let payload = serde_json::to_value(notification()).unwrap();
+
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "root": "0x2a",
+                "block_number": 1234,
+                "timestamp": "2023-10-01T12:00:00Z",
+            })
+        );
+    }
+}
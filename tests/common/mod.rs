@@ -299,7 +299,7 @@ pub async fn test_inclusion_status(
     client: &Client<HttpConnector>,
     leaf: &Hash,
     expected_status: impl Into<Status>,
-) {
+) -> serde_json::Value {
     let body = construct_inclusion_proof_body(leaf);
     info!(?uri, "Contacting");
     let req = Request::builder()
@@ -336,6 +336,8 @@ pub async fn test_inclusion_status(
         expected_status,
         Status::from_str(status).expect("Could not convert str to Status")
     );
+
+    result_json
 }
 
 #[instrument(skip_all)]
@@ -2,7 +2,7 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use anyhow::Context;
-use ethers::types::Address;
+use ethers::types::{Address, U256};
 use signup_sequencer::config::{
     default, AppConfig, Config, DatabaseConfig, NetworkConfig, OzDefenderConfig, ProvidersConfig,
     RelayerConfig, ServerConfig, TreeConfig,
@@ -20,35 +20,37 @@ pub const DEFAULT_TREE_DENSE_PREFIX_DEPTH: usize = 10;
 pub const DEFAULT_TIME_BETWEEN_SCANS_SECONDS: u64 = 1;
 
 pub struct TestConfigBuilder {
-    tree_depth:               usize,
-    dense_tree_prefix_depth:  usize,
-    prover_urls:              Vec<ProverConfig>,
-    batch_insertion_timeout:  Duration,
-    batch_deletion_timeout:   Duration,
-    min_batch_deletion_size:  usize,
-    db_url:                   Option<String>,
-    oz_api_url:               Option<String>,
-    oz_address:               Option<Address>,
-    cache_file:               Option<String>,
+    tree_depth: usize,
+    dense_tree_prefix_depth: usize,
+    prover_urls: Vec<ProverConfig>,
+    batch_insertion_timeout: Duration,
+    batch_deletion_timeout: Duration,
+    min_batch_deletion_size: usize,
+    db_url: Option<String>,
+    oz_api_url: Option<String>,
+    oz_address: Option<Address>,
+    cache_file: Option<String>,
     identity_manager_address: Option<Address>,
     primary_network_provider: Option<SecretUrl>,
+    resume_finalization_from_root: Option<U256>,
 }
 
 impl TestConfigBuilder {
     pub fn new() -> Self {
         Self {
-            tree_depth:               DEFAULT_TREE_DEPTH,
-            dense_tree_prefix_depth:  DEFAULT_TREE_DENSE_PREFIX_DEPTH,
-            prover_urls:              vec![],
-            batch_insertion_timeout:  Duration::from_secs(DEFAULT_BATCH_INSERTION_TIMEOUT_SECONDS),
-            batch_deletion_timeout:   Duration::from_secs(DEFAULT_BATCH_DELETION_TIMEOUT_SECONDS),
-            min_batch_deletion_size:  1,
-            db_url:                   None,
-            oz_api_url:               None,
-            oz_address:               None,
-            cache_file:               None,
+            tree_depth: DEFAULT_TREE_DEPTH,
+            dense_tree_prefix_depth: DEFAULT_TREE_DENSE_PREFIX_DEPTH,
+            prover_urls: vec![],
+            batch_insertion_timeout: Duration::from_secs(DEFAULT_BATCH_INSERTION_TIMEOUT_SECONDS),
+            batch_deletion_timeout: Duration::from_secs(DEFAULT_BATCH_DELETION_TIMEOUT_SECONDS),
+            min_batch_deletion_size: 1,
+            db_url: None,
+            oz_api_url: None,
+            oz_address: None,
+            cache_file: None,
             identity_manager_address: None,
             primary_network_provider: None,
+            resume_finalization_from_root: None,
         }
     }
 
@@ -106,6 +108,11 @@ impl TestConfigBuilder {
         self
     }
 
+    pub fn resume_finalization_from_root(mut self, root: U256) -> Self {
+        self.resume_finalization_from_root = Some(root);
+        self
+    }
+
     pub fn add_prover(mut self, prover: &ProverService) -> Self {
         let prover_config = ProverConfig {
             url:         prover.url().to_string(),
@@ -127,15 +134,47 @@ impl TestConfigBuilder {
 
         let config = Config {
             app:       AppConfig {
-                provers_urls:               self.prover_urls.into(),
-                batch_insertion_timeout:    self.batch_insertion_timeout,
-                batch_deletion_timeout:     self.batch_deletion_timeout,
-                min_batch_deletion_size:    self.min_batch_deletion_size,
-                max_epoch_duration:         default::max_epoch_duration(),
-                scanning_window_size:       default::scanning_window_size(),
-                scanning_chain_head_offset: default::scanning_chain_head_offset(),
-                time_between_scans:         Duration::from_secs(DEFAULT_TIME_BETWEEN_SCANS_SECONDS),
-                monitored_txs_capacity:     default::monitored_txs_capacity(),
+                provers_urls:                   self.prover_urls.into(),
+                batch_insertion_timeout:        self.batch_insertion_timeout,
+                batch_deletion_timeout:         self.batch_deletion_timeout,
+                insertion_ordering:             Default::default(),
+                min_batch_deletion_size:        self.min_batch_deletion_size,
+                max_epoch_duration:             default::max_epoch_duration(),
+                scanning_window_size:           default::scanning_window_size(),
+                scanning_chain_head_offset:     default::scanning_chain_head_offset(),
+                time_between_scans:             Duration::from_secs(
+                    DEFAULT_TIME_BETWEEN_SCANS_SECONDS,
+                ),
+                finalization_startup_jitter:    default::finalization_startup_jitter(),
+                monitored_txs_capacity:         default::monitored_txs_capacity(),
+                min_confirmations:              default::min_confirmations(),
+                resume_finalization_from_root:  self.resume_finalization_from_root,
+                prover_max_requests_per_second: None,
+                input_hash_domain:              None,
+                input_hash_function:            Default::default(),
+                input_hash_endianness:          Default::default(),
+                prover_proof_format:            Default::default(),
+                prover_warmup:                  default::prover_warmup(),
+                prover_startup_check:           Default::default(),
+                prover_failure_threshold:       None,
+                prover_failure_cooldown:        default::prover_failure_cooldown(),
+                finalization_alert_after:       None,
+                finalization_webhook_url:       None,
+                finalization_webhook_retries:   default::finalization_webhook_retries(),
+                prover_user_agent:              None,
+                prover_circuit_validation:      false,
+                verify_echoed_inputs:           false,
+                prover_check_interval:          None,
+                pause_drifted_provers:          false,
+                log_proof_truncate_elements:    default::log_proof_truncate_elements(),
+                prover_max_response_bytes:      default::prover_max_response_bytes(),
+                max_unfinalized_roots:          None,
+                finalization_rpc_rate_limit:    None,
+                max_bridge_clock_skew:          default::max_bridge_clock_skew(),
+                prover_proof_cache_capacity:    None,
+                skip_clean_slate:               false,
+                on_root_mismatch:               Default::default(),
+                inter_batch_submission_delay:   default::inter_batch_submission_delay(),
             },
             tree:      TreeConfig {
                 tree_depth:              self.tree_depth,
@@ -150,11 +189,28 @@ impl TestConfigBuilder {
                     .identity_manager_address
                     .context("Missing identity manager address")?,
                 relayed_identity_manager_addresses: Default::default(),
+                mirrored_identity_manager_address:  None,
+                submission_mode:                    Default::default(),
+                nonce_strategy:                     Default::default(),
+                mining_timeout:                     default::mining_timeout(),
+                gas_price_oracle:                   Default::default(),
+                owner_check_retries:                default::owner_check_retries(),
+                owner_check_retry_delay:            default::owner_check_retry_delay(),
+                owner_check_retry_max_delay:        default::owner_check_retry_max_delay(),
+                owner_check_retry_multiplier:       default::owner_check_retry_multiplier(),
+                view_call_retries:                  default::view_call_retries(),
+                view_call_retry_delay:              default::view_call_retry_delay(),
+                view_call_retry_max_delay:          default::view_call_retry_max_delay(),
+                view_call_retry_multiplier:         default::view_call_retry_multiplier(),
+                multicall_address:                  default::multicall_address(),
+                backfill_from_block:                None,
+                expected_contract_codehash:         None,
             },
             providers: ProvidersConfig {
                 primary_network_provider:  self
                     .primary_network_provider
                     .context("Missing primary network provider")?,
+                write_network_provider:    None,
                 relayed_network_providers: Default::default(),
             },
             relayer:   RelayerConfig::OzDefender(OzDefenderConfig {
@@ -0,0 +1,103 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::identity_tree::ProcessedStatus;
+
+use crate::common::test_inclusion_status;
+
+async fn post(uri: &str, client: &Client<HttpConnector>, path: &str) {
+    let request = Request::builder()
+        .method("POST")
+        .uri(uri.to_owned() + path)
+        .body(Body::empty())
+        .expect("Failed to create request");
+
+    let response = client
+        .request(request)
+        .await
+        .expect("Failed to execute request.");
+    assert!(response.status().is_success());
+}
+
+/// Tests that no batches are submitted while the pipeline is paused, and that
+/// the queued identities are submitted once it is resumed.
+#[tokio::test]
+async fn pause_pipeline() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting pause pipeline test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let batch_size: usize = 3;
+
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) =
+        spawn_deps(initial_root, &[batch_size], &[], DEFAULT_TREE_DEPTH as u8).await?;
+
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(prover_mock)
+        .build()?;
+
+    let (app, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    post(&uri, &client, "/pause").await;
+
+    // Insert enough identities to fill a batch
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 0).await;
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 1).await;
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 2).await;
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    // Nothing is submitted while paused
+    for identity in &identities_ref {
+        test_inclusion_status(&uri, &client, identity, ProcessedStatus::Pending).await;
+    }
+
+    post(&uri, &client, "/resume").await;
+
+    // The processing task picks the batch up on its next timer tick
+    tokio::time::sleep(Duration::from_secs(
+        test_config::DEFAULT_BATCH_INSERTION_TIMEOUT_SECONDS + 10,
+    ))
+    .await;
+
+    test_inclusion_proof(&uri, &client, 0, &ref_tree, &identities_ref[0], false).await;
+    test_inclusion_proof(&uri, &client, 1, &ref_tree, &identities_ref[1], false).await;
+    test_inclusion_proof(&uri, &client, 2, &ref_tree, &identities_ref[2], false).await;
+
+    shutdown();
+    app.await?;
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::identity_tree::{DeletionStatus, ProcessedStatus};
+
+use crate::common::{test_delete_identity, test_inclusion_status};
+
+const IDLE_TIME: u64 = 7;
+
+/// Tests that an identity queued for deletion is reported as pending deletion,
+/// along with the status of the root it is still proven against.
+#[tokio::test]
+async fn pending_deletion() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting pending deletion test");
+
+    let insertion_batch_size: usize = 3;
+    let deletion_batch_size: usize = 3;
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let (mock_chain, db_container, insertion_prover_map, deletion_prover_map, micro_oz) =
+        spawn_deps(
+            initial_root,
+            &[insertion_batch_size],
+            &[deletion_batch_size],
+            DEFAULT_TREE_DEPTH as u8,
+        )
+        .await?;
+
+    let mock_insertion_prover = &insertion_prover_map[&insertion_batch_size];
+    let mock_deletion_prover = &deletion_prover_map[&deletion_batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+
+    // A single deletion stays queued until the batch deletion timeout
+    let config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .min_batch_deletion_size(deletion_batch_size)
+        .add_prover(mock_insertion_prover)
+        .add_prover(mock_deletion_prover)
+        .build()?;
+
+    let (app, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(insertion_batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    for i in 0..insertion_batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, i).await;
+    }
+
+    tokio::time::sleep(Duration::from_secs(IDLE_TIME)).await;
+
+    let response =
+        test_inclusion_status(&uri, &client, &identities_ref[0], ProcessedStatus::Mined).await;
+    assert!(response.get("rootStatus").is_none());
+
+    test_delete_identity(&uri, &client, &mut ref_tree, &identities_ref, 0, false).await;
+
+    let response = test_inclusion_status(
+        &uri,
+        &client,
+        &identities_ref[0],
+        DeletionStatus::PendingDeletion,
+    )
+    .await;
+    assert_eq!(response["rootStatus"], "mined");
+
+    // The other identities are unaffected
+    test_inclusion_status(&uri, &client, &identities_ref[1], ProcessedStatus::Mined).await;
+
+    shutdown();
+    app.await.unwrap();
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    for (_, prover) in deletion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
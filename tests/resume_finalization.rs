@@ -0,0 +1,109 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::identity_tree::ProcessedStatus;
+
+use crate::common::test_inclusion_status;
+
+const IDLE_TIME: u64 = 7;
+
+#[tokio::test]
+async fn resume_finalization() -> anyhow::Result<()> {
+    // Initialize logging for the test.
+    init_tracing_subscriber();
+    info!("Starting integration test");
+
+    let batch_size: usize = 3;
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let (mock_chain, db_container, insertion_prover_map, _, micro_oz) =
+        spawn_deps(initial_root, &[batch_size], &[], DEFAULT_TREE_DEPTH as u8).await?;
+
+    let prover_mock = &insertion_prover_map[&batch_size];
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    // temp dir will be deleted on drop call
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let config_builder = || {
+        TestConfigBuilder::new()
+            .db_url(&db_url)
+            .oz_api_url(&micro_oz.endpoint())
+            .oz_address(micro_oz.address())
+            .identity_manager_address(mock_chain.identity_manager.address())
+            .primary_network_provider(mock_chain.anvil.endpoint())
+            .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+            .add_prover(prover_mock)
+    };
+
+    let (app, local_addr) = spawn_app(config_builder().build()?)
+        .await
+        .expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    for i in 0..batch_size {
+        test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, i).await;
+    }
+
+    tokio::time::sleep(Duration::from_secs(IDLE_TIME)).await;
+
+    for i in 0..batch_size {
+        test_inclusion_proof(&uri, &client, i, &ref_tree, &identities_ref[i], false).await;
+    }
+
+    info!("Stopping the app for testing purposes");
+    shutdown();
+    app.await.unwrap();
+    reset_shutdown();
+
+    // A root the sequencer has never seen must be rejected at startup
+    let unknown_root = U256::from(42);
+    let config = config_builder()
+        .resume_finalization_from_root(unknown_root)
+        .build()?;
+
+    assert!(
+        App::new(config).await.is_err(),
+        "App should not start with an unknown root to resume finalization from"
+    );
+
+    // A known root is enqueued and finalized once the app is up again
+    let latest_root: U256 = ref_tree.root().into();
+    let config = config_builder()
+        .resume_finalization_from_root(latest_root)
+        .build()?;
+
+    let (app, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+    let uri = "http://".to_owned() + &local_addr.to_string();
+
+    tokio::time::sleep(Duration::from_secs(IDLE_TIME)).await;
+
+    for identity in &identities_ref {
+        test_inclusion_status(&uri, &client, identity, ProcessedStatus::Mined).await;
+    }
+
+    shutdown();
+    app.await.unwrap();
+    for (_, prover) in insertion_prover_map.into_iter() {
+        prover.stop();
+    }
+    reset_shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::prelude::*;
+use signup_sequencer::identity_tree::ProcessedStatus;
+
+use crate::common::test_inclusion_status;
+
+/// Tests that identities are still accepted and queued while no prover can be
+/// reached at all.
+#[tokio::test]
+async fn unreachable_provers() -> anyhow::Result<()> {
+    init_tracing_subscriber();
+    info!("Starting unreachable provers test");
+
+    let mut ref_tree = PoseidonTree::new(DEFAULT_TREE_DEPTH + 1, ruint::Uint::ZERO);
+    let initial_root: U256 = ref_tree.root().into();
+
+    let batch_size: usize = 3;
+
+    let (mock_chain, db_container, mut insertion_prover_map, _, micro_oz) =
+        spawn_deps(initial_root, &[batch_size], &[], DEFAULT_TREE_DEPTH as u8).await?;
+
+    let prover_mock = insertion_prover_map
+        .remove(&batch_size)
+        .context("Missing prover")?;
+
+    let db_socket_addr = db_container.address();
+    let db_url = format!("postgres://postgres:postgres@{db_socket_addr}/database");
+
+    let temp_dir = tempfile::tempdir()?;
+    info!(
+        "temp dir created at: {:?}",
+        temp_dir.path().join("testfile")
+    );
+
+    let config = TestConfigBuilder::new()
+        .db_url(&db_url)
+        .oz_api_url(&micro_oz.endpoint())
+        .oz_address(micro_oz.address())
+        .identity_manager_address(mock_chain.identity_manager.address())
+        .primary_network_provider(mock_chain.anvil.endpoint())
+        .cache_file(temp_dir.path().join("testfile").to_str().unwrap())
+        .add_prover(&prover_mock)
+        .build()?;
+
+    // The prover is gone before the sequencer ever reaches it
+    prover_mock.stop();
+
+    let (app, local_addr) = spawn_app(config).await.expect("Failed to spawn app.");
+
+    let test_identities = generate_test_identities(batch_size);
+    let identities_ref: Vec<Field> = test_identities
+        .iter()
+        .map(|i| Hash::from_str_radix(i, 16).unwrap())
+        .collect();
+
+    let uri = "http://".to_owned() + &local_addr.to_string();
+    let client = Client::new();
+
+    // Insert enough identities to fill a batch
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 0).await;
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 1).await;
+    test_insert_identity(&uri, &client, &mut ref_tree, &identities_ref, 2).await;
+
+    // Give the processing task time to find that no prover is reachable
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    // The identities are queued and the API keeps serving them
+    for identity in &identities_ref {
+        test_inclusion_status(&uri, &client, identity, ProcessedStatus::Pending).await;
+    }
+
+    shutdown();
+    app.await?;
+    reset_shutdown();
+
+    Ok(())
+}